@@ -1,22 +1,29 @@
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::diagnostics::messages;
+use crate::robust::Diagnostic;
+use crate::interpreter::{builtin_arity_error, format_real, is_builtin, reals_equal, repeat_string, suggest_name, IndexBase, BUILTINS, DEFAULT_MAX_STRING_LEN};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub struct SymbolInfo {
     pub name: String,
     pub declared: bool,
     pub used: bool,
+    pub assigned: bool,  // by an assignment after its declaration
+    pub unset: bool,     // declared by `var x` without a value
+    pub constant: Option<i64>,  // the integer it holds throughout, when its initializer folds to one and nothing assigns it
     pub is_function: bool,
     pub symbol_type: SymbolType,  
+    pub declared_at: Span,  // statement that declared it (unknown for the prelude)
 }
 
 #[derive(Debug, Clone)]
 pub enum SymbolType {
     Variable,
-    Function { param_count: usize },
+    Function { params: Vec<String> },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AnalysisError {
     Message(String),
 }
@@ -29,6 +36,8 @@ impl std::fmt::Display for AnalysisError {
     }
 }
 
+impl std::error::Error for AnalysisError {}
+
 pub type AnalysisResult<T> = Result<T, AnalysisError>;
 
 // ====
@@ -37,37 +46,203 @@ pub type AnalysisResult<T> = Result<T, AnalysisError>;
 
 pub struct SemanticChecker {
     scope_stack: Vec<HashMap<String, SymbolInfo>>,
-    array_sizes_stack: Vec<HashMap<String, usize>>,  
+    literal_sizes_stack: Vec<HashMap<String, LiteralSize>>,  // variables holding an array or tuple literal
+    value_types_stack: Vec<HashMap<String, &'static str>>,  // variables whose type never changes
+    assignments: HashMap<String, Vec<Expr>>,  // every value assigned to each name, see check()
     inside_function: bool,
     inside_loop: bool,
+    loop_labels: Vec<String>,  // labels of the enclosing loops, innermost last
+    loop_vars: Vec<(String, usize)>,  // variables of the enclosing for loops, with the scope holding them
+    function_scope: Option<usize>,    // scope of the innermost function literal's parameters
+    captured_loop_vars: Vec<String>,  // loop variables the innermost function literal reads
+    reachable: bool,  // false inside dead branches and after return/exit
+    initializing: Vec<String>,  // variables whose (non-function) initializer is being checked
+    storing: Option<String>,    // variable whose assigned value is being checked; reading it there is no use
+    warn_no_effect: bool,        // expression statements that do nothing
+    warn_function_print: bool,   // printing a function instead of calling it
+    strict_bool: bool,           // mirror Interpreter::with_strict_bool
+    allow_redeclaration: bool,   // a same-scope `var` replaces the binding, with a warning
+    index_base: IndexBase,       // mirror Interpreter::with_index_base
     errors: Vec<String>,
+    warnings: Vec<String>,  // non-fatal findings, see warnings()
+    current_span: Span,     // statement being checked
+    error_sites: Vec<DiagnosticSite>,    // one per error, see error_sites()
+    warning_sites: Vec<DiagnosticSite>,  // one per warning
+    notes: Vec<String>,  // worth knowing but not a problem, see notes()
+    note_sites: Vec<DiagnosticSite>,  // one per note
+    read_all_names: bool,   // the statement being checked looked at every visible name (for a suggestion)
+    cache: Option<CheckCache>,  // what the last check() or recheck() found, see recheck()
+    rechecked: Vec<usize>,      // see rechecked()
+    commit_declarations: bool,  // see set_commit_declarations
+}
+
+// What check() learned about a program, kept so recheck() can reuse the
+// results of the statements an edit left alone
+#[derive(Debug, Clone)]
+struct CheckCache {
+    scopes: Vec<HashMap<String, SymbolInfo>>,  // the scopes as the check found them
+    literal_sizes: Vec<HashMap<String, LiteralSize>>,
+    value_types: Vec<HashMap<String, &'static str>>,
+    assignments: HashMap<String, Vec<Expr>>,
+    statements: Vec<CheckedStmt>,  // one per top-level statement
+}
+
+#[derive(Debug, Clone)]
+struct CheckedStmt {
+    reachable: bool,
+    mentions: HashSet<String>,  // every name it uses or binds, see collect_mentions
+    read_all_names: bool,
+    errors: Vec<(String, DiagnosticSite)>,
+    warnings: Vec<(String, DiagnosticSite)>,
+    notes: Vec<(String, DiagnosticSite)>,
+    // what each scope held for each mentioned name after the statement
+    effects: Vec<(String, Vec<ScopedName>)>,
+}
+
+// A name's symbol, literal size and value type in one scope
+type ScopedName = (Option<SymbolInfo>, Option<LiteralSize>, Option<&'static str>);
+
+// Which top-level statements of a program are unchanged since an earlier
+// version of it; see Optimizer::changes and SemanticChecker::recheck
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeSet {
+    origins: Vec<Option<usize>>,  // per statement, its index in the earlier version; None once changed
+}
+
+impl ChangeSet {
+    // Unchanged statements keep their order, so one whose origin does not
+    // come after the last kept origin was moved, and counts as changed
+    pub fn new(mut origins: Vec<Option<usize>>) -> Self {
+        let mut last = None;
+        for origin in &mut origins {
+            if origin.is_some() && *origin <= last {
+                *origin = None;
+            }
+            last = last.max(*origin);
+        }
+        Self { origins }
+    }
+
+    // `len` statements, none of them changed
+    pub fn unchanged(len: usize) -> Self {
+        Self { origins: (0..len).map(Some).collect() }
+    }
+
+    // Where statement `index` was in the earlier version, if it is unchanged;
+    // a statement past the end is new
+    pub fn origin(&self, index: usize) -> Option<usize> {
+        self.origins.get(index).copied().flatten()
+    }
+
+    pub fn is_changed(&self, index: usize) -> bool {
+        self.origin(index).is_none()
+    }
+}
+
+// Where the checker found an error or warning
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticSite {
+    pub span: Span,                // the innermost statement it belongs to
+    pub name: Option<String>,      // the name it is about, e.g. an undeclared variable
+    pub previous: Option<Span>,    // for a redeclaration, the statement that declared the name first
+}
+
+// The names a check left visible, scope by scope, for checking more code
+// against later; see SemanticChecker::symbol_table
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    scopes: Vec<HashMap<String, SymbolInfo>>,
+    literal_sizes: Vec<HashMap<String, LiteralSize>>,
+    value_types: Vec<HashMap<String, &'static str>>,
+}
+
+impl SymbolTable {
+    // The innermost declaration of `name`
+    pub fn get(&self, name: &str) -> Option<&SymbolInfo> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // Every visible name, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.scopes.iter().flat_map(|scope| scope.keys().map(String::as_str)).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
 }
 
 impl SemanticChecker {
     pub fn new() -> Self {
         Self {
             scope_stack: vec![HashMap::new()],
-            array_sizes_stack: vec![HashMap::new()],
+            literal_sizes_stack: vec![HashMap::new()],
+            value_types_stack: vec![HashMap::new()],
+            assignments: HashMap::new(),
             inside_function: false,
             inside_loop: false,
+            loop_labels: Vec::new(),
+            loop_vars: Vec::new(),
+            function_scope: None,
+            captured_loop_vars: Vec::new(),
+            reachable: true,
+            initializing: Vec::new(),
+            storing: None,
+            warn_no_effect: true,
+            warn_function_print: true,
+            strict_bool: false,
+            allow_redeclaration: false,
+            index_base: IndexBase::default(),
             errors: Vec::new(),
+            warnings: Vec::new(),
+            current_span: Span::default(),
+            error_sites: Vec::new(),
+            warning_sites: Vec::new(),
+            notes: Vec::new(),
+            note_sites: Vec::new(),
+            read_all_names: false,
+            cache: None,
+            rechecked: Vec::new(),
+            commit_declarations: false,
         }
     }
     
     // entrance to the new scope
     fn push_scope(&mut self) {
         self.scope_stack.push(HashMap::new());
-        self.array_sizes_stack.push(HashMap::new());
+        self.literal_sizes_stack.push(HashMap::new());
+        self.value_types_stack.push(HashMap::new());
     }
     
     // exit from the scope
     fn pop_scope(&mut self) {
         if self.scope_stack.len() > 1 {
-            self.scope_stack.pop();
-            self.array_sizes_stack.pop();
+            if let Some(scope) = self.scope_stack.pop() {
+                self.warn_dead_stores(dead_stores(&scope));
+            }
+            self.literal_sizes_stack.pop();
+            self.value_types_stack.pop();
         }
     }
-    
+
+    // a reused label is legal; `exit` with it leaves the innermost loop
+    fn enter_labelled_loop(&mut self, label: &Option<String>) {
+        if let Some(label) = label {
+            if self.loop_labels.contains(label) {
+                self.warnings.push(format!(
+                    "Loop label '{}' shadows the label of an enclosing loop; `exit {}` leaves the inner one",
+                    messages::shorten(label), messages::shorten(label)
+                ));
+            }
+            self.loop_labels.push(label.clone());
+        }
+    }
+
+    fn leave_labelled_loop(&mut self, label: &Option<String>) {
+        if label.is_some() {
+            self.loop_labels.pop();
+        }
+    }
+
     fn get_symbol(&self, name: &str) -> Option<&SymbolInfo> {
         // Искать в scope_stack (не scopes!)
         for scope in self.scope_stack.iter().rev() {
@@ -79,6 +254,51 @@ impl SemanticChecker {
     }
     
     
+    // Note that the innermost `name` is read
+    fn mark_used(&mut self, name: &str) {
+        if self.storing.as_deref() == Some(name) {
+            return;
+        }
+        let found = self.scope_stack.iter_mut().enumerate().rev().find_map(|(i, scope)| Some(i).zip(scope.get_mut(name)));
+        if let Some((scope, symbol)) = found {
+            symbol.used = true;
+            let from_function = self.function_scope.is_some_and(|function_scope| scope < function_scope);
+            // a `var x` without a value read before anything is assigned to it
+            // (a function literal may run later, so its reads do not count);
+            // reported once per variable
+            let unset_read = symbol.unset && !symbol.assigned && !from_function && self.reachable;
+            if unset_read {
+                symbol.unset = false;
+            }
+            // a for loop's variable read from a function literal inside the loop
+            if from_function
+                && self.loop_vars.iter().any(|(var, var_scope)| var == name && *var_scope == scope)
+                && !self.captured_loop_vars.iter().any(|var| var == name)
+            {
+                self.captured_loop_vars.push(name.to_string());
+            }
+            if unset_read {
+                self.settle_sites();
+                self.warnings.push(messages::read_before_assignment(name));
+                self.warning_sites.push(DiagnosticSite { span: self.current_span, name: Some(name.to_string()), previous: None });
+            }
+        }
+    }
+
+    fn mark_assigned(&mut self, name: &str) {
+        if let Some(symbol) = self.scope_stack.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            symbol.assigned = true;
+        }
+    }
+
+    fn warn_dead_stores(&mut self, dead: Vec<(String, Span)>) {
+        for (name, declared_at) in dead {
+            self.settle_sites();
+            self.warnings.push(format!("Variable '{}' is assigned but its value is never used", messages::shorten(&name)));
+            self.warning_sites.push(DiagnosticSite { span: declared_at, name: Some(name), previous: None });
+        }
+    }
+
     fn is_declared(&self, name: &str) -> bool {
         for scope in self.scope_stack.iter().rev() {
             if scope.contains_key(name) {
@@ -89,11 +309,26 @@ impl SemanticChecker {
     }
     
     fn declare_var(&mut self, name: String, info: SymbolInfo) -> bool {
+        let allow_redeclaration = self.allow_redeclaration;
         if let Some(scope) = self.scope_stack.last_mut() {
-            if scope.contains_key(&name) {
+            let previous = scope.get(&name).map(|symbol| symbol.declared_at);
+            if previous.is_some() && !allow_redeclaration {
                 return false;  // Уже объявлена
             }
-            scope.insert(name, info);
+            let what = if info.is_function { "Function" } else { "Variable" };
+            scope.insert(name.clone(), info);
+            if let Some(previous) = previous {
+                // the newest declaration is the one later checks see
+                if let Some(sizes) = self.literal_sizes_stack.last_mut() {
+                    sizes.remove(&name);
+                }
+                if let Some(types) = self.value_types_stack.last_mut() {
+                    types.remove(&name);
+                }
+                self.settle_sites();
+                self.warnings.push(format!("{} '{}' is redeclared; the new declaration replaces the old one", what, messages::shorten(&name)));
+                self.warning_sites.push(DiagnosticSite { span: self.current_span, name: Some(name), previous: Some(previous).filter(Span::is_known) });
+            }
             true
         } else {
             false
@@ -101,15 +336,24 @@ impl SemanticChecker {
     }
     
     
-    // arr size in curr scope
-    fn record_array_size(&mut self, name: String, size: usize) {
-        let current_sizes = self.array_sizes_stack.last_mut().unwrap();
-        current_sizes.insert(name, size);
+    // arr or tuple size in curr scope
+    fn record_literal_size(&mut self, name: String, size: LiteralSize) {
+        if let Some(current_sizes) = self.literal_sizes_stack.last_mut() {
+            current_sizes.insert(name, size);
+        }
     }
     
-    // get the size of the arr
-    fn get_array_size(&self, name: &str) -> Option<usize> {
-        for sizes in self.array_sizes_stack.iter().rev() {
+    // size is no longer known at compile time (e.g. after reassignment)
+    fn forget_literal_size(&mut self, name: &str) {
+        for sizes in self.literal_sizes_stack.iter_mut().rev() {
+            if sizes.remove(name).is_some() {
+                return;
+            }
+        }
+    }
+
+    fn get_literal_size(&self, name: &str) -> Option<LiteralSize> {
+        for sizes in self.literal_sizes_stack.iter().rev() {
             if let Some(&size) = sizes.get(name) {
                 return Some(size);
             }
@@ -117,16 +361,344 @@ impl SemanticChecker {
         None
     }
 
+    // get the size of the arr
+    fn get_array_size(&self, name: &str) -> Option<usize> {
+        match self.get_literal_size(name) {
+            Some(LiteralSize::Array(size)) => Some(size),
+            _ => None,
+        }
+    }
+
+    // Type of the variable `name` refers to, if it keeps one type for the whole program
+    fn known_type(&self, name: &str) -> Option<&'static str> {
+        let scope = self.scope_stack.iter().rposition(|scope| scope.contains_key(name))?;
+        self.value_types_stack[scope].get(name).copied()
+    }
+
+    // Record the type of a new variable when its initializer and every value
+    // assigned to the name anywhere in the program agree on one
+    fn record_value_type(&mut self, name: &str, init: &Expr) {
+        let Some(ty) = self.static_type(init, None) else { return };
+        let consistent = self.assignments.get(name).is_none_or(|values| {
+            values.iter().all(|value| self.static_type(value, Some((name, ty))) == Some(ty))
+        });
+        if consistent && let Some(types) = self.value_types_stack.last_mut() {
+            types.insert(name.to_string(), ty);
+        }
+    }
+
+    // Type of `expr` as the interpreter names it, when it can be told without
+    // running it; `assumed` gives a variable's type while that is being verified
+    fn static_type(&self, expr: &Expr, assumed: Option<(&str, &'static str)>) -> Option<&'static str> {
+        expr_type(expr, &|name| match assumed {
+            Some((assumed_name, ty)) if assumed_name == name => Some(ty),
+            _ => self.known_type(name),
+        })
+    }
+
+    // Comparisons whose outcome the operand types already decide
+    fn check_comparison_types(&mut self, left: &Expr, op: &BinOp, right: &Expr) {
+        if !matches!(op, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge) {
+            return;
+        }
+        let (Some(left_type), Some(right_type)) = (self.static_type(left, None), self.static_type(right, None)) else {
+            return;
+        };
+        let numeric = |ty| matches!(ty, "int" | "real");
+        let both_numeric = numeric(left_type) && numeric(right_type);
+        // only numbers and arrays are ordered
+        let ordered = both_numeric || (left_type == "array" && right_type == "array");
+        match op {
+            // values of different types are never equal; ints and reals are both numbers
+            BinOp::Eq | BinOp::Ne if left_type != right_type && !both_numeric => {
+                self.warnings.push(format!(
+                    "Comparison between {} and {} is always {}",
+                    left_type, right_type, *op == BinOp::Ne
+                ));
+            }
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge if !ordered => {
+                self.warnings.push(format!(
+                    "Comparison between {} and {} will fail at runtime", left_type, right_type
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    // warnings from the last check() call (e.g. truncating integer division)
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // Where each error of the last check() call was found, in the order of
+    // the lines of its AnalysisError
+    pub fn error_sites(&self) -> &[DiagnosticSite] {
+        &self.error_sites
+    }
+
+    // Where each of warnings() was found
+    pub fn warning_sites(&self) -> &[DiagnosticSite] {
+        &self.warning_sites
+    }
+
+    // Informational findings from the last check() call, such as a function
+    // literal capturing a loop variable; they never fail the check
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    // Where each of notes() was found
+    pub fn note_sites(&self) -> &[DiagnosticSite] {
+        &self.note_sites
+    }
+
+    // Errors and warnings pushed without a site of their own belong to the current statement
+    fn settle_sites(&mut self) {
+        let site = DiagnosticSite { span: self.current_span, name: None, previous: None };
+        self.error_sites.resize(self.errors.len(), site.clone());
+        self.warning_sites.resize(self.warnings.len(), site);
+    }
+
+    fn error_about(&mut self, name: &str, previous: Option<Span>, message: String) {
+        self.settle_sites();
+        self.errors.push(message);
+        self.error_sites.push(DiagnosticSite { span: self.current_span, name: Some(name.to_string()), previous });
+    }
+
+    // `name` is already declared in the current scope
+    fn report_redeclaration(&mut self, what: &str, name: &str) {
+        let previous = self.scope_stack.last()
+            .and_then(|scope| scope.get(name))
+            .map(|symbol| symbol.declared_at)
+            .filter(Span::is_known);
+        self.error_about(name, previous, format!("{} '{}' is already declared", what, messages::shorten(name)));
+    }
+
+    pub fn set_warn_no_effect(&mut self, enabled: bool) {
+        self.warn_no_effect = enabled;
+    }
+
+    pub fn set_warn_function_print(&mut self, enabled: bool) {
+        self.warn_function_print = enabled;
+    }
+
+    // Reject literals that can never be a bool where strict mode requires one
+    pub fn set_strict_bool(&mut self, strict: bool) {
+        self.strict_bool = strict;
+    }
+
+    // For sessions that redefine bindings as they go (a REPL): declaring a
+    // name again in the same scope is a warning instead of an error, and the
+    // new declaration wins, as it does in the interpreter
+    pub fn set_allow_redeclaration(&mut self, allow: bool) {
+        self.allow_redeclaration = allow;
+    }
+
+    // Constant indices are checked against the same base the interpreter uses
+    pub fn set_index_base(&mut self, base: IndexBase) {
+        self.index_base = base;
+    }
+
+    fn check_bool_operand(&mut self, expr: &Expr, construct: &str) {
+        if self.strict_bool && let Some(type_name) = literal_type_name(expr) {
+            self.errors.push(messages::not_a_bool(construct, type_name));
+        }
+    }
+
+    // A function literal, or a name declared as a function
+    fn is_function_value(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Func { .. } => true,
+            Expr::Ident(name) => self.get_symbol(name).is_some_and(|symbol| symbol.is_function),
+            _ => false,
+        }
+    }
+
+    // Declare the top-level names of `prelude` (see stdlib.rs) in the
+    // outermost scope; the checked program gets a scope of its own, so its
+    // declarations may shadow them
+    pub fn declare_prelude(&mut self, prelude: &Program) {
+        let Program::Stmts(stmts) = prelude;
+        for stmt in stmts {
+            if let Stmt::VarDecl { name, init, .. } = stmt {
+                let symbol_type = match init {
+                    Some(Expr::Func { params, .. }) => SymbolType::Function { params: params.clone() },
+                    _ => SymbolType::Variable,
+                };
+                self.declare_var(name.clone(), SymbolInfo {
+                    name: name.clone(),
+                    declared: true,
+                    used: false,
+                    assigned: false,
+                    unset: init.is_none(),
+                    constant: None,
+                    is_function: matches!(init, Some(Expr::Func { .. })),
+                    symbol_type,
+                    declared_at: Span::default(),
+                });
+            }
+        }
+        self.push_scope();
+    }
+
     pub fn check(&mut self, program: &Program) -> AnalysisResult<Vec<String>> {
-        self.errors.clear();
-    
-        match program {
-            Program::Stmts(stmts) => {
-                for stmt in stmts {
-                    self.check_stmt(stmt);
-                }
+        let Program::Stmts(stmts) = program;
+        let mut cache = self.begin_check(stmts);
+        self.rechecked = (0..stmts.len()).collect();
+        cache.statements = stmts.iter().map(|stmt| self.check_top_level(stmt)).collect();
+        self.cache = Some(cache);
+        self.finish_check()
+    }
+
+    // Check `program` again after some of its top-level statements changed,
+    // reusing what the last check() or recheck() found for the others: only
+    // the changed statements are checked, with every later one that mentions
+    // a name they use or bind (or that an earlier version of the program
+    // did). The result is the same as check() from the same starting state,
+    // with the same settings. Without an earlier check it is check().
+    pub fn recheck(&mut self, program: &Program, changes: &ChangeSet) -> AnalysisResult<Vec<String>> {
+        let Some(previous) = self.cache.take() else {
+            return self.check(program);
+        };
+        self.scope_stack = previous.scopes.clone();
+        self.literal_sizes_stack = previous.literal_sizes.clone();
+        self.value_types_stack = previous.value_types.clone();
+        let Program::Stmts(stmts) = program;
+        let mut cache = self.begin_check(stmts);
+
+        // names whose declarations or uses may differ from last time: those
+        // of the statements not carried over, and those assigned differently
+        let kept: HashSet<usize> = (0..stmts.len()).filter_map(|i| changes.origin(i)).collect();
+        let mut changed_names: HashSet<String> = previous.statements.iter().enumerate()
+            .filter(|(i, _)| !kept.contains(i))
+            .flat_map(|(_, checked)| checked.mentions.iter().cloned())
+            .collect();
+        for name in previous.assignments.keys().chain(self.assignments.keys()) {
+            if previous.assignments.get(name) != self.assignments.get(name) {
+                changed_names.insert(name.clone());
             }
         }
+
+        self.rechecked.clear();
+        for (i, stmt) in stmts.iter().enumerate() {
+            let reusable = changes.origin(i)
+                .and_then(|origin| previous.statements.get(origin))
+                .filter(|checked| {
+                    checked.reachable == self.reachable
+                        && (!checked.read_all_names || changed_names.is_empty())
+                        && checked.mentions.is_disjoint(&changed_names)
+                });
+            let checked = match reusable {
+                Some(checked) => {
+                    self.replay(checked);
+                    if ends_block(stmt) {
+                        self.reachable = false;
+                    }
+                    checked.clone()
+                }
+                None => {
+                    self.rechecked.push(i);
+                    let checked = self.check_top_level(stmt);
+                    changed_names.extend(checked.mentions.iter().cloned());
+                    checked
+                }
+            };
+            cache.statements.push(checked);
+        }
+        self.cache = Some(cache);
+        self.finish_check()
+    }
+
+    // Top-level statements the last check() or recheck() checked; recheck()
+    // reused the results of the others
+    pub fn rechecked(&self) -> &[usize] {
+        &self.rechecked
+    }
+
+    // The names visible after the last check, with what is known about them
+    pub fn symbol_table(&self) -> SymbolTable {
+        SymbolTable {
+            scopes: self.scope_stack.clone(),
+            literal_sizes: self.literal_sizes_stack.clone(),
+            value_types: self.value_types_stack.clone(),
+        }
+    }
+
+    // For check_stmt_with_context: keep what the checked code declares, so
+    // that symbol_table() includes it, instead of leaving the state as it was
+    pub fn set_commit_declarations(&mut self, commit: bool) {
+        self.commit_declarations = commit;
+    }
+
+    // Check one statement (e.g. a line entered in a REPL) as if it followed
+    // the code `context` was exported from, without checking that again.
+    // Returns its errors; warnings() and notes() hold the rest.
+    pub fn check_stmt_with_context(&mut self, stmt: &Stmt, context: &SymbolTable) -> Vec<Diagnostic> {
+        self.check_with_context(std::slice::from_ref(stmt), context, |checker| checker.check_stmt(stmt))
+    }
+
+    // check_stmt_with_context for an expression
+    pub fn check_expr_with_context(&mut self, expr: &Expr, context: &SymbolTable) -> Vec<Diagnostic> {
+        self.check_with_context(&[], context, |checker| checker.check_expr(expr))
+    }
+
+    fn check_with_context(&mut self, stmts: &[Stmt], context: &SymbolTable, check: impl FnOnce(&mut Self)) -> Vec<Diagnostic> {
+        let saved = self.symbol_table();
+        self.scope_stack = context.scopes.clone();
+        self.literal_sizes_stack = context.literal_sizes.clone();
+        self.value_types_stack = context.value_types.clone();
+        if self.scope_stack.is_empty() {
+            self.push_scope();
+        }
+        self.begin_check(stmts);
+        check(self);
+        self.reachable = true;
+        self.settle_sites();
+
+        if !self.commit_declarations {
+            self.scope_stack = saved.scopes;
+            self.literal_sizes_stack = saved.literal_sizes;
+            self.value_types_stack = saved.value_types;
+        }
+        self.errors.iter().zip(&self.error_sites)
+            .map(|(message, site)| Diagnostic { message: message.clone(), line: site.span.line, col: site.span.col })
+            .collect()
+    }
+
+    // Clears the results of the last check and notes the starting state
+    // (the statements are left for the caller to fill in)
+    fn begin_check(&mut self, stmts: &[Stmt]) -> CheckCache {
+        self.errors.clear();
+        self.warnings.clear();
+        self.error_sites.clear();
+        self.warning_sites.clear();
+        self.notes.clear();
+        self.note_sites.clear();
+        self.reachable = true;
+        self.initializing.clear();
+        let cache = CheckCache {
+            scopes: self.scope_stack.clone(),
+            literal_sizes: self.literal_sizes_stack.clone(),
+            value_types: self.value_types_stack.clone(),
+            assignments: HashMap::new(),
+            statements: Vec::new(),
+        };
+
+        self.assignments.clear();
+        stmts.iter().for_each(|stmt| collect_assignments(stmt, &mut self.assignments));
+        // variables left from an earlier check() may be reassigned here
+        for types in &mut self.value_types_stack {
+            types.retain(|name, _| !self.assignments.contains_key(name));
+        }
+        CheckCache { assignments: self.assignments.clone(), ..cache }
+    }
+
+    fn finish_check(&mut self) -> AnalysisResult<Vec<String>> {
+        self.reachable = true;
+        // the program's own scope is never popped
+        let dead = self.scope_stack.iter().rev().flat_map(dead_stores).collect();
+        self.warn_dead_stores(dead);
+        self.settle_sites();
     
         if self.errors.is_empty() {
             Ok(vec![])
@@ -134,185 +706,410 @@ impl SemanticChecker {
             Err(AnalysisError::Message(self.errors.join("\n")))
         }
     }
+
+    // Checks one top-level statement and notes what recheck() needs to reuse the result
+    fn check_top_level(&mut self, stmt: &Stmt) -> CheckedStmt {
+        let reachable = self.reachable;
+        let (errors_from, warnings_from, notes_from) = (self.errors.len(), self.warnings.len(), self.notes.len());
+        self.read_all_names = false;
+        self.check_stmt(stmt);
+        if ends_block(stmt) {
+            self.reachable = false;
+        }
+
+        let mut mentions = HashSet::new();
+        collect_mentions(stmt, &mut mentions);
+        let effects = mentions.iter().map(|name| {
+            let per_scope = (0..self.scope_stack.len()).map(|scope| (
+                self.scope_stack[scope].get(name).cloned(),
+                self.literal_sizes_stack[scope].get(name).copied(),
+                self.value_types_stack[scope].get(name).copied(),
+            ));
+            (name.clone(), per_scope.collect())
+        }).collect();
+        let pair = |messages: &[String], sites: &[DiagnosticSite]| messages.iter().cloned().zip(sites.iter().cloned()).collect();
+        CheckedStmt {
+            reachable,
+            read_all_names: self.read_all_names,
+            errors: pair(&self.errors[errors_from..], &self.error_sites[errors_from..]),
+            warnings: pair(&self.warnings[warnings_from..], &self.warning_sites[warnings_from..]),
+            notes: pair(&self.notes[notes_from..], &self.note_sites[notes_from..]),
+            mentions,
+            effects,
+        }
+    }
+
+    // What checking `checked` again would do: its diagnostics, and what it left in the scopes
+    fn replay(&mut self, checked: &CheckedStmt) {
+        for (message, site) in &checked.errors {
+            self.errors.push(message.clone());
+            self.error_sites.push(site.clone());
+        }
+        for (message, site) in &checked.warnings {
+            self.warnings.push(message.clone());
+            self.warning_sites.push(site.clone());
+        }
+        for (message, site) in &checked.notes {
+            self.notes.push(message.clone());
+            self.note_sites.push(site.clone());
+        }
+        for (name, per_scope) in &checked.effects {
+            for (scope, (symbol, size, ty)) in per_scope.iter().enumerate() {
+                set_or_remove(&mut self.scope_stack[scope], name, symbol.clone());
+                set_or_remove(&mut self.literal_sizes_stack[scope], name, *size);
+                set_or_remove(&mut self.value_types_stack[scope], name, *ty);
+            }
+        }
+    }
     
 
+    // Checks statements in order; the ones after return/exit cannot run
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        let prev_reachable = self.reachable;
+        for stmt in stmts {
+            self.check_stmt(stmt);
+            if ends_block(stmt) {
+                self.reachable = false;
+            }
+        }
+        self.reachable = prev_reachable;
+    }
+
+    // Checks a branch that may be dead code, e.g. the body of `if false`
+    fn check_branch(&mut self, stmts: &[Stmt], live: bool) {
+        let prev_reachable = self.reachable;
+        self.reachable &= live;
+        self.check_block(stmts);
+        self.reachable = prev_reachable;
+    }
+
     fn check_stmt(&mut self, stmt: &Stmt) {
+        self.settle_sites();
+        let outer_span = std::mem::replace(&mut self.current_span, stmt.span());
         match stmt {
-            Stmt::VarDecl { name, init } => {
-                if let Expr::Func { params, .. } = init {
+            Stmt::VarDecl { name, init, .. } => {
+                if let Some(Expr::Func { params, .. }) = init {
                     if !self.declare_var(name.clone(), SymbolInfo {
                         name: name.clone(),
                         declared: true,
                         used: false,
+                        assigned: false,
+                        unset: false,
+                        constant: None,
                         is_function: true,
                         symbol_type: SymbolType::Function {
-                            param_count: params.len(),
+                            params: params.clone(),
                         },
+                        declared_at: self.current_span,
                     }) {
-                        self.errors.push(format!("Function '{}' is already declared", name));
+                        self.report_redeclaration("Function", name);
                     }
                 }
                 
+                // A function is declared first so it can call itself; any other
+                // initializer runs before its variable exists, so a reference
+                // to the same name means an outer variable, if there is one
+                let is_function = matches!(init, Some(Expr::Func { .. }));
+                if !is_function {
+                    self.initializing.push(name.clone());
+                }
+
                 // Проверить тело функции
-                self.check_expr(init);
+                if let Some(init) = init {
+                    self.check_expr(init);
+                }
+                if !is_function {
+                    self.initializing.pop();
+                }
                 
-                if !matches!(init, Expr::Func { .. }) {
+                if !is_function {
                     if !self.declare_var(name.clone(), SymbolInfo {
                         name: name.clone(),
                         declared: true,
                         used: false,
+                        assigned: false,
+                        unset: init.is_none(),
+                        constant: init.as_ref()
+                            .filter(|_| !self.assignments.contains_key(name))
+                            .and_then(|init| self.constant_value(init)),
                         is_function: false,
                         symbol_type: SymbolType::Variable,
+                        declared_at: self.current_span,
                     }) {
-                        self.errors.push(format!("Variable '{}' is already declared", name));
+                        self.report_redeclaration("Variable", name);
                     }
                     
                     // Записать размер массива (если это массив)
-                    if let Expr::Array(elems) = init {
-                        self.record_array_size(name.clone(), elems.len());
+                    match init {
+                        Some(Expr::Array(elems)) if !elems.iter().any(is_spread) => {
+                            self.record_literal_size(name.clone(), LiteralSize::Array(elems.len()))
+                        }
+                        // tuples never gain fields, see Interpreter::assign_to_target
+                        Some(Expr::Tuple(elems)) if !elems.iter().any(|elem| is_spread(&elem.value)) => {
+                            self.record_literal_size(name.clone(), LiteralSize::Tuple(elems.len()))
+                        }
+                        _ => {}
                     }
+                    // `var x` holds none until it is assigned
+                    self.record_value_type(name, init.as_ref().unwrap_or(&Expr::None));
                 }
             }
             
             
             
             
-            Stmt::Assign { target, value } => {
-                self.check_expr(target);
+            Stmt::Assign { target, value, .. } => {
+                match target {
+                    // a store, not a read
+                    Expr::Ident(name) if self.is_declared(name) => self.mark_assigned(name),
+                    _ => self.check_expr(target),
+                }
+                let stored = match target {
+                    Expr::Ident(name) => Some(name.clone()),
+                    _ => None,
+                };
+                let outer_store = std::mem::replace(&mut self.storing, stored);
                 self.check_expr(value);
-                
-                self.check_array_bounds(target);
+                self.storing = outer_store;
+
+                // the variable may now hold an array of a different size
+                if let Expr::Ident(name) = target {
+                    self.forget_literal_size(name);
+                }
+            }
+
+            // both operands are read, then each stores the other's value
+            Stmt::Swap { left, right, .. } => {
+                for operand in [left, right] {
+                    if !operand.is_assignable() {
+                        self.errors.push(messages::not_swappable(&operand.to_string()));
+                    }
+                    self.check_expr(operand);
+                    if let Expr::Ident(name) = operand {
+                        self.mark_assigned(name);
+                        self.forget_literal_size(name);
+                    }
+                }
             }
             
-            Stmt::Print { args } => {
+            Stmt::Print { args, .. } => {
                 for arg in args {
                     self.check_expr(arg);
+                    if self.warn_function_print && self.is_function_value(arg) {
+                        let name = match arg {
+                            Expr::Ident(name) => format!(" '{}'", messages::shorten(name)),
+                            _ => String::new(),
+                        };
+                        self.warnings.push(format!("Printing a function value{}; did you mean to call it?", name));
+                    }
                 }
             }
-            Stmt::If { cond, then_branch, else_branch } => {
+            Stmt::If { cond, then_branch, else_branch, .. } => {
                 self.check_expr(cond);
+                self.check_bool_operand(cond, "'if' condition");
+                let truth = constant_truth(cond);
+                if else_branch.as_ref().is_some_and(|else_branch| same_code(then_branch, else_branch)) {
+                    self.warnings.push("The 'then' and 'else' branches of this 'if' are identical; the condition makes no difference".to_string());
+                }
                 
                 // new scope for then_branch
                 self.push_scope();
-                for stmt in then_branch {
-                    self.check_stmt(stmt);
-                }
+                self.check_branch(then_branch, truth != Some(false));
                 self.pop_scope();
                 
                 // new scope for else_branch 
                 if let Some(else_branch) = else_branch {
                     self.push_scope();
-                    for stmt in else_branch {
-                        self.check_stmt(stmt);
-                    }
+                    self.check_branch(else_branch, truth != Some(true));
                     self.pop_scope();
                 }
             }
             
-            Stmt::While { cond, body } => {
+            Stmt::While { label, cond, body, .. } => {
                 self.check_expr(cond);
+                self.check_bool_operand(cond, "'while' condition");
                 
                 let prev_inside_loop = self.inside_loop;
                 self.inside_loop = true;
+                self.enter_labelled_loop(label);
                 
                 self.push_scope();
                 
-                for stmt in body {
-                    self.check_stmt(stmt);
-                }
+                self.check_branch(body, constant_truth(cond) != Some(false));
                 
                 self.pop_scope();
                 
+                self.leave_labelled_loop(label);
+                self.inside_loop = prev_inside_loop;
+            }
+
+            // the body always runs; names declared in it are gone by the condition
+            Stmt::RepeatUntil { body, cond, .. } => {
+                let prev_inside_loop = self.inside_loop;
+                self.inside_loop = true;
+
+                self.push_scope();
+                self.check_block(body);
+                self.pop_scope();
+
                 self.inside_loop = prev_inside_loop;
+
+                self.check_expr(cond);
+                self.check_bool_operand(cond, "'until' condition");
             }
             
             
-            Stmt::For { var, iterable, body } => {
+            Stmt::For { label, var, iterable, body, .. } => {
                 self.check_expr(iterable);
+
+                // the loop gets its own variables; the outer ones are untouched by them
+                for var in var.names() {
+                    if self.is_declared(var) {
+                        self.warnings.push(format!(
+                            "Loop variable '{}' shadows an outer variable; the outer '{}' keeps its value after the loop",
+                            messages::shorten(var), messages::shorten(var)
+                        ));
+                    }
+                }
                 
                 let prev_inside_loop = self.inside_loop;
                 self.inside_loop = true;
+                self.enter_labelled_loop(label);
                 
                 self.push_scope();
+                let loop_scope = self.scope_stack.len() - 1;
                 
-                self.declare_var(var.clone(), SymbolInfo {
-                    name: var.clone(),
-                    declared: true,
-                    used: false,
-                    is_function: false,
-                    symbol_type: SymbolType::Variable,
-                });
-                
-                for stmt in body {
-                    self.check_stmt(stmt);
+                for var in var.names() {
+                    self.loop_vars.push((var.to_string(), loop_scope));
+                    self.declare_var(var.to_string(), SymbolInfo {
+                        name: var.to_string(),
+                        declared: true,
+                        used: false,
+                        assigned: false,
+                        unset: false,
+                        constant: None,
+                        is_function: false,
+                        symbol_type: SymbolType::Variable,
+                        declared_at: self.current_span,
+                    });
                 }
                 
+                self.check_block(body);
+                
                 self.pop_scope();
+                self.loop_vars.retain(|(_, scope)| *scope != loop_scope);
                 
+                self.leave_labelled_loop(label);
                 self.inside_loop = prev_inside_loop;
             }
             
-            Stmt::Return(_) => {
+            Stmt::Return(value, _) => {
                 // Check: Correct Keyword Usage - return should be inside function
                 if !self.inside_function {
-                    self.errors.push("Return statement outside of function".to_string());
+                    self.errors.push(messages::RETURN_OUTSIDE_FUNCTION.to_string());
+                }
+                if let Some(value) = value {
+                    self.check_expr(value);
                 }
             }
-            Stmt::Exit => {}
-            Stmt::Expr(expr) => {
+            // an unlabelled exit is checked when it runs, see Interpreter's EXIT_OUTSIDE_LOOP
+            Stmt::Exit(Some(label), _) => {
+                if !self.loop_labels.contains(label) {
+                    self.errors.push(messages::unknown_loop_label(label));
+                }
+            }
+            Stmt::Exit(None, _) => {}
+            // the loader splices top-level includes, so one left here is misplaced
+            Stmt::Include(path, _) => {
+                self.errors.push(format!(
+                    "Unresolved include of '{}' (includes are only allowed at the top level of a file)", messages::shorten(path)
+                ));
+            }
+            Stmt::Expr(expr, _) => {
                 self.check_expr(expr);
+                if let Expr::Binary { left, op: BinOp::Eq, .. } = expr
+                    && matches!(left.as_ref(), Expr::Ident(_) | Expr::Local { .. } | Expr::Index { .. } | Expr::Member { .. })
+                {
+                    // `x = 5` on its own is an assignment written with the wrong operator
+                    self.warnings.push("Comparison result is discarded; did you mean ':='?".to_string());
+                } else if self.warn_no_effect && !contains_call(expr) {
+                    // without a call nothing can happen, the value is just dropped
+                    self.warnings.push("Expression statement has no effect".to_string());
+                }
             }
         }
+        self.settle_sites();
+        self.current_span = outer_span;
     }
 
     fn check_expr(&mut self, expr: &Expr) {
         match expr {
             Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::String(_) | Expr::None => {}
-            Expr::Ident(name) => {
+            Expr::Ident(name) | Expr::Local { name, .. } => {
                 // Check: Declarations Before Usage
-                if !self.is_declared(name) {
-                    self.errors.push(format!("Variable or function '{}' used before declaration", name));
+                if self.is_declared(name) {
+                    self.mark_used(name);
+                } else {
+                    if self.initializing.contains(name) {
+                        self.error_about(name, None, messages::own_initializer(name));
+                    } else {
+                        self.read_all_names = true;
+                        let visible = self.scope_stack.iter().flat_map(|scope| scope.keys().map(String::as_str));
+                        let hint = suggest_name(name, visible.chain(BUILTINS.iter().map(|(n, _, _)| *n)))
+                            .map(|suggestion| format!("; did you mean '{}'?", suggestion))
+                            .unwrap_or_default();
+                        self.error_about(name, None, format!("{}{}", messages::undeclared(name), hint));
+                    }
                 }
             }
             
-            Expr::Binary { left, op: BinOp::Div, right } => {
-                if let Expr::Integer(0) = right.as_ref() {
-                    self.errors.push("Division by zero detected".to_string());
+            Expr::Binary { .. } => {
+                // along the left operands of a chain with a loop, however long it is
+                let mut operations = Vec::new();
+                let mut node = expr;
+                while let Expr::Binary { left, op, right } = node {
+                    self.check_division(left, op, right);
+                    operations.push((left.as_ref(), op, right.as_ref()));
+                    node = left;
                 }
-                if let Expr::Real(val) = right.as_ref() {
-                    if *val == 0.0 {
-                        self.errors.push("Division by zero detected".to_string());
-                    }
+                self.check_expr(node);
+                for (left, op, right) in operations.into_iter().rev() {
+                    self.check_expr(right);
+                    self.check_operands(left, op, right);
                 }
-                
-                self.check_expr(left);
-                self.check_expr(right);
             }
-            
-            Expr::Binary { left, right, .. } => {
-                self.check_expr(left);
-                self.check_expr(right);
-            }
-            Expr::Unary { expr, .. } => {
+            Expr::Unary { op, expr } => {
                 self.check_expr(expr);
+                if *op == UnOp::Not {
+                    self.check_bool_operand(expr, "Operand of 'not'");
+                }
+                if *op == UnOp::Plus
+                    && self.reachable
+                    && let Some(ty) = self.static_type(expr, None).filter(|ty| !matches!(*ty, "int" | "real"))
+                {
+                    self.errors.push(messages::unary_plus_operand(ty));
+                }
             }
             Expr::Call { callee, args } => {
-                self.check_expr(callee);
+                match callee.as_ref() {
+                    Expr::Ident(name) if is_builtin(name) && !self.is_declared(name) => {
+                        self.check_builtin_call(name, args.len());
+                    }
+                    _ => self.check_expr(callee),
+                }
                 
                 for arg in args {
-                    self.check_expr(arg);
+                    if !self.names_builtin_signature(callee, arg) {
+                        self.check_expr(arg);
+                    }
                 }
                 
                 if let Expr::Ident(func_name) = callee.as_ref() {
                     if let Some(symbol) = self.get_symbol(func_name) {
-                        if let SymbolType::Function { param_count } = symbol.symbol_type {
-                            if args.len() != param_count {
-                                self.errors.push(format!(
-                                    "Function '{}' expects {} arguments, got {}",
-                                    func_name,
-                                    param_count,
-                                    args.len()
-                                ));
+                        if let SymbolType::Function { params } = &symbol.symbol_type {
+                            if args.len() != params.len() {
+                                let msg = messages::arity(Some(func_name), params, args.len());
+                                self.errors.push(msg);
                             }
                         }
                     }
@@ -324,30 +1121,58 @@ impl SemanticChecker {
                 self.check_expr(target);
                 self.check_expr(index);
                 self.check_array_bounds(expr);
+                if let Some(position) = self.constant_value(index) {
+                    self.check_tuple_position(target, position);
+                }
             }
-            Expr::Member { target, .. } => {
+            Expr::Member { target, field } => {
                 self.check_expr(target);
+                if let Ok(position) = field.parse::<i64>() {
+                    self.check_tuple_position(target, position);
+                }
             }
             Expr::Array(elems) => {
                 for elem in elems {
                     self.check_expr(elem);
+                    self.check_spread(elem, "array");
                 }
             }
             Expr::Tuple(elems) => {
+                let mut names = HashSet::new();
                 for elem in elems {
                     self.check_expr(&elem.value);
+                    self.check_spread(&elem.value, "tuple");
+                    if let Some(name) = &elem.name
+                        && !names.insert(name)
+                    {
+                        self.errors.push(format!("Tuple field '{}' is defined more than once", messages::shorten(name)));
+                    }
                 }
             }
             Expr::Range(low, high) => {
                 self.check_expr(low);
                 self.check_expr(high);
+
+                if self.reachable {
+                    for (bound, expr) in [("start", low), ("end", high)] {
+                        if is_non_integer_literal(expr) {
+                            self.errors.push(messages::range_bound_not_integer(bound));
+                        }
+                    }
+                }
             }
-            Expr::IsType { expr, .. } => {
+            Expr::IsType { expr, .. } | Expr::Spread(expr) => {
                 self.check_expr(expr);
             }
             Expr::Func { params, body } => {
                 let prev_inside_function = self.inside_function;
                 self.inside_function = true;
+                // a label cannot be reached from inside a function body
+                let outer_labels = std::mem::take(&mut self.loop_labels);
+                // the function may be called after the store, so its reads count
+                let outer_store = self.storing.take();
+                let outer_function_scope = self.function_scope.replace(self.scope_stack.len());
+                let outer_captures = std::mem::take(&mut self.captured_loop_vars);
                 
                 self.push_scope();
                 
@@ -356,8 +1181,12 @@ impl SemanticChecker {
                         name: param.clone(),        
                         declared: true,             
                         used: false,                
+                        assigned: false,
+                        unset: false,
+                        constant: None,
                         is_function: false,  
                         symbol_type: SymbolType::Variable, 
+                        declared_at: self.current_span,
                     });
                 }
                 
@@ -365,63 +1194,781 @@ impl SemanticChecker {
                     FuncBody::Expr(expr) => {
                         self.check_expr(expr);
                     }
-                    FuncBody::Block(stmts) => {
-                        for stmt in stmts {
-                            self.check_stmt(stmt);
-                        }
+                    FuncBody::Block(stmts) => self.check_block(stmts),
+                }
+
+                // a leading underscore says the parameter is ignored on purpose
+                let unused: Vec<&String> = params.iter()
+                    .filter(|param| !param.starts_with('_'))
+                    .filter(|param| self.scope_stack.last().and_then(|scope| scope.get(*param)).is_some_and(|symbol| !symbol.used))
+                    .collect();
+                for param in unused {
+                    self.settle_sites();
+                    self.warnings.push(format!("Parameter '{}' is never used; rename it to '_{}' if that is intended", messages::shorten(param), messages::shorten(param)));
+                    self.warning_sites.push(DiagnosticSite { span: self.current_span, name: Some(param.clone()), previous: None });
+                    self.mark_used(param);  // reported once, not again as a dead store
+                }
+
+                // each iteration of a for loop has its own variables, so the
+                // function keeps the value of the iteration that created it
+                for var in std::mem::replace(&mut self.captured_loop_vars, outer_captures) {
+                    if self.reachable {
+                        self.notes.push(format!(
+                            "Function captures the loop variable '{}'; it keeps the value of the iteration that created it",
+                            messages::shorten(&var)
+                        ));
+                        self.note_sites.push(DiagnosticSite { span: self.current_span, name: Some(var), previous: None });
                     }
                 }
 
                 self.pop_scope();  
                 self.inside_function = prev_inside_function;
+                self.loop_labels = outer_labels;
+                self.storing = outer_store;
+                self.function_scope = outer_function_scope;
             
             }
         }
     }
 
-    fn check_array_bounds(&mut self, expr: &Expr) {
-        if let Expr::Index { target, index } = expr {
-            if let Expr::Integer(idx) = index.as_ref() {
-                match target.as_ref() {
-                    Expr::Array(elems) => {
-                        
-                        if *idx < 1 || *idx > elems.len() as i64 {
-                            self.errors.push(format!(
-                                "Array index {} out of bounds (valid range: 1..{})", 
-                                idx, elems.len()
-                            ));
-                        }
-                    }
-                    
-                    Expr::Ident(name) => {
-                        if let Some(size) = self.get_array_size(name) {
-                            if *idx < 1 || *idx > size as i64 {
-                                self.errors.push(format!(
-                                    "Array index {} out of bounds (valid range: 1..{})", 
-                                    idx, size
-                                ));
-                            }
-                        }
+    // Reported before the operands are checked
+    fn check_division(&mut self, left: &Expr, op: &BinOp, right: &Expr) {
+        // dead code never divides, so it is not reported
+        if !self.reachable || !matches!(op, BinOp::Div | BinOp::IntDiv) {
+            return;
+        }
+        if *op == BinOp::Div
+            && let (Expr::Integer(a), Expr::Integer(b)) = (left, right)
+            && *b != 0
+            && a % b != 0
+        {
+            self.warnings.push(format!(
+                "Integer division {a} / {b} truncates to {}; write {a}.0 / {b} for a real result or {a} div {b} to make truncation explicit",
+                a / b
+            ));
+        }
+        if is_zero_literal(right) {
+            self.errors.push(messages::DIVISION_BY_ZERO.to_string());
+        }
+    }
+
+    // Reported after the operands are checked
+    fn check_operands(&mut self, left: &Expr, op: &BinOp, right: &Expr) {
+        match op {
+            BinOp::And | BinOp::Or | BinOp::Xor => {
+                let construct = match op {
+                    BinOp::And => "Operand of 'and'",
+                    BinOp::Or => "Operand of 'or'",
+                    _ => "Operand of 'xor'",
+                };
+                // an int literal may meet another int and combine bitwise,
+                // so it is only reported next to an operand known to be a bool
+                for (operand, other) in [(left, right), (right, left)] {
+                    if !matches!(operand, Expr::Integer(_)) || is_bool_expr(other) {
+                        self.check_bool_operand(operand, construct);
                     }
-                    
-                    _ => {}
+                }
+            }
+            BinOp::Div | BinOp::IntDiv => {}
+            _ => {
+                if self.reachable {
+                    self.check_comparison_types(left, op, right);
                 }
             }
         }
     }
-    
-    
-    
+
+    fn check_builtin_call(&mut self, name: &str, arg_count: usize) {
+        if let Some(msg) = builtin_arity_error(name, arg_count) {
+            self.errors.push(msg);
+        }
+    }
+
+    // `arity(sum)` and `params(sum)` name a builtin, which has no declaration
+    fn names_builtin_signature(&self, callee: &Expr, arg: &Expr) -> bool {
+        matches!(callee, Expr::Ident(name) if matches!(name.as_str(), "arity" | "params") && !self.is_declared(name))
+            && matches!(arg, Expr::Ident(name) if is_builtin(name) && !self.is_declared(name))
+    }
+
+    // `...x` in a literal of `container`s, when x is known to be something else
+    fn check_spread(&mut self, elem: &Expr, container: &str) {
+        if let Expr::Spread(operand) = elem
+            && self.reachable
+            && let Some(type_name) = self.static_type(operand, None)
+            && type_name != container
+        {
+            self.errors.push(messages::bad_spread(container, type_name));
+        }
+    }
+
+    // `t.3` or `t[3]` on a tuple literal with fewer elements
+    fn check_tuple_position(&mut self, target: &Expr, position: i64) {
+        let size = match target {
+            Expr::Tuple(elems) if !elems.iter().any(|elem| is_spread(&elem.value)) => Some(elems.len()),
+            Expr::Ident(name) => match self.get_literal_size(name) {
+                Some(LiteralSize::Tuple(size)) => Some(size),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(size) = size
+            && !(1..=size as i64).contains(&position)
+        {
+            self.errors.push(messages::tuple_position_out_of_bounds(position, size));
+        }
+    }
+
+    fn check_array_bounds(&mut self, expr: &Expr) {
+        if let Expr::Index { target, index } = expr
+            && let Some(size) = match target.as_ref() {
+                Expr::Array(elems) if !elems.iter().any(is_spread) => Some(elems.len()),
+                Expr::Ident(name) => self.get_array_size(name),
+                _ => None,
+            }
+            && let Some(idx) = self.constant_value(index)
+            && self.index_base.offset(idx, size).is_none()
+        {
+            self.errors.push(messages::index_out_of_bounds(idx, size, &self.index_base.valid_range(size)));
+        }
+    }
+}
+
+impl SemanticChecker {
+    // The integer `expr` folds to as the optimizer would fold it, with the
+    // variables known to hold a constant (see SymbolInfo::constant) replaced
+    // by their values, e.g. `i + 1` after `var i := 2`
+    fn constant_value(&self, expr: &Expr) -> Option<i64> {
+        if let Some(value) = constant_int(expr) {
+            return Some(value);
+        }
+        let mut folded = expr.clone();
+        let mut pending = vec![&mut folded];
+        while let Some(expr) = pending.pop() {
+            let value = match &*expr {
+                Expr::Ident(name) => self.get_symbol(name).and_then(|symbol| symbol.constant),
+                _ => None,
+            };
+            match expr {
+                Expr::Ident(_) => {
+                    if let Some(value) = value {
+                        *expr = Expr::Integer(value);
+                    }
+                }
+                Expr::Binary { left, right, .. } => {
+                    pending.push(left);
+                    pending.push(right);
+                }
+                Expr::Unary { expr: operand, .. } => pending.push(operand),
+                _ => {}
+            }
+        }
+        match Optimizer::new().simplify_expr(&mut folded) {
+            Some(simplified) => constant_int(&simplified),
+            None => constant_int(&folded),
+        }
+    }
+}
+
+// Element count of a literal a variable was declared with
+#[derive(Debug, Clone, Copy)]
+enum LiteralSize {
+    Array(usize),
+    Tuple(usize),
+}
+
+// ===
+// helpers shared by the checker and the optimizer
+// ===
+
+fn set_or_remove<T>(map: &mut HashMap<String, T>, name: &str, value: Option<T>) {
+    match value {
+        Some(value) => {
+            map.insert(name.to_string(), value);
+        }
+        None => {
+            map.remove(name);
+        }
+    }
+}
+
+// Statements after these in the same block never run
+fn ends_block(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::Return(..) | Stmt::Exit(..))
+}
+
+// Variables of a scope that were assigned to but never read, like an
+// accumulator nobody prints, in the order they were declared
+fn dead_stores(scope: &HashMap<String, SymbolInfo>) -> Vec<(String, Span)> {
+    let mut dead: Vec<&SymbolInfo> = scope.values().filter(|symbol| symbol.assigned && !symbol.used && !symbol.is_function).collect();
+    dead.sort_by_key(|symbol| (symbol.declared_at.line, symbol.declared_at.col));
+    dead.into_iter().map(|symbol| (symbol.name.clone(), symbol.declared_at)).collect()
+}
+
+// Whether two blocks are the same code, wherever they stand in the program
+fn same_code(a: &[Stmt], b: &[Stmt]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.without_spans() == b.without_spans())
+}
+
+// Value of a condition that folds to a boolean constant, e.g. `false` or `1 > 2`
+fn constant_truth(cond: &Expr) -> Option<bool> {
+    let mut folded = cond.clone();
+    if let Some(simplified) = Optimizer::new().simplify_expr(&mut folded) {
+        folded = simplified;
+    }
+    match folded {
+        Expr::Bool(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Integer literal, possibly negated (`-1` parses as negation of 1)
+fn constant_int(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Integer(n) => Some(*n),
+        Expr::Unary { op: UnOp::Neg, expr } => constant_int(expr).and_then(i64::checked_neg),
+        Expr::Unary { op: UnOp::Plus, expr } => constant_int(expr),
+        _ => None,
+    }
+}
+
+fn is_non_integer_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Real(_) | Expr::Bool(_) | Expr::String(_) | Expr::None => true,
+        Expr::Unary { op: UnOp::Neg | UnOp::Plus, expr } => is_non_integer_literal(expr),
+        _ => false,
+    }
+}
+
+fn is_spread(expr: &Expr) -> bool {
+    matches!(expr, Expr::Spread(_))
+}
+
+// `[1, ...[2, 3]]` is `[1, 2, 3]`
+fn flatten_array_spreads(elems: &mut Vec<Expr>) -> bool {
+    if !elems.iter().any(|elem| matches!(elem, Expr::Spread(operand) if matches!(**operand, Expr::Array(_)))) {
+        return false;
+    }
+    *elems = std::mem::take(elems).into_iter()
+        .flat_map(|mut elem| {
+            if let Expr::Spread(operand) = &mut elem
+                && let Expr::Array(inner) = &mut **operand
+            {
+                return std::mem::take(inner);
+            }
+            vec![elem]
+        })
+        .collect();
+    true
+}
+
+// `{...{a := 1}, b := 2}` is `{a := 1, b := 2}`. Spliced elements keep
+// their order, so positions come out as the interpreter numbers them; a name
+// given twice stays spread, since a literal may not repeat a field.
+fn flatten_tuple_spreads(elems: &mut Vec<TupleElement>) -> bool {
+    let literal = |elem: &TupleElement| match &elem.value {
+        Expr::Spread(operand) => match operand.as_ref() {
+            Expr::Tuple(inner) => Some(inner.clone()),
+            _ => None,
+        },
+        _ => None,
+    };
+    if !elems.iter().any(|elem| literal(elem).is_some()) {
+        return false;
+    }
+    let spliced: Vec<TupleElement> = elems.iter()
+        .flat_map(|elem| literal(elem).unwrap_or_else(|| vec![elem.clone()]))
+        .collect();
+    let mut names = HashSet::new();
+    if !spliced.iter().filter_map(|elem| elem.name.as_ref()).all(|name| names.insert(name)) {
+        return false;
+    }
+    *elems = spliced;
+    true
+}
+
+// The first operand of a chain such as `a + b - c` and the operators with
+// the operands that follow them, in the order they are evaluated. The parser
+// builds a chain as deep as it is long, so walks loop over this instead of
+// recursing into the left operand.
+fn binary_chain(expr: &Expr) -> (&Expr, Vec<(&BinOp, &Expr)>) {
+    let mut operations = Vec::new();
+    let mut node = expr;
+    while let Expr::Binary { left, op, right } = node {
+        operations.push((op, right.as_ref()));
+        node = left;
+    }
+    operations.reverse();
+    (node, operations)
+}
+
+// Type of `expr` given the types of the names it uses (see static_type)
+fn expr_type(expr: &Expr, name_type: &dyn Fn(&str) -> Option<&'static str>) -> Option<&'static str> {
+    match expr {
+        Expr::Integer(_) => Some("int"),
+        Expr::Real(_) => Some("real"),
+        Expr::Bool(_) => Some("bool"),
+        Expr::String(_) => Some("string"),
+        Expr::None => Some("none"),
+        Expr::Array(_) | Expr::Range(..) => Some("array"),
+        Expr::Tuple(_) => Some("tuple"),
+        Expr::Func { .. } => Some("func"),
+        Expr::Ident(name) | Expr::Local { name, .. } => name_type(name),
+        Expr::IsType { .. } | Expr::Unary { op: UnOp::Not, .. } => Some("bool"),
+        Expr::Unary { op: UnOp::Neg | UnOp::Plus, expr } => expr_type(expr, name_type).filter(|ty| matches!(*ty, "int" | "real")),
+        Expr::Binary { .. } => {
+            // from the left of the chain, one operator at a time
+            let (first, operations) = binary_chain(expr);
+            let mut ty = expr_type(first, name_type);
+            for (op, right) in operations {
+                ty = binary_type(ty, op, expr_type(right, name_type));
+            }
+            ty
+        }
+        _ => None,
+    }
+}
+
+// Type of `left op right` from the types of its operands (see static_type)
+fn binary_type(left: Option<&'static str>, op: &BinOp, right: Option<&'static str>) -> Option<&'static str> {
+    let numeric = |ty| matches!(ty, "int" | "real");
+    match op {
+        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => return Some("bool"),
+        BinOp::IntDiv | BinOp::Shl | BinOp::Shr => return Some("int"),
+        _ => {}
+    }
+    match (left?, op, right?) {
+        (a, BinOp::OrElse | BinOp::AndThen, b) if a == b => Some(a),
+        (_, BinOp::OrElse | BinOp::AndThen, _) => None,
+        ("int", _, "int") => Some("int"),
+        ("bool", BinOp::And | BinOp::Or | BinOp::Xor, _) | (_, BinOp::And | BinOp::Or | BinOp::Xor, "bool") => Some("bool"),
+        (a, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div, b) if numeric(a) && numeric(b) => Some("real"),
+        ("string", BinOp::Add, _) | (_, BinOp::Add, "string") => Some("string"),
+        ("string", BinOp::Mul, "int") | ("int", BinOp::Mul, "string") => Some("string"),
+        ("tuple", BinOp::Add, "tuple") => Some("tuple"),
+        _ => None,
+    }
+}
+
+// Calls anywhere in `expr`, except inside function literals (those only run when called)
+fn contains_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call { .. } => true,
+        Expr::Binary { .. } => {
+            let (first, operations) = binary_chain(expr);
+            contains_call(first) || operations.into_iter().any(|(_, right)| contains_call(right))
+        }
+        Expr::Range(left, right) => contains_call(left) || contains_call(right),
+        Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr) => contains_call(expr),
+        Expr::Index { target, index } => contains_call(target) || contains_call(index),
+        Expr::Array(elems) => elems.iter().any(contains_call),
+        Expr::Tuple(elems) => elems.iter().any(|elem| contains_call(&elem.value)),
+        _ => false,
+    }
+}
+
+// Whether evaluating `expr` can raise a runtime error: a call, an index, a
+// member access, or an operator whose operands are not of types it is known
+// to succeed on, going by the variable `types` of program_value_types. The
+// optimizer never drops such an expression, so a failing program still fails.
+fn expr_may_fail(expr: &Expr, types: &HashMap<String, &'static str>) -> bool {
+    let may_fail = |expr| expr_may_fail(expr, types);
+    let ty = |expr| expr_type(expr, &|name| types.get(name).copied());
+    match expr {
+        // a spread fails on anything but the right kind of container
+        Expr::Call { .. } | Expr::Index { .. } | Expr::Member { .. } | Expr::Spread(_) => true,
+        Expr::Binary { .. } => {
+            let (first, operations) = binary_chain(expr);
+            let mut left = ty(first);
+            may_fail(first) || operations.into_iter().any(|(op, right)| {
+                let fails = !binary_succeeds(left, op, right, ty(right)) || may_fail(right);
+                left = binary_type(left, op, ty(right));
+                fails
+            })
+        }
+        Expr::Range(left, right) => may_fail(left) || may_fail(right),
+        Expr::Unary { op, expr } => may_fail(expr) || !match op {
+            // only i64::MIN cannot be negated, and no literal is that
+            UnOp::Neg => matches!(expr.as_ref(), Expr::Integer(_)) || ty(expr) == Some("real"),
+            UnOp::Plus => matches!(ty(expr), Some("int" | "real")),
+            // anything but a bool fails under strict_bool
+            UnOp::Not => ty(expr) == Some("bool"),
+        },
+        Expr::IsType { expr, .. } => may_fail(expr),
+        Expr::Array(elems) => elems.iter().any(may_fail),
+        Expr::Tuple(elems) => elems.iter().any(|elem| may_fail(&elem.value)),
+        _ => false,
+    }
+}
+
+// Whether `left op right` always succeeds for operands of these types; a
+// divisor or shift amount must also be a literal the operator accepts
+fn binary_succeeds(left: Option<&str>, op: &BinOp, right: &Expr, right_type: Option<&str>) -> bool {
+    let numeric = |ty| matches!(ty, Some("int" | "real"));
+    match op {
+        BinOp::Eq | BinOp::Ne | BinOp::OrElse | BinOp::AndThen => true,
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => numeric(left) && numeric(right_type),
+        BinOp::And | BinOp::Or | BinOp::Xor => matches!((left, right_type), (Some("bool"), Some("bool")) | (Some("int"), Some("int"))),
+        // a string joins with anything; a real result fails when it overflows
+        BinOp::Add => matches!((left, right_type), (Some("int"), Some("int")) | (Some("string"), Some(_)) | (Some(_), Some("string")) | (Some("tuple"), Some("tuple"))),
+        BinOp::Sub | BinOp::Mul => left == Some("int") && right_type == Some("int"),
+        // -1 is out too: i64::MIN / -1 overflows; a real divisor below 1 can overflow
+        BinOp::Div => numeric(left) && match right {
+            Expr::Integer(n) => *n != 0 && *n != -1,
+            Expr::Real(n) => n.abs() >= 1.0,
+            _ => false,
+        },
+        BinOp::IntDiv => left == Some("int") && matches!(right, Expr::Integer(n) if *n != 0 && *n != -1),
+        BinOp::Shl | BinOp::Shr => left == Some("int") && matches!(right, Expr::Integer(0..=63)),
+        BinOp::Is => false,
+    }
+}
+
+// Every name a statement uses or binds, wherever it appears
+fn collect_mentions(stmt: &Stmt, names: &mut HashSet<String>) {
+    match stmt {
+        Stmt::VarDecl { name, init, .. } => {
+            names.insert(name.clone());
+            init.iter().for_each(|init| collect_expr_mentions(init, names));
+        }
+        Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => {
+            collect_expr_mentions(left, names);
+            collect_expr_mentions(right, names);
+        }
+        Stmt::Print { args, .. } => args.iter().for_each(|arg| collect_expr_mentions(arg, names)),
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            collect_expr_mentions(cond, names);
+            then_branch.iter().chain(else_branch.iter().flatten()).for_each(|s| collect_mentions(s, names));
+        }
+        Stmt::While { cond, body, .. } | Stmt::RepeatUntil { cond, body, .. } => {
+            collect_expr_mentions(cond, names);
+            body.iter().for_each(|s| collect_mentions(s, names));
+        }
+        Stmt::For { var, iterable, body, .. } => {
+            names.extend(var.names().into_iter().map(str::to_string));
+            collect_expr_mentions(iterable, names);
+            body.iter().for_each(|s| collect_mentions(s, names));
+        }
+        Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => collect_expr_mentions(expr, names),
+        Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => {}
+    }
+}
+
+fn collect_expr_mentions(expr: &Expr, names: &mut HashSet<String>) {
+    match expr {
+        Expr::Ident(name) | Expr::Local { name, .. } => {
+            names.insert(name.clone());
+        }
+        Expr::Binary { .. } => {
+            let (first, operations) = binary_chain(expr);
+            collect_expr_mentions(first, names);
+            operations.into_iter().for_each(|(_, right)| collect_expr_mentions(right, names));
+        }
+        Expr::Range(left, right) | Expr::Index { target: left, index: right } => {
+            collect_expr_mentions(left, names);
+            collect_expr_mentions(right, names);
+        }
+        Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr) => {
+            collect_expr_mentions(expr, names);
+        }
+        Expr::Call { callee, args } => {
+            collect_expr_mentions(callee, names);
+            args.iter().for_each(|arg| collect_expr_mentions(arg, names));
+        }
+        Expr::Array(elems) => elems.iter().for_each(|elem| collect_expr_mentions(elem, names)),
+        Expr::Tuple(elems) => elems.iter().for_each(|elem| collect_expr_mentions(&elem.value, names)),
+        Expr::Func { params, body } => {
+            names.extend(params.iter().cloned());
+            match body {
+                FuncBody::Expr(expr) => collect_expr_mentions(expr, names),
+                FuncBody::Block(stmts) => stmts.iter().for_each(|s| collect_mentions(s, names)),
+            }
+        }
+        Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) => {}
+    }
+}
+
+// The values assigned to plain names, wherever the assignment appears
+fn collect_assignments(stmt: &Stmt, assignments: &mut HashMap<String, Vec<Expr>>) {
+    match stmt {
+        Stmt::Assign { target: Expr::Ident(name) | Expr::Local { name, .. }, value, .. } => {
+            assignments.entry(name.clone()).or_default().push(value.clone());
+            collect_expr_assignments(value, assignments);
+        }
+        Stmt::VarDecl { init: Some(expr), .. } | Stmt::Assign { value: expr, .. } | Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => {
+            collect_expr_assignments(expr, assignments);
+        }
+        Stmt::Swap { left, right, .. } => {
+            for (target, value) in [(left, right), (right, left)] {
+                if let Expr::Ident(name) | Expr::Local { name, .. } = target {
+                    assignments.entry(name.clone()).or_default().push(value.clone());
+                }
+            }
+        }
+        Stmt::Print { args, .. } => args.iter().for_each(|arg| collect_expr_assignments(arg, assignments)),
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            collect_expr_assignments(cond, assignments);
+            then_branch.iter().chain(else_branch.iter().flatten()).for_each(|s| collect_assignments(s, assignments));
+        }
+        Stmt::While { cond: expr, body, .. } | Stmt::RepeatUntil { cond: expr, body, .. } | Stmt::For { iterable: expr, body, .. } => {
+            collect_expr_assignments(expr, assignments);
+            body.iter().for_each(|s| collect_assignments(s, assignments));
+        }
+        Stmt::VarDecl { init: None, .. } | Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => {}
+    }
+}
+
+// Assignments only appear in statements, so only function bodies are searched
+fn collect_expr_assignments(expr: &Expr, assignments: &mut HashMap<String, Vec<Expr>>) {
+    match expr {
+        Expr::Func { body: FuncBody::Block(stmts), .. } => stmts.iter().for_each(|s| collect_assignments(s, assignments)),
+        Expr::Func { body: FuncBody::Expr(expr), .. } | Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. }
+        | Expr::Spread(expr) => {
+            collect_expr_assignments(expr, assignments);
+        }
+        Expr::Binary { .. } => {
+            let (first, operations) = binary_chain(expr);
+            collect_expr_assignments(first, assignments);
+            operations.into_iter().for_each(|(_, right)| collect_expr_assignments(right, assignments));
+        }
+        Expr::Range(left, right) | Expr::Index { target: left, index: right } => {
+            collect_expr_assignments(left, assignments);
+            collect_expr_assignments(right, assignments);
+        }
+        Expr::Call { callee, args } => {
+            collect_expr_assignments(callee, assignments);
+            args.iter().for_each(|arg| collect_expr_assignments(arg, assignments));
+        }
+        Expr::Array(elems) => elems.iter().for_each(|elem| collect_expr_assignments(elem, assignments)),
+        Expr::Tuple(elems) => elems.iter().for_each(|elem| collect_expr_assignments(&elem.value, assignments)),
+        Expr::Ident(_) | Expr::Local { .. } | Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) => {}
+    }
+}
+
+// Variables that keep one type for the whole program, as the optimizer sees
+// them: like the checker's known_type(), but a name bound more than once
+// anywhere (a parameter, a loop variable, a second declaration) is left out
+fn program_value_types(stmts: &[Stmt]) -> HashMap<String, &'static str> {
+    let mut bindings = HashMap::new();
+    stmts.iter().for_each(|stmt| collect_bindings(stmt, &mut bindings));
+    let mut assignments = HashMap::new();
+    stmts.iter().for_each(|stmt| collect_assignments(stmt, &mut assignments));
+    let mut types: HashMap<String, &'static str> = HashMap::new();
+    // one variable's type can rest on another's, so go round until nothing new is learned
+    loop {
+        let known = types.len();
+        for (name, bound) in &bindings {
+            let [Some(init)] = bound.as_slice() else { continue };
+            if types.contains_key(name) {
+                continue;
+            }
+            let Some(ty) = expr_type(init, &|other| types.get(other).copied()) else { continue };
+            let consistent = assignments.get(name).is_none_or(|values: &Vec<Expr>| {
+                values.iter().all(|value| expr_type(value, &|other| if other == name { Some(ty) } else { types.get(other).copied() }) == Some(ty))
+            });
+            if consistent {
+                types.insert(name.clone(), ty);
+            }
+        }
+        if types.len() == known {
+            return types;
+        }
+    }
+}
+
+// Every place a name is bound, with the initializer when it has one; a
+// parameter or a loop variable over anything but a range binds a value of
+// unknown type
+fn collect_bindings(stmt: &Stmt, bindings: &mut HashMap<String, Vec<Option<Expr>>>) {
+    match stmt {
+        Stmt::VarDecl { name, init, .. } => {
+            bindings.entry(name.clone()).or_default().push(init.clone());
+            init.iter().for_each(|init| collect_expr_bindings(init, bindings));
+        }
+        Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => {
+            collect_expr_bindings(left, bindings);
+            collect_expr_bindings(right, bindings);
+        }
+        Stmt::Print { args, .. } => args.iter().for_each(|arg| collect_expr_bindings(arg, bindings)),
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            collect_expr_bindings(cond, bindings);
+            then_branch.iter().chain(else_branch.iter().flatten()).for_each(|s| collect_bindings(s, bindings));
+        }
+        Stmt::While { cond, body, .. } | Stmt::RepeatUntil { cond, body, .. } => {
+            collect_expr_bindings(cond, bindings);
+            body.iter().for_each(|s| collect_bindings(s, bindings));
+        }
+        Stmt::For { var, iterable, body, .. } => {
+            // a range's variable takes the type of its bounds, which must be ints
+            let value = match (var, iterable) {
+                (LoopPattern::Name(_), Expr::Range(low, _)) => Some(low.as_ref().clone()),
+                _ => None,
+            };
+            for name in var.names() {
+                bindings.entry(name.to_string()).or_default().push(value.clone());
+            }
+            collect_expr_bindings(iterable, bindings);
+            body.iter().for_each(|s| collect_bindings(s, bindings));
+        }
+        Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => collect_expr_bindings(expr, bindings),
+        Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => {}
+    }
+}
+
+// Only function literals bind names inside an expression
+fn collect_expr_bindings(expr: &Expr, bindings: &mut HashMap<String, Vec<Option<Expr>>>) {
+    match expr {
+        Expr::Func { params, body } => {
+            for param in params {
+                bindings.entry(param.clone()).or_default().push(None);
+            }
+            match body {
+                FuncBody::Block(stmts) => stmts.iter().for_each(|s| collect_bindings(s, bindings)),
+                FuncBody::Expr(expr) => collect_expr_bindings(expr, bindings),
+            }
+        }
+        Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr) => {
+            collect_expr_bindings(expr, bindings);
+        }
+        Expr::Binary { .. } => {
+            let (first, operations) = binary_chain(expr);
+            collect_expr_bindings(first, bindings);
+            operations.into_iter().for_each(|(_, right)| collect_expr_bindings(right, bindings));
+        }
+        Expr::Range(left, right) | Expr::Index { target: left, index: right } => {
+            collect_expr_bindings(left, bindings);
+            collect_expr_bindings(right, bindings);
+        }
+        Expr::Call { callee, args } => {
+            collect_expr_bindings(callee, bindings);
+            args.iter().for_each(|arg| collect_expr_bindings(arg, bindings));
+        }
+        Expr::Array(elems) => elems.iter().for_each(|elem| collect_expr_bindings(elem, bindings)),
+        Expr::Tuple(elems) => elems.iter().for_each(|elem| collect_expr_bindings(&elem.value, bindings)),
+        Expr::Ident(_) | Expr::Local { .. } | Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) => {}
+    }
+}
+
+// Expressions that always evaluate to a bool
+fn is_bool_expr(expr: &Expr) -> bool {
+    match expr {
+        Expr::Bool(_) | Expr::IsType { .. } => true,
+        Expr::Unary { op: UnOp::Not, .. } => true,
+        Expr::Binary { .. } => {
+            let (first, operations) = binary_chain(expr);
+            let mut is_bool = is_bool_expr(first);
+            for (op, right) in operations {
+                is_bool = match op {
+                    // `and`, `or` and `xor` give an int for two ints, so one side must be a bool
+                    BinOp::And | BinOp::Or | BinOp::Xor => is_bool || is_bool_expr(right),
+                    // `orelse` and `andthen` give one of their operands
+                    BinOp::OrElse | BinOp::AndThen => is_bool && is_bool_expr(right),
+                    op => matches!(op, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge),
+                };
+            }
+            is_bool
+        }
+        _ => false,
+    }
+}
+
+// Truthiness of a literal as the interpreter judges it (`0`, `""`, `[]` and
+// `none` are false), for folding `orelse` and `andthen`
+fn literal_truthiness(expr: &Expr) -> Option<bool> {
+    match expr {
+        Expr::Bool(value) => Some(*value),
+        Expr::None => Some(false),
+        Expr::Integer(n) => Some(*n != 0),
+        Expr::Real(n) => Some(*n != 0.0),
+        Expr::String(s) => Some(!s.is_empty()),
+        Expr::Array(elems) if elems.is_empty() => Some(false),
+        _ => None,
+    }
+}
+
+// Type of a literal that is not a bool, as the interpreter names it
+fn literal_type_name(expr: &Expr) -> Option<&'static str> {
+    match expr {
+        Expr::Integer(_) => Some("int"),
+        Expr::Real(_) => Some("real"),
+        Expr::String(_) => Some("string"),
+        Expr::None => Some("none"),
+        Expr::Array(_) | Expr::Range(..) => Some("array"),
+        Expr::Tuple(_) => Some("tuple"),
+        Expr::Func { .. } => Some("func"),
+        Expr::Unary { op: UnOp::Neg | UnOp::Plus, expr } => literal_type_name(expr),
+        _ => None,
+    }
+}
+
+// Overflowing real arithmetic is left unfolded so the interpreter reports it
+fn finite_real(n: f64) -> Option<Expr> {
+    Some(Expr::Real(n)).filter(|_| n.is_finite())
+}
+
+// Whether two scalar literals are equal at run time; literals of different
+// types never are (not even 1 and 1.0)
+fn constant_equality(left: &Expr, right: &Expr) -> Option<bool> {
+    let scalar = |e: &Expr| matches!(e, Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::String(_) | Expr::None);
+    match (left, right) {
+        (Expr::Integer(a), Expr::Integer(b)) => Some(a == b),
+        (Expr::Real(a), Expr::Real(b)) => Some(reals_equal(*a, *b)),
+        (Expr::Bool(a), Expr::Bool(b)) => Some(a == b),
+        (Expr::String(a), Expr::String(b)) => Some(a == b),
+        (Expr::None, Expr::None) => Some(true),
+        _ if scalar(left) && scalar(right) => Some(false),
+        _ => None,
+    }
+}
+
+fn is_zero_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Integer(0)) || matches!(expr, Expr::Real(val) if *val == 0.0)
 }
 
 // ===
 // part 2: optimizer (modifies AST)
 // ===
 
+// What optimize() did: iterations until the fixpoint and, for each one,
+// how many top-level statements the passes walked
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OptReport {
+    pub iterations: usize,
+    pub visits: Vec<usize>,
+}
+
+// Default for Optimizer::set_max_inline_len
+pub const DEFAULT_MAX_INLINE_LEN: usize = 64;
+
+// Per top-level statement bookkeeping for optimize(): a statement the passes
+// left alone is only walked again when a constant it uses changed
+#[derive(Debug, Clone, Default)]
+struct StmtState {
+    facts: Option<StmtFacts>,  // None once the statement changed
+    visit: bool,               // walked in the current iteration
+    touched: bool,             // changed in the current iteration
+    origin: Option<usize>,     // index in the program given to optimize(); None once changed
+}
+
+#[derive(Debug, Clone, Default)]
+struct StmtFacts {
+    assigned: HashSet<String>,      // see collect_assigned_vars
+    nested_decls: HashSet<String>,  // declared in nested blocks, candidates for shadowing
+    mentions: HashSet<String>,      // every name used or bound anywhere inside
+}
+
 pub struct Optimizer {
     modified: bool,
     constants: HashMap<String, Expr>,
-    shadowed_vars: std::collections::HashSet<String>, 
+    known_constants: HashMap<String, Expr>,  // every constant seen by the last optimize(), see known_constants()
+    shadowed_vars: HashSet<String>, 
+    max_string_len: usize,  // same cap the interpreter applies to string repetition
+    max_inline_len: usize,  // longer string constants stay behind their variable
+    warnings: Vec<String>,  // problems noticed while folding, reported by the caller
+    dirty_tracking: bool,   // off: every pass walks every statement, every iteration
+    report: OptReport,
+    changes: ChangeSet,
+    next_id: NodeId,  // for statements the passes make, see NodeId
+    value_types: HashMap<String, &'static str>,  // see program_value_types
 }
 
 impl Optimizer {
@@ -429,105 +1976,228 @@ impl Optimizer {
         Self {
             modified: false,
             constants: HashMap::new(),
-            shadowed_vars: std::collections::HashSet::new(), 
+            known_constants: HashMap::new(),
+            shadowed_vars: HashSet::new(), 
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            max_inline_len: DEFAULT_MAX_INLINE_LEN,
+            warnings: Vec::new(),
+            dirty_tracking: true,
+            report: OptReport::default(),
+            changes: ChangeSet::default(),
+            next_id: 1,
+            value_types: HashMap::new(),
+        }
+    }
+
+    // The result is the same either way; only the amount of work differs
+    pub fn with_dirty_tracking(mut self, enabled: bool) -> Self {
+        self.dirty_tracking = enabled;
+        self
+    }
+
+    // keep in sync with Interpreter::set_max_string_len so folding never hides a runtime error
+    pub fn set_max_string_len(&mut self, max_len: usize) {
+        self.max_string_len = max_len;
+    }
+
+    // String constants longer than this (in bytes) are not copied into each
+    // use; numbers, bools and none always are
+    pub fn set_max_inline_len(&mut self, max_len: usize) {
+        self.max_inline_len = max_len;
+    }
+
+    // warnings from the last optimize() call
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    // work done by the last optimize() call
+    pub fn report(&self) -> &OptReport {
+        &self.report
+    }
+
+    // Which statements of the program the last optimize() call left alone,
+    // for SemanticChecker::recheck
+    pub fn changes(&self) -> &ChangeSet {
+        &self.changes
+    }
+
+    // Top-level variables the last optimize() call proved constant, with
+    // their folded values; a declaration it went on to remove is still here
+    pub fn known_constants(&self) -> &HashMap<String, Expr> {
+        &self.known_constants
+    }
+
+    fn warn(&mut self, message: &str) {
+        // passes repeat until nothing changes, so the same fold can be seen several times
+        if !self.warnings.iter().any(|w| w == message) {
+            self.warnings.push(message.to_string());
         }
     }
 
+    // Constant folding on a lone expression, without any variables known;
+    // true when it changed. Problems found are in warnings() afterwards.
+    pub fn fold_expression(&mut self, expr: &mut Expr) -> bool {
+        self.warnings.clear();
+        let before = expr.clone();
+        self.simplify_child(expr);
+        *expr != before
+    }
+
     pub fn optimize(&mut self, program: &mut Program) -> bool {
         self.modified = false;
+        self.warnings.clear();
+        self.report = OptReport::default();
+        self.known_constants.clear();
+        self.next_id = program.max_id() + 1;
+        let Program::Stmts(stmts) = program;
+        self.value_types = program_value_types(stmts);
+        let mut states: Vec<StmtState> = (0..stmts.len()).map(|i| StmtState { origin: Some(i), ..StmtState::default() }).collect();
+        let mut previous_constants = HashMap::new();
         loop {
-            let mut changed = false;
-            self.constants.clear();
-            self.shadowed_vars.clear();
-            
-            self.collect_shadowed_vars(program);
-            
+            self.report.iterations += 1;
+            for (stmt, state) in stmts.iter().zip(states.iter_mut()) {
+                if !self.dirty_tracking {
+                    state.facts = None;
+                }
+                state.visit = state.facts.is_none();
+                if state.visit {
+                    state.facts = Some(self.stmt_facts(stmt));
+                }
+            }
+            self.collect_constants(stmts, &states);
+
+            // the passes left the other statements alone last time, and will
+            // again unless a constant they use is new, gone or different
+            let changed_names: HashSet<&String> = previous_constants.keys()
+                .chain(self.constants.keys())
+                .filter(|name| previous_constants.get(*name) != self.constants.get(*name))
+                .collect();
+            if !changed_names.is_empty() {
+                for state in states.iter_mut().filter(|state| !state.visit) {
+                    let mentions = &state.facts.as_ref().expect("facts").mentions;
+                    state.visit = mentions.iter().any(|name| changed_names.contains(name));
+                }
+            }
+            previous_constants = self.constants.clone();
+            self.report.visits.push(states.iter().filter(|state| state.visit).count());
+
             // Run all optimizations
-            changed |= self.collect_constants(program);      
-            changed |= self.propagate_constants(program);    
-            changed |= self.fold_constants(program);
-            changed |= self.simplify_conditionals(program);
-            changed |= self.remove_unreachable_code(program);
-            changed |= self.remove_unused_variables(program);
+            let mut changed = false;
+            for (stmt, state) in stmts.iter_mut().zip(states.iter_mut()) {
+                if state.visit && self.propagate_in_stmt(stmt) {
+                    state.touched = true;
+                    changed = true;
+                }
+            }
+            // dead code goes before folding, so nothing is reported for
+            // divisions that can never run
+            changed |= self.simplify_conditionals(stmts, &mut states);
+            changed |= self.remove_unreachable_code(stmts, &mut states);
+            for (stmt, state) in stmts.iter_mut().zip(states.iter_mut()) {
+                if state.visit && self.fold_stmt(stmt) {
+                    state.touched = true;
+                    changed = true;
+                }
+            }
+            changed |= self.remove_unused_variables(stmts, &mut states);
 
             if !changed {
                 break;
             }
             self.modified = true;
+            for state in states.iter_mut().filter(|state| state.touched) {
+                state.facts = None;
+                state.touched = false;
+                state.origin = None;
+            }
         }
+        self.changes = ChangeSet::new(states.iter().map(|state| state.origin).collect());
         self.modified
     }
-    
-    fn collect_shadowed_vars(&mut self, program: &Program) {
-        match program {
-            Program::Stmts(stmts) => {
-                let mut outer_vars = std::collections::HashSet::new();
-                
-                // Собрать переменные внешнего scope
-                for stmt in stmts {
-                    if let Stmt::VarDecl { name, .. } = stmt {
-                        outer_vars.insert(name.clone());
-                    }
-                }
-                
-                // Найти затеняемые переменные во вложенных блоках
-                for stmt in stmts {
-                    self.find_shadowed_in_stmt(stmt, &outer_vars);
-                }
-            }
-        }
+
+    fn stmt_facts(&self, stmt: &Stmt) -> StmtFacts {
+        let mut facts = StmtFacts::default();
+        self.collect_assigned_vars(stmt, &mut facts.assigned);
+        Self::collect_nested_decls(stmt, &mut facts.nested_decls);
+        collect_mentions(stmt, &mut facts.mentions);
+        facts
     }
-    
-    fn find_shadowed_in_stmt(&mut self, stmt: &Stmt, outer_vars: &std::collections::HashSet<String>) {
+
+    // Variables declared in nested blocks (not function bodies) of a top-level statement
+    fn collect_nested_decls(stmt: &Stmt, names: &mut HashSet<String>) {
         match stmt {
             Stmt::If { then_branch, else_branch, .. } => {
-                self.find_shadowed_in_block(then_branch, outer_vars);
+                Self::collect_nested_decls_in_block(then_branch, names);
                 if let Some(else_branch) = else_branch {
-                    self.find_shadowed_in_block(else_branch, outer_vars);
+                    Self::collect_nested_decls_in_block(else_branch, names);
                 }
             }
-            Stmt::While { body, .. } | Stmt::For { body, .. } => {
-                self.find_shadowed_in_block(body, outer_vars);
+            Stmt::While { body, .. } | Stmt::RepeatUntil { body, .. } => {
+                Self::collect_nested_decls_in_block(body, names);
+            }
+            Stmt::For { var, body, .. } => {
+                // the loop variables shadow like declarations inside the body
+                names.extend(var.names().into_iter().map(str::to_string));
+                Self::collect_nested_decls_in_block(body, names);
             }
             _ => {}
         }
     }
-    
-    fn find_shadowed_in_block(&mut self, stmts: &[Stmt], outer_vars: &std::collections::HashSet<String>) {
+
+    fn collect_nested_decls_in_block(stmts: &[Stmt], names: &mut HashSet<String>) {
         for stmt in stmts {
             if let Stmt::VarDecl { name, .. } = stmt {
-                // if there's variable with the same name in outer scope
-                if outer_vars.contains(name) {
-                    self.shadowed_vars.insert(name.clone());
-                }
+                names.insert(name.clone());
             }
             
             // recursively for nested blockes
-            self.find_shadowed_in_stmt(stmt, outer_vars);
+            Self::collect_nested_decls(stmt, names);
         }
     }
 
-    fn collect_constants(&mut self, program: &Program) -> bool {
-        match program {
-            Program::Stmts(stmts) => {
-                let mut assigned_vars = std::collections::HashSet::new();
-                
-                for stmt in stmts {
-                    self.collect_assigned_vars(stmt, &mut assigned_vars);
+    fn collect_constants(&mut self, stmts: &[Stmt], states: &[StmtState]) {
+        self.constants.clear();
+        self.shadowed_vars.clear();
+        let facts = || states.iter().map(|state| state.facts.as_ref().expect("facts"));
+
+        // a global is shadowed when a nested block declares it again, or the
+        // top level does (see SemanticChecker::set_allow_redeclaration)
+        let mut outer_vars: HashSet<&String> = HashSet::new();
+        for stmt in stmts {
+            if let Stmt::VarDecl { name, .. } = stmt
+                && !outer_vars.insert(name)
+            {
+                self.shadowed_vars.insert(name.clone());
+            }
+        }
+        for name in facts().flat_map(|facts| &facts.nested_decls) {
+            if outer_vars.contains(name) {
+                self.shadowed_vars.insert(name.clone());
+            }
+        }
+
+        let assigned_vars: HashSet<&String> = facts().flat_map(|facts| &facts.assigned).collect();
+        for stmt in stmts {
+            // `var x` with no value is no constant: it is meant to be assigned
+            if let Stmt::VarDecl { name, init: Some(init), .. } = stmt {
+                if assigned_vars.contains(name) || self.shadowed_vars.contains(name) {
+                    continue;
                 }
-                
-                for stmt in stmts {
-                    if let Stmt::VarDecl { name, init } = stmt {
-                        if self.is_constant_expr(init) 
-                            && !assigned_vars.contains(name)
-                            && !self.shadowed_vars.contains(name) {  
-                            self.constants.insert(name.clone(), init.clone());
-                        }
-                    }
+                // `2 + 3` counts as 5 already; fold_stmt reports what folding finds
+                let mut init = init.clone();
+                let warned = self.warnings.len();
+                self.simplify_child(&mut init);
+                self.warnings.truncate(warned);
+                if !self.is_constant_expr(&init) {
+                    continue;
+                }
+                self.known_constants.insert(name.clone(), init.clone());
+                if !matches!(&init, Expr::String(s) if s.len() > self.max_inline_len) {
+                    self.constants.insert(name.clone(), init);
                 }
             }
         }
-        false
     }
     
    
@@ -535,7 +2205,7 @@ impl Optimizer {
         let mut changed = false;
         
         match stmt {
-            Stmt::If { cond, then_branch, else_branch } => {
+            Stmt::If { cond, then_branch, else_branch, .. } => {
                 if self.propagate_in_expr(cond) {
                     changed = true;
                 }
@@ -558,7 +2228,7 @@ impl Optimizer {
                     }
                 }
             }
-            Stmt::While { cond, body } => {
+            Stmt::While { cond, body, .. } | Stmt::RepeatUntil { cond, body, .. } => {
                 if self.propagate_in_expr(cond) {
                     changed = true;
                 }
@@ -584,7 +2254,7 @@ impl Optimizer {
                     }
                 }
             }
-            Stmt::Print { args } => {
+            Stmt::Print { args, .. } => {
                 for arg in args {
                     if self.propagate_in_expr(arg) {
                         changed = true;
@@ -596,6 +2266,12 @@ impl Optimizer {
                     changed = true;
                 }
             }
+            Stmt::VarDecl { init: Some(Expr::Func { params, body }), .. } => {
+                changed |= self.propagate_in_func(params, body);
+            }
+            Stmt::Return(Some(expr), _) => {
+                changed |= self.propagate_in_expr(expr);
+            }
             _ => {}
         }
         
@@ -609,22 +2285,42 @@ impl Optimizer {
     
     fn collect_assigned_vars(&self, stmt: &Stmt, assigned: &mut std::collections::HashSet<String>) {
         match stmt {
-            Stmt::Assign { target, .. } => {
+            Stmt::Assign { target, value, .. } => {
                 if let Expr::Ident(name) = target {
                     assigned.insert(name.clone());
                 }
+                self.collect_assigned_in_expr(target, assigned);
+                self.collect_assigned_in_expr(value, assigned);
             }
-            Stmt::If { then_branch, else_branch, .. } => {
-                for s in then_branch {
-                    self.collect_assigned_vars(s, assigned);
-                }
-                if let Some(else_branch) = else_branch {
-                    for s in else_branch {
-                        self.collect_assigned_vars(s, assigned);
+            Stmt::Swap { left, right, .. } => {
+                for operand in [left, right] {
+                    if let Expr::Ident(name) = operand {
+                        assigned.insert(name.clone());
                     }
+                    self.collect_assigned_in_expr(operand, assigned);
                 }
             }
-            Stmt::While { body, .. } | Stmt::For { body, .. } => {
+            Stmt::VarDecl { init: Some(expr), .. } | Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => {
+                self.collect_assigned_in_expr(expr, assigned);
+            }
+            Stmt::Print { args, .. } => {
+                for arg in args {
+                    self.collect_assigned_in_expr(arg, assigned);
+                }
+            }
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                self.collect_assigned_in_expr(cond, assigned);
+                for s in then_branch {
+                    self.collect_assigned_vars(s, assigned);
+                }
+                if let Some(else_branch) = else_branch {
+                    for s in else_branch {
+                        self.collect_assigned_vars(s, assigned);
+                    }
+                }
+            }
+            Stmt::While { cond: expr, body, .. } | Stmt::RepeatUntil { cond: expr, body, .. } | Stmt::For { iterable: expr, body, .. } => {
+                self.collect_assigned_in_expr(expr, assigned);
                 for s in body {
                     self.collect_assigned_vars(s, assigned);
                 }
@@ -635,24 +2331,102 @@ impl Optimizer {
     
     
   
-    fn propagate_constants(&mut self, program: &mut Program) -> bool {
-        let mut changed = false;
-        
-        match program {
-            Program::Stmts(stmts) => {
-                for stmt in stmts.iter_mut() {
-                    if self.propagate_in_stmt(stmt) {
-                        changed = true;
+    // assignments inside function bodies can change a global whenever the function runs
+    fn collect_assigned_in_expr(&self, expr: &Expr, assigned: &mut std::collections::HashSet<String>) {
+        match expr {
+            Expr::Func { body, .. } => match body {
+                FuncBody::Expr(body) => self.collect_assigned_in_expr(body, assigned),
+                FuncBody::Block(stmts) => {
+                    for s in stmts {
+                        self.collect_assigned_vars(s, assigned);
+                    }
+                }
+            },
+            Expr::Binary { .. } => {
+                let (first, operations) = binary_chain(expr);
+                self.collect_assigned_in_expr(first, assigned);
+                for (_, right) in operations {
+                    self.collect_assigned_in_expr(right, assigned);
+                }
+            }
+            Expr::Range(left, right) => {
+                self.collect_assigned_in_expr(left, assigned);
+                self.collect_assigned_in_expr(right, assigned);
+            }
+            Expr::Unary { expr, .. } | Expr::IsType { expr, .. } | Expr::Member { target: expr, .. } | Expr::Spread(expr) => {
+                self.collect_assigned_in_expr(expr, assigned);
+            }
+            Expr::Index { target, index } => {
+                self.collect_assigned_in_expr(target, assigned);
+                self.collect_assigned_in_expr(index, assigned);
+            }
+            Expr::Call { callee, args } => {
+                self.collect_assigned_in_expr(callee, assigned);
+                for arg in args {
+                    self.collect_assigned_in_expr(arg, assigned);
+                }
+            }
+            Expr::Array(elems) => {
+                for elem in elems {
+                    self.collect_assigned_in_expr(elem, assigned);
+                }
+            }
+            Expr::Tuple(elems) => {
+                for elem in elems {
+                    self.collect_assigned_in_expr(&elem.value, assigned);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // names a function binds itself (params, locals, loop variables) hide globals of the same name
+    fn collect_declared_names(stmts: &[Stmt], names: &mut std::collections::HashSet<String>) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::VarDecl { name, .. } => {
+                    names.insert(name.clone());
+                }
+                Stmt::If { then_branch, else_branch, .. } => {
+                    Self::collect_declared_names(then_branch, names);
+                    if let Some(else_branch) = else_branch {
+                        Self::collect_declared_names(else_branch, names);
                     }
                 }
+                Stmt::While { body, .. } | Stmt::RepeatUntil { body, .. } => Self::collect_declared_names(body, names),
+                Stmt::For { var, body, .. } => {
+                    names.extend(var.names().into_iter().map(str::to_string));
+                    Self::collect_declared_names(body, names);
+                }
+                _ => {}
             }
         }
-        
+    }
+
+    fn propagate_in_func(&mut self, params: &[String], body: &mut FuncBody) -> bool {
+        let mut hidden: std::collections::HashSet<String> = params.iter().cloned().collect();
+        if let FuncBody::Block(stmts) = body {
+            Self::collect_declared_names(stmts, &mut hidden);
+        }
+
+        let saved = self.constants.clone();
+        self.constants.retain(|name, _| !hidden.contains(name));
+        let changed = match body {
+            FuncBody::Expr(expr) => self.propagate_in_expr(expr),
+            FuncBody::Block(stmts) => {
+                let mut changed = false;
+                for s in stmts {
+                    if self.propagate_in_stmt(s) {
+                        changed = true;
+                    }
+                }
+                changed
+            }
+        };
+        self.constants = saved;
         changed
     }
-    
-    
-    
+
     fn propagate_in_expr(&mut self, expr: &mut Expr) -> bool {
         match expr {
             Expr::Ident(name) => {
@@ -662,19 +2436,28 @@ impl Optimizer {
                     return true;
                 }
             }
-            Expr::Binary { left, right, .. } => {
-                let mut changed = false;
-                if self.propagate_in_expr(left) {
-                    changed = true;
+            Expr::Binary { .. } => {
+                // along the left operands of a chain with a loop
+                let mut rights = Vec::new();
+                let mut node = expr;
+                while let Expr::Binary { left, right, .. } = node {
+                    rights.push(right);
+                    node = left;
                 }
-                if self.propagate_in_expr(right) {
-                    changed = true;
+                let mut changed = self.propagate_in_expr(node);
+                for right in rights.into_iter().rev() {
+                    if self.propagate_in_expr(right) {
+                        changed = true;
+                    }
                 }
                 return changed;
             }
             Expr::Unary { expr: inner, .. } => {
                 return self.propagate_in_expr(inner);
             }
+            Expr::Func { params, body } => {
+                return self.propagate_in_func(params, body);
+            }
             _ => {}
         }
         false
@@ -689,29 +2472,27 @@ impl Optimizer {
     }
 
     // OPTIMIZATION 1: Constant Folding
-    fn fold_constants(&mut self, program: &mut Program) -> bool {
-        let mut changed = false;
-
-        match program {
-            Program::Stmts(stmts) => {
-                for stmt in stmts {
-                    if self.fold_stmt(stmt) {
-                        changed = true;
-                    }
-                }
-            }
-        }
-        changed
-    }
-
     fn fold_stmt(&mut self, stmt: &mut Stmt) -> bool {
         let mut changed = false;
         match stmt {
-            Stmt::VarDecl { init, .. } => {
+            Stmt::VarDecl { init: Some(init), .. } => {
                 if let Some(new_expr) = self.simplify_expr(init) {
                     *init = new_expr;
                     changed = true;
                 }
+                if let Expr::Func { body: FuncBody::Block(body), .. } = init {
+                    for s in body {
+                        if self.fold_stmt(s) {
+                            changed = true;
+                        }
+                    }
+                }
+            }
+            Stmt::Return(Some(expr), _) => {
+                if let Some(new_expr) = self.simplify_expr(expr) {
+                    *expr = new_expr;
+                    changed = true;
+                }
             }
             Stmt::Assign { value, .. } => {
                 if let Some(new_expr) = self.simplify_expr(value) {
@@ -719,7 +2500,7 @@ impl Optimizer {
                     changed = true;
                 }
             }
-            Stmt::Print { args } => {
+            Stmt::Print { args, .. } => {
                 for arg in args {
                     if let Some(new_expr) = self.simplify_expr(arg) {
                         *arg = new_expr;
@@ -727,20 +2508,24 @@ impl Optimizer {
                     }
                 }
             }
-            Stmt::If { cond, then_branch, else_branch } => {
+            Stmt::If { cond, then_branch, else_branch, .. } => {
                 // Simplify condition
                 if let Some(new_expr) = self.simplify_expr(cond) {
                     *cond = new_expr;
                     changed = true;
                 }
 
-                // Recursively optimize branches
-                for s in then_branch {
-                    if self.fold_stmt(s) {
-                        changed = true;
+                // Recursively optimize branches; a branch the condition rules
+                // out is left for simplify_conditionals to drop
+                let truth = constant_truth(cond);
+                if truth != Some(false) {
+                    for s in then_branch.iter_mut() {
+                        changed |= self.fold_stmt(s);
                     }
                 }
-                if let Some(else_branch) = else_branch {
+                if let Some(else_branch) = else_branch
+                    && truth != Some(true)
+                {
                     for s in else_branch {
                         if self.fold_stmt(s) {
                             changed = true;
@@ -748,17 +2533,27 @@ impl Optimizer {
                     }
                 }
             }
-            Stmt::While { cond, body } => {
+            Stmt::While { cond, body, .. } => {
                 if let Some(new_expr) = self.simplify_expr(cond) {
                     *cond = new_expr;
                     changed = true;
                 }
-                for s in body {
-                    if self.fold_stmt(s) {
-                        changed = true;
+                if constant_truth(cond) != Some(false) {
+                    for s in body {
+                        changed |= self.fold_stmt(s);
                     }
                 }
             }
+            // the loop itself is kept whatever the condition folds to
+            Stmt::RepeatUntil { body, cond, .. } => {
+                for s in body {
+                    changed |= self.fold_stmt(s);
+                }
+                if let Some(new_expr) = self.simplify_expr(cond) {
+                    *cond = new_expr;
+                    changed = true;
+                }
+            }
             Stmt::For { iterable, body, .. } => {
                 if let Some(new_expr) = self.simplify_expr(iterable) {
                     *iterable = new_expr;
@@ -778,103 +2573,55 @@ impl Optimizer {
     fn simplify_expr(&mut self, expr: &mut Expr) -> Option<Expr> {
         match expr {
             Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::String(_) | Expr::None
-            | Expr::Ident(_) | Expr::Array(_) | Expr::Tuple(_) => None,
+            | Expr::Ident(_) => None,
 
-            Expr::Binary { left, op, right } => {
-                // sub-expressions first
-                if let Some(new_left) = self.simplify_expr(left) {
-                    *left = Box::new(new_left);
+            // containers keep their shape, only their parts are folded
+            // (so `-3..3` ends up with literal endpoints)
+            Expr::Range(low, high) | Expr::Index { target: low, index: high } => {
+                let changed = self.simplify_child(low) | self.simplify_child(high);
+                changed.then(|| expr.clone())
+            }
+            Expr::Call { args, .. } | Expr::Array(args) => {
+                let mut changed = false;
+                for arg in args.iter_mut() {
+                    changed |= self.simplify_child(arg);
                 }
-                if let Some(new_right) = self.simplify_expr(right) {
-                    *right = Box::new(new_right);
+                if let Expr::Array(elems) = expr {
+                    changed |= flatten_array_spreads(elems);
                 }
+                changed.then(|| expr.clone())
+            }
+            Expr::Tuple(elems) => {
+                let mut changed = false;
+                for elem in elems.iter_mut() {
+                    changed |= self.simplify_child(&mut elem.value);
+                }
+                changed |= flatten_tuple_spreads(elems);
+                changed.then(|| expr.clone())
+            }
+            Expr::Spread(operand) => self.simplify_child(operand).then(|| expr.clone()),
 
-                // evaluate expr (if both sides constants)
-                match (left.as_ref(), op.clone(), right.as_ref()) {
-                    (Expr::Integer(a), BinOp::Add, Expr::Integer(b)) => {
-                        Some(Expr::Integer(a + b))
-                    }
-                    (Expr::Integer(a), BinOp::Sub, Expr::Integer(b)) => {
-                        Some(Expr::Integer(a - b))
-                    }
-                    (Expr::Integer(a), BinOp::Mul, Expr::Integer(b)) => {
-                        Some(Expr::Integer(a * b))
-                    }
-                    (Expr::Integer(a), BinOp::Div, Expr::Integer(b)) => {
-                        if *b != 0 {
-                            Some(Expr::Integer(a / b))
-                        } else {
-                            eprintln!("Warning: Division by zero detected during optimization");
-                            None
-                        }
-                    }
-                    (Expr::Integer(a), BinOp::Eq, Expr::Integer(b)) => {
-                        Some(Expr::Bool(a == b))
-                    }
-                    (Expr::Integer(a), BinOp::Ne, Expr::Integer(b)) => {
-                        Some(Expr::Bool(a != b))
-                    }
-                    (Expr::Integer(a), BinOp::Lt, Expr::Integer(b)) => {
-                        Some(Expr::Bool(a < b))
-                    }
-                    (Expr::Integer(a), BinOp::Le, Expr::Integer(b)) => {
-                        Some(Expr::Bool(a <= b))
-                    }
-                    (Expr::Integer(a), BinOp::Gt, Expr::Integer(b)) => {
-                        Some(Expr::Bool(a > b))
-                    }
-                    (Expr::Integer(a), BinOp::Ge, Expr::Integer(b)) => {
-                        Some(Expr::Bool(a >= b))
-                    }
-                    (Expr::Bool(a), BinOp::And, Expr::Bool(b)) => {
-                        Some(Expr::Bool(*a && *b))
-                    }
-                    (Expr::Bool(a), BinOp::Or, Expr::Bool(b)) => {
-                        Some(Expr::Bool(*a || *b))
-                    }
-                    (Expr::Bool(a), BinOp::Xor, Expr::Bool(b)) => {
-                        Some(Expr::Bool(*a ^ *b))
-                    }
-                    (Expr::Real(a), BinOp::Add, Expr::Real(b)) => {
-                        Some(Expr::Real(a + b))
-                    }
-                    (Expr::Real(a), BinOp::Sub, Expr::Real(b)) => {
-                        Some(Expr::Real(a - b))
-                    }
-                    (Expr::Real(a), BinOp::Mul, Expr::Real(b)) => {
-                        Some(Expr::Real(a * b))
-                    }
-
-
-                    
-                    
-                    (Expr::Ident(_), BinOp::Add, Expr::Integer(0)) => Some(*left.clone()),
-                    (Expr::Integer(0), BinOp::Add, Expr::Ident(_)) => Some(*right.clone()),
-                    (Expr::Ident(_), BinOp::Mul, Expr::Integer(1)) => Some(*left.clone()),
-                    (Expr::Integer(1), BinOp::Mul, Expr::Ident(_)) => Some(*right.clone()),
-                    (_, BinOp::Mul, Expr::Integer(0)) => Some(Expr::Integer(0)),
-                    (Expr::Integer(0), BinOp::Mul, _) => Some(Expr::Integer(0)),
-
-                    (Expr::Bool(true), BinOp::And, _) => Some(*right.clone()),
-                    (_, BinOp::And, Expr::Bool(true)) => Some(*left.clone()),
-                    (Expr::Bool(false), BinOp::And, _) => Some(Expr::Bool(false)),
-                    (_, BinOp::And, Expr::Bool(false)) => Some(Expr::Bool(false)),
-                    (Expr::Bool(true), BinOp::Or, _) => Some(Expr::Bool(true)),
-                    (_, BinOp::Or, Expr::Bool(true)) => Some(Expr::Bool(true)),
-                    (Expr::Bool(false), BinOp::Or, _) => Some(*right.clone()),
-                    (_, BinOp::Or, Expr::Bool(false)) => Some(*left.clone()),
-
-
-                    (Expr::Real(a), BinOp::Div, Expr::Real(b)) => {
-                        if *b != 0.0 {
-                            Some(Expr::Real(a / b))
-                        } else {
-                            eprintln!("Warning: Division by zero detected during optimization");
-                            None
-                        }
+            Expr::Binary { .. } => {
+                // the chain is taken apart and folded from the left with a
+                // loop, since it is as deep as it is long
+                let mut operations = Vec::new();
+                let mut node = expr.take();
+                while let Expr::Binary { left, op, right } = &mut node {
+                    operations.push((op.clone(), right.take()));
+                    node = left.take();
+                }
+                self.simplify_child(&mut node);
+                let mut folded = None;
+                for (op, mut right) in operations.into_iter().rev() {
+                    if let Some(value) = folded.take() {
+                        node = value;
                     }
-                    _ => None,
+                    self.simplify_child(&mut right);
+                    folded = self.fold_binary(&node, &op, &right);
+                    node = Expr::Binary { left: Box::new(node), op, right: Box::new(right) };
                 }
+                *expr = node;
+                folded
             }
 
             Expr::Unary { op, expr } => {
@@ -882,10 +2629,16 @@ impl Optimizer {
                     *expr = Box::new(new_expr);
                 }
 
+                // the operand is folded already, so a constant chain like
+                // `- - 5` collapses one level at a time from the inside
                 match (op.clone(), expr.as_ref()) {
                     (UnOp::Not, Expr::Bool(val)) => Some(Expr::Bool(!val)),
-                    (UnOp::Neg, Expr::Integer(val)) => Some(Expr::Integer(-val)),
+                    // i64::MIN has no negation; the interpreter reports it
+                    (UnOp::Neg, Expr::Integer(val)) => val.checked_neg().map(Expr::Integer),
                     (UnOp::Neg, Expr::Real(val)) => Some(Expr::Real(-val)),
+                    (UnOp::Plus, operand @ (Expr::Integer(_) | Expr::Real(_))) => Some(operand.clone()),
+                    // `not not x` is x itself when x is a bool
+                    (UnOp::Not, Expr::Unary { op: UnOp::Not, expr: inner }) if is_bool_expr(inner) => Some(*inner.clone()),
                     _ => None,
                 }
             }
@@ -894,286 +2647,771 @@ impl Optimizer {
         }
     }
 
+    // Folds a sub-expression in place; true when it changed
+    fn simplify_child(&mut self, expr: &mut Expr) -> bool {
+        match self.simplify_expr(expr) {
+            Some(new_expr) => {
+                *expr = new_expr;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The value of `left op right` for operands that are folded already,
+    // when it can be told without running it
+    fn fold_binary(&mut self, left: &Expr, op: &BinOp, right: &Expr) -> Option<Expr> {
+        match (left, op.clone(), right) {
+            // overflowing results are left for the interpreter
+            (Expr::Integer(a), BinOp::Add, Expr::Integer(b)) => {
+                a.checked_add(*b).map(Expr::Integer)
+            }
+            (Expr::Integer(a), BinOp::Sub, Expr::Integer(b)) => {
+                a.checked_sub(*b).map(Expr::Integer)
+            }
+            (Expr::Integer(a), BinOp::Mul, Expr::Integer(b)) => {
+                a.checked_mul(*b).map(Expr::Integer)
+            }
+            (Expr::Integer(a), BinOp::Div | BinOp::IntDiv, Expr::Integer(b)) => {
+                if *b != 0 {
+                    // i64::MIN / -1 overflows; leave it for the interpreter to report
+                    a.checked_div(*b).map(Expr::Integer)
+                } else {
+                    self.warn("Division by zero detected during optimization");
+                    None
+                }
+            }
+            (Expr::Integer(a), BinOp::Eq, Expr::Integer(b)) => {
+                Some(Expr::Bool(a == b))
+            }
+            (Expr::Integer(a), BinOp::Ne, Expr::Integer(b)) => {
+                Some(Expr::Bool(a != b))
+            }
+            // other literals compare as the interpreter's PartialEq does
+            (a, BinOp::Eq, b) if constant_equality(a, b).is_some() => constant_equality(a, b).map(Expr::Bool),
+            (a, BinOp::Ne, b) if constant_equality(a, b).is_some() => constant_equality(a, b).map(|eq| Expr::Bool(!eq)),
+            (Expr::Integer(a), BinOp::Lt, Expr::Integer(b)) => {
+                Some(Expr::Bool(a < b))
+            }
+            (Expr::Integer(a), BinOp::Le, Expr::Integer(b)) => {
+                Some(Expr::Bool(a <= b))
+            }
+            (Expr::Integer(a), BinOp::Gt, Expr::Integer(b)) => {
+                Some(Expr::Bool(a > b))
+            }
+            (Expr::Integer(a), BinOp::Ge, Expr::Integer(b)) => {
+                Some(Expr::Bool(a >= b))
+            }
+            (Expr::Integer(a), BinOp::And, Expr::Integer(b)) => Some(Expr::Integer(a & b)),
+            (Expr::Integer(a), BinOp::Or, Expr::Integer(b)) => Some(Expr::Integer(a | b)),
+            (Expr::Integer(a), BinOp::Xor, Expr::Integer(b)) => Some(Expr::Integer(a ^ b)),
+            // out-of-range shift amounts are left for the interpreter to report
+            (Expr::Integer(a), BinOp::Shl, Expr::Integer(b @ 0..=63)) => Some(Expr::Integer(a << b)),
+            (Expr::Integer(a), BinOp::Shr, Expr::Integer(b @ 0..=63)) => Some(Expr::Integer(a >> b)),
+            (Expr::Bool(a), BinOp::And, Expr::Bool(b)) => {
+                Some(Expr::Bool(*a && *b))
+            }
+            (Expr::Bool(a), BinOp::Or, Expr::Bool(b)) => {
+                Some(Expr::Bool(*a || *b))
+            }
+            (Expr::Bool(a), BinOp::Xor, Expr::Bool(b)) => {
+                Some(Expr::Bool(*a ^ *b))
+            }
+            (Expr::Real(a), BinOp::Add, Expr::Real(b)) => {
+                finite_real(a + b)
+            }
+            (Expr::Real(a), BinOp::Sub, Expr::Real(b)) => {
+                finite_real(a - b)
+            }
+            (Expr::Real(a), BinOp::Mul, Expr::Real(b)) => {
+                finite_real(a * b)
+            }
+            (Expr::String(s), BinOp::Mul, Expr::Integer(n))
+            | (Expr::Integer(n), BinOp::Mul, Expr::String(s)) => {
+                // out-of-range counts are left for the interpreter to report
+                repeat_string(s, *n, self.max_string_len).ok().map(Expr::String)
+            }
+            // `+` with a string converts the other side as print would
+            (Expr::String(a), BinOp::Add, Expr::String(b)) => Some(Expr::String(format!("{}{}", a, b))),
+            (Expr::String(s), BinOp::Add, Expr::Integer(n)) => Some(Expr::String(format!("{}{}", s, n))),
+            (Expr::Integer(n), BinOp::Add, Expr::String(s)) => Some(Expr::String(format!("{}{}", n, s))),
+            (Expr::String(s), BinOp::Add, Expr::Real(n)) => Some(Expr::String(format!("{}{}", s, format_real(*n)))),
+            (Expr::Real(n), BinOp::Add, Expr::String(s)) => Some(Expr::String(format!("{}{}", format_real(*n), s))),
+
+
+            
+            
+            (Expr::Ident(_), BinOp::Add, Expr::Integer(0)) => Some(left.clone()),
+            (Expr::Integer(0), BinOp::Add, Expr::Ident(_)) => Some(right.clone()),
+            (Expr::Ident(_), BinOp::Mul, Expr::Integer(1)) => Some(left.clone()),
+            (Expr::Integer(1), BinOp::Mul, Expr::Ident(_)) => Some(right.clone()),
+
+            // `true and x` is x only when x is a bool; otherwise the
+            // interpreter converts (or, in strict mode, rejects) it
+            (Expr::Bool(true), BinOp::And, other) if is_bool_expr(other) => Some(right.clone()),
+            (other, BinOp::And, Expr::Bool(true)) if is_bool_expr(other) => Some(left.clone()),
+            // both operands are always evaluated, so one that may fail is kept
+            (Expr::Bool(false), BinOp::And, other) if !expr_may_fail(other, &self.value_types) => Some(Expr::Bool(false)),
+            (other, BinOp::And, Expr::Bool(false)) if !expr_may_fail(other, &self.value_types) => Some(Expr::Bool(false)),
+            (Expr::Bool(true), BinOp::Or, other) if !expr_may_fail(other, &self.value_types) => Some(Expr::Bool(true)),
+            (other, BinOp::Or, Expr::Bool(true)) if !expr_may_fail(other, &self.value_types) => Some(Expr::Bool(true)),
+            (Expr::Bool(false), BinOp::Or, other) if is_bool_expr(other) => Some(right.clone()),
+            (other, BinOp::Or, Expr::Bool(false)) if is_bool_expr(other) => Some(left.clone()),
+            // a constant left operand decides which operand is the
+            // result; the other one would never run
+            (constant, BinOp::OrElse | BinOp::AndThen, _) if literal_truthiness(constant).is_some() => {
+                let keeps_left = literal_truthiness(constant) == Some(*op == BinOp::OrElse);
+                Some(if keeps_left { left.clone() } else { right.clone() })
+            }
+
+
+            (Expr::Real(a), BinOp::Div, Expr::Real(b)) => {
+                if *b != 0.0 {
+                    finite_real(a / b)
+                } else {
+                    self.warn("Division by zero detected during optimization");
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
     // OPTIMIZATION 2: Simplify conditionals (if true/false)
-    fn simplify_conditionals(&mut self, program: &mut Program) -> bool {
+    // Same as simplify_block for the top level, keeping `states` in step
+    fn simplify_conditionals(&mut self, stmts: &mut Vec<Stmt>, states: &mut Vec<StmtState>) -> bool {
         let mut changed = false;
-        
-        match program {
-            Program::Stmts(stmts) => {
-                let mut i = 0;
-                while i < stmts.len() {
-                    if let Stmt::If { cond, then_branch, else_branch } = &stmts[i] {
-                        
-                        let contains_vardecl = |stmts: &[Stmt]| {
-                            stmts.iter().any(|s| matches!(s, Stmt::VarDecl { .. }))
-                        };
-                        
-                        if contains_vardecl(then_branch) || 
-                           else_branch.as_ref().map(|b| contains_vardecl(b)).unwrap_or(false) {
-                            i += 1;
-                            continue;  // skip optimization
-                        }
-                        
-                        // safe optimization
-                        if let Expr::Bool(true) = cond {
-                            let then_clone = then_branch.clone();
-                            stmts.splice(i..=i, then_clone);
-                            changed = true;
-                            continue;
-                        } else if let Expr::Bool(false) = cond {
-                            if let Some(else_branch) = else_branch {
-                                let else_clone = else_branch.clone();
-                                stmts.splice(i..=i, else_clone);
-                            } else {
-                                stmts.remove(i);
-                            }
-                            changed = true;
-                            continue;
-                        }
-                    }
-                    
-                    if let Some(stmt) = stmts.get_mut(i) {
-                        if self.simplify_stmt(stmt) {
-                            changed = true;
-                        }
+        let mut i = 0;
+        while i < stmts.len() {
+            if let Stmt::If { cond: Expr::Bool(truth), then_branch, else_branch, .. } = &stmts[i]
+                && !self.has_vardecl(then_branch)
+                && !else_branch.as_deref().is_some_and(|b| self.has_vardecl(b))
+            {
+                let kept = if *truth { then_branch.clone() } else { else_branch.clone().unwrap_or_default() };
+                let count = kept.len();
+                stmts.splice(i..=i, kept);
+                states.splice(i..=i, (0..count).map(|_| StmtState { facts: None, visit: true, touched: true, origin: None }));
+                changed = true;
+                continue;
+            }
+            if let Some(kept) = self.collapse_identical_branches(&stmts[i]) {
+                let count = kept.len();
+                stmts.splice(i..=i, kept);
+                states.splice(i..=i, (0..count).map(|_| StmtState { facts: None, visit: true, touched: true, origin: None }));
+                changed = true;
+                continue;
+            }
+            if states[i].visit && self.simplify_stmt(&mut stmts[i]) {
+                states[i].touched = true;
+                changed = true;
+            }
+            i += 1;
+        }
+        changed
+    }
+
+    fn simplify_block(&mut self, stmts: &mut Vec<Stmt>) -> bool {
+        let mut changed = false;
+        let mut i = 0;
+        while i < stmts.len() {
+            if let Stmt::If { cond, then_branch, else_branch, .. } = &stmts[i] {
+                
+                let contains_vardecl = |stmts: &[Stmt]| {
+                    stmts.iter().any(|s| matches!(s, Stmt::VarDecl { .. }))
+                };
+                
+                if contains_vardecl(then_branch) || 
+                   else_branch.as_ref().map(|b| contains_vardecl(b)).unwrap_or(false) {
+                    if self.simplify_stmt(&mut stmts[i]) {
+                        changed = true;
                     }
-                    
                     i += 1;
+                    continue;  // skip optimization
+                }
+                
+                // safe optimization
+                if let Expr::Bool(true) = cond {
+                    let then_clone = then_branch.clone();
+                    stmts.splice(i..=i, then_clone);
+                    changed = true;
+                    continue;
+                } else if let Expr::Bool(false) = cond {
+                    if let Some(else_branch) = else_branch {
+                        let else_clone = else_branch.clone();
+                        stmts.splice(i..=i, else_clone);
+                    } else {
+                        stmts.remove(i);
+                    }
+                    changed = true;
+                    continue;
                 }
             }
+            if let Some(kept) = self.collapse_identical_branches(&stmts[i]) {
+                stmts.splice(i..=i, kept);
+                changed = true;
+                continue;
+            }
+            
+            if let Some(stmt) = stmts.get_mut(i) {
+                if self.simplify_stmt(stmt) {
+                    changed = true;
+                }
+            }
+            
+            i += 1;
         }
         changed
     }
     
     
 
+    // An `if` whose branches are the same runs that branch either way; the
+    // condition stays as an expression statement when evaluating it may fail
+    fn collapse_identical_branches(&mut self, stmt: &Stmt) -> Option<Vec<Stmt>> {
+        let Stmt::If { cond, then_branch, else_branch: Some(else_branch), span } = stmt else {
+            return None;
+        };
+        if !same_code(then_branch, else_branch) || self.has_vardecl(then_branch) {
+            return None;
+        }
+        let mut kept = Vec::new();
+        if expr_may_fail(cond, &self.value_types) {
+            kept.push(Stmt::Expr(cond.clone(), span.with_id(self.next_id)));
+            self.next_id += 1;
+        }
+        kept.extend(then_branch.iter().cloned());
+        Some(kept)
+    }
+
     fn simplify_stmt(&mut self, stmt: &mut Stmt) -> bool {
         match stmt {
             Stmt::If { then_branch, else_branch, .. } => {
-                let mut changed = false;
-                for s in then_branch {
-                    if self.simplify_stmt(s) {
-                        changed = true;
-                    }
-                }
+                let mut changed = self.simplify_block(then_branch);
                 if let Some(else_branch) = else_branch {
-                    for s in else_branch {
-                        if self.simplify_stmt(s) {
-                            changed = true;
-                        }
-                    }
-                }
-                changed
-            }
-            Stmt::While { body, .. } => {
-                let mut changed = false;
-                for s in body {
-                    if self.simplify_stmt(s) {
+                    if self.simplify_block(else_branch) {
                         changed = true;
                     }
                 }
                 changed
             }
-            Stmt::For { body, .. } => {
-                let mut changed = false;
-                for s in body {
-                    if self.simplify_stmt(s) {
-                        changed = true;
-                    }
-                }
-                changed
+            Stmt::While { body, .. } | Stmt::RepeatUntil { body, .. } | Stmt::For { body, .. } => self.simplify_block(body),
+            // function bodies are blocks of their own
+            Stmt::VarDecl { init: Some(Expr::Func { body: FuncBody::Block(body), .. }), .. } => {
+                self.simplify_block(body)
             }
             _ => false,
         }
     }
 
     // OPTIMIZATION 3: Remove unreachable code
-    fn remove_unreachable_code(&mut self, program: &mut Program) -> bool {
+    fn remove_unreachable_code(&mut self, stmts: &mut Vec<Stmt>, states: &mut Vec<StmtState>) -> bool {
+        let mut changed = false;
+        if let Some(end) = stmts.iter().position(ends_block)
+            && end + 1 < stmts.len()
+        {
+            stmts.truncate(end + 1);
+            states.truncate(end + 1);
+            changed = true;
+        }
+        for (stmt, state) in stmts.iter_mut().zip(states.iter_mut()) {
+            if state.visit && self.remove_unreachable_inside(stmt) {
+                state.touched = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    fn remove_unreachable_in_block(&mut self, stmts: &mut Vec<Stmt>) -> bool {
         let mut changed = false;
 
-        match program {
-            Program::Stmts(stmts) => {
-                let mut new_stmts = Vec::new();
-                for stmt in stmts.iter() {
-                    new_stmts.push(stmt.clone());
-
-                    // Check if this is a return statement
-                    match stmt {
-                        Stmt::Return(_) | Stmt::Exit => {
-                            // Everything after this is unreachable
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
+        // Everything after the first return/exit is unreachable
+        if let Some(end) = stmts.iter().position(ends_block)
+            && end + 1 < stmts.len()
+        {
+            stmts.truncate(end + 1);
+            changed = true;
+        }
 
-                if new_stmts.len() != stmts.len() {
-                    *stmts = new_stmts;
-                    changed = true;
-                }
+        // Also check within if/while/for blocks and function bodies
+        for stmt in stmts.iter_mut() {
+            changed |= self.remove_unreachable_inside(stmt);
+        }
+        changed
+    }
 
-                // Also check within if/while/for blocks
-                for stmt in stmts.iter_mut() {
-                    match stmt {
-                        Stmt::If { then_branch, else_branch, .. } => {
-                            changed |= self.remove_unreachable_code(&mut Program::Stmts(then_branch.clone()));
-                            if let Some(else_branch) = else_branch {
-                                changed |= self.remove_unreachable_code(&mut Program::Stmts(else_branch.clone()));
-                            }
-                        }
-                        Stmt::While { body, .. } => {
-                            changed |= self.remove_unreachable_code(&mut Program::Stmts(body.clone()));
-                        }
-                        Stmt::For { body, .. } => {
-                            changed |= self.remove_unreachable_code(&mut Program::Stmts(body.clone()));
-                        }
-                        _ => {}
-                    }
+    fn remove_unreachable_inside(&mut self, stmt: &mut Stmt) -> bool {
+        let mut changed = false;
+        match stmt {
+            Stmt::If { then_branch, else_branch, .. } => {
+                changed |= self.remove_unreachable_in_block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    changed |= self.remove_unreachable_in_block(else_branch);
                 }
             }
+            Stmt::While { body, .. } | Stmt::RepeatUntil { body, .. } | Stmt::For { body, .. } => {
+                changed |= self.remove_unreachable_in_block(body);
+            }
+            Stmt::VarDecl { init: Some(Expr::Func { body: FuncBody::Block(body), .. }), .. } => {
+                changed |= self.remove_unreachable_in_block(body);
+            }
+            _ => {}
         }
         changed
     }
 
     // OPTIMIZATION 4: Remove unused variables
-    fn remove_unused_variables(&mut self, program: &mut Program) -> bool {
+    // Same as remove_unused_in_block for the top level: the names each
+    // statement mentions rule out most statements before any is walked
+    fn remove_unused_variables(&self, stmts: &mut Vec<Stmt>, states: &mut Vec<StmtState>) -> bool {
         let mut changed = false;
-        let mut used_vars = std::collections::HashSet::new();
-
-        // First pass: collect all used variables
-        self.collect_used_vars(program, &mut used_vars);
+        for (stmt, state) in stmts.iter_mut().zip(states.iter_mut()) {
+            if state.visit && self.remove_unused_inside(stmt) {
+                state.touched = true;
+                changed = true;
+            }
+            // names propagated away this iteration are no longer mentioned
+            if state.touched || state.facts.is_none() {
+                state.facts = Some(self.stmt_facts(stmt));
+            }
+        }
 
-        // Second pass: remove unused variable declarations
-        match program {
-            Program::Stmts(stmts) => {
-                stmts.retain(|stmt| {
-                    if let Stmt::VarDecl { name, .. } = stmt {
-                        if !used_vars.contains(name) {
-                            changed = true;
-                            return false; // Remove this declaration
-                        }
+        // backwards as in remove_unused_in_block, removing everything at the end
+        let mut removed = vec![false; stmts.len()];
+        let mut stores_removed = Vec::new();  // statements that kept a loop or branch around dead stores
+        let mut mentioned_later: HashSet<&str> = HashSet::new();
+        // declaring a global again rebinds it for the functions that read it
+        // by name, so it stays like an assignment would
+        let mut declared = HashSet::new();
+        let redeclared: Vec<bool> = stmts.iter()
+            .map(|stmt| matches!(stmt, Stmt::VarDecl { name, .. } if !declared.insert(name.as_str())))
+            .collect();
+        for i in (0..stmts.len()).rev() {
+            if let Stmt::VarDecl { name, init, .. } = &stmts[i]
+                && !redeclared[i]
+                && !init.as_ref().is_some_and(|init| expr_may_fail(init, &self.value_types))
+                && !(mentioned_later.contains(name.as_str()) && Self::kept_refer_to(stmts, states, &removed, i + 1, name))
+            {
+                removed[i] = true;
+                changed = true;
+                continue;
+            }
+            if let Stmt::VarDecl { name, init, .. } = &stmts[i]
+                && !redeclared[i]
+                && !init.as_ref().is_some_and(|init| expr_may_fail(init, &self.value_types))
+                && self.kept_only_stored(stmts, states, &removed, i + 1, name)
+            {
+                let name = name.clone();
+                for j in i + 1..stmts.len() {
+                    if removed[j] || !states[j].facts.as_ref().expect("facts").mentions.contains(&name) {
+                        continue;
                     }
-                    true
-                });
+                    if matches!(&stmts[j], Stmt::VarDecl { name: declared, .. } if *declared == name) {
+                        break;
+                    }
+                    if Self::remove_stores_in(&mut stmts[j], &name) {
+                        stores_removed.push(j);
+                    } else {
+                        removed[j] = true;
+                    }
+                }
+                removed[i] = true;
+                changed = true;
+                continue;
             }
+            mentioned_later.extend(states[i].facts.as_ref().expect("facts").mentions.iter().map(String::as_str));
+        }
+        for j in stores_removed {
+            states[j].touched = true;
         }
+        let mut keep = removed.iter().map(|removed| !removed);
+        stmts.retain(|_| keep.next().unwrap_or(true));
+        let mut keep = removed.iter().map(|removed| !removed);
+        states.retain(|_| keep.next().unwrap_or(true));
         changed
     }
 
-    fn collect_used_vars(&self, program: &Program, used_vars: &mut std::collections::HashSet<String>) {
-        match program {
-            Program::Stmts(stmts) => {
-                for stmt in stmts {
-                    self.collect_used_vars_stmt(stmt, used_vars);
-                }
+    // block_refers_to over the statements from `start` on that are kept,
+    // skipping those that never mention `name`
+    fn kept_refer_to(stmts: &[Stmt], states: &[StmtState], removed: &[bool], start: usize, name: &str) -> bool {
+        for (j, stmt) in stmts.iter().enumerate().skip(start) {
+            if removed[j] || !states[j].facts.as_ref().expect("facts").mentions.contains(name) {
+                continue;
+            }
+            if let Stmt::VarDecl { name: declared, init, .. } = stmt
+                && declared == name
+            {
+                return init.as_ref().is_some_and(|init| !matches!(init, Expr::Func { .. }) && Self::expr_refers_to(init, name));
+            }
+            if Self::stmt_refers_to(stmt, name) {
+                return true;
             }
         }
+        false
     }
 
-    fn collect_used_vars_stmt(&self, stmt: &Stmt, used_vars: &mut std::collections::HashSet<String>) {
-        match stmt {
-            Stmt::VarDecl { init, .. } => {
-                self.collect_used_vars_expr(init, used_vars);
-                // we're collecting vars used in init, but the decl itself is being removed if unused
-            }
-            Stmt::Assign { target, value } => {
-                self.collect_used_vars_expr(target, used_vars);
-                self.collect_used_vars_expr(value, used_vars);
+    // kept_refer_to for only_stored_to
+    fn kept_only_stored(&self, stmts: &[Stmt], states: &[StmtState], removed: &[bool], start: usize, name: &str) -> bool {
+        let kept = stmts.iter().enumerate().skip(start)
+            .filter(|(j, _)| !removed[*j] && states[*j].facts.as_ref().expect("facts").mentions.contains(name))
+            .map(|(_, stmt)| stmt);
+        self.only_stored_to(kept, name)
+    }
+
+    // Every block is its own scope: a declaration is unused when nothing after
+    // it in the same block refers to it (inner redeclarations shadow it)
+    fn remove_unused_in_block(&self, stmts: &mut Vec<Stmt>) -> bool {
+        let mut changed = false;
+
+        // Inner scopes first, their removals may free outer variables
+        for stmt in stmts.iter_mut() {
+            changed |= self.remove_unused_inside(stmt);
+        }
+
+        // Backwards, so a chain like `var a := 1  var b := a` goes in one pass
+        for i in (0..stmts.len()).rev() {
+            if let Stmt::VarDecl { name, init, .. } = &stmts[i]
+                && !init.as_ref().is_some_and(|init| expr_may_fail(init, &self.value_types))  // a call may have side effects, and any failure must still happen
+                && !Self::block_refers_to(&stmts[i + 1..], name)
+            {
+                stmts.remove(i);
+                changed = true;
+            } else if let Stmt::VarDecl { name, init, .. } = &stmts[i]
+                && !init.as_ref().is_some_and(|init| expr_may_fail(init, &self.value_types))
+                && self.only_stored_to(&stmts[i + 1..], name)
+            {
+                let name = name.clone();
+                stmts.remove(i);
+                Self::remove_stores(stmts, i, &name);
+                changed = true;
             }
-            Stmt::Print { args } => {
-                for arg in args {
-                    self.collect_used_vars_expr(arg, used_vars);
+        }
+        changed
+    }
+
+    // Whether every use of `name` in `stmts` (as bound just before them) is
+    // an assignment to it that cannot fail, e.g. `total := total + x` into
+    // a total nobody reads: the declaration can go together with the stores
+    fn only_stored_to<'a>(&self, stmts: impl IntoIterator<Item = &'a Stmt>, name: &str) -> bool {
+        for stmt in stmts {
+            let only_stores = match stmt {
+                Stmt::VarDecl { name: declared, init, .. } if declared == name => {
+                    return init.as_ref().is_none_or(|init| matches!(init, Expr::Func { .. }) || !Self::expr_refers_to(init, name));
                 }
-            }
-            Stmt::If { cond, then_branch, else_branch } => {
-                self.collect_used_vars_expr(cond, used_vars);
-                for s in then_branch {
-                    self.collect_used_vars_stmt(s, used_vars);
+                Stmt::Assign { target: Expr::Ident(target), value, .. } if target == name => !expr_may_fail(value, &self.value_types),
+                Stmt::If { cond, then_branch, else_branch, .. } => {
+                    !Self::expr_refers_to(cond, name)
+                        && self.only_stored_to(then_branch, name)
+                        && else_branch.as_ref().is_none_or(|else_branch| self.only_stored_to(else_branch, name))
                 }
-                if let Some(else_branch) = else_branch {
-                    for s in else_branch {
-                        self.collect_used_vars_stmt(s, used_vars);
-                    }
+                Stmt::While { cond, body, .. } | Stmt::RepeatUntil { cond, body, .. } => {
+                    !Self::expr_refers_to(cond, name) && self.only_stored_to(body, name)
+                }
+                Stmt::For { var, iterable, body, .. } => {
+                    !Self::expr_refers_to(iterable, name) && (var.binds(name) || self.only_stored_to(body, name))
                 }
+                _ => !Self::stmt_refers_to(stmt, name),
+            };
+            if !only_stores {
+                return false;
             }
-            Stmt::While { cond, body } => {
-                self.collect_used_vars_expr(cond, used_vars);
-                for s in body {
-                    self.collect_used_vars_stmt(s, used_vars);
+        }
+        true
+    }
+
+    // Removes the stores only_stored_to found, from `start` up to a redeclaration
+    fn remove_stores(stmts: &mut Vec<Stmt>, start: usize, name: &str) {
+        let end = stmts.iter().skip(start)
+            .position(|stmt| matches!(stmt, Stmt::VarDecl { name: declared, .. } if declared == name))
+            .map_or(stmts.len(), |offset| start + offset);
+        let mut index = 0;
+        stmts.retain_mut(|stmt| {
+            let in_range = (start..end).contains(&index);
+            index += 1;
+            !in_range || Self::remove_stores_in(stmt, name)
+        });
+    }
+
+    // Removes the stores to `name` nested in `stmt`; false when `stmt` is one itself
+    fn remove_stores_in(stmt: &mut Stmt, name: &str) -> bool {
+        match stmt {
+            Stmt::Assign { target: Expr::Ident(target), .. } => return target != name,
+            Stmt::If { then_branch, else_branch, .. } => {
+                Self::remove_stores(then_branch, 0, name);
+                if let Some(else_branch) = else_branch {
+                    Self::remove_stores(else_branch, 0, name);
                 }
             }
-            Stmt::For { var, iterable, body } => {
-                used_vars.insert(var.clone());
-                self.collect_used_vars_expr(iterable, used_vars);
-                for s in body {
-                    self.collect_used_vars_stmt(s, used_vars);
+            Stmt::While { body, .. } | Stmt::RepeatUntil { body, .. } => Self::remove_stores(body, 0, name),
+            Stmt::For { var, body, .. } if !var.binds(name) => Self::remove_stores(body, 0, name),
+            _ => {}
+        }
+        true
+    }
+
+    fn remove_unused_inside(&self, stmt: &mut Stmt) -> bool {
+        let mut changed = false;
+        match stmt {
+            Stmt::If { then_branch, else_branch, .. } => {
+                changed |= self.remove_unused_in_block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    changed |= self.remove_unused_in_block(else_branch);
                 }
             }
-            Stmt::Return(Some(expr)) => {
-                self.collect_used_vars_expr(expr, used_vars);
+            Stmt::While { body, .. } | Stmt::RepeatUntil { body, .. } | Stmt::For { body, .. } => {
+                changed |= self.remove_unused_in_block(body);
             }
-            Stmt::Expr(expr) => {
-                self.collect_used_vars_expr(expr, used_vars);
+            Stmt::VarDecl { init: Some(Expr::Func { body: FuncBody::Block(body), .. }), .. } => {
+                changed |= self.remove_unused_in_block(body);
             }
             _ => {}
         }
+        changed
     }
 
-    fn collect_used_vars_expr(&self, expr: &Expr, used_vars: &mut std::collections::HashSet<String>) {
-        match expr {
-            Expr::Ident(name) => {
-                used_vars.insert(name.clone());
+    // Whether `stmts` refer to `name` as it is bound just before them
+    fn block_refers_to(stmts: &[Stmt], name: &str) -> bool {
+        for stmt in stmts {
+            if let Stmt::VarDecl { name: declared, init, .. } = stmt
+                && declared == name
+            {
+                // a function sees its own name (recursion), other initializers the outer binding
+                return init.as_ref().is_some_and(|init| !matches!(init, Expr::Func { .. }) && Self::expr_refers_to(init, name));
             }
-            Expr::Binary { left, right, .. } => {
-                self.collect_used_vars_expr(left, used_vars);
-                self.collect_used_vars_expr(right, used_vars);
+            if Self::stmt_refers_to(stmt, name) {
+                return true;
             }
-            Expr::Unary { expr, .. } => {
-                self.collect_used_vars_expr(expr, used_vars);
+        }
+        false
+    }
+
+    fn stmt_refers_to(stmt: &Stmt, name: &str) -> bool {
+        match stmt {
+            Stmt::VarDecl { init, .. } => init.as_ref().is_some_and(|init| Self::expr_refers_to(init, name)),
+            // assigning counts: the declaration must exist for the assignment to work
+            Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => {
+                Self::expr_refers_to(left, name) || Self::expr_refers_to(right, name)
             }
-            Expr::Call { callee, args } => {
-                self.collect_used_vars_expr(callee, used_vars);
-                for arg in args {
-                    self.collect_used_vars_expr(arg, used_vars);
-                }
+            Stmt::Print { args, .. } => args.iter().any(|arg| Self::expr_refers_to(arg, name)),
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                Self::expr_refers_to(cond, name)
+                    || Self::block_refers_to(then_branch, name)
+                    || else_branch.as_deref().is_some_and(|b| Self::block_refers_to(b, name))
             }
-            Expr::Index { target, index } => {
-                self.collect_used_vars_expr(target, used_vars);
-                self.collect_used_vars_expr(index, used_vars);
+            Stmt::While { cond, body, .. } | Stmt::RepeatUntil { cond, body, .. } => {
+                Self::expr_refers_to(cond, name) || Self::block_refers_to(body, name)
             }
-            Expr::Member { target, .. } => {
-                self.collect_used_vars_expr(target, used_vars);
+            Stmt::For { var, iterable, body, .. } => {
+                Self::expr_refers_to(iterable, name) || (!var.binds(name) && Self::block_refers_to(body, name))
             }
-            Expr::Array(elems) => {
-                for elem in elems {
-                    self.collect_used_vars_expr(elem, used_vars);
-                }
+            Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => Self::expr_refers_to(expr, name),
+            Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => false,
+        }
+    }
+
+    fn expr_refers_to(expr: &Expr, name: &str) -> bool {
+        match expr {
+            Expr::Ident(n) | Expr::Local { name: n, .. } => n == name,
+            Expr::Binary { .. } => {
+                let (first, operations) = binary_chain(expr);
+                Self::expr_refers_to(first, name) || operations.into_iter().any(|(_, right)| Self::expr_refers_to(right, name))
             }
-            Expr::Tuple(elems) => {
-                for elem in elems {
-                    self.collect_used_vars_expr(&elem.value, used_vars);
-                }
+            Expr::Range(left, right) => Self::expr_refers_to(left, name) || Self::expr_refers_to(right, name),
+            Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr) => {
+                Self::expr_refers_to(expr, name)
             }
-            Expr::Range(low, high) => {
-                self.collect_used_vars_expr(low, used_vars);
-                self.collect_used_vars_expr(high, used_vars);
+            Expr::Call { callee, args } => {
+                Self::expr_refers_to(callee, name) || args.iter().any(|arg| Self::expr_refers_to(arg, name))
             }
-            Expr::IsType { expr, .. } => {
-                self.collect_used_vars_expr(expr, used_vars);
+            Expr::Index { target, index } => {
+                Self::expr_refers_to(target, name) || Self::expr_refers_to(index, name)
             }
-            Expr::Func { params: _, body } => {
-                match body {
-                    FuncBody::Expr(expr) => {
-                        self.collect_used_vars_expr(expr, used_vars);
-                    }
-                    FuncBody::Block(stmts) => {
-                        for stmt in stmts {
-                            self.collect_used_vars_stmt(stmt, used_vars);
-                        }
-                    }
+            Expr::Array(elems) => elems.iter().any(|elem| Self::expr_refers_to(elem, name)),
+            Expr::Tuple(elems) => elems.iter().any(|elem| Self::expr_refers_to(&elem.value, name)),
+            Expr::Func { params, body } => {
+                // a parameter of the same name hides the variable
+                !params.iter().any(|p| p == name) && match body {
+                    FuncBody::Expr(expr) => Self::expr_refers_to(expr, name),
+                    FuncBody::Block(stmts) => Self::block_refers_to(stmts, name),
                 }
             }
-            _ => {}
+            Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) => false,
+        }
+    }
+}
+
+// ===
+// part 3: what the optimizer changed, for showing it to a reader
+// ===
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstChangeKind {
+    Folded,    // an expression was simplified
+    Removed,   // a statement was dropped
+    Replaced,  // an `if` gave way to the branch that always runs
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AstChange {
+    pub statement: String,  // position in the original program: "3", "3.2" (nested), "3.else.1"
+    pub kind: AstChangeKind,
+    pub detail: String,
+}
+
+impl std::fmt::Display for AstChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            AstChangeKind::Removed => write!(f, "statement {} removed: {}", self.statement, self.detail),
+            _ => write!(f, "statement {}: {}", self.statement, self.detail),
+        }
+    }
+}
+
+// Changes between a program and its optimized version. Statements are
+// matched by source position, so removed and spliced statements don't
+// throw off the rest; without positions they are matched by index.
+pub fn diff_programs(original: &Program, optimized: &Program) -> Vec<AstChange> {
+    let (Program::Stmts(before), Program::Stmts(after)) = (original, optimized);
+    let mut changes = Vec::new();
+    diff_blocks(before, after, "", &mut changes);
+    changes
+}
+
+fn diff_blocks(before: &[Stmt], after: &[Stmt], prefix: &str, changes: &mut Vec<AstChange>) {
+    let by_index = before.len() == after.len();
+    for (i, stmt) in before.iter().enumerate() {
+        let statement = format!("{}{}", prefix, i + 1);
+        let counterpart = if stmt.span().is_known() {
+            after.iter().find(|s| same_position(stmt, s))
+        } else {
+            after.get(i).filter(|s| by_index && std::mem::discriminant(*s) == std::mem::discriminant(stmt))
+        };
+        match counterpart {
+            Some(new) => diff_stmts(stmt, new, &statement, changes),
+            None => diff_removed(stmt, after, &statement, changes),
+        }
+    }
+}
+
+// The same statement by NodeId when both have one, else by position
+fn same_position(a: &Stmt, b: &Stmt) -> bool {
+    let (sa, sb) = (a.span(), b.span());
+    let same = if sa.id != 0 && sb.id != 0 { sa.id == sb.id } else { sa.line == sb.line && sa.col == sb.col };
+    same && std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+fn diff_stmts(old: &Stmt, new: &Stmt, statement: &str, changes: &mut Vec<AstChange>) {
+    let nested = format!("{}.", statement);
+    match (old, new) {
+        (Stmt::VarDecl { init: Some(Expr::Func { body: old_body, .. }), .. }, Stmt::VarDecl { init: Some(Expr::Func { body: new_body, .. }), .. }) => {
+            match (old_body, new_body) {
+                (FuncBody::Block(a), FuncBody::Block(b)) => diff_blocks(a, b, &nested, changes),
+                (FuncBody::Expr(a), FuncBody::Expr(b)) => diff_exprs(a, b, statement, changes),
+                _ => {}
+            }
+        }
+        (Stmt::VarDecl { init: Some(a), .. }, Stmt::VarDecl { init: Some(b), .. })
+        | (Stmt::Assign { value: a, .. }, Stmt::Assign { value: b, .. })
+        | (Stmt::Return(Some(a), _), Stmt::Return(Some(b), _))
+        | (Stmt::Expr(a, _), Stmt::Expr(b, _)) => diff_exprs(a, b, statement, changes),
+        (Stmt::Print { args: a, .. }, Stmt::Print { args: b, .. }) if a.len() == b.len() => {
+            for (a, b) in a.iter().zip(b) {
+                diff_exprs(a, b, statement, changes);
+            }
+        }
+        (Stmt::If { cond: a, then_branch: then_a, else_branch: else_a, .. },
+         Stmt::If { cond: b, then_branch: then_b, else_branch: else_b, .. }) => {
+            diff_exprs(a, b, statement, changes);
+            diff_blocks(then_a, then_b, &nested, changes);
+            if let (Some(else_a), Some(else_b)) = (else_a, else_b) {
+                diff_blocks(else_a, else_b, &format!("{}.else.", statement), changes);
+            }
+        }
+        (Stmt::While { cond: a, body: body_a, .. }, Stmt::While { cond: b, body: body_b, .. })
+        | (Stmt::RepeatUntil { cond: a, body: body_a, .. }, Stmt::RepeatUntil { cond: b, body: body_b, .. })
+        | (Stmt::For { iterable: a, body: body_a, .. }, Stmt::For { iterable: b, body: body_b, .. }) => {
+            diff_exprs(a, b, statement, changes);
+            diff_blocks(body_a, body_b, &nested, changes);
+        }
+        _ => {}
+    }
+}
+
+fn diff_exprs(old: &Expr, new: &Expr, statement: &str, changes: &mut Vec<AstChange>) {
+    if old == new {
+        return;
+    }
+    let verb = if Optimizer::new().is_constant_expr(new) { "folded" } else { "rewritten" };
+    changes.push(AstChange {
+        statement: statement.to_string(),
+        kind: AstChangeKind::Folded,
+        detail: format!("`{}` {} to `{}`", old, verb, new),
+    });
+}
+
+// `old` has no counterpart: it was dropped, or it is an `if` whose live
+// branch was spliced in its place
+fn diff_removed(old: &Stmt, after: &[Stmt], statement: &str, changes: &mut Vec<AstChange>) {
+    if let Stmt::If { cond, then_branch, else_branch, .. } = old {
+        let spliced = |branch: &[Stmt]| branch.iter().any(|s| survives(s, after));
+        let live = if spliced(then_branch) {
+            Some(("then", then_branch, format!("{}.", statement)))
+        } else {
+            else_branch.as_ref()
+                .filter(|branch| spliced(branch))
+                .map(|branch| ("else", branch, format!("{}.else.", statement)))
+        };
+        if let Some((name, branch, prefix)) = live {
+            changes.push(AstChange {
+                statement: statement.to_string(),
+                kind: AstChangeKind::Replaced,
+                detail: format!("`if {}` replaced by its {} branch", cond, name),
+            });
+            diff_blocks(branch, after, &prefix, changes);
+            return;
+        }
+    }
+    let detail = match old {
+        Stmt::VarDecl { name, .. } => format!("unused variable '{}'", name),
+        Stmt::If { cond, .. } => format!("dead branch `if {}`", cond),
+        stmt => format!("unreachable {}", describe_stmt(stmt)),
+    };
+    changes.push(AstChange { statement: statement.to_string(), kind: AstChangeKind::Removed, detail });
+}
+
+// Whether `stmt`, or for an `if` what it was simplified to, is in `after`
+fn survives(stmt: &Stmt, after: &[Stmt]) -> bool {
+    if after.iter().any(|a| same_position(stmt, a)) {
+        return true;
+    }
+    match stmt {
+        Stmt::If { then_branch, else_branch, .. } => {
+            then_branch.iter().chain(else_branch.iter().flatten()).any(|s| survives(s, after))
+        }
+        _ => false,
+    }
+}
+
+// First line of a statement as written, for change records
+fn describe_stmt(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::VarDecl { name, init: Some(init), .. } => format!("`var {} := {}`", name, init),
+        Stmt::VarDecl { name, init: None, .. } => format!("`var {}`", name),
+        Stmt::Assign { target, value, .. } => format!("`{} := {}`", target, value),
+        Stmt::Swap { left, right, .. } => format!("`swap {}, {}`", left, right),
+        Stmt::Print { args, .. } => {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            format!("`print {}`", args.join(", "))
         }
+        Stmt::If { cond, .. } => format!("`if {}`", cond),
+        Stmt::While { cond, .. } => format!("`while {}`", cond),
+        Stmt::RepeatUntil { cond, .. } => format!("`repeat ... until {}`", cond),
+        Stmt::For { var, iterable, .. } => format!("`for {} in {}`", var, iterable),
+        Stmt::Return(Some(expr), _) => format!("`return {}`", expr),
+        Stmt::Return(None, _) => "`return`".to_string(),
+        Stmt::Exit(None, _) => "`exit`".to_string(),
+        Stmt::Exit(Some(label), _) => format!("`exit {}`", label),
+        Stmt::Expr(expr, _) => format!("`{}`", expr),
+        Stmt::Include(path, _) => format!("`include \"{}\"`", path),
     }
 }
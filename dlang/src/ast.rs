@@ -1,21 +1,139 @@
+use crate::diagnostics::messages;
 use crate::token::Token;
 
+pub mod arena;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Program {
     Stmts(Vec<Stmt>),
 }
 
+// Identity of a statement, unique within a parsed program. Passes that
+// move statements keep it, so it still names the statement once the
+// optimizer has spliced or removed the ones around it, and a statement the
+// optimizer makes gets one past the program's largest; 0 means none.
+// Expressions have no id: they are found through their statement.
+pub type NodeId = u32;
+
+// Source position of a statement (1-based; 0 means unknown, e.g. built by
+// hand) and its NodeId
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub id: NodeId,
+}
+
+impl Span {
+    pub fn new(line: usize, col: usize) -> Self {
+        Self { line, col, id: 0 }
+    }
+
+    pub fn with_id(self, id: NodeId) -> Self {
+        Self { id, ..self }
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
-    VarDecl { name: String, init: Expr },
-    Assign { target: Expr, value: Expr },
-    Print { args: Vec<Expr> },
-    If { cond: Expr, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>> },
-    While { cond: Expr, body: Vec<Stmt> },
-    For { var: String, iterable: Expr, body: Vec<Stmt> },
-    Return(Option<Expr>),
-    Exit,
-    Expr(Expr),
+    VarDecl { name: String, init: Option<Expr>, span: Span },  // `var x` has no init
+    Assign { target: Expr, value: Expr, span: Span },
+    Swap { left: Expr, right: Expr, span: Span },  // `swap a, b` exchanges the values of two assignable targets
+    Print { args: Vec<Expr>, span: Span },
+    If { cond: Expr, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>>, span: Span },
+    While { label: Option<String>, cond: Expr, body: Vec<Stmt>, span: Span },
+    RepeatUntil { body: Vec<Stmt>, cond: Expr, span: Span },  // runs the body, then stops once `cond` holds
+    For { label: Option<String>, var: LoopPattern, iterable: Expr, body: Vec<Stmt>, span: Span },
+    Return(Option<Expr>, Span),
+    Exit(Option<String>, Span),  // `exit outer` leaves the enclosing loop labelled `outer:`
+    Expr(Expr, Span),
+    Include(String, Span),  // spliced away by the loader before analysis
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::VarDecl { span, .. }
+            | Stmt::Assign { span, .. }
+            | Stmt::Swap { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::RepeatUntil { span, .. }
+            | Stmt::For { span, .. }
+            | Stmt::Return(_, span)
+            | Stmt::Exit(_, span)
+            | Stmt::Expr(_, span)
+            | Stmt::Include(_, span) => *span,
+        }
+    }
+
+    // A copy with every span cleared, so that `==` compares only the code:
+    // the same statement parsed at another position or given another id
+    // compares equal
+    pub fn without_spans(&self) -> Stmt {
+        let mut stmt = self.clone();
+        stmt.for_each_span_mut(&mut |span| *span = Span::default());
+        stmt
+    }
+}
+
+impl Program {
+    pub fn without_spans(&self) -> Program {
+        let Program::Stmts(stmts) = self;
+        Program::Stmts(stmts.iter().map(Stmt::without_spans).collect())
+    }
+}
+
+// What a `for` loop binds each element to
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoopPattern {
+    Name(String),               // `for p in ...`; `_` when the loop has no variable
+    Tuple(Vec<PatternField>),   // `for {x, y} in ...`, unpacking tuple elements
+}
+
+// `x` binds field x to x; `first := 1` binds the element's first position to first
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternField {
+    pub name: String,
+    pub field: String,  // a field name or a 1-based position, like the keys of a tuple value
+}
+
+impl LoopPattern {
+    // The bound names in binding order (the order the resolver numbers their slots)
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            LoopPattern::Name(name) => vec![name.as_str()],
+            LoopPattern::Tuple(fields) => fields.iter().map(|field| field.name.as_str()).collect(),
+        }
+    }
+
+    pub fn binds(&self, name: &str) -> bool {
+        self.names().contains(&name)
+    }
+
+    // The variable of a loop written without one
+    pub fn is_placeholder(&self) -> bool {
+        matches!(self, LoopPattern::Name(name) if name == "_")
+    }
+}
+
+impl std::fmt::Display for LoopPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopPattern::Name(name) => write!(f, "{}", name),
+            LoopPattern::Tuple(fields) => {
+                let fields: Vec<String> = fields.iter().map(|field| {
+                    if field.name == field.field { field.name.clone() } else { format!("{} := {}", field.name, field.field) }
+                }).collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,7 +148,8 @@ pub enum TypeIndicator {
     Func,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+// Clone, PartialEq and Drop are written out below so that long chains do not recurse
+#[derive(Debug)]
 pub enum Expr {
     Integer(i64),
     Real(f64),
@@ -38,6 +157,7 @@ pub enum Expr {
     None,
     String(String),
     Ident(String),
+    Local { name: String, depth: usize, slot: usize },  // Ident bound by the resolver (depth = scopes up)
     Range(Box<Expr>, Box<Expr>),
     Binary { left: Box<Expr>, op: BinOp, right: Box<Expr> },
     Unary { op: UnOp, expr: Box<Expr> },
@@ -46,6 +166,7 @@ pub enum Expr {
     Member { target: Box<Expr>, field: String },
     Array(Vec<Expr>),
     Tuple(Vec<TupleElement>),
+    Spread(Box<Expr>),  // `...xs`, only as an element of an array or tuple literal
     IsType { expr: Box<Expr>, type_ind: TypeIndicator },
     Func { params: Vec<String>, body: FuncBody },
 }
@@ -59,8 +180,11 @@ pub enum FuncBody {
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinOp {
     Add, Sub, Mul, Div,
+    IntDiv,  // `a div b`: truncating division, integers only
     Eq, Ne, Lt, Le, Gt, Ge,
-    And, Or, Xor,
+    Shl, Shr,  // `<<` and `>>` on integers
+    And, Or, Xor,  // bitwise on two integers, logical otherwise
+    OrElse, AndThen,  // short-circuit, giving one of the operands rather than a bool
     Is,
 }
 
@@ -68,6 +192,7 @@ pub enum BinOp {
 pub enum UnOp {
     Neg,
     Not,
+    Plus,  // `+x`: numbers only, which it leaves unchanged
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -76,7 +201,656 @@ pub struct TupleElement {
     pub value: Expr,
 }
 
-// Simple helper for pretty printing tokens in errors
+impl BinOp {
+    // Binding strength as in the grammar (higher binds tighter)
+    fn precedence(&self) -> u8 {
+        match self {
+            BinOp::And | BinOp::Or | BinOp::Xor | BinOp::OrElse | BinOp::AndThen => 1,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Is => 2,
+            BinOp::Shl | BinOp::Shr => 3,
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Mul | BinOp::Div | BinOp::IntDiv => 5,
+        }
+    }
+
+    fn is_comparison(&self) -> bool {
+        self.precedence() == 2
+    }
+}
+
+// Expressions print back as dlang source, e.g. for diagnostics and AST diffs;
+// function bodies are abbreviated to `is ... end` (see pretty_print for all of it)
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source(None))
+    }
+}
+
+impl Expr {
+    // Source for this expression; with `depth` function bodies are printed in
+    // full, their statements indented one level deeper than `depth`
+    fn source(&self, depth: Option<usize>) -> String {
+        let show = |e: &Expr| e.source(depth);
+        // operands of unary and postfix operators get parentheses unless atomic
+        let operand = |e: &Expr| match e {
+            Expr::Binary { .. } | Expr::Range(..) | Expr::IsType { .. } | Expr::Unary { .. } | Expr::Func { .. } => {
+                format!("({})", show(e))
+            }
+            // `t.1.2` would lex as `t` `.` `1.2`
+            Expr::Member { field, .. } if field.starts_with(|c: char| c.is_ascii_digit()) => format!("({})", show(e)),
+            _ => show(e),
+        };
+        // binary operands only when they bind looser; operators are
+        // left-associative, except comparisons, which do not chain
+        let side = |e: &Expr, op: &BinOp, right: bool| match e {
+            Expr::Binary { op: inner, .. }
+                if inner.precedence() > op.precedence()
+                    || (!right && inner.precedence() == op.precedence() && !op.is_comparison()) =>
+            {
+                show(e)
+            }
+            Expr::Unary { .. } | Expr::IsType { .. } => show(e),
+            _ => operand(e),
+        };
+        let list = |items: &[Expr]| items.iter().map(show).collect::<Vec<_>>().join(", ");
+        match self {
+            Expr::Integer(n) => n.to_string(),
+            Expr::Real(n) => format!("{:?}", n),
+            Expr::Bool(b) => b.to_string(),
+            Expr::None => "none".to_string(),
+            // there are no escapes, so a string holding `"` is quoted with `'`
+            Expr::String(s) if s.contains('"') => format!("'{}'", s),
+            Expr::String(s) => format!("\"{}\"", s),
+            Expr::Ident(name) | Expr::Local { name, .. } => name.clone(),
+            Expr::Range(low, high) => format!("{}..{}", operand(low), operand(high)),
+            Expr::Binary { .. } => {
+                // from the left of the chain with a loop, however long it is
+                let mut operations = Vec::new();
+                let mut node = self;
+                while let Expr::Binary { left, op, right } = node {
+                    operations.push((op, right.as_ref()));
+                    node = left;
+                }
+                let mut text = String::new();
+                let mut inner: Option<&BinOp> = None;
+                for (op, right) in operations.into_iter().rev() {
+                    match inner {
+                        None => text = side(node, op, false),
+                        Some(inner) if inner.precedence() > op.precedence()
+                            || (inner.precedence() == op.precedence() && !op.is_comparison()) => {}
+                        Some(_) => {
+                            text.insert(0, '(');
+                            text.push(')');
+                        }
+                    }
+                    text.push_str(&format!(" {} {}", op, side(right, op, true)));
+                    inner = Some(op);
+                }
+                text
+            }
+            Expr::Unary { op: UnOp::Neg, expr } => format!("-{}", operand(expr)),
+            Expr::Unary { op: UnOp::Not, expr } => format!("not {}", operand(expr)),
+            Expr::Unary { op: UnOp::Plus, expr } => format!("+{}", operand(expr)),
+            Expr::Call { callee, args } => format!("{}({})", operand(callee), list(args)),
+            Expr::Index { target, index } => format!("{}[{}]", operand(target), show(index)),
+            Expr::Member { target, field } => format!("{}.{}", operand(target), field),
+            Expr::Array(elems) => format!("[{}]", list(elems)),
+            Expr::Tuple(elems) => {
+                let fields: Vec<String> = elems.iter()
+                    .map(|elem| match &elem.name {
+                        Some(name) => format!("{} := {}", name, show(&elem.value)),
+                        None => show(&elem.value),
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            Expr::Spread(expr) => format!("...{}", operand(expr)),
+            Expr::IsType { expr, type_ind } => format!("{} is {}", operand(expr), type_ind),
+            Expr::Func { params, body: FuncBody::Expr(body) } => format!("func({}) => {}", params.join(", "), show(body)),
+            Expr::Func { params, body: FuncBody::Block(stmts) } => match depth {
+                Some(depth) => format!("func({}) is\n{}{}end", params.join(", "), block_source(stmts, depth + 1), indent(depth)),
+                None => format!("func({}) is ... end", params.join(", ")),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::IntDiv => "div",
+            BinOp::Eq => "=",
+            BinOp::Ne => "/=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Xor => "xor",
+            BinOp::OrElse => "orelse",
+            BinOp::AndThen => "andthen",
+            BinOp::Is => "is",
+        };
+        f.write_str(op)
+    }
+}
+
+impl std::fmt::Display for TypeIndicator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TypeIndicator::Int => "int",
+            TypeIndicator::Real => "real",
+            TypeIndicator::Bool => "bool",
+            TypeIndicator::String => "string",
+            TypeIndicator::None => "none",
+            TypeIndicator::Array => "[]",
+            TypeIndicator::Tuple => "{}",
+            TypeIndicator::Func => "func",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Program {
+    // The statement with this id at any depth, function bodies included
+    pub fn find_by_id(&self, id: NodeId) -> Option<&Stmt> {
+        if id == 0 {
+            return None;
+        }
+        self.statements().into_iter().find(|stmt| stmt.span().id == id)
+    }
+
+    // The largest id of any statement, 0 when none has one
+    pub fn max_id(&self) -> NodeId {
+        self.statements().into_iter().map(|stmt| stmt.span().id).max().unwrap_or(0)
+    }
+
+    // Every statement at any depth, function bodies included
+    fn statements(&self) -> Vec<&Stmt> {
+        let Program::Stmts(stmts) = self;
+        let mut all = Vec::new();
+        let mut stmts: Vec<&Stmt> = stmts.iter().collect();
+        let mut exprs: Vec<&Expr> = Vec::new();
+        loop {
+            if let Some(stmt) = stmts.pop() {
+                all.push(stmt);
+                match stmt {
+                    Stmt::VarDecl { init: Some(expr), .. } | Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => exprs.push(expr),
+                    Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => exprs.extend([left, right]),
+                    Stmt::Print { args, .. } => exprs.extend(args),
+                    Stmt::If { cond, then_branch, else_branch, .. } => {
+                        exprs.push(cond);
+                        stmts.extend(then_branch.iter().chain(else_branch.iter().flatten()));
+                    }
+                    Stmt::While { cond: expr, body, .. } | Stmt::RepeatUntil { cond: expr, body, .. } | Stmt::For { iterable: expr, body, .. } => {
+                        exprs.push(expr);
+                        stmts.extend(body);
+                    }
+                    Stmt::VarDecl { init: None, .. } | Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => {}
+                }
+            } else if let Some(expr) = exprs.pop() {
+                match expr {
+                    Expr::Range(left, right) | Expr::Binary { left, right, .. } | Expr::Index { target: left, index: right } => {
+                        exprs.extend([left.as_ref(), right.as_ref()]);
+                    }
+                    Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr)
+                    | Expr::Func { body: FuncBody::Expr(expr), .. } => exprs.push(expr),
+                    Expr::Call { callee, args } => exprs.extend(std::iter::once(callee.as_ref()).chain(args)),
+                    Expr::Array(elems) => exprs.extend(elems),
+                    Expr::Tuple(elems) => exprs.extend(elems.iter().map(|elem| &elem.value)),
+                    Expr::Func { body: FuncBody::Block(body), .. } => stmts.extend(body),
+                    Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) | Expr::Ident(_)
+                    | Expr::Local { .. } => {}
+                }
+            } else {
+                return all;
+            }
+        }
+    }
+}
+
+impl Stmt {
+    // Calls `f` on the span of this statement and of every statement nested
+    // in it, function bodies included
+    pub fn for_each_span_mut(&mut self, f: &mut dyn FnMut(&mut Span)) {
+        let mut stmts: Vec<&mut Stmt> = vec![self];
+        let mut exprs: Vec<&mut Expr> = Vec::new();
+        loop {
+            if let Some(stmt) = stmts.pop() {
+                match stmt {
+                    Stmt::VarDecl { init: Some(expr), span, .. } | Stmt::Return(Some(expr), span) | Stmt::Expr(expr, span) => {
+                        f(span);
+                        exprs.push(expr);
+                    }
+                    Stmt::Assign { target: left, value: right, span } | Stmt::Swap { left, right, span } => {
+                        f(span);
+                        exprs.extend([left, right]);
+                    }
+                    Stmt::Print { args, span } => {
+                        f(span);
+                        exprs.extend(args);
+                    }
+                    Stmt::If { cond, then_branch, else_branch, span } => {
+                        f(span);
+                        exprs.push(cond);
+                        stmts.extend(then_branch.iter_mut().chain(else_branch.iter_mut().flatten()));
+                    }
+                    Stmt::While { cond: expr, body, span, .. } | Stmt::RepeatUntil { cond: expr, body, span }
+                    | Stmt::For { iterable: expr, body, span, .. } => {
+                        f(span);
+                        exprs.push(expr);
+                        stmts.extend(body);
+                    }
+                    Stmt::VarDecl { init: None, span, .. } | Stmt::Return(None, span) | Stmt::Exit(_, span) | Stmt::Include(_, span) => f(span),
+                }
+            } else if let Some(expr) = exprs.pop() {
+                match expr {
+                    Expr::Range(left, right) | Expr::Binary { left, right, .. } | Expr::Index { target: left, index: right } => {
+                        exprs.extend([left.as_mut(), right.as_mut()]);
+                    }
+                    Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr)
+                    | Expr::Func { body: FuncBody::Expr(expr), .. } => exprs.push(expr),
+                    Expr::Call { callee, args } => exprs.extend(std::iter::once(callee.as_mut()).chain(args)),
+                    Expr::Array(elems) => exprs.extend(elems),
+                    Expr::Tuple(elems) => exprs.extend(elems.iter_mut().map(|elem| &mut elem.value)),
+                    Expr::Func { body: FuncBody::Block(body), .. } => stmts.extend(body),
+                    Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) | Expr::Ident(_)
+                    | Expr::Local { .. } => {}
+                }
+            } else {
+                return;
+            }
+        }
+    }
+}
+
+// Structural size: one per node plus the bytes of every string literal,
+// so duplicated constants show up
+impl Program {
+    pub fn size(&self) -> usize {
+        let Program::Stmts(stmts) = self;
+        stmts.iter().map(Stmt::size).sum()
+    }
+}
+
+impl Stmt {
+    pub fn size(&self) -> usize {
+        let block = |stmts: &[Stmt]| stmts.iter().map(Stmt::size).sum::<usize>();
+        1 + match self {
+            Stmt::VarDecl { init: Some(expr), .. } | Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => expr.size(),
+            Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => left.size() + right.size(),
+            Stmt::Print { args, .. } => args.iter().map(Expr::size).sum(),
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                cond.size() + block(then_branch) + else_branch.as_deref().map_or(0, block)
+            }
+            Stmt::While { cond: expr, body, .. } | Stmt::RepeatUntil { cond: expr, body, .. } | Stmt::For { iterable: expr, body, .. } => {
+                expr.size() + block(body)
+            }
+            Stmt::VarDecl { init: None, .. } | Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => 0,
+        }
+    }
+}
+
+impl Expr {
+    pub fn size(&self) -> usize {
+        let list = |exprs: &[Expr]| exprs.iter().map(Expr::size).sum::<usize>();
+        1 + match self {
+            Expr::String(s) => s.len(),
+            Expr::Range(left, right) | Expr::Binary { left, right, .. } | Expr::Index { target: left, index: right } => {
+                left.size() + right.size()
+            }
+            Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr) => expr.size(),
+            Expr::Call { callee, args } => callee.size() + list(args),
+            Expr::Array(elems) => list(elems),
+            Expr::Tuple(elems) => elems.iter().map(|elem| elem.value.size()).sum(),
+            Expr::Func { body: FuncBody::Expr(expr), .. } => expr.size(),
+            Expr::Func { body: FuncBody::Block(stmts), .. } => stmts.iter().map(Stmt::size).sum(),
+            Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::Ident(_) | Expr::Local { .. } => 0,
+        }
+    }
+}
+
+impl Expr {
+    // What `swap` can store into, as the interpreter assigns: a variable, or
+    // an element or field of one
+    pub fn is_assignable(&self) -> bool {
+        let variable = |expr: &Expr| matches!(expr, Expr::Ident(_) | Expr::Local { .. });
+        match self {
+            Expr::Index { target, .. } | Expr::Member { target, .. } => variable(target),
+            expr => variable(expr),
+        }
+    }
+}
+
+// ====
+// Deep trees: the parser builds `1 + 1 + ... + 1` as a left-leaning tree as
+// deep as the chain is long, so copying one walks the chain with a loop, and
+// dropping any tree keeps its own list of nodes instead of recursing
+// ====
+
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        let mut operations = Vec::new();
+        let mut node = self;
+        while let Expr::Binary { left, op, right } = node {
+            operations.push((op, right));
+            node = left;
+        }
+        let mut copy = node.clone_node();
+        for (op, right) in operations.into_iter().rev() {
+            copy = Expr::Binary { left: Box::new(copy), op: op.clone(), right: right.clone() };
+        }
+        copy
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut a, mut b) = (self, other);
+        while let (Expr::Binary { left: left_a, op: op_a, right: right_a }, Expr::Binary { left: left_b, op: op_b, right: right_b }) = (a, b) {
+            if op_a != op_b || right_a != right_b {
+                return false;
+            }
+            a = left_a;
+            b = left_b;
+        }
+        a.eq_node(b)
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_operands(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.take_operands(&mut pending);
+        }
+    }
+}
+
+impl Expr {
+    // Moves the expression out, leaving `none` in its place
+    pub fn take(&mut self) -> Expr {
+        std::mem::replace(self, Expr::None)
+    }
+
+    // A copy of this node; its operands are copied by Clone
+    fn clone_node(&self) -> Expr {
+        match self {
+            Expr::Integer(n) => Expr::Integer(*n),
+            Expr::Real(n) => Expr::Real(*n),
+            Expr::Bool(b) => Expr::Bool(*b),
+            Expr::None => Expr::None,
+            Expr::String(s) => Expr::String(s.clone()),
+            Expr::Ident(name) => Expr::Ident(name.clone()),
+            Expr::Local { name, depth, slot } => Expr::Local { name: name.clone(), depth: *depth, slot: *slot },
+            Expr::Range(low, high) => Expr::Range(low.clone(), high.clone()),
+            Expr::Binary { left, op, right } => Expr::Binary { left: left.clone(), op: op.clone(), right: right.clone() },
+            Expr::Unary { op, expr } => Expr::Unary { op: op.clone(), expr: expr.clone() },
+            Expr::Call { callee, args } => Expr::Call { callee: callee.clone(), args: args.clone() },
+            Expr::Index { target, index } => Expr::Index { target: target.clone(), index: index.clone() },
+            Expr::Member { target, field } => Expr::Member { target: target.clone(), field: field.clone() },
+            Expr::Array(elems) => Expr::Array(elems.clone()),
+            Expr::Tuple(elems) => Expr::Tuple(elems.clone()),
+            Expr::Spread(expr) => Expr::Spread(expr.clone()),
+            Expr::IsType { expr, type_ind } => Expr::IsType { expr: expr.clone(), type_ind: type_ind.clone() },
+            Expr::Func { params, body } => Expr::Func { params: params.clone(), body: body.clone() },
+        }
+    }
+
+    // Whether the two nodes are equal, for any but two binary operations;
+    // their operands are compared by PartialEq
+    fn eq_node(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::Integer(a), Expr::Integer(b)) => a == b,
+            (Expr::Real(a), Expr::Real(b)) => a == b,
+            (Expr::Bool(a), Expr::Bool(b)) => a == b,
+            (Expr::None, Expr::None) => true,
+            (Expr::String(a), Expr::String(b)) | (Expr::Ident(a), Expr::Ident(b)) => a == b,
+            (Expr::Local { name: name_a, depth: depth_a, slot: slot_a }, Expr::Local { name: name_b, depth: depth_b, slot: slot_b }) => {
+                name_a == name_b && depth_a == depth_b && slot_a == slot_b
+            }
+            (Expr::Range(low_a, high_a), Expr::Range(low_b, high_b)) => low_a == low_b && high_a == high_b,
+            (Expr::Unary { op: op_a, expr: a }, Expr::Unary { op: op_b, expr: b }) => op_a == op_b && a == b,
+            (Expr::Call { callee: callee_a, args: args_a }, Expr::Call { callee: callee_b, args: args_b }) => {
+                callee_a == callee_b && args_a == args_b
+            }
+            (Expr::Index { target: target_a, index: index_a }, Expr::Index { target: target_b, index: index_b }) => {
+                target_a == target_b && index_a == index_b
+            }
+            (Expr::Member { target: target_a, field: field_a }, Expr::Member { target: target_b, field: field_b }) => {
+                target_a == target_b && field_a == field_b
+            }
+            (Expr::Array(a), Expr::Array(b)) => a == b,
+            (Expr::Tuple(a), Expr::Tuple(b)) => a == b,
+            (Expr::Spread(a), Expr::Spread(b)) => a == b,
+            (Expr::IsType { expr: a, type_ind: type_a }, Expr::IsType { expr: b, type_ind: type_b }) => a == b && type_a == type_b,
+            (Expr::Func { params: params_a, body: body_a }, Expr::Func { params: params_b, body: body_b }) => {
+                params_a == params_b && body_a == body_b
+            }
+            _ => false,
+        }
+    }
+
+    // Moves the operands that have operands of their own to `pending`, so
+    // they are dropped one at a time
+    fn take_operands(&mut self, pending: &mut Vec<Expr>) {
+        let mut take = |expr: &mut Expr| {
+            if !matches!(expr, Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) | Expr::Ident(_) | Expr::Local { .. }) {
+                pending.push(expr.take());
+            }
+        };
+        match self {
+            Expr::Range(left, right) | Expr::Binary { left, right, .. } | Expr::Index { target: left, index: right } => {
+                take(left);
+                take(right);
+            }
+            Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr)
+            | Expr::Func { body: FuncBody::Expr(expr), .. } => take(expr),
+            Expr::Call { callee, args } => {
+                take(callee);
+                args.iter_mut().for_each(take);
+            }
+            Expr::Array(elems) => elems.iter_mut().for_each(take),
+            Expr::Tuple(elems) => elems.iter_mut().for_each(|elem| take(&mut elem.value)),
+            Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) | Expr::Ident(_) | Expr::Local { .. }
+            | Expr::Func { body: FuncBody::Block(_), .. } => {}
+        }
+    }
+}
+
+// ====
+// Pretty printer: a whole program back to source that parses to the same
+// AST (spans aside), one statement per line and blocks indented
+// ====
+
+pub fn pretty_print(program: &Program) -> String {
+    let Program::Stmts(stmts) = program;
+    block_source(stmts, 0)
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+fn block_source(stmts: &[Stmt], depth: usize) -> String {
+    stmts.iter().map(|stmt| format!("{}{}\n", indent(depth), stmt_source(stmt, depth))).collect()
+}
+
+fn label_source(label: &Option<String>) -> String {
+    label.as_ref().map(|label| format!("{}: ", label)).unwrap_or_default()
+}
+
+fn stmt_source(stmt: &Stmt, depth: usize) -> String {
+    let expr = |e: &Expr| e.source(Some(depth));
+    let block = |stmts: &[Stmt]| block_source(stmts, depth + 1);
+    let end = indent(depth);
+    match stmt {
+        Stmt::VarDecl { name, init: Some(init), .. } => format!("var {} := {}", name, expr(init)),
+        Stmt::VarDecl { name, init: None, .. } => format!("var {}", name),
+        Stmt::Assign { target, value, .. } => format!("{} := {}", expr(target), expr(value)),
+        Stmt::Swap { left, right, .. } => format!("swap {}, {}", expr(left), expr(right)),
+        Stmt::Print { args, .. } if args.is_empty() => "print".to_string(),
+        Stmt::Print { args, .. } => format!("print {}", args.iter().map(expr).collect::<Vec<_>>().join(", ")),
+        Stmt::If { cond, then_branch, else_branch: None, .. } => {
+            format!("if {} then\n{}{}end", expr(cond), block(then_branch), end)
+        }
+        Stmt::If { cond, then_branch, else_branch: Some(else_branch), .. } => {
+            format!("if {} then\n{}{}else\n{}{}end", expr(cond), block(then_branch), end, block(else_branch), end)
+        }
+        Stmt::While { label, cond, body, .. } => {
+            format!("{}while {} loop\n{}{}end", label_source(label), expr(cond), block(body), end)
+        }
+        Stmt::RepeatUntil { body, cond, .. } => format!("repeat\n{}{}until {}", block(body), end, expr(cond)),
+        // `_` is what the parser names the variable of a loop without one
+        Stmt::For { label, var, iterable: Expr::None, body, .. } if var.is_placeholder() => {
+            format!("{}for loop\n{}{}end", label_source(label), block(body), end)
+        }
+        Stmt::For { label, var, iterable, body, .. } if var.is_placeholder() => {
+            format!("{}for {} loop\n{}{}end", label_source(label), expr(iterable), block(body), end)
+        }
+        Stmt::For { label, var, iterable, body, .. } => {
+            format!("{}for {} in {} loop\n{}{}end", label_source(label), var, expr(iterable), block(body), end)
+        }
+        Stmt::Return(None, _) => "return".to_string(),
+        Stmt::Return(Some(value), _) => format!("return {}", expr(value)),
+        Stmt::Exit(None, _) => "exit".to_string(),
+        Stmt::Exit(Some(label), _) => format!("exit {}", label),
+        Stmt::Include(path, _) => format!("include {}", Expr::String(path.clone())),
+        Stmt::Expr(e, _) => expr(e),
+    }
+}
+
+// ====
+// Summary: an outline of a program for logs, one line per statement with
+// its expressions cut down to their kind, e.g. `Binary(Add)`. Blocks
+// `max_depth` levels down are not shown; their statements are counted on
+// the line of the statement holding them instead.
+// ====
+
+impl Program {
+    pub fn summary(&self, max_depth: usize) -> String {
+        let Program::Stmts(stmts) = self;
+        let mut out = String::new();
+        if max_depth == 0 {
+            out.push_str(&format!("[{}]\n", count(stmts.len(), "statement")));
+        } else {
+            summarize_block(stmts, 0, max_depth, &mut out);
+        }
+        out
+    }
+}
+
+fn count(n: usize, what: &str) -> String {
+    format!("{} {}{}", n, what, if n == 1 { "" } else { "s" })
+}
+
+fn summarize_block(stmts: &[Stmt], depth: usize, max_depth: usize, out: &mut String) {
+    for stmt in stmts {
+        let (mut line, blocks) = summarize_stmt(stmt);
+        let nested: usize = blocks.iter().map(|(_, block)| block.len()).sum();
+        if depth + 1 < max_depth {
+            out.push_str(&format!("{}{}\n", indent(depth), line));
+            for (heading, block) in blocks {
+                if let Some(heading) = heading {
+                    out.push_str(&format!("{}{}\n", indent(depth), heading));
+                }
+                summarize_block(block, depth + 1, max_depth, out);
+            }
+        } else {
+            if nested > 0 {
+                line.push_str(&format!(" [{}]", count(nested, "statement")));
+            }
+            out.push_str(&format!("{}{}\n", indent(depth), line));
+        }
+    }
+}
+
+// A nested block, under an optional heading line such as "Else"
+type SummaryBlock<'a> = (Option<&'static str>, &'a [Stmt]);
+
+// The statement's line and its blocks
+fn summarize_stmt(stmt: &Stmt) -> (String, Vec<SummaryBlock<'_>>) {
+    fn function_body(expr: &Expr) -> Option<&[Stmt]> {
+        match expr {
+            Expr::Func { body: FuncBody::Block(body), .. } => Some(body),
+            _ => None,
+        }
+    }
+    let label = |label: &Option<String>| label.as_ref().map(|l| format!(" {}:", l)).unwrap_or_default();
+    match stmt {
+        Stmt::VarDecl { name, init: Some(init), .. } => {
+            (format!("VarDecl {} := {}", name, summarize_expr(init)), function_body(init).map(|b| (None, b)).into_iter().collect())
+        }
+        Stmt::VarDecl { name, init: None, .. } => (format!("VarDecl {}", name), vec![]),
+        Stmt::Assign { target, value, .. } => (
+            format!("Assign {} := {}", summarize_expr(target), summarize_expr(value)),
+            function_body(value).map(|b| (None, b)).into_iter().collect(),
+        ),
+        Stmt::Swap { left, right, .. } => (format!("Swap {}, {}", summarize_expr(left), summarize_expr(right)), vec![]),
+        Stmt::Print { args, .. } if args.is_empty() => ("Print".to_string(), vec![]),
+        Stmt::Print { args, .. } => {
+            (format!("Print {}", args.iter().map(summarize_expr).collect::<Vec<_>>().join(", ")), vec![])
+        }
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            let mut blocks = vec![(None, then_branch.as_slice())];
+            blocks.extend(else_branch.as_deref().map(|b| (Some("Else"), b)));
+            (format!("If {}", summarize_expr(cond)), blocks)
+        }
+        Stmt::While { label: l, cond, body, .. } => (format!("While{} {}", label(l), summarize_expr(cond)), vec![(None, body)]),
+        Stmt::RepeatUntil { body, cond, .. } => (format!("RepeatUntil {}", summarize_expr(cond)), vec![(None, body)]),
+        Stmt::For { label: l, var, iterable, body, .. } => {
+            (format!("For{} {} in {}", label(l), var, summarize_expr(iterable)), vec![(None, body)])
+        }
+        Stmt::Return(Some(value), _) => (format!("Return {}", summarize_expr(value)), vec![]),
+        Stmt::Return(None, _) => ("Return".to_string(), vec![]),
+        Stmt::Exit(label, _) => (format!("Exit{}", label.as_ref().map(|l| format!(" {}", l)).unwrap_or_default()), vec![]),
+        Stmt::Expr(e, _) => (format!("Expr {}", summarize_expr(e)), vec![]),
+        Stmt::Include(path, _) => (format!("Include {:?}", path), vec![]),
+    }
+}
+
+fn summarize_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Integer(n) => format!("Integer({})", n),
+        Expr::Real(n) => format!("Real({})", n),
+        Expr::Bool(b) => format!("Bool({})", b),
+        Expr::String(s) => format!("String({:?})", messages::shorten(s)),
+        Expr::None => "None".to_string(),
+        Expr::Ident(name) => format!("Ident({})", name),
+        Expr::Local { name, .. } => format!("Local({})", name),
+        Expr::Binary { op, .. } => format!("Binary({:?})", op),
+        Expr::Unary { op, .. } => format!("Unary({:?})", op),
+        Expr::Range(..) => "Range".to_string(),
+        Expr::Call { callee, args } => match callee.as_ref() {
+            Expr::Ident(name) | Expr::Local { name, .. } => format!("Call({}, {})", name, count(args.len(), "arg")),
+            _ => format!("Call({})", count(args.len(), "arg")),
+        },
+        Expr::Index { .. } => "Index".to_string(),
+        Expr::Member { field, .. } => format!("Member(.{})", field),
+        Expr::Array(elems) => format!("Array({})", count(elems.len(), "element")),
+        Expr::Tuple(elems) => format!("Tuple({})", count(elems.len(), "field")),
+        Expr::Spread(_) => "Spread".to_string(),
+        Expr::IsType { type_ind, .. } => format!("IsType({})", type_ind),
+        Expr::Func { params, .. } => format!("Func({})", params.join(", ")),
+    }
+}
+
+// A token in parse errors: its Token::describe name, with the text of a
+// name or literal; a long name or string is cut short
 pub fn token_to_string(tok: &Token) -> String {
-    format!("{:?}", tok)
+    messages::shorten(&match tok {
+        Token::Identifier(name) => format!("{} '{}'", tok.describe(), name),
+        Token::Integer(n) => format!("{} {}", tok.describe(), n),
+        Token::Real(r) => format!("{} {}", tok.describe(), r),
+        Token::String(s) => format!("{} {:?}", tok.describe(), s),
+        _ => tok.describe().to_string(),
+    })
 }
@@ -0,0 +1,423 @@
+use super::*;
+use crate::parser::{Build, ExprNode, FuncBodyNode, StmtNode};
+
+// ====
+// Arena form of the AST: nodes live in two flat vectors and refer to each
+// other by index, and the children of a call, array, print or block are a
+// run of one shared id vector. Ids are Copy, so walking or sharing a
+// subtree never allocates. `to_program` gives back the boxed form for the
+// optimizer and interpreter.
+// ====
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StmtId(u32);
+
+// A run of `ParseArena::expr_ids`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExprList {
+    start: u32,
+    len: u32,
+}
+
+// A run of `ParseArena::stmt_ids`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StmtList {
+    start: u32,
+    len: u32,
+}
+
+// A list whose items are still pending: where they start in the pending vector
+pub(crate) struct PendingList(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArenaExpr {
+    Integer(i64),
+    Real(f64),
+    Bool(bool),
+    None,
+    String(String),
+    Ident(String),
+    Local { name: String, depth: usize, slot: usize },
+    Range(ExprId, ExprId),
+    Binary { left: ExprId, op: BinOp, right: ExprId },
+    Unary { op: UnOp, expr: ExprId },
+    Call { callee: ExprId, args: ExprList },
+    Index { target: ExprId, index: ExprId },
+    Member { target: ExprId, field: String },
+    Array(ExprList),
+    Tuple(Vec<(Option<String>, ExprId)>),
+    Spread(ExprId),
+    IsType { expr: ExprId, type_ind: TypeIndicator },
+    Func { params: Vec<String>, body: ArenaFuncBody },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArenaFuncBody {
+    Expr(ExprId),
+    Block(StmtList),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArenaStmt {
+    VarDecl { name: String, init: Option<ExprId>, span: Span },
+    Assign { target: ExprId, value: ExprId, span: Span },
+    Swap { left: ExprId, right: ExprId, span: Span },
+    Print { args: ExprList, span: Span },
+    If { cond: ExprId, then_branch: StmtList, else_branch: Option<StmtList>, span: Span },
+    While { label: Option<String>, cond: ExprId, body: StmtList, span: Span },
+    RepeatUntil { body: StmtList, cond: ExprId, span: Span },
+    For { label: Option<String>, var: LoopPattern, iterable: ExprId, body: StmtList, span: Span },
+    Return(Option<ExprId>, Span),
+    Exit(Option<String>, Span),
+    Expr(ExprId, Span),
+    Include(String, Span),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseArena {
+    exprs: Vec<ArenaExpr>,
+    stmts: Vec<ArenaStmt>,
+    expr_ids: Vec<ExprId>,
+    stmt_ids: Vec<StmtId>,
+    top_level: Vec<StmtId>,
+    // children of lists still being lowered; each list is moved into
+    // expr_ids/stmt_ids in one piece once all of its children are done
+    pending_exprs: Vec<ExprId>,
+    pending_stmts: Vec<StmtId>,
+}
+
+impl ParseArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_program(program: Program) -> Self {
+        let mut arena = Self::new();
+        let Program::Stmts(stmts) = program;
+        for stmt in stmts {
+            arena.push_top_level(stmt);
+        }
+        arena
+    }
+
+    // Lower one more top-level statement
+    pub fn push_top_level(&mut self, stmt: Stmt) -> StmtId {
+        let id = self.lower_stmt(stmt);
+        self.top_level.push(id);
+        id
+    }
+
+    // A statement already in the arena, as the parser's arena mode builds it
+    pub(crate) fn add_top_level(&mut self, id: StmtId) {
+        self.top_level.push(id);
+    }
+
+    pub fn top_level(&self) -> &[StmtId] {
+        &self.top_level
+    }
+
+    pub fn expr(&self, id: ExprId) -> &ArenaExpr {
+        &self.exprs[id.0 as usize]
+    }
+
+    pub fn stmt(&self, id: StmtId) -> &ArenaStmt {
+        &self.stmts[id.0 as usize]
+    }
+
+    pub fn exprs(&self, list: ExprList) -> &[ExprId] {
+        &self.expr_ids[list.start as usize..(list.start + list.len) as usize]
+    }
+
+    pub fn stmts(&self, list: StmtList) -> &[StmtId] {
+        &self.stmt_ids[list.start as usize..(list.start + list.len) as usize]
+    }
+
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+
+    // Bytes held by the node and id vectors (strings and tuple/parameter
+    // lists not included)
+    pub fn heap_bytes(&self) -> usize {
+        use std::mem::size_of;
+        self.exprs.capacity() * size_of::<ArenaExpr>()
+            + self.stmts.capacity() * size_of::<ArenaStmt>()
+            + (self.expr_ids.capacity() + self.pending_exprs.capacity()) * size_of::<ExprId>()
+            + (self.stmt_ids.capacity() + self.pending_stmts.capacity() + self.top_level.capacity()) * size_of::<StmtId>()
+    }
+
+    // ===
+    // lowering
+    // ===
+
+    fn add_expr(&mut self, expr: ArenaExpr) -> ExprId {
+        self.exprs.push(expr);
+        ExprId((self.exprs.len() - 1) as u32)
+    }
+
+    fn add_stmt(&mut self, stmt: ArenaStmt) -> StmtId {
+        self.stmts.push(stmt);
+        StmtId((self.stmts.len() - 1) as u32)
+    }
+
+    // Move the pending items from `list` on into one run of ids
+    fn finish_exprs(&mut self, list: PendingList) -> ExprList {
+        let run = ExprList { start: self.expr_ids.len() as u32, len: (self.pending_exprs.len() - list.0) as u32 };
+        self.expr_ids.extend(self.pending_exprs.drain(list.0..));
+        run
+    }
+
+    fn finish_stmts(&mut self, list: PendingList) -> StmtList {
+        let run = StmtList { start: self.stmt_ids.len() as u32, len: (self.pending_stmts.len() - list.0) as u32 };
+        self.stmt_ids.extend(self.pending_stmts.drain(list.0..));
+        run
+    }
+
+    fn lower_exprs(&mut self, exprs: Vec<Expr>) -> ExprList {
+        let list = PendingList(self.pending_exprs.len());
+        for expr in exprs {
+            let id = self.lower_expr(expr);
+            self.pending_exprs.push(id);
+        }
+        self.finish_exprs(list)
+    }
+
+    fn lower_block(&mut self, stmts: Vec<Stmt>) -> StmtList {
+        let list = PendingList(self.pending_stmts.len());
+        for stmt in stmts {
+            let id = self.lower_stmt(stmt);
+            self.pending_stmts.push(id);
+        }
+        self.finish_stmts(list)
+    }
+
+    fn lower_stmt(&mut self, stmt: Stmt) -> StmtId {
+        let node = match stmt {
+            Stmt::VarDecl { name, init, span } => ArenaStmt::VarDecl { name, init: init.map(|init| self.lower_expr(init)), span },
+            Stmt::Assign { target, value, span } => {
+                ArenaStmt::Assign { target: self.lower_expr(target), value: self.lower_expr(value), span }
+            }
+            Stmt::Swap { left, right, span } => ArenaStmt::Swap { left: self.lower_expr(left), right: self.lower_expr(right), span },
+            Stmt::Print { args, span } => ArenaStmt::Print { args: self.lower_exprs(args), span },
+            Stmt::If { cond, then_branch, else_branch, span } => ArenaStmt::If {
+                cond: self.lower_expr(cond),
+                then_branch: self.lower_block(then_branch),
+                else_branch: else_branch.map(|stmts| self.lower_block(stmts)),
+                span,
+            },
+            Stmt::While { label, cond, body, span } => {
+                ArenaStmt::While { label, cond: self.lower_expr(cond), body: self.lower_block(body), span }
+            }
+            Stmt::RepeatUntil { body, cond, span } => {
+                ArenaStmt::RepeatUntil { body: self.lower_block(body), cond: self.lower_expr(cond), span }
+            }
+            Stmt::For { label, var, iterable, body, span } => {
+                ArenaStmt::For { label, var, iterable: self.lower_expr(iterable), body: self.lower_block(body), span }
+            }
+            Stmt::Return(value, span) => ArenaStmt::Return(value.map(|e| self.lower_expr(e)), span),
+            Stmt::Exit(label, span) => ArenaStmt::Exit(label, span),
+            Stmt::Expr(expr, span) => ArenaStmt::Expr(self.lower_expr(expr), span),
+            Stmt::Include(path, span) => ArenaStmt::Include(path, span),
+        };
+        self.add_stmt(node)
+    }
+
+    // Expr has a Drop of its own, so the parts are taken rather than moved out
+    fn lower_expr(&mut self, mut expr: Expr) -> ExprId {
+        let node = match &mut expr {
+            Expr::Integer(n) => ArenaExpr::Integer(*n),
+            Expr::Real(n) => ArenaExpr::Real(*n),
+            Expr::Bool(b) => ArenaExpr::Bool(*b),
+            Expr::None => ArenaExpr::None,
+            Expr::String(s) => ArenaExpr::String(std::mem::take(s)),
+            Expr::Ident(name) => ArenaExpr::Ident(std::mem::take(name)),
+            Expr::Local { name, depth, slot } => ArenaExpr::Local { name: std::mem::take(name), depth: *depth, slot: *slot },
+            Expr::Range(low, high) => ArenaExpr::Range(self.lower_expr(low.take()), self.lower_expr(high.take())),
+            Expr::Binary { left, op, right } => {
+                ArenaExpr::Binary { left: self.lower_expr(left.take()), op: op.clone(), right: self.lower_expr(right.take()) }
+            }
+            Expr::Unary { op, expr } => ArenaExpr::Unary { op: op.clone(), expr: self.lower_expr(expr.take()) },
+            Expr::Call { callee, args } => {
+                ArenaExpr::Call { callee: self.lower_expr(callee.take()), args: self.lower_exprs(std::mem::take(args)) }
+            }
+            Expr::Index { target, index } => {
+                ArenaExpr::Index { target: self.lower_expr(target.take()), index: self.lower_expr(index.take()) }
+            }
+            Expr::Member { target, field } => ArenaExpr::Member { target: self.lower_expr(target.take()), field: std::mem::take(field) },
+            Expr::Array(items) => ArenaExpr::Array(self.lower_exprs(std::mem::take(items))),
+            Expr::Tuple(elements) => {
+                ArenaExpr::Tuple(std::mem::take(elements).into_iter().map(|e| (e.name, self.lower_expr(e.value))).collect())
+            }
+            Expr::Spread(expr) => ArenaExpr::Spread(self.lower_expr(expr.take())),
+            Expr::IsType { expr, type_ind } => ArenaExpr::IsType { expr: self.lower_expr(expr.take()), type_ind: type_ind.clone() },
+            Expr::Func { params, body } => {
+                let body = match body {
+                    FuncBody::Expr(expr) => ArenaFuncBody::Expr(self.lower_expr(expr.take())),
+                    FuncBody::Block(stmts) => ArenaFuncBody::Block(self.lower_block(std::mem::take(stmts))),
+                };
+                ArenaExpr::Func { params: std::mem::take(params), body }
+            }
+        };
+        self.add_expr(node)
+    }
+
+    // ===
+    // back to the boxed form
+    // ===
+
+    pub fn to_program(&self) -> Program {
+        Program::Stmts(self.top_level.iter().map(|&id| self.to_stmt(id)).collect())
+    }
+
+    fn to_block(&self, list: StmtList) -> Vec<Stmt> {
+        self.stmts(list).iter().map(|&id| self.to_stmt(id)).collect()
+    }
+
+    fn to_exprs(&self, list: ExprList) -> Vec<Expr> {
+        self.exprs(list).iter().map(|&id| self.to_expr(id)).collect()
+    }
+
+    pub fn to_stmt(&self, id: StmtId) -> Stmt {
+        match self.stmt(id) {
+            ArenaStmt::VarDecl { name, init, span } => Stmt::VarDecl { name: name.clone(), init: init.map(|init| self.to_expr(init)), span: *span },
+            ArenaStmt::Assign { target, value, span } => {
+                Stmt::Assign { target: self.to_expr(*target), value: self.to_expr(*value), span: *span }
+            }
+            ArenaStmt::Swap { left, right, span } => Stmt::Swap { left: self.to_expr(*left), right: self.to_expr(*right), span: *span },
+            ArenaStmt::Print { args, span } => Stmt::Print { args: self.to_exprs(*args), span: *span },
+            ArenaStmt::If { cond, then_branch, else_branch, span } => Stmt::If {
+                cond: self.to_expr(*cond),
+                then_branch: self.to_block(*then_branch),
+                else_branch: else_branch.map(|list| self.to_block(list)),
+                span: *span,
+            },
+            ArenaStmt::While { label, cond, body, span } => {
+                Stmt::While { label: label.clone(), cond: self.to_expr(*cond), body: self.to_block(*body), span: *span }
+            }
+            ArenaStmt::RepeatUntil { body, cond, span } => {
+                Stmt::RepeatUntil { body: self.to_block(*body), cond: self.to_expr(*cond), span: *span }
+            }
+            ArenaStmt::For { label, var, iterable, body, span } => {
+                Stmt::For { label: label.clone(), var: var.clone(), iterable: self.to_expr(*iterable), body: self.to_block(*body), span: *span }
+            }
+            ArenaStmt::Return(value, span) => Stmt::Return(value.map(|id| self.to_expr(id)), *span),
+            ArenaStmt::Exit(label, span) => Stmt::Exit(label.clone(), *span),
+            ArenaStmt::Expr(expr, span) => Stmt::Expr(self.to_expr(*expr), *span),
+            ArenaStmt::Include(path, span) => Stmt::Include(path.clone(), *span),
+        }
+    }
+
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        let boxed = |id: &ExprId| Box::new(self.to_expr(*id));
+        match self.expr(id) {
+            ArenaExpr::Integer(n) => Expr::Integer(*n),
+            ArenaExpr::Real(n) => Expr::Real(*n),
+            ArenaExpr::Bool(b) => Expr::Bool(*b),
+            ArenaExpr::None => Expr::None,
+            ArenaExpr::String(s) => Expr::String(s.clone()),
+            ArenaExpr::Ident(name) => Expr::Ident(name.clone()),
+            ArenaExpr::Local { name, depth, slot } => Expr::Local { name: name.clone(), depth: *depth, slot: *slot },
+            ArenaExpr::Range(low, high) => Expr::Range(boxed(low), boxed(high)),
+            ArenaExpr::Binary { left, op, right } => Expr::Binary { left: boxed(left), op: op.clone(), right: boxed(right) },
+            ArenaExpr::Unary { op, expr } => Expr::Unary { op: op.clone(), expr: boxed(expr) },
+            ArenaExpr::Call { callee, args } => Expr::Call { callee: boxed(callee), args: self.to_exprs(*args) },
+            ArenaExpr::Index { target, index } => Expr::Index { target: boxed(target), index: boxed(index) },
+            ArenaExpr::Member { target, field } => Expr::Member { target: boxed(target), field: field.clone() },
+            ArenaExpr::Array(items) => Expr::Array(self.to_exprs(*items)),
+            ArenaExpr::Tuple(elements) => Expr::Tuple(elements.iter()
+                .map(|(name, value)| TupleElement { name: name.clone(), value: self.to_expr(*value) })
+                .collect()),
+            ArenaExpr::Spread(expr) => Expr::Spread(boxed(expr)),
+            ArenaExpr::IsType { expr, type_ind } => Expr::IsType { expr: boxed(expr), type_ind: type_ind.clone() },
+            ArenaExpr::Func { params, body } => Expr::Func {
+                params: params.clone(),
+                body: match body {
+                    ArenaFuncBody::Expr(expr) => FuncBody::Expr(boxed(expr)),
+                    ArenaFuncBody::Block(list) => FuncBody::Block(self.to_block(*list)),
+                },
+            },
+        }
+    }
+}
+
+// The parser's arena mode: nodes go straight into the arena, and the
+// items of a list wait in `pending_exprs`/`pending_stmts` until its node is
+// built (a list started later is always finished first)
+impl Build for ParseArena {
+    type Expr = ExprId;
+    type Stmt = StmtId;
+    type Exprs = PendingList;
+    type Stmts = PendingList;
+
+    fn expr(&mut self, node: ExprNode<Self>) -> ExprId {
+        let node = match node {
+            ExprNode::Leaf(expr) => return self.lower_expr(expr),
+            ExprNode::Range(low, high) => ArenaExpr::Range(low, high),
+            ExprNode::Binary { left, op, right } => ArenaExpr::Binary { left, op, right },
+            ExprNode::Unary { op, expr } => ArenaExpr::Unary { op, expr },
+            ExprNode::Call { callee, args } => ArenaExpr::Call { callee, args: self.finish_exprs(args) },
+            ExprNode::Index { target, index } => ArenaExpr::Index { target, index },
+            ExprNode::Member { target, field } => ArenaExpr::Member { target, field },
+            ExprNode::Array(items) => ArenaExpr::Array(self.finish_exprs(items)),
+            ExprNode::Tuple(elements) => ArenaExpr::Tuple(elements),
+            ExprNode::Spread(expr) => ArenaExpr::Spread(expr),
+            ExprNode::IsType { expr, type_ind } => ArenaExpr::IsType { expr, type_ind },
+            ExprNode::Func { params, body: FuncBodyNode::Expr(body) } => ArenaExpr::Func { params, body: ArenaFuncBody::Expr(body) },
+            ExprNode::Func { params, body: FuncBodyNode::Block(body) } => {
+                ArenaExpr::Func { params, body: ArenaFuncBody::Block(self.finish_stmts(body)) }
+            }
+        };
+        self.add_expr(node)
+    }
+
+    fn stmt(&mut self, node: StmtNode<Self>) -> StmtId {
+        let node = match node {
+            StmtNode::VarDecl { name, init, span } => ArenaStmt::VarDecl { name, init, span },
+            StmtNode::Assign { target, value, span } => ArenaStmt::Assign { target, value, span },
+            StmtNode::Swap { left, right, span } => ArenaStmt::Swap { left, right, span },
+            StmtNode::Print { args, span } => ArenaStmt::Print { args: self.finish_exprs(args), span },
+            StmtNode::If { cond, then_branch, else_branch, span } => {
+                // the else branch was started last
+                let else_branch = else_branch.map(|list| self.finish_stmts(list));
+                ArenaStmt::If { cond, then_branch: self.finish_stmts(then_branch), else_branch, span }
+            }
+            StmtNode::While { label, cond, body, span } => ArenaStmt::While { label, cond, body: self.finish_stmts(body), span },
+            StmtNode::RepeatUntil { body, cond, span } => ArenaStmt::RepeatUntil { body: self.finish_stmts(body), cond, span },
+            StmtNode::For { label, var, iterable, body, span } => {
+                ArenaStmt::For { label, var, iterable, body: self.finish_stmts(body), span }
+            }
+            StmtNode::Return(value, span) => ArenaStmt::Return(value, span),
+            StmtNode::Exit(label, span) => ArenaStmt::Exit(label, span),
+            StmtNode::Expr(expr, span) => ArenaStmt::Expr(expr, span),
+            StmtNode::Include(path, span) => ArenaStmt::Include(path, span),
+        };
+        self.add_stmt(node)
+    }
+
+    fn exprs(&mut self) -> PendingList {
+        PendingList(self.pending_exprs.len())
+    }
+
+    fn push_expr(&mut self, _list: &mut PendingList, expr: ExprId) {
+        self.pending_exprs.push(expr);
+    }
+
+    fn stmts(&mut self) -> PendingList {
+        PendingList(self.pending_stmts.len())
+    }
+
+    fn push_stmt(&mut self, _list: &mut PendingList, stmt: StmtId) {
+        self.pending_stmts.push(stmt);
+    }
+
+    fn is_literal(&self, expr: &ExprId) -> bool {
+        matches!(self.expr(*expr), ArenaExpr::Integer(_) | ArenaExpr::Real(_) | ArenaExpr::Bool(_) | ArenaExpr::String(_) | ArenaExpr::None)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::ast::{Expr, FuncBody, Program, Span, Stmt};
+use crate::interpreter::{HookEvent, Interpreter};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+// ====
+// Statement coverage: which statements of a program ran, recorded through
+// an interpreter hook (see Interpreter::set_hook). Statements are told apart
+// by their position, so those without one (built by hand) are not tracked.
+// ====
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    executed: Rc<RefCell<BTreeSet<(usize, usize)>>>,  // line and column of every statement that ran
+}
+
+impl Coverage {
+    // Records the statements `interpreter` executes from now on; this
+    // replaces any hook it had
+    pub fn attach(interpreter: &mut Interpreter) -> Coverage {
+        let coverage = Coverage::default();
+        let executed = Rc::clone(&coverage.executed);
+        interpreter.set_hook(Some(Box::new(move |event| {
+            if let HookEvent::Statement(span) = event {
+                executed.borrow_mut().insert((span.line, span.col));
+            }
+        })));
+        coverage
+    }
+
+    pub fn is_executed(&self, span: Span) -> bool {
+        self.executed.borrow().contains(&(span.line, span.col))
+    }
+
+    // Positions of the statements that ran, in source order
+    pub fn executed(&self) -> Vec<Span> {
+        self.executed.borrow().iter().map(|&(line, col)| Span::new(line, col)).collect()
+    }
+
+    // Every statement of `program` that never ran, nested ones and those in
+    // function bodies included, in source order
+    pub fn unexecuted<'a>(&self, program: &'a Program) -> Vec<&'a Stmt> {
+        let Program::Stmts(stmts) = program;
+        let mut all = Vec::new();
+        collect_stmts(stmts, &mut all);
+        all.into_iter()
+            .filter(|stmt| stmt.span().is_known() && !self.is_executed(stmt.span()))
+            .collect()
+    }
+}
+
+fn collect_stmts<'a>(stmts: &'a [Stmt], all: &mut Vec<&'a Stmt>) {
+    for stmt in stmts {
+        all.push(stmt);
+        match stmt {
+            Stmt::VarDecl { init: Some(expr), .. } | Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => collect_expr_stmts(expr, all),
+            Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => {
+                collect_expr_stmts(left, all);
+                collect_expr_stmts(right, all);
+            }
+            Stmt::Print { args, .. } => args.iter().for_each(|arg| collect_expr_stmts(arg, all)),
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                collect_expr_stmts(cond, all);
+                collect_stmts(then_branch, all);
+                if let Some(else_branch) = else_branch {
+                    collect_stmts(else_branch, all);
+                }
+            }
+            Stmt::While { cond: expr, body, .. } | Stmt::For { iterable: expr, body, .. } => {
+                collect_expr_stmts(expr, all);
+                collect_stmts(body, all);
+            }
+            Stmt::RepeatUntil { body, cond, .. } => {
+                collect_stmts(body, all);
+                collect_expr_stmts(cond, all);
+            }
+            Stmt::VarDecl { init: None, .. } | Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => {}
+        }
+    }
+}
+
+// The statements of function literals inside `expr`
+fn collect_expr_stmts<'a>(expr: &'a Expr, all: &mut Vec<&'a Stmt>) {
+    match expr {
+        Expr::Func { body: FuncBody::Block(stmts), .. } => collect_stmts(stmts, all),
+        Expr::Func { body: FuncBody::Expr(expr), .. } | Expr::Unary { expr, .. } | Expr::Member { target: expr, .. }
+        | Expr::IsType { expr, .. } | Expr::Spread(expr) => collect_expr_stmts(expr, all),
+        Expr::Range(left, right) | Expr::Binary { left, right, .. } | Expr::Index { target: left, index: right } => {
+            collect_expr_stmts(left, all);
+            collect_expr_stmts(right, all);
+        }
+        Expr::Call { callee, args } => {
+            collect_expr_stmts(callee, all);
+            args.iter().for_each(|arg| collect_expr_stmts(arg, all));
+        }
+        Expr::Array(elems) => elems.iter().for_each(|elem| collect_expr_stmts(elem, all)),
+        Expr::Tuple(elems) => elems.iter().for_each(|elem| collect_expr_stmts(&elem.value, all)),
+        Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) | Expr::Ident(_) | Expr::Local { .. } => {}
+    }
+}
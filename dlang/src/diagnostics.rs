@@ -0,0 +1,367 @@
+use crate::analyzer::{AnalysisError, DiagnosticSite, SemanticChecker};
+use crate::ast::Span;
+use crate::interpreter::InterpreterError;
+use crate::lexer::Lexer;
+use crate::parser::{ParseError, Parser};
+use crate::stdlib::prelude;
+use crate::token::Token;
+
+// ====
+// Editor diagnostics shaped like the Language Server Protocol's: ranges
+// are 0-based and count UTF-16 code units within a line, and every
+// diagnostic has a stable code an editor can link to.
+// ====
+
+// Texts of the problems both the checker and the interpreter can find, so
+// a problem reads the same whether it is caught before or during the run
+pub mod messages {
+    pub const DIVISION_BY_ZERO: &str = "Division by zero";
+    pub const RETURN_OUTSIDE_FUNCTION: &str = "Return statement outside of function";
+    pub const EXIT_OUTSIDE_LOOP: &str = "Exit statement outside of loop";
+
+    // Longest name or token shown in a message before it is cut short
+    pub const MAX_SHOWN_CHARS: usize = 80;
+
+    // `text` cut to MAX_SHOWN_CHARS characters, with `...` when anything was cut
+    pub fn shorten(text: &str) -> String {
+        match text.char_indices().nth(MAX_SHOWN_CHARS) {
+            Some((end, _)) => format!("{}...", &text[..end]),
+            None => text.to_string(),
+        }
+    }
+
+    pub fn undeclared(name: &str) -> String {
+        format!("Variable or function '{}' used before declaration", shorten(name))
+    }
+
+    pub fn own_initializer(name: &str) -> String {
+        format!("Variable '{}' used in its own initializer", shorten(name))
+    }
+
+    pub fn read_before_assignment(name: &str) -> String {
+        format!("Variable '{}' is read before it is assigned a value", shorten(name))
+    }
+
+    pub fn unknown_loop_label(label: &str) -> String {
+        format!("Exit label '{}' does not name an enclosing loop", shorten(label))
+    }
+
+    // `function` is None for an anonymous function; `params` are its parameter names
+    pub fn arity(function: Option<&str>, params: &[String], got: usize) -> String {
+        let expected = match params {
+            [] => "0 arguments".to_string(),
+            _ => format!("{} arguments ({})", params.len(), shorten(&params.join(", "))),
+        };
+        match function {
+            Some(name) => format!("Function '{}' expects {}, got {}", shorten(name), expected, got),
+            None => format!("Function expects {}, got {}", expected, got),
+        }
+    }
+
+    pub fn index_out_of_bounds(index: i64, size: usize, valid_range: &str) -> String {
+        format!("Index {} out of bounds (array size: {}, valid range: {})", index, size, valid_range)
+    }
+
+    pub fn tuple_position_out_of_bounds(position: i64, size: usize) -> String {
+        let plural = if size == 1 { "" } else { "s" };
+        format!("Tuple position {} out of bounds (the tuple has {} element{})", position, size, plural)
+    }
+
+    pub fn not_a_bool(construct: &str, type_name: &str) -> String {
+        format!("{} must be a bool, got {}", construct, type_name)
+    }
+
+    // `operand` is the source of the expression
+    pub fn not_swappable(operand: &str) -> String {
+        format!("'swap' needs a variable, array element or tuple field, got {}", shorten(operand))
+    }
+
+    pub fn unary_plus_operand(type_name: &str) -> String {
+        format!("Unary '+' expects a number, got {}", type_name)
+    }
+
+    // `bound` is "start" or "end"
+    pub fn range_bound_not_integer(bound: &str) -> String {
+        format!("Range {} must be an integer", bound)
+    }
+
+    // `container` is "array" or "tuple", the kind of literal and of value it takes
+    pub fn bad_spread(container: &str, type_name: &str) -> String {
+        let article = if container == "array" { "an" } else { "a" };
+        format!("Spread in {a} {c} literal needs {a} {c}, got {t}", a = article, c = container, t = type_name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+// Numbered as in LSP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+// Another place in the same document that explains the diagnostic
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelatedInformation {
+    pub range: Range,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LspDiagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub related: Vec<RelatedInformation>,
+}
+
+pub const PARSE_ERROR_CODE: &str = "E0100";
+
+// Checker messages by the part of them that identifies the problem
+const SEMANTIC_CODES: &[(&str, &str)] = &[
+    ("used before declaration", "E0001"),
+    ("is already declared", "E0002"),
+    ("used in its own initializer", "E0003"),
+    ("arguments, got", "E0004"),
+    ("arguments (", "E0004"),
+    ("Return statement outside of function", "E0005"),
+    ("Division by zero", "E0006"),
+    ("out of bounds", "E0007"),
+    ("must be a bool", "E0008"),
+    ("Range start must be an integer", "E0009"),
+    ("Range end must be an integer", "E0009"),
+    ("Unresolved include", "E0010"),
+    ("is defined more than once", "E0011"),
+    ("Spread in an array literal", "E0012"),
+    ("Spread in a tuple literal", "E0012"),
+    ("does not name an enclosing loop", "E0013"),
+    ("Unary '+' expects a number", "E0014"),
+    ("'swap' needs a variable", "E0015"),
+    ("Printing a function value", "W0001"),
+    ("shadows an outer variable", "W0002"),
+    ("has no effect", "W0003"),
+    ("truncates to", "W0004"),
+    ("Comparison result is discarded", "W0005"),
+    ("is always", "W0006"),
+    ("will fail at runtime", "W0007"),
+    ("assigned but its value is never used", "W0009"),  // before W0008, whose text it contains
+    ("is never used", "W0008"),
+    ("is redeclared", "W0010"),
+    ("shadows the label of an enclosing loop", "W0011"),
+    ("is read before it is assigned", "W0012"),
+    ("captures the loop variable", "I0001"),
+];
+
+fn semantic_code(message: &str, severity: Severity) -> &'static str {
+    SEMANTIC_CODES.iter()
+        .find(|(part, _)| message.contains(part))
+        .map(|(_, code)| *code)
+        .unwrap_or(match severity {
+            Severity::Error => "E0000",
+            Severity::Warning => "W0000",
+            Severity::Information | Severity::Hint => "I0000",
+        })
+}
+
+// A runtime error with the same cause as a checker error shares its code
+fn runtime_code(error: &InterpreterError) -> &'static str {
+    let message = error.message();
+    let shared = SEMANTIC_CODES.iter()
+        .find(|(part, code)| code.starts_with('E') && message.contains(part))
+        .map(|(_, code)| *code);
+    shared.unwrap_or(match error.kind() {
+        InterpreterError::UndefinedVariable { .. } => "E0201",
+        InterpreterError::TypeError(_) => "E0202",
+        InterpreterError::DivisionByZero => "E0203",
+        InterpreterError::IndexOutOfBounds { .. } => "E0204",
+        InterpreterError::InvalidOperation(_) => "E0205",
+        _ => "E0200",
+    })
+}
+
+pub fn from_parse_error(source: &str, error: &ParseError) -> LspDiagnostic {
+    LspDiagnostic {
+        range: token_range(source, error.line, error.col),
+        severity: Severity::Error,
+        code: PARSE_ERROR_CODE,
+        message: error.message.clone(),
+        related: Vec::new(),
+    }
+}
+
+// One checker error or warning with the site the checker recorded for it
+pub fn from_semantic(source: &str, message: &str, site: &DiagnosticSite, severity: Severity) -> LspDiagnostic {
+    let range = |span: Span| match &site.name {
+        Some(name) => name_range(source, span, name),
+        None => token_range(source, span.line, span.col),
+    };
+    let related = match (&site.name, site.previous) {
+        (Some(name), Some(previous)) => vec![RelatedInformation {
+            range: range(previous),
+            message: format!("'{}' is first declared here", name),
+        }],
+        _ => Vec::new(),
+    };
+    LspDiagnostic {
+        range: range(site.span),
+        severity,
+        code: semantic_code(message, severity),
+        message: message.to_string(),
+        related,
+    }
+}
+
+// Range of the statement that failed, when the error carries its position
+pub fn from_runtime(source: &str, error: &InterpreterError) -> LspDiagnostic {
+    let (line, col) = error.position().unwrap_or((0, 0));
+    LspDiagnostic {
+        range: token_range(source, line, col),
+        severity: Severity::Error,
+        code: runtime_code(error),
+        message: error.message(),
+        related: Vec::new(),
+    }
+}
+
+// Parse and check `source` (with the prelude declared, as pipeline::check
+// does); errors come before warnings, and warnings before notes
+pub fn collect_all(source: &str) -> Vec<LspDiagnostic> {
+    let mut parser = Parser::new(source);
+    let program = match parser.parse_program() {
+        Ok(program) => program,
+        Err(_) => return parser.errors().iter().map(|e| from_parse_error(source, e)).collect(),
+    };
+    let mut checker = SemanticChecker::new();
+    checker.declare_prelude(&prelude());
+    let mut diagnostics = Vec::new();
+    if let Err(AnalysisError::Message(messages)) = checker.check(&program) {
+        for (message, site) in messages.lines().zip(checker.error_sites()) {
+            diagnostics.push(from_semantic(source, message, site, Severity::Error));
+        }
+    }
+    for (message, site) in checker.warnings().iter().zip(checker.warning_sites()) {
+        diagnostics.push(from_semantic(source, message, site, Severity::Warning));
+    }
+    for (message, site) in checker.notes().iter().zip(checker.note_sites()) {
+        diagnostics.push(from_semantic(source, message, site, Severity::Information));
+    }
+    diagnostics
+}
+
+// The line at `span` behind a line-number gutter, with carets under the
+// token that starts there. A token running onto later lines is underlined
+// to the end of the first one and followed by `...`. Empty when the
+// position is not in `source`
+//
+//   3 |     print a + )
+//     |               ^
+pub fn render_snippet(source: &str, span: Span) -> String {
+    let Some(offset) = offset_of(source, span.line, span.col) else {
+        return String::new();
+    };
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let line_end = line_start + line.len();
+
+    let mut lexer = Lexer::new(&source[offset..]);
+    lexer.next_token();
+    let bytes = lexer.token_byte_range();
+    let (start, end) = ((offset + bytes.start).min(line_end), offset + bytes.end);
+    let continues = source[start..end].trim_end_matches(['\r', '\n']).contains('\n');
+
+    // tabs stay tabs so the carets line up however the terminal shows them
+    let indent: String = source[line_start..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let width = source[start..end.min(line_end)].chars().count().max(1);
+    let gutter = span.line.to_string();
+    format!(
+        "{} | {}\n{} | {}{}{}",
+        gutter,
+        line,
+        " ".repeat(gutter.len()),
+        indent,
+        "^".repeat(width),
+        if continues { " ..." } else { "" },
+    )
+}
+
+// ===
+// positions
+// ===
+
+// Byte offset of a 1-based line/col as the lexer counts them (cols in chars)
+fn offset_of(source: &str, line: usize, col: usize) -> Option<usize> {
+    if line == 0 || col == 0 {
+        return None;
+    }
+    let line_start = if line == 1 {
+        0
+    } else {
+        source.match_indices('\n').nth(line - 2)?.0 + 1
+    };
+    let rest = &source[line_start..];
+    rest.char_indices().map(|(i, _)| i).chain(std::iter::once(rest.len())).nth(col - 1).map(|i| line_start + i)
+}
+
+fn position_at(source: &str, offset: usize) -> Position {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+fn range_of(source: &str, bytes: std::ops::Range<usize>) -> Range {
+    Range { start: position_at(source, bytes.start), end: position_at(source, bytes.end) }
+}
+
+// The token starting at line/col; an empty range at the start of the
+// document when the position is unknown
+fn token_range(source: &str, line: usize, col: usize) -> Range {
+    let Some(offset) = offset_of(source, line, col) else {
+        return range_of(source, 0..0);
+    };
+    let mut lexer = Lexer::new(&source[offset..]);
+    lexer.next_token();
+    let bytes = lexer.token_byte_range();
+    range_of(source, offset + bytes.start..offset + bytes.end)
+}
+
+// The first `name` on the first line of the statement at `span` (a line
+// break inside brackets does not end it), or the statement's first token
+fn name_range(source: &str, span: Span, name: &str) -> Range {
+    let Some(offset) = offset_of(source, span.line, span.col) else {
+        return range_of(source, 0..0);
+    };
+    let mut lexer = Lexer::new(&source[offset..]);
+    let mut nesting = 0usize;
+    loop {
+        match lexer.next_token() {
+            Token::Identifier(ident) if ident == name => {
+                let bytes = lexer.token_byte_range();
+                return range_of(source, offset + bytes.start..offset + bytes.end);
+            }
+            Token::LParen | Token::LBracket | Token::LBrace => nesting += 1,
+            Token::RParen | Token::RBracket | Token::RBrace => nesting = nesting.saturating_sub(1),
+            Token::Newline if nesting == 0 => break,
+            Token::EOF => break,
+            _ => {}
+        }
+    }
+    token_range(source, span.line, span.col)
+}
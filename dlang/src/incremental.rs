@@ -0,0 +1,272 @@
+use crate::ast::{NodeId, Program, Stmt};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::pipeline::{check_program, CheckReport, RunOptions};
+use crate::robust::Diagnostic;
+use crate::token::Token;
+use std::ops::Range;
+use std::rc::Rc;
+
+// ====
+// Incremental front end for editors: a document keeps its tokens and
+// top-level statements between edits, re-lexes only the lines an edit
+// touches and re-parses only the statements over those tokens. Statements
+// outside the edit are kept as they are (the same Rc).
+// ====
+
+// How the last edit was parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reparse {
+    Full,
+    Statements(Range<usize>),  // indices of the re-parsed statements in the new document
+}
+
+pub struct Document {
+    source: String,
+    tokens: Vec<Token>,                // ends with EOF, as from the lexer
+    positions: Vec<(usize, usize)>,    // start line/col of each token
+    ranges: Vec<Range<usize>>,         // bytes of each token
+    statements: Vec<Rc<Stmt>>,
+    statement_tokens: Vec<Range<usize>>,  // token indices of each statement
+    parsed: bool,                      // false while the source has a parse error
+    report: CheckReport,
+    last_reparse: Reparse,
+    next_id: NodeId,                   // for the next statement parsed, so re-parsed ones never reuse an id
+}
+
+// One lexed token: the token, its start line/col and its bytes
+type Lexed = (Token, (usize, usize), Range<usize>);
+
+impl Document {
+    pub fn new(source: &str) -> Self {
+        let mut doc = Document {
+            source: source.to_string(),
+            tokens: Vec::new(),
+            positions: Vec::new(),
+            ranges: Vec::new(),
+            statements: Vec::new(),
+            statement_tokens: Vec::new(),
+            parsed: false,
+            report: CheckReport::default(),
+            last_reparse: Reparse::Full,
+            next_id: 1,
+        };
+        doc.reparse_all();
+        doc
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // None while the source does not parse
+    pub fn program(&self) -> Option<&Program> {
+        self.report.program.as_ref()
+    }
+
+    pub fn statements(&self) -> &[Rc<Stmt>] {
+        &self.statements
+    }
+
+    pub fn report(&self) -> &CheckReport {
+        &self.report
+    }
+
+    // Parse and semantic errors of the current source
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.report.parse_errors.iter().chain(&self.report.semantic_errors).cloned().collect()
+    }
+
+    pub fn last_reparse(&self) -> &Reparse {
+        &self.last_reparse
+    }
+
+    // Replace the bytes in `range` with `new_text`. Panics like String::replace_range
+    // when the range is out of bounds or not on char boundaries.
+    pub fn apply_edit(&mut self, range: Range<usize>, new_text: &str) {
+        let rest_started_line = range.end == 0 || self.source.as_bytes()[range.end - 1] == b'\n';
+        self.source.replace_range(range.clone(), new_text);
+        if !self.parsed {
+            // no statement boundaries to keep
+            self.reparse_all();
+            return;
+        }
+        let (first, old_end, lexed) = self.relex(range.start, range.start + new_text.len(), rest_started_line);
+        // the last lexed token is the old one at `old_end` again
+        let token_delta = (lexed.len() - 1) as isize - (old_end - first) as isize;
+        self.splice_tokens(first..old_end, lexed, new_text.len() as isize - range.len() as isize);
+        if self.reparse_statements(first, old_end, token_delta).is_none() {
+            self.reparse_all();
+            return;
+        }
+        self.report = check_program(self.current_program(), &RunOptions::default());
+    }
+
+    fn current_program(&self) -> Program {
+        Program::Stmts(self.statements.iter().map(|s| (**s).clone()).collect())
+    }
+
+    fn reparse_all(&mut self) {
+        let lexed = lex(&self.source, 0, (1, 1), |_| false);
+        self.tokens = lexed.iter().map(|(t, _, _)| t.clone()).collect();
+        self.positions = lexed.iter().map(|(_, p, _)| *p).collect();
+        self.ranges = lexed.into_iter().map(|(_, _, r)| r).collect();
+        self.last_reparse = Reparse::Full;
+        let mut parser = Parser::from_tokens(self.tokens.clone(), self.positions.clone()).with_first_id(self.next_id);
+        let parsed = parser.parse_top_level();
+        self.next_id = parser.next_id();
+        match parsed {
+            Ok(stmts) => {
+                self.parsed = true;
+                self.statement_tokens = stmts.iter().map(|(_, r)| r.clone()).collect();
+                self.statements = stmts.into_iter().map(|(s, _)| Rc::new(s)).collect();
+                self.report = check_program(self.current_program(), &RunOptions::default());
+            }
+            Err(e) => {
+                self.parsed = false;
+                self.statements.clear();
+                self.statement_tokens.clear();
+                self.report = CheckReport { parse_errors: vec![Diagnostic::from(e)], ..CheckReport::default() };
+            }
+        }
+    }
+
+    // Re-lex from the start of the line holding `start` (or of a token running
+    // up to or across that line start, such as a string left open) until the new tokens line up with the old ones
+    // again past the end of the line holding `end`, or past `end` itself when
+    // the text from there started a line before the edit and still does.
+    // Returns the index of the first re-lexed token, the index of the old
+    // token the lexer caught up with, and the tokens in between.
+    fn relex(&self, start: usize, end: usize, rest_started_line: bool) -> (usize, usize, Vec<Lexed>) {
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let first = self.ranges.partition_point(|r| r.end < line_start).min(self.tokens.len() - 1);
+        let (from, pos) = if self.ranges[first].start < line_start {
+            (self.ranges[first].start, self.positions[first])
+        } else {
+            (line_start, (self.source[..line_start].matches('\n').count() + 1, 1))
+        };
+        // text after the edit on the same line (before or after it) has new columns
+        let line_end = if rest_started_line && (end == 0 || self.source.as_bytes()[end - 1] == b'\n') {
+            end
+        } else {
+            self.source[end..].find('\n').map_or(self.source.len(), |i| end + i + 1)
+        };
+
+        let delta = self.source.len() as isize - self.ranges[self.ranges.len() - 1].end as isize;
+        let mut caught_up = self.tokens.len() - 1;
+        let lexed = lex(&self.source, from, pos, |byte| {
+            if byte < line_end {
+                return false;
+            }
+            let old = (byte as isize - delta) as usize;
+            match self.ranges.binary_search_by_key(&old, |r| r.start) {
+                Ok(i) if i >= first => { caught_up = i; true }
+                _ => false,
+            }
+        });
+        (first, caught_up, lexed)
+    }
+
+    // Put `lexed` in place of the old tokens `old`, moving the tokens after them
+    fn splice_tokens(&mut self, old: Range<usize>, mut lexed: Vec<Lexed>, delta: isize) {
+        // the token the lexer caught up with was lexed again; the old one is kept
+        let caught_up = lexed.pop().expect("lexing stops at a token");
+        let lines = caught_up.1 .0 as isize - self.positions[old.end].0 as isize;
+        for i in old.end..self.tokens.len() {
+            self.ranges[i] = shift(self.ranges[i].start, delta)..shift(self.ranges[i].end, delta);
+            self.positions[i].0 = shift(self.positions[i].0, lines);
+            if let Token::Error { line, .. } = &mut self.tokens[i] {
+                *line = shift(*line, lines);
+            }
+        }
+        self.tokens.splice(old.clone(), lexed.iter().map(|(t, _, _)| t.clone()));
+        self.positions.splice(old.clone(), lexed.iter().map(|(_, p, _)| *p));
+        self.ranges.splice(old, lexed.into_iter().map(|(_, _, r)| r));
+    }
+
+    // Re-parse the statements over the old tokens `first..old_end`, which
+    // `splice_tokens` has already replaced, moving later tokens by
+    // `token_delta`. None when the statement boundaries around them could
+    // not be re-established.
+    fn reparse_statements(&mut self, first: usize, old_end: usize, token_delta: isize) -> Option<()> {
+        let a = self.statement_tokens.partition_point(|r| r.end <= first);
+        let b = self.statement_tokens.partition_point(|r| r.start < old_end);
+        let start = if a < b { self.statement_tokens[a].start.min(first) } else { first };
+        let old_window_end = if a < b { self.statement_tokens[b - 1].end.max(old_end) } else { old_end };
+        let end = shift(old_window_end, token_delta);
+        if !self.is_boundary(start) || !self.is_boundary(end) {
+            return None;
+        }
+
+        let mut tokens = self.tokens[start..end].to_vec();
+        let mut positions = self.positions[start..end].to_vec();
+        tokens.push(Token::EOF);
+        positions.push(self.positions[end]);
+        let mut parser = Parser::from_tokens(tokens, positions).with_first_id(self.next_id);
+        let stmts = parser.parse_top_level().ok()?;
+        self.next_id = parser.next_id();
+        // a statement split in two or two merged into one moves every boundary after it
+        if stmts.len() != b - a {
+            return None;
+        }
+
+        let ranges: Vec<Range<usize>> = stmts.iter().map(|(_, r)| r.start + start..r.end + start).collect();
+        self.statements.splice(a..b, stmts.into_iter().map(|(s, _)| Rc::new(s)));
+        self.statement_tokens.splice(a..b, ranges);
+        for i in b..self.statement_tokens.len() {
+            let r = &mut self.statement_tokens[i];
+            *r = shift(r.start, token_delta)..shift(r.end, token_delta);
+            // statements after the edit keep their node unless their position moved
+            let (line, col) = self.positions[r.start];
+            let span = self.statements[i].span();
+            if (span.line, span.col) != (line, col) {
+                let mut stmt = (*self.statements[i]).clone();
+                move_stmt(&mut stmt, span.line, line as isize - span.line as isize, col as isize - span.col as isize);
+                self.statements[i] = Rc::new(stmt);
+            }
+        }
+        self.last_reparse = Reparse::Statements(a..b);
+        Some(())
+    }
+
+    // A statement may start or end between token i - 1 and token i
+    fn is_boundary(&self, i: usize) -> bool {
+        let ends_statement = |t: &Token| matches!(t, Token::Newline | Token::Semicolon | Token::Comment(_) | Token::EOF);
+        i == 0 || ends_statement(&self.tokens[i - 1]) || ends_statement(&self.tokens[i])
+    }
+}
+
+fn shift(n: usize, by: isize) -> usize {
+    (n as isize + by) as usize
+}
+
+// Lex `source` from byte `from`, which is at line/col `pos`, until `stop` is
+// true for the start byte of a token (that token is included) or up to EOF
+fn lex(source: &str, from: usize, pos: (usize, usize), mut stop: impl FnMut(usize) -> bool) -> Vec<Lexed> {
+    let at = |(line, col): (usize, usize)| if line == 1 { (pos.0, pos.1 + col - 1) } else { (pos.0 + line - 1, col) };
+    let mut lexer = Lexer::new(&source[from..]);
+    let mut lexed = Vec::new();
+    loop {
+        let mut token = lexer.next_token();
+        if let Token::Error { line, col, .. } = &mut token {
+            (*line, *col) = at((*line, *col));
+        }
+        let range = lexer.token_byte_range();
+        let range = range.start + from..range.end + from;
+        let done = token == Token::EOF || stop(range.start);
+        lexed.push((token, at(lexer.token_start()), range));
+        if done {
+            return lexed;
+        }
+    }
+}
+
+// Move the spans in `stmt` by `lines`, and those on `line` (its first) also by `cols`
+fn move_stmt(stmt: &mut Stmt, line: usize, lines: isize, cols: isize) {
+    stmt.for_each_span_mut(&mut |span| {
+        if span.line == line {
+            span.col = shift(span.col, cols);
+        }
+        span.line = shift(span.line, lines);
+    });
+}
@@ -1,68 +1,395 @@
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::diagnostics::messages;
+use crate::json::{from_json, write_object, JsonError};
+use crate::resolver::{declares_locals, Resolver};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cmp::Ordering;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 
 // Runtime value representation
-#[derive(Debug, Clone)]
 pub enum Value {
     Integer(i64),
     Real(f64),
     Bool(bool),
-    String(String),
+    String(Rc<str>),  // shared, so copies of a value do not copy its text
     None,
-    Array(Vec<Value>),
-    Tuple(HashMap<String, Value>),  // Named fields
+    Array(Rc<Vec<Value>>),  // copy-on-write: copies share the elements until one is assigned to
+    Tuple(TupleFields),  // Positional and named fields
+    Map(BTreeMap<String, Value>),   // String-keyed dictionary, ordered by key
     Function {
+        id: u64,               // Identity: each evaluation of a literal gets a new one, copies keep it
+        name: Option<String>,  // Set when the literal initializes a `var` (None = anonymous)
+        line: usize,           // Line of that declaration (0 = unknown)
         params: Vec<String>,
         body: FuncBody,
         closure: Rc<RefCell<Environment>>,  // Captured environment for closures
     },
 }
 
+// Functions show their identity instead of the captured environment, which
+// may contain the function itself
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Integer(n) => f.debug_tuple("Integer").field(n).finish(),
+            Value::Real(n) => f.debug_tuple("Real").field(n).finish(),
+            Value::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Value::String(s) => f.debug_tuple("String").field(s).finish(),
+            Value::None => f.write_str("None"),
+            Value::Array(items) => f.debug_tuple("Array").field(items).finish(),
+            Value::Tuple(fields) => {
+                let sorted: BTreeMap<&String, &Value> = fields.iter().collect();
+                f.debug_tuple("Tuple").field(&sorted).finish()
+            }
+            Value::Map(map) => f.debug_tuple("Map").field(map).finish(),
+            Value::Function { id, name, params, .. } => f
+                .debug_struct("Function")
+                .field("id", id)
+                .field("name", name)
+                .field("params", params)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Value {
+    // Type name as used in messages (the `is` indicators where there is one)
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "int",
+            Value::Real(_) => "real",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::None => "none",
+            Value::Array(_) => "array",
+            Value::Tuple(_) => "tuple",
+            Value::Map(_) => "map",
+            Value::Function { .. } => "func",
+        }
+    }
+}
+
+// Clone, equality and drop walk nested containers with an explicit stack
+// rather than recursion, so a value nested thousands of levels deep
+// (e.g. built by `a := [a]` in a loop) cannot overflow the native stack.
+// Arrays are shared rather than copied, so clone stops at them.
+impl Value {
+    pub fn array(items: Vec<Value>) -> Value {
+        Value::Array(Rc::new(items))
+    }
+
+    fn has_children(&self) -> bool {
+        match self {
+            Value::Array(items) => !items.is_empty(),
+            Value::Tuple(fields) => !fields.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+            _ => false,
+        }
+    }
+
+    // Children that a clone has to copy one by one
+    fn has_owned_children(&self) -> bool {
+        !matches!(self, Value::Array(_)) && self.has_children()
+    }
+
+    // Copies everything but the children of a container
+    fn clone_shallow(&self) -> Value {
+        match self {
+            Value::Integer(n) => Value::Integer(*n),
+            Value::Real(n) => Value::Real(*n),
+            Value::Bool(b) => Value::Bool(*b),
+            Value::String(s) => Value::String(Rc::clone(s)),
+            Value::None => Value::None,
+            Value::Array(items) => Value::Array(Rc::clone(items)),
+            Value::Tuple(fields) => Value::Tuple(fields.without_values()),
+            Value::Map(_) => Value::Map(BTreeMap::new()),
+            Value::Function { id, name, line, params, body, closure } => Value::Function {
+                id: *id,
+                name: name.clone(),
+                line: *line,
+                params: params.clone(),
+                body: body.clone(),
+                closure: Rc::clone(closure),
+            },
+        }
+    }
+
+    fn children(&self) -> Children<'_> {
+        match self {
+            Value::Array(items) => Children::Array(items.iter()),
+            Value::Tuple(fields) => Children::Tuple(fields.iter()),
+            Value::Map(map) => Children::Map(map.iter()),
+            _ => Children::Array([].iter()),
+        }
+    }
+
+    fn push_child(&mut self, key: Option<String>, child: Value) {
+        match (self, key) {
+            (Value::Tuple(fields), Some(key)) => { fields.insert(key, child); }
+            (Value::Map(map), Some(key)) => { map.insert(key, child); }
+            _ => {}
+        }
+    }
+
+    // Moves the children of a container out so it can be dropped shallowly
+    fn take_children(&mut self, out: &mut Vec<Value>) {
+        match self {
+            // only the last owner of an array's elements drops them
+            Value::Array(items) => {
+                if let Some(items) = Rc::get_mut(items) {
+                    out.append(items);
+                }
+            }
+            Value::Tuple(fields) => out.extend(fields.drain().map(|(_, v)| v)),
+            Value::Map(map) => out.extend(std::mem::take(map).into_values()),
+            _ => {}
+        }
+    }
+}
+
+// Borrowed children of a container, keyed for tuples and maps
+enum Children<'a> {
+    Array(std::slice::Iter<'a, Value>),
+    Tuple(std::collections::hash_map::Iter<'a, String, Value>),
+    Map(std::collections::btree_map::Iter<'a, String, Value>),
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = (Option<&'a String>, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Children::Array(iter) => iter.next().map(|v| (None, v)),
+            Children::Tuple(iter) => iter.next().map(|(k, v)| (Some(k), v)),
+            Children::Map(iter) => iter.next().map(|(k, v)| (Some(k), v)),
+        }
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        if !self.has_owned_children() {
+            return self.clone_shallow();
+        }
+
+        // Each frame holds a source container, its remaining children,
+        // the copy built so far, and the key it goes under in its parent
+        struct Frame<'a> {
+            children: Children<'a>,
+            copy: Value,
+            key: Option<String>,
+        }
+
+        let mut stack = vec![Frame {
+            children: self.children(),
+            copy: self.clone_shallow(),
+            key: None,
+        }];
+        loop {
+            let top = stack.last_mut().expect("clone stack is never empty here");
+            match top.children.next() {
+                Some((key, child)) if child.has_owned_children() => {
+                    let frame = Frame {
+                        children: child.children(),
+                        copy: child.clone_shallow(),
+                        key: key.cloned(),
+                    };
+                    stack.push(frame);
+                }
+                Some((key, child)) => top.copy.push_child(key.cloned(), child.clone_shallow()),
+                None => {
+                    let done = stack.pop().expect("clone stack is never empty here");
+                    match stack.last_mut() {
+                        Some(parent) => parent.copy.push_child(done.key, done.copy),
+                        None => return done.copy,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        if !self.has_children() {
+            return;
+        }
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut value) = pending.pop() {
+            value.take_children(&mut pending);
+        }
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Value::Integer(a), Value::Integer(b)) => a == b,
-            (Value::Real(a), Value::Real(b)) => (a - b).abs() < f64::EPSILON,
-            (Value::Bool(a), Value::Bool(b)) => a == b,
-            (Value::String(a), Value::String(b)) => a == b,
-            (Value::None, Value::None) => true,
-            (Value::Array(a), Value::Array(b)) => a == b,
-            (Value::Tuple(a), Value::Tuple(b)) => a == b,
-            (Value::Function { .. }, Value::Function { .. }) => false,  // Functions are never equal
-            _ => false,
+        let mut pending = vec![(self, other)];
+        while let Some((left, right)) = pending.pop() {
+            match (left, right) {
+                (Value::Integer(a), Value::Integer(b)) if a == b => {}
+                (Value::Real(a), Value::Real(b)) if reals_equal(*a, *b) => {}
+                (Value::Bool(a), Value::Bool(b)) if a == b => {}
+                (Value::String(a), Value::String(b)) if Rc::ptr_eq(a, b) || a == b => {}
+                (Value::None, Value::None) => {}
+                (Value::Array(a), Value::Array(b)) if Rc::ptr_eq(a, b) => {}
+                (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+                    pending.extend(a.iter().zip(b.iter()));
+                }
+                (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => {
+                    for (key, value) in a.iter() {
+                        match b.get(key) {
+                            Some(other) => pending.push((value, other)),
+                            None => return false,
+                        }
+                    }
+                }
+                (Value::Map(a), Value::Map(b)) if a.len() == b.len() => {
+                    for ((ka, va), (kb, vb)) in a.iter().zip(b.iter()) {
+                        if ka != kb {
+                            return false;
+                        }
+                        pending.push((va, vb));
+                    }
+                }
+                // Functions compare by identity
+                (Value::Function { id: a, .. }, Value::Function { id: b, .. }) if a == b => {}
+                _ => return false,
+            }
         }
+        true
     }
 }
 
 // Environment for variable storage with scoping
+// Globals live in `variables` and are looked up by name; locals were bound
+// to slots by the resolver and live in `slots`.
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Environment {
     variables: HashMap<String, Value>,
+    slots: Vec<Value>,
     parent: Option<Rc<RefCell<Environment>>>,  
+    depth: usize,  // parents above this one
+    unset_names: HashSet<String>,  // globals declared by `var x` and not assigned yet, see mark_unset
+    unset_slots: HashSet<usize>,   // the same for locals
+}
+
+// Debug output is the dump, so it does not depend on hash order
+impl std::fmt::Debug for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.dump())
+    }
 }
 
 impl Environment {
+    // One line per binding: named variables sorted by name, then slots by
+    // index, then the parent scope indented below
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.write_dump(&mut out, 0);
+        out
+    }
+
+    fn write_dump(&self, out: &mut String, indent: usize) {
+        let pad = "  ".repeat(indent);
+        let variables: BTreeMap<&String, &Value> = self.variables.iter().collect();
+        let unset = |is_unset: bool| if is_unset { " (unset)" } else { "" };
+        for (name, value) in variables {
+            out.push_str(&format!("{}{} = {:?}{}\n", pad, name, value, unset(self.unset_names.contains(name))));
+        }
+        for (slot, value) in self.slots.iter().enumerate() {
+            out.push_str(&format!("{}#{} = {:?}{}\n", pad, slot, value, unset(self.unset_slots.contains(&slot))));
+        }
+        if let Some(parent) = &self.parent {
+            out.push_str(&format!("{}parent:\n", pad));
+            parent.borrow().write_dump(out, indent + 1);
+        }
+    }
+
     pub fn new() -> Self {
         Self {
             variables: HashMap::new(),
+            slots: Vec::new(),
             parent: None,
+            depth: 0,
+            unset_names: HashSet::new(),
+            unset_slots: HashSet::new(),
         }
     }
 
     pub fn new_with_parent(parent: Rc<RefCell<Environment>>) -> Self {
+        let depth = parent.borrow().depth + 1;
         Self {
             variables: HashMap::new(),
+            slots: Vec::new(),
             parent: Some(parent),
+            depth,
+            unset_names: HashSet::new(),
+            unset_slots: HashSet::new(),
         }
     }
 
     pub fn define(&mut self, name: String, value: Value) {
+        self.unset_names.remove(&name);
         self.variables.insert(name, value);
     }
+
+    // locals are declared in the order the resolver numbered them
+    pub fn define_slot(&mut self, value: Value) -> usize {
+        self.slots.push(value);
+        self.slots.len() - 1
+    }
+
+    // Marks the global `name`, or the local in `slot`, as declared without a
+    // value; it stays unset until it is assigned (see Interpreter::with_strict_unset)
+    pub fn mark_unset(&mut self, name: &str, slot: Option<usize>) {
+        match slot {
+            Some(slot) => self.unset_slots.insert(slot),
+            None => self.unset_names.insert(name.to_string()),
+        };
+    }
+
+    pub fn is_unset(&self, name: &str) -> bool {
+        if self.variables.contains_key(name) {
+            return self.unset_names.contains(name);
+        }
+        self.parent.as_ref().is_some_and(|parent| parent.borrow().is_unset(name))
+    }
+
+    pub fn is_unset_at(&self, depth: usize, slot: usize) -> bool {
+        if depth == 0 {
+            return self.unset_slots.contains(&slot);
+        }
+        self.parent.as_ref().is_some_and(|parent| parent.borrow().is_unset_at(depth - 1, slot))
+    }
+
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<Value> {
+        if depth == 0 {
+            return self.slots.get(slot).cloned();
+        }
+        self.parent.as_ref()?.borrow().get_at(depth - 1, slot)
+    }
+
+    pub fn assign_at(&mut self, depth: usize, slot: usize, value: Value) -> bool {
+        if depth == 0 {
+            return match self.slots.get_mut(slot) {
+                Some(current) => {
+                    *current = value;
+                    self.unset_slots.remove(&slot);
+                    true
+                }
+                None => false,
+            };
+        }
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().assign_at(depth - 1, slot, value),
+            None => false,
+        }
+    }
     
 
     pub fn get(&self, name: &str) -> Option<Value> {
@@ -80,8 +407,18 @@ impl Environment {
     }
     
 
+    // Every name bound here or in a parent scope (slots have no names)
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.variables.keys().cloned().collect();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.borrow().names());
+        }
+        names
+    }
+
     pub fn assign(&mut self, name: &str, value: Value) -> bool {
         if self.variables.contains_key(name) {
+            self.unset_names.remove(name);
             self.variables.insert(name.to_string(), value);
             true
         } else if let Some(ref parent) = self.parent {
@@ -93,56 +430,759 @@ impl Environment {
 }
 
 // Interpreter errors
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InterpreterError {
     RuntimeError(String),
-    UndefinedVariable(String),
+    UndefinedVariable { name: String, suggestion: Option<String> },
     TypeError(String),
     DivisionByZero,
-    IndexOutOfBounds { index: i64, size: usize },
+    IndexOutOfBounds { index: i64, size: usize, base: IndexBase },
     InvalidOperation(String),
     Return(Value),  // Special: return value
-    Exit,           // Special: exit signal
+    Exit(Option<String>),  // Special: exit signal, with the label of the loop to leave
+    MemoryLimitExceeded { limit: usize },  // see Interpreter::set_memory_limit
+    Located { error: Box<InterpreterError>, line: usize, col: usize },  // Error raised by the statement at line:col
+}
+
+impl InterpreterError {
+    // Attach the failing statement's position; the innermost statement wins
+    fn at(self, span: Span) -> Self {
+        match self {
+            InterpreterError::Return(_) | InterpreterError::Exit(_) | InterpreterError::Located { .. } => self,
+            error if span.is_known() => InterpreterError::Located {
+                error: Box::new(error),
+                line: span.line,
+                col: span.col,
+            },
+            error => error,
+        }
+    }
+
+    // Whether this is an exit the loop labelled `label` stops at: an unlabelled
+    // one, or one naming that loop
+    fn exits(&self, label: &Option<String>) -> bool {
+        match self {
+            InterpreterError::Exit(None) => true,
+            InterpreterError::Exit(target) => target == label,
+            _ => false,
+        }
+    }
+
+    // A labelled exit still unmatched when it leaves the outermost loop names no loop at all
+    fn unmatched_exit(self, outermost: bool) -> Self {
+        match self {
+            InterpreterError::Exit(Some(label)) if outermost => {
+                InterpreterError::RuntimeError(messages::unknown_loop_label(&label))
+            }
+            error => error,
+        }
+    }
+
+    // Reading the variable a declaration is initializing, before it exists
+    fn own_initializer(self, declared: &str) -> Self {
+        match self {
+            InterpreterError::UndefinedVariable { name, .. } if name == declared => {
+                InterpreterError::RuntimeError(messages::own_initializer(declared))
+            }
+            error => error,
+        }
+    }
+
+    // The error's text without its kind or position; for a cause the checker
+    // also finds, this is the checker's message
+    pub fn message(&self) -> String {
+        match self.kind() {
+            InterpreterError::RuntimeError(msg) | InterpreterError::TypeError(msg) | InterpreterError::InvalidOperation(msg) => msg.clone(),
+            error => error.to_string(),
+        }
+    }
+
+    // Source position of the statement that raised the error, if known
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            InterpreterError::Located { line, col, .. } => Some((*line, *col)),
+            _ => None,
+        }
+    }
+
+    // The error without its position
+    pub fn kind(&self) -> &InterpreterError {
+        match self {
+            InterpreterError::Located { error, .. } => error,
+            error => error,
+        }
+    }
 }
 
 impl std::fmt::Display for InterpreterError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             InterpreterError::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
-            InterpreterError::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
+            InterpreterError::UndefinedVariable { name, suggestion: Some(suggestion) } => {
+                write!(f, "{}; did you mean '{}'?", messages::undeclared(name), suggestion)
+            }
+            InterpreterError::UndefinedVariable { name, suggestion: None } => write!(f, "{}", messages::undeclared(name)),
             InterpreterError::TypeError(msg) => write!(f, "Type error: {}", msg),
-            InterpreterError::DivisionByZero => write!(f, "Division by zero"),
-            InterpreterError::IndexOutOfBounds { index, size } => {
-                write!(f, "Index {} out of bounds (array size: {})", index, size)
+            InterpreterError::DivisionByZero => write!(f, "{}", messages::DIVISION_BY_ZERO),
+            InterpreterError::IndexOutOfBounds { index, size, base } => {
+                write!(f, "{}", messages::index_out_of_bounds(*index, *size, &base.valid_range(*size)))
             }
             InterpreterError::InvalidOperation(msg) => write!(f, "Invalid operation: {}", msg),
             InterpreterError::Return(_) => write!(f, "Return"),
-            InterpreterError::Exit => write!(f, "Exit"),
+            InterpreterError::Exit(_) => write!(f, "Exit"),
+            InterpreterError::MemoryLimitExceeded { limit } => {
+                write!(f, "Memory limit of {} bytes exceeded", limit)
+            }
+            InterpreterError::Located { error, line, col } => write!(f, "{} at {}:{}", error, line, col),
         }
     }
 }
 
+impl std::error::Error for InterpreterError {}
+
 pub type InterpreterResult<T> = Result<T, InterpreterError>;
 
+// Builtin functions callable without a declaration: (name, min args, max args).
+// A user variable with the same name takes precedence over the builtin.
+pub const BUILTINS: &[(&str, usize, usize)] = &[
+    ("dict", 0, 0),
+    ("put", 3, 3),
+    ("getKey", 2, 2),
+    ("has", 2, 2),
+    ("keys", 1, 1),
+    ("delete", 2, 2),
+    ("split", 1, 2),
+    ("random", 0, 0),
+    ("randomInt", 2, 2),
+    ("now", 0, 0),
+    ("clone", 1, 1),
+    ("repeat", 2, 2),
+    ("sum", 1, 1),
+    ("minOf", 1, 1),
+    ("maxOf", 1, 1),
+    ("avg", 1, 1),
+    ("range", 1, 2),
+    ("enumerate", 1, 1),
+    ("arity", 1, 1),
+    ("params", 1, 1),
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|(n, _, _)| *n == name)
+}
+
+// The candidate closest to a misspelled `name`, for "did you mean ...?".
+// Short names are left alone: almost any other short name is within two edits.
+pub(crate) fn suggest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    if name.chars().count() < 4 {
+        return None;
+    }
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Levenshtein distance over chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitute.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// A numeric key is a position, reported the way the analyzer reports it
+fn missing_tuple_field(tuple: &HashMap<String, Value>, key: &str) -> InterpreterError {
+    match key.parse::<i64>() {
+        Ok(position) => {
+            let size = tuple.keys().filter(|key| key.parse::<usize>().is_ok()).count();
+            InterpreterError::RuntimeError(messages::tuple_position_out_of_bounds(position, size))
+        }
+        Err(_) => InterpreterError::RuntimeError(format!("Tuple field '{}' not found", key)),
+    }
+}
+
+// Index of the first array element: the course material counts from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexBase {
+    Zero,
+    #[default]
+    One,
+}
+
+impl IndexBase {
+    pub fn first(self) -> i64 {
+        match self {
+            IndexBase::Zero => 0,
+            IndexBase::One => 1,
+        }
+    }
+
+    // Position in an array of `len` elements, None when out of bounds
+    pub fn offset(self, index: i64, len: usize) -> Option<usize> {
+        let offset = index.checked_sub(self.first())?;
+        (offset >= 0 && offset < len as i64).then_some(offset as usize)
+    }
+
+    // Valid indices for `len` elements as shown in messages, e.g. `1..3`
+    pub fn valid_range(self, len: usize) -> String {
+        format!("{}..{}", self.first(), self.first() + len as i64 - 1)
+    }
+}
+
+// Default upper bound on the length of a string built by repetition
+pub const DEFAULT_MAX_STRING_LEN: usize = 1 << 20;
+
+// Default limits for rendering values: nesting deeper than this prints `...`,
+// and containers show this many entries followed by `... (+N more)`
+pub const DEFAULT_MAX_PRINT_DEPTH: usize = 100;
+pub const DEFAULT_MAX_PRINT_ELEMENTS: usize = 1000;
+
+// Statements between two sweeps of the scopes for the memory limit; a
+// single stored value over the limit is caught when it is stored
+const MEMORY_SWEEP_INTERVAL: u64 = 1024;
+
+// First id given to prelude functions, far above any a program reaches
+const PRELUDE_FUNCTION_IDS: u64 = 1 << 63;
+
+// Rough bytes held by a value: the value itself and everything it owns. An
+// array shared by several values is counted once, and a function counts its
+// parameters but not its closure, which is a scope of its own.
+pub fn approx_size(value: &Value) -> usize {
+    values_size([value], &mut HashSet::new())
+}
+
+// Walks with a stack of its own, as values can nest deeper than the Rust stack allows
+fn values_size<'a>(values: impl IntoIterator<Item = &'a Value>, seen: &mut HashSet<*const Vec<Value>>) -> usize {
+    let mut pending: Vec<&Value> = values.into_iter().collect();
+    let mut total = 0;
+    while let Some(value) = pending.pop() {
+        total += std::mem::size_of::<Value>();
+        match value {
+            Value::String(s) => total += s.len(),
+            Value::Array(elems) => {
+                if seen.insert(Rc::as_ptr(elems)) {
+                    pending.extend(elems.iter());
+                }
+            }
+            Value::Tuple(fields) => {
+                for (key, field) in fields.iter() {
+                    total += std::mem::size_of::<String>() + key.len();
+                    pending.push(field);
+                }
+            }
+            Value::Map(entries) => {
+                for (key, entry) in entries {
+                    total += std::mem::size_of::<String>() + key.len();
+                    pending.push(entry);
+                }
+            }
+            Value::Function { params, .. } => {
+                total += params.iter().map(|p| std::mem::size_of::<String>() + p.len()).sum::<usize>();
+            }
+            Value::Integer(_) | Value::Real(_) | Value::Bool(_) | Value::None => {}
+        }
+    }
+    total
+}
+
+// Default limit on how deeply a program's expressions may nest; evaluating
+// a deeper tree could overflow the stack. The left operands of a binary
+// chain such as `1 + 1 + ... + 1` are evaluated with a loop and do not count.
+pub const DEFAULT_MAX_EXPR_DEPTH: usize = 256;
+
+// String repetition shared by `*`, repeat() and the optimizer's constant folding
+pub fn repeat_string(s: &str, count: i64, max_len: usize) -> Result<String, String> {
+    if count < 0 {
+        return Err(format!("String repeat count must be non-negative, got {}", count));
+    }
+    match s.len().checked_mul(count as usize) {
+        Some(len) if len <= max_len => Ok(s.repeat(count as usize)),
+        _ => Err(format!(
+            "String repetition of {} characters {} times exceeds the limit of {} characters",
+            s.len(), count, max_len
+        )),
+    }
+}
+
+// Real equality shared by `=`, the comparisons and the optimizer's folding:
+// values within a relative epsilon of each other are equal, so 0.1 + 0.2 = 0.3
+pub fn reals_equal(a: f64, b: f64) -> bool {
+    a == b || (a - b).abs() <= f64::EPSILON * a.abs().max(b.abs())
+}
+
+// Below this (and above zero) or at REAL_EXPONENT_ABOVE and up, a real
+// prints in exponent notation
+pub const REAL_EXPONENT_BELOW: f64 = 1e-4;
+pub const REAL_EXPONENT_ABOVE: f64 = 1e15;
+
+// How a real prints, shared by print, string concatenation and the
+// optimizer's string folding: whole values without decimals, -0 as 0, very
+// small and very large values as e.g. 1e-7 and 1.5e20
+pub fn format_real(n: f64) -> String {
+    if n == 0.0 {
+        "0".to_string()
+    } else if !(REAL_EXPONENT_BELOW..REAL_EXPONENT_ABOVE).contains(&n.abs()) {
+        format!("{:e}", n)
+    } else if n.fract() == 0.0 {
+        format!("{:.0}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+// Real arithmetic never produces infinity or NaN: an overflowing result is an error
+fn finite_real(n: f64) -> InterpreterResult<Value> {
+    if n.is_finite() {
+        Ok(Value::Real(n))
+    } else {
+        Err(InterpreterError::RuntimeError("Real overflow: result is not a finite number".to_string()))
+    }
+}
+
+// The fields of a tuple by key: its positions "1", "2", ... and its names.
+// A named element is stored under its name and its position, and
+// `positions` remembers that position, so a literal overriding the name
+// after a spread writes both. Assignment writes only the key it is given;
+// tuples read from JSON have names without positions.
+#[derive(Debug, Clone, Default)]
+pub struct TupleFields {
+    fields: HashMap<String, Value>,
+    positions: Vec<(String, usize)>,  // name and the position it shares
+}
+
+impl TupleFields {
+    // The element at `position` (from 1), also under `name` if it has one
+    fn insert_element(&mut self, position: usize, name: Option<String>, value: Value) {
+        if let Some(name) = name {
+            self.fields.insert(name.clone(), value.clone());
+            self.forget_position(&name);
+            self.positions.push((name, position));
+        }
+        self.fields.insert(position.to_string(), value);
+    }
+
+    fn position_of(&self, name: &str) -> Option<usize> {
+        self.positions.iter().find(|(named, _)| named == name).map(|&(_, position)| position)
+    }
+
+    fn forget_position(&mut self, name: &str) {
+        self.positions.retain(|(named, _)| named != name);
+    }
+
+    fn position_count(&self) -> usize {
+        self.fields.keys().filter(|key| key.parse::<usize>().is_ok()).count()
+    }
+
+    // Same names and positions, no values yet (see Value::clone)
+    fn without_values(&self) -> TupleFields {
+        TupleFields { fields: HashMap::new(), positions: self.positions.clone() }
+    }
+
+    pub fn into_fields(self) -> HashMap<String, Value> {
+        self.fields
+    }
+}
+
+impl From<HashMap<String, Value>> for TupleFields {
+    fn from(fields: HashMap<String, Value>) -> Self {
+        TupleFields { fields, positions: Vec::new() }
+    }
+}
+
+impl std::ops::Deref for TupleFields {
+    type Target = HashMap<String, Value>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+impl std::ops::DerefMut for TupleFields {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.fields
+    }
+}
+
+// Positions of `right` continue after those of `tuple`; its names replace
+// fields of the same name
+fn append_tuple(tuple: &mut TupleFields, right: &TupleFields) {
+    let offset = tuple.position_count();
+    for (key, value) in right.iter() {
+        let key = match key.parse::<usize>() {
+            Ok(position) => (position + offset).to_string(),
+            Err(_) => {
+                tuple.forget_position(key);
+                key.clone()
+            }
+        };
+        tuple.fields.insert(key, value.clone());
+    }
+    for (name, position) in &right.positions {
+        tuple.positions.push((name.clone(), position + offset));
+    }
+}
+
+// Tuples keep the fields they were built with: assignment updates one, but
+// a missing key is an error rather than a new field
+fn set_tuple_field(tuple: &mut TupleFields, key: &str, value: Value) -> InterpreterResult<()> {
+    match tuple.get_mut(key) {
+        Some(field) => {
+            *field = value;
+            Ok(())
+        }
+        None => Err(InterpreterError::RuntimeError(format!(
+            "Tuple has no field '{}'; build a new tuple to add one, e.g. t + {{name := value}}", key
+        ))),
+    }
+}
+
+// Message for a builtin called with the wrong number of arguments, shared with the analyzer
+pub fn builtin_arity_error(name: &str, arg_count: usize) -> Option<String> {
+    let (_, min_args, max_args) = BUILTINS.iter().find(|(n, _, _)| *n == name)?;
+    if arg_count >= *min_args && arg_count <= *max_args {
+        return None;
+    }
+    let expected = if min_args == max_args {
+        min_args.to_string()
+    } else {
+        format!("{} to {}", min_args, max_args)
+    };
+    Some(format!("Builtin '{}' expects {} arguments, got {}", name, expected, arg_count))
+}
+
+// arity(builtin) is the number of arguments it requires; builtins have no
+// parameter names to give params(builtin)
+fn builtin_signature(query: &str, builtin: &str) -> InterpreterResult<Value> {
+    let (_, min_args, _) = BUILTINS.iter().find(|(n, _, _)| *n == builtin).expect("a builtin");
+    match query {
+        "arity" => Ok(Value::Integer(*min_args as i64)),
+        _ => Err(InterpreterError::RuntimeError(format!("Builtin '{}' has no parameter names", builtin))),
+    }
+}
+
+// What the interpreter reports to a hook set with Interpreter::set_hook, as it happens
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookEvent {
+    Statement(Span),  // a statement is about to execute
+    Call { name: Option<String>, line: usize },  // a function value is called (see Value::Function)
+    LoopIteration { span: Span, iteration: u64 },  // a loop's body is about to run for the nth time, from 1
+}
+
+pub type Hook = Box<dyn FnMut(HookEvent)>;
+
+// A node of the tree nesting_depth walks
+enum Nested<'a> {
+    Stmt(&'a Stmt),
+    Expr(&'a Expr),
+}
+
+// How deeply the expressions of `stmts` nest, up to just past `limit`: one
+// level per expression inside another (a function literal's body included),
+// except for the left operand of a binary operator. Blocks do not count.
+fn nesting_depth(stmts: &[Stmt], limit: usize) -> usize {
+    let mut pending: Vec<(Nested, usize)> = stmts.iter().map(|stmt| (Nested::Stmt(stmt), 0)).collect();
+    let mut deepest = 0;
+    while let Some((node, depth)) = pending.pop() {
+        deepest = deepest.max(depth);
+        if deepest > limit {
+            break;
+        }
+        let (exprs, blocks): (Vec<&Expr>, Vec<&[Stmt]>) = match node {
+            Nested::Stmt(stmt) => match stmt {
+                Stmt::VarDecl { init: Some(expr), .. } | Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => (vec![expr], vec![]),
+                Stmt::Assign { target: left, value: right, .. } | Stmt::Swap { left, right, .. } => (vec![left, right], vec![]),
+                Stmt::Print { args, .. } => (args.iter().collect(), vec![]),
+                Stmt::If { cond, then_branch, else_branch, .. } => {
+                    (vec![cond], [Some(then_branch), else_branch.as_ref()].into_iter().flatten().map(Vec::as_slice).collect())
+                }
+                Stmt::While { cond: expr, body, .. } | Stmt::RepeatUntil { cond: expr, body, .. } | Stmt::For { iterable: expr, body, .. } => {
+                    (vec![expr], vec![body])
+                }
+                Stmt::VarDecl { init: None, .. } | Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => (vec![], vec![]),
+            },
+            Nested::Expr(expr) => match expr {
+                Expr::Binary { left, right, .. } => {
+                    pending.push((Nested::Expr(left), depth));
+                    (vec![right], vec![])
+                }
+                Expr::Range(left, right) | Expr::Index { target: left, index: right } => (vec![left, right], vec![]),
+                Expr::Unary { expr, .. } | Expr::Member { target: expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr)
+                | Expr::Func { body: FuncBody::Expr(expr), .. } => (vec![expr], vec![]),
+                Expr::Call { callee, args } => (std::iter::once(callee.as_ref()).chain(args).collect(), vec![]),
+                Expr::Array(elems) => (elems.iter().collect(), vec![]),
+                Expr::Tuple(elems) => (elems.iter().map(|elem| &elem.value).collect(), vec![]),
+                Expr::Func { body: FuncBody::Block(stmts), .. } => (vec![], vec![stmts]),
+                Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::None | Expr::String(_) | Expr::Ident(_)
+                | Expr::Local { .. } => (vec![], vec![]),
+            },
+        };
+        pending.extend(exprs.into_iter().map(|expr| (Nested::Expr(expr), depth + 1)));
+        pending.extend(blocks.into_iter().flatten().map(|stmt| (Nested::Stmt(stmt), depth)));
+    }
+    deepest
+}
+
+// The error for a program whose expressions nest deeper than `limit`; the
+// pipeline checks this before the checker and optimizer, which recurse too
+pub(crate) fn check_nesting(program: &Program, limit: usize) -> InterpreterResult<()> {
+    let Program::Stmts(stmts) = program;
+    if nesting_depth(stmts, limit) > limit {
+        return Err(InterpreterError::RuntimeError(format!("Expressions nest more than {} levels deep", limit)));
+    }
+    Ok(())
+}
+
 // Main interpreter
 pub struct Interpreter {
     environment: Rc<RefCell<Environment>>,  
+    globals: Rc<RefCell<Environment>>,      // Root scope, holds top-level declarations by name
     inside_loop: bool,
     inside_function: bool,
+    output: Option<Vec<String>>,  // Captured print lines (None = write to stdout)
+    rng_state: u64,               // xorshift64* state for random()/randomInt()
+    start_time: Instant,          // Reference point for now()
+    max_string_len: usize,        // Cap for strings built by repetition
+    max_print_depth: usize,       // Nesting rendered before eliding with `...`
+    max_print_elements: usize,    // Container entries rendered before `... (+N more)`
+    max_steps: Option<u64>,       // Steps allowed to run, see set_max_steps (None = unlimited)
+    steps: u64,
+    max_call_depth: Option<usize>, // Nested function calls allowed (None = unlimited)
+    memory_limit: Option<usize>,  // Approximate bytes the program's variables may hold (None = unlimited)
+    call_depth: usize,
+    max_expr_depth: usize,        // Nesting allowed in a program, see DEFAULT_MAX_EXPR_DEPTH
+    strict_bool: bool,            // Conditions and logical operands must be bools (no truthiness)
+    strict_unset: bool,           // Reading a `var x` declared without a value is an error until it is assigned
+    index_base: IndexBase,        // Index of the first array element
+    error_on_implicit_none_return: bool, // Using the result of a call that fell off the end is an error
+    fell_through: Option<String>, // Set by the last call when its block ended without `return`
+    next_function_id: u64,        // Id for the next function value created
+    literals: HashMap<String, Rc<str>>, // String literals seen so far, shared by every evaluation
+    stats: InterpreterStats,
+    hook: Option<Hook>,           // Told about statements, calls and loop iterations (None = no overhead)
+}
+
+// Scope bookkeeping since the interpreter was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterpreterStats {
+    pub max_env_depth: usize,  // longest parent chain seen below the globals
+    pub scopes_created: u64,
+    pub slots_defined: u64,    // locals, parameters and loop variables
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
         Self {
-            environment: Rc::new(RefCell::new(Environment::new())),  
+            environment: Rc::clone(&globals),  
+            globals,
             inside_loop: false,
             inside_function: false,
+            output: None,
+            rng_state: Self::clock_seed(),
+            start_time: Instant::now(),
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            max_print_depth: DEFAULT_MAX_PRINT_DEPTH,
+            max_print_elements: DEFAULT_MAX_PRINT_ELEMENTS,
+            max_steps: None,
+            steps: 0,
+            max_call_depth: None,
+            memory_limit: None,
+            call_depth: 0,
+            max_expr_depth: DEFAULT_MAX_EXPR_DEPTH,
+            strict_bool: false,
+            strict_unset: false,
+            index_base: IndexBase::default(),
+            error_on_implicit_none_return: false,
+            fell_through: None,
+            next_function_id: 1,
+            literals: HashMap::new(),
+            stats: InterpreterStats::default(),
+            hook: None,
+        }
+    }
+
+    // Interpreter that collects printed lines instead of writing them to stdout
+    pub fn with_output_capture() -> Self {
+        Self {
+            output: Some(Vec::new()),
+            ..Self::new()
         }
     }
 
+    // Require real bools in `if`/`while` conditions and for and/or/xor/not,
+    // instead of treating 0, "", [] and none as false
+    pub fn with_strict_bool(mut self, strict: bool) -> Self {
+        self.strict_bool = strict;
+        self
+    }
+
+    // Make reading a variable declared without a value (`var x`) an error
+    // until it is assigned, instead of giving none; `var x := none` is
+    // unaffected
+    pub fn with_strict_unset(mut self, strict: bool) -> Self {
+        self.strict_unset = strict;
+        self
+    }
+
+    // Count array indices from 0 instead of 1 (also the indices enumerate() reports)
+    pub fn with_index_base(mut self, base: IndexBase) -> Self {
+        self.index_base = base;
+        self
+    }
+
+    // Report a call whose block body ends without `return` when its result
+    // is used, instead of quietly producing none; calls made as statements
+    // are still fine
+    pub fn with_error_on_implicit_none_return(mut self, enabled: bool) -> Self {
+        self.error_on_implicit_none_return = enabled;
+        self
+    }
+
+    // Take the lines printed so far (empty when output is not captured)
+    pub fn take_output(&mut self) -> Vec<String> {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // Reseed the random number generator so random()/randomInt() sequences are reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        // xorshift gets stuck on a zero state
+        self.rng_state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+    }
+
+    // Limit the length of strings produced by `s * n` and repeat(s, n)
+    pub fn set_max_string_len(&mut self, max_len: usize) {
+        self.max_string_len = max_len;
+    }
+
+    // Limit how deeply nested containers are rendered when printed
+    pub fn set_max_print_depth(&mut self, depth: usize) {
+        self.max_print_depth = depth;
+    }
+
+    // Limit how many entries of a container are rendered when printed
+    pub fn set_max_print_elements(&mut self, count: usize) {
+        self.max_print_elements = count;
+    }
+
+    // Stop with a runtime error once this many steps have run: a step is a
+    // statement executed or a loop iteration begun
+    pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+        self.max_steps = max_steps;
+    }
+
+    // Stop with a runtime error when calls nest deeper than this
+    pub fn set_max_call_depth(&mut self, max_depth: Option<usize>) {
+        self.max_call_depth = max_depth;
+    }
+
+    // Stop with MemoryLimitExceeded when the variables in scope hold more
+    // than about this many bytes (see approx_size). A value is checked when
+    // a declaration or assignment stores it, and all of them every
+    // MEMORY_SWEEP_INTERVAL statements.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    // Refuse to run a program whose expressions nest deeper than this
+    pub fn set_max_expr_depth(&mut self, max_depth: usize) {
+        self.max_expr_depth = max_depth;
+    }
+
+    pub(crate) fn max_expr_depth(&self) -> usize {
+        self.max_expr_depth
+    }
+
+    // Call `hook` for every HookEvent from now on (see coverage.rs); None removes it
+    pub fn set_hook(&mut self, hook: Option<Hook>) {
+        self.hook = hook;
+    }
+
+    // The event is only built when a hook is set
+    fn notify(&mut self, event: impl FnOnce() -> HookEvent) {
+        if let Some(hook) = &mut self.hook {
+            hook(event());
+        }
+    }
+
+    // Switch between collecting printed lines and writing them to stdout
+    pub fn set_output_capture(&mut self, capture: bool) {
+        self.output = if capture { Some(Vec::new()) } else { None };
+    }
+
+    // Snapshot of the top-level bindings
+    pub fn globals(&self) -> BTreeMap<String, Value> {
+        self.globals.borrow().variables.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    // The current scope chain as Environment::dump prints it
+    pub fn dump_environment(&self) -> String {
+        self.environment.borrow().dump()
+    }
+
+    fn clock_seed() -> u64 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        nanos | 1
+    }
+
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Run the prelude's definitions (see stdlib.rs) into the globals; its
+    // statements do not count against the step limit, and its functions take
+    // ids from their own range so user functions are numbered as without it
+    pub fn load_prelude(&mut self, prelude: &Program) -> InterpreterResult<()> {
+        let steps = self.steps;
+        let next_function_id = std::mem::replace(&mut self.next_function_id, PRELUDE_FUNCTION_IDS);
+        let result = self.interpret(prelude);
+        self.steps = steps;
+        self.next_function_id = next_function_id;
+        result
+    }
+
     pub fn interpret(&mut self, program: &Program) -> InterpreterResult<()> {
-        match program {
+        self.run_program(program, false)
+    }
+
+    // Like interpret, but every top-level `var f := func ...` is defined
+    // before the first statement runs, so a function can be called above its
+    // declaration. Other declarations still take effect in order.
+    pub fn interpret_hoisted(&mut self, program: &Program) -> InterpreterResult<()> {
+        self.run_program(program, true)
+    }
+
+    fn run_program(&mut self, program: &Program, hoist: bool) -> InterpreterResult<()> {
+        check_nesting(program, self.max_expr_depth)?;
+
+        // bind local identifiers to frame slots before running
+        let mut program = program.clone();
+        Resolver::new().resolve(&mut program);
+
+        match &program {
             Program::Stmts(stmts) => {
+                if hoist {
+                    self.hoist_functions(stmts)?;
+                }
                 for stmt in stmts {
                     self.execute_stmt(stmt)?;
                 }
@@ -151,6 +1191,20 @@ impl Interpreter {
         }
     }
 
+    // Top-level functions close over the globals, which they look up by
+    // name when called, so they see the functions declared after them too
+    fn hoist_functions(&mut self, stmts: &[Stmt]) -> InterpreterResult<()> {
+        for stmt in stmts {
+            if let Stmt::VarDecl { name, init: Some(init), span } = stmt
+                && matches!(init, Expr::Func { .. })
+            {
+                let value = self.declared_function(name, init, *span)?;
+                self.globals.borrow_mut().define(name.clone(), value);
+            }
+        }
+        Ok(())
+    }
+
     fn is_truthy(&self, value: &Value) -> bool {
         match value {
             Value::Bool(b) => *b,
@@ -163,88 +1217,195 @@ impl Interpreter {
         }
     }
 
-    fn restore_parent(&mut self) {
-        let parent = {
-            let env_borrow = self.environment.borrow();
-            env_borrow.parent.as_ref().unwrap().clone()
-        };
-        self.environment = parent;
+    pub fn stats(&self) -> InterpreterStats {
+        self.stats
+    }
+
+    fn new_scope(&mut self, parent: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let env = Environment::new_with_parent(parent);
+        self.stats.scopes_created += 1;
+        self.stats.max_env_depth = self.stats.max_env_depth.max(env.depth);
+        Rc::new(RefCell::new(env))
+    }
+
+    fn define_local(&mut self, value: Value) -> usize {
+        self.stats.slots_defined += 1;
+        self.environment.borrow_mut().define_slot(value)
+    }
+
+    // Slots in the order the resolver declared the pattern's names
+    fn bind_loop_pattern(&mut self, pattern: &LoopPattern, item: Value) -> InterpreterResult<()> {
+        match pattern {
+            LoopPattern::Name(_) => {
+                self.define_local(item);
+            }
+            LoopPattern::Tuple(fields) => {
+                let Value::Tuple(tuple) = &item else {
+                    return Err(InterpreterError::TypeError(format!(
+                        "Cannot unpack {} with the loop pattern {}; the elements must be tuples", item.type_name(), pattern
+                    )));
+                };
+                for field in fields {
+                    let value = tuple.get(&field.field).cloned().ok_or_else(|| missing_tuple_field(tuple, &field.field))?;
+                    self.define_local(value);
+                }
+            }
+        }
+        Ok(())
     }
 
     fn execute_stmt(&mut self, stmt: &Stmt) -> InterpreterResult<()> {
+        // positions are only attached on the error path
+        self.count_step()
+            .and_then(|()| {
+                self.notify(|| HookEvent::Statement(stmt.span()));
+                self.execute_stmt_kind(stmt)
+            })
+            .map_err(|e| e.at(stmt.span()))
+    }
+
+    fn count_step(&mut self) -> InterpreterResult<()> {
+        self.steps += 1;
+        match self.max_steps {
+            Some(max) if self.steps > max => {
+                Err(InterpreterError::RuntimeError(format!("Step limit of {} exceeded", max)))
+            }
+            _ if self.steps.is_multiple_of(MEMORY_SWEEP_INTERVAL) => self.check_memory(),
+            _ => Ok(()),
+        }
+    }
+
+    // Sizes everything in the scope chain, the globals included
+    fn check_memory(&self) -> InterpreterResult<()> {
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        let mut seen = HashSet::new();
+        let mut total = 0;
+        let mut scope = Some(Rc::clone(&self.environment));
+        while let Some(env) = scope {
+            let env = env.borrow();
+            total += values_size(env.variables.values().chain(&env.slots), &mut seen);
+            scope = env.parent.clone();
+        }
+        if total > limit {
+            return Err(InterpreterError::MemoryLimitExceeded { limit });
+        }
+        Ok(())
+    }
+
+    // A value about to be stored in a variable, on its own
+    fn check_stored_size(&self, value: &Value) -> InterpreterResult<()> {
+        match self.memory_limit {
+            Some(limit) if approx_size(value) > limit => Err(InterpreterError::MemoryLimitExceeded { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn execute_stmt_kind(&mut self, stmt: &Stmt) -> InterpreterResult<()> {
         match stmt {
-            Stmt::VarDecl { name, init } => {
+            Stmt::VarDecl { name, init: None, .. } => {
+                let slot = self.declare(name, Value::None);
+                if self.strict_unset {
+                    self.environment.borrow_mut().mark_unset(name, slot);
+                }
+                Ok(())
+            }
+            Stmt::VarDecl { name, init: Some(init), span } => {
                 if matches!(init, Expr::Func { .. }) {
-                    self.environment.borrow_mut().define(name.clone(), Value::None);
+                    let slot = self.declare(name, Value::None);
+
+                    // calc the val
+                    let value = self.declared_function(name, init, *span)?;
+
+                    // update val (change None to real func)
+                    match slot {
+                        Some(slot) => self.environment.borrow_mut().slots[slot] = value,
+                        None => self.environment.borrow_mut().define(name.clone(), value),
+                    }
+                } else {
+                    let value = self.evaluate_expr(init).map_err(|e| e.own_initializer(name))?;
+                    self.check_stored_size(&value)?;
+                    self.declare(name, value);
                 }
                 
-                // calc the val
-                let value = self.evaluate_expr(init)?;
-                
-                // update val (change None to real func)
-                self.environment.borrow_mut().define(name.clone(), value);
-                
                 Ok(())
             }
             
 
-            Stmt::Assign { target, value } => {
+            Stmt::Assign { target, value, .. } => {
                 let val = self.evaluate_expr(value)?;
+                self.check_stored_size(&val)?;
                 self.assign_to_target(target, val)?;
                 Ok(())
             }
 
-            Stmt::Print { args } => {
+            // Both values are read before either is stored, so a failing read
+            // changes nothing; each index is evaluated once. Stores go through
+            // assignment, so a named tuple field's position is left as it was.
+            Stmt::Swap { left, right, .. } => {
+                let left = self.settle_target(left)?;
+                let right = self.settle_target(right)?;
+                let left_val = self.evaluate_expr(&left)?;
+                let right_val = self.evaluate_expr(&right)?;
+                self.assign_to_target(&left, right_val)?;
+                self.assign_to_target(&right, left_val)
+            }
+
+            Stmt::Print { args, .. } => {
                 let mut output = Vec::new();
                 for arg in args {
                     let val = self.evaluate_expr(arg)?;
                     output.push(self.value_to_string(&val));
                 }
-                println!("{}", output.join(" "));
+                let line = output.join(" ");
+                match self.output.as_mut() {
+                    Some(captured) => captured.push(line),
+                    None => println!("{}", line),
+                }
                 Ok(())
             }
 
-            Stmt::If { cond, then_branch, else_branch } => {
+            Stmt::If { cond, then_branch, else_branch, .. } => {
                 let cond_val = self.evaluate_expr(cond)?;
+                let cond_bool = if self.strict_bool {
+                    self.value_to_bool(&cond_val, "'if' condition")?
+                } else {
+                    self.is_truthy(&cond_val)
+                };
                 
-                if self.is_truthy(&cond_val) {
-                    let prev_env = Rc::clone(&self.environment);
-                    self.environment = Rc::new(RefCell::new(Environment::new_with_parent(prev_env)));
-                    
-                    for stmt in then_branch {
-                        self.execute_stmt(stmt)?;
-                    }
-                    
-                    self.restore_parent();
+                if cond_bool {
+                    self.execute_block(then_branch)?;
                 } else if let Some(else_branch) = else_branch {
-                    let prev_env = Rc::clone(&self.environment);
-                    self.environment = Rc::new(RefCell::new(Environment::new_with_parent(prev_env)));
-                    
-                    for stmt in else_branch {
-                        self.execute_stmt(stmt)?;
-                    }
-                    
-                    self.restore_parent();
+                    self.execute_block(else_branch)?;
                 }
                 
                 Ok(())
             }
             
 
-            Stmt::While { cond, body } => {
+            Stmt::While { label, cond, body, span } => {
                 let prev_inside_loop = self.inside_loop;
                 self.inside_loop = true;
 
-                loop {
-                    let cond_val = self.evaluate_expr(cond)?;
-                    let cond_bool = self.value_to_bool(&cond_val)?;
-                    if !cond_bool {
-                        break;
+                for iteration in 1.. {
+                    // each iteration is a step, so even an empty body runs out of them
+                    let cond_bool = self.count_step()
+                        .and_then(|()| self.evaluate_expr(cond))
+                        .and_then(|value| self.value_to_bool(&value, "'while' condition"));
+                    match cond_bool {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) => {
+                            self.inside_loop = prev_inside_loop;
+                            return Err(e);
+                        }
                     }
+                    self.notify(|| HookEvent::LoopIteration { span: *span, iteration });
 
                     match self.execute_block(body) {
                         Ok(()) => {}
-                        Err(InterpreterError::Exit) => {
+                        Err(e) if e.exits(label) => {
                             self.inside_loop = prev_inside_loop;
                             return Ok(());  // Exit breaks out of loop
                         }
@@ -255,35 +1416,65 @@ impl Interpreter {
                         }
                         Err(e) => {
                             self.inside_loop = prev_inside_loop;
-                            return Err(e);
+                            return Err(e.unmatched_exit(!prev_inside_loop));
                         }
                     }
                 }
 
                 self.inside_loop = prev_inside_loop;
-                Ok(())
+                Ok(())
+            }
+
+            Stmt::RepeatUntil { body, cond, span } => {
+                let prev_inside_loop = self.inside_loop;
+                self.inside_loop = true;
+
+                // the body runs at least once; the condition sees only the enclosing scope
+                let mut iteration = 0;
+                let result = loop {
+                    iteration += 1;
+                    if let Err(e) = self.count_step() {
+                        break Err(e);
+                    }
+                    self.notify(|| HookEvent::LoopIteration { span: *span, iteration });
+                    match self.execute_block(body) {
+                        Ok(()) => {}
+                        Err(e) if e.exits(&None) => break Ok(()),
+                        Err(e) => break Err(e.unmatched_exit(!prev_inside_loop)),
+                    }
+                    match self.evaluate_expr(cond).and_then(|value| self.value_to_bool(&value, "'until' condition")) {
+                        Ok(true) => break Ok(()),
+                        Ok(false) => {}
+                        Err(e) => break Err(e),
+                    }
+                };
+
+                self.inside_loop = prev_inside_loop;
+                result
             }
 
-            Stmt::For { var, iterable, body } => {
+            Stmt::For { label, var, iterable, body, span } => {
                 let prev_inside_loop = self.inside_loop;
                 self.inside_loop = true;
             
                 // Handle infinite loop (when iterable is None)
                 if matches!(iterable, Expr::None) {
-                    loop {
-                        let new_env = Environment::new_with_parent(Rc::clone(&self.environment));
-                        let old_env = std::mem::replace(
-                            &mut self.environment,
-                            Rc::new(RefCell::new(new_env))
-                        );
+                    for iteration in 1.. {
+                        if let Err(e) = self.count_step() {
+                            self.inside_loop = prev_inside_loop;
+                            return Err(e);
+                        }
+                        self.notify(|| HookEvent::LoopIteration { span: *span, iteration });
+                        let new_env = self.new_scope(Rc::clone(&self.environment));
+                        let old_env = std::mem::replace(&mut self.environment, new_env);
                         
-                        if var != "_" {
-                            self.environment.borrow_mut().define(var.clone(), Value::None);
+                        if !var.is_placeholder() {
+                            self.define_local(Value::None);
                         }
             
                         match self.execute_block(body) {
                             Ok(()) => {}
-                            Err(InterpreterError::Exit) => {
+                            Err(e) if e.exits(label) => {
                                 self.environment = old_env;
                                 self.inside_loop = prev_inside_loop;
                                 return Ok(());
@@ -296,7 +1487,7 @@ impl Interpreter {
                             Err(e) => {
                                 self.environment = old_env;
                                 self.inside_loop = prev_inside_loop;
-                                return Err(e);
+                                return Err(e.unmatched_exit(!prev_inside_loop));
                             }
                         }
             
@@ -315,18 +1506,26 @@ impl Interpreter {
                 };
                 let items = self.iterable_to_vec(&iterable_val)?;
             
-                for item in items {
-                    let new_env = Environment::new_with_parent(Rc::clone(&self.environment));
-                    let old_env = std::mem::replace(
-                        &mut self.environment,
-                        Rc::new(RefCell::new(new_env))
-                    );
+                // a fresh scope per iteration: functions created in the body
+                // keep the loop variables of the iteration that created them
+                for (item, iteration) in items.into_iter().zip(1..) {
+                    if let Err(e) = self.count_step() {
+                        self.inside_loop = prev_inside_loop;
+                        return Err(e);
+                    }
+                    self.notify(|| HookEvent::LoopIteration { span: *span, iteration });
+                    let new_env = self.new_scope(Rc::clone(&self.environment));
+                    let old_env = std::mem::replace(&mut self.environment, new_env);
                     
-                    self.environment.borrow_mut().define(var.clone(), item);
+                    if let Err(e) = self.bind_loop_pattern(var, item) {
+                        self.environment = old_env;
+                        self.inside_loop = prev_inside_loop;
+                        return Err(e);
+                    }
             
                     match self.execute_block(body) {
                         Ok(()) => {}
-                        Err(InterpreterError::Exit) => {
+                        Err(e) if e.exits(label) => {
                             self.environment = old_env;
                             self.inside_loop = prev_inside_loop;
                             return Ok(());
@@ -339,7 +1538,7 @@ impl Interpreter {
                         Err(e) => {
                             self.environment = old_env;
                             self.inside_loop = prev_inside_loop;
-                            return Err(e);
+                            return Err(e.unmatched_exit(!prev_inside_loop));
                         }
                     }
             
@@ -351,9 +1550,9 @@ impl Interpreter {
             }
             
 
-            Stmt::Return(expr) => {
+            Stmt::Return(expr, _) => {
                 if !self.inside_function {
-                    return Err(InterpreterError::RuntimeError("Return statement outside of function".to_string()));
+                    return Err(InterpreterError::RuntimeError(messages::RETURN_OUTSIDE_FUNCTION.to_string()));
                 }
                 let value = if let Some(expr) = expr {
                     self.evaluate_expr(expr)?
@@ -363,32 +1562,54 @@ impl Interpreter {
                 Err(InterpreterError::Return(value))
             }
 
-            Stmt::Exit => {
+            Stmt::Exit(label, _) => {
                 if !self.inside_loop {
-                    return Err(InterpreterError::RuntimeError("Exit statement outside of loop".to_string()));
+                    return Err(InterpreterError::RuntimeError(messages::EXIT_OUTSIDE_LOOP.to_string()));
                 }
-                Err(InterpreterError::Exit)
+                Err(InterpreterError::Exit(label.clone()))
+            }
+
+            Stmt::Include(path, _) => {
+                Err(InterpreterError::RuntimeError(format!("Unresolved include of '{}'", path)))
             }
 
-            Stmt::Expr(expr) => {
-                self.evaluate_expr(expr)?;
+            Stmt::Expr(expr, _) => {
+                // a call made for its effects may fall off the end
+                match expr {
+                    Expr::Call { callee, args } => self.evaluate_call(callee, args)?,
+                    _ => self.evaluate_expr(expr)?,
+                };
                 Ok(())
             }
         }
     }
 
+    // Top-level declarations are globals; everything else gets the next slot.
+    // Returns the slot index for locals.
+    fn declare(&mut self, name: &str, value: Value) -> Option<usize> {
+        if Rc::ptr_eq(&self.environment, &self.globals) {
+            self.globals.borrow_mut().define(name.to_string(), value);
+            None
+        } else {
+            Some(self.define_local(value))
+        }
+    }
+
     fn execute_block(&mut self, stmts: &[Stmt]) -> InterpreterResult<()> {
+        // a block that declares nothing would only lengthen the parent chain
+        // (the resolver leaves it out as well)
+        if !declares_locals(stmts) {
+            return stmts.iter().try_for_each(|stmt| self.execute_stmt(stmt));
+        }
+
         // create new scope
-        let new_env = Environment::new_with_parent(Rc::clone(&self.environment));
-        let old_env = std::mem::replace(
-            &mut self.environment,
-            Rc::new(RefCell::new(new_env))
-        );
+        let new_env = self.new_scope(Rc::clone(&self.environment));
+        let old_env = std::mem::replace(&mut self.environment, new_env);
 
         for stmt in stmts {
             match self.execute_stmt(stmt) {
                 Ok(()) => {}
-                Err(e @ InterpreterError::Return(_)) | Err(e @ InterpreterError::Exit) => {
+                Err(e @ InterpreterError::Return(_)) | Err(e @ InterpreterError::Exit(_)) => {
                     self.environment = old_env;
                     return Err(e);
                 }
@@ -403,95 +1624,257 @@ impl Interpreter {
         Ok(())
     }
 
+    // Every case that evaluates operands is a method of its own, so the
+    // frame of this function, which nested expressions recurse through,
+    // stays small
     fn evaluate_expr(&mut self, expr: &Expr) -> InterpreterResult<Value> {
         match expr {
             Expr::Integer(n) => Ok(Value::Integer(*n)),
             Expr::Real(n) => Ok(Value::Real(*n)),
             Expr::Bool(b) => Ok(Value::Bool(*b)),
-            Expr::String(s) => Ok(Value::String(s.clone())),
+            Expr::String(s) => Ok(Value::String(self.intern(s))),
             Expr::None => Ok(Value::None),
 
             Expr::Ident(name) => {
-                self.environment.borrow().get(name)  
-                    .ok_or_else(|| InterpreterError::UndefinedVariable(name.clone()))
+                if self.strict_unset && self.globals.borrow().is_unset(name) {
+                    return Err(InterpreterError::RuntimeError(messages::read_before_assignment(name)));
+                }
+                self.globals.borrow().get(name)  
+                    .ok_or_else(|| self.undefined_variable(name))
             }
-            
 
-            Expr::Binary { left, op, right } => {
-                let left_val = self.evaluate_expr(left)?;
-                let right_val = self.evaluate_expr(right)?;
-                self.evaluate_binary_op(op, &left_val, &right_val)
+            Expr::Local { name, depth, slot } => {
+                if self.strict_unset && self.environment.borrow().is_unset_at(*depth, *slot) {
+                    return Err(InterpreterError::RuntimeError(messages::read_before_assignment(name)));
+                }
+                self.environment.borrow().get_at(*depth, *slot)
+                    .ok_or_else(|| self.undefined_variable(name))
             }
 
-            Expr::Unary { op, expr } => {
-                let val = self.evaluate_expr(expr)?;
-                self.evaluate_unary_op(op, &val)
-            }
+            Expr::Binary { .. } => self.evaluate_binary_chain(expr),
+            Expr::Unary { op, expr } => self.evaluate_unary(op, expr),
+            Expr::Call { callee, args } => self.evaluate_used_call(callee, args),
+            Expr::Index { target, index } => self.evaluate_index_expr(target, index),
+            Expr::Member { target, field } => self.evaluate_member_expr(target, field),
+            Expr::Array(elems) => self.evaluate_array(elems),
+            Expr::Tuple(elems) => self.evaluate_tuple(elems),
+            Expr::Range(low, high) => self.evaluate_range_expr(low, high),
+
+            // the literals above take their spreads apart; the parser allows no other place
+            Expr::Spread(_) => Err(InterpreterError::RuntimeError(
+                "Spread '...' is only allowed inside an array or tuple literal".to_string(),
+            )),
+
+            Expr::IsType { expr, type_ind } => self.evaluate_is_type(expr, type_ind),
+            Expr::Func { params, body } => Ok(self.make_function(params, body)),
+        }
+    }
 
-            Expr::Call { callee, args } => {
-                let callee_val = self.evaluate_expr(callee)?;
-                let arg_values: Vec<Value> = args.iter()
-                    .map(|arg| self.evaluate_expr(arg))
-                    .collect::<Result<_, _>>()?;
+    fn evaluate_unary(&mut self, op: &UnOp, operand: &Expr) -> InterpreterResult<Value> {
+        let val = self.evaluate_expr(operand)?;
+        self.evaluate_unary_op(op, &val)
+    }
 
-                self.call_function(&callee_val, &arg_values)
-            }
+    // A call whose result the enclosing expression uses
+    fn evaluate_used_call(&mut self, callee: &Expr, args: &[Expr]) -> InterpreterResult<Value> {
+        let value = self.evaluate_call(callee, args)?;
+        match self.fell_through.take() {
+            Some(function) if self.error_on_implicit_none_return => Err(InterpreterError::RuntimeError(format!(
+                "{} ended without returning a value but its result is used", function
+            ))),
+            _ => Ok(value),
+        }
+    }
 
-            Expr::Index { target, index } => {
-                let target_val = self.evaluate_expr(target)?;
-                let index_val = self.evaluate_expr(index)?;
-                self.evaluate_index(&target_val, &index_val)
-            }
+    fn evaluate_index_expr(&mut self, target: &Expr, index: &Expr) -> InterpreterResult<Value> {
+        let target_val = self.evaluate_expr(target)?;
+        let index_val = self.evaluate_expr(index)?;
+        self.evaluate_index(target, &target_val, &index_val)
+    }
 
-            Expr::Member { target, field } => {
-                let target_val = self.evaluate_expr(target)?;
-                self.evaluate_member(&target_val, field)
-            }
+    fn evaluate_member_expr(&mut self, target: &Expr, field: &str) -> InterpreterResult<Value> {
+        let target_val = self.evaluate_expr(target)?;
+        self.evaluate_member(&target_val, field)
+    }
+
+    fn evaluate_range_expr(&mut self, low: &Expr, high: &Expr) -> InterpreterResult<Value> {
+        // Range is evaluated to produce a sequence for for loops
+        // For now, we'll handle it in iterable_to_vec
+        let low_val = self.evaluate_expr(low)?;
+        let high_val = self.evaluate_expr(high)?;
+        self.evaluate_range(&low_val, &high_val)
+    }
+
+    fn evaluate_is_type(&mut self, expr: &Expr, type_ind: &TypeIndicator) -> InterpreterResult<Value> {
+        let val = self.evaluate_expr(expr)?;
+        Ok(Value::Bool(self.check_type(&val, type_ind)))
+    }
 
-            Expr::Array(elems) => {
-                let values: Vec<Value> = elems.iter()
-                    .map(|elem| self.evaluate_expr(elem))
-                    .collect::<Result<_, _>>()?;
-                Ok(Value::Array(values))
+    // A function declared with `var`, named after its variable
+    fn declared_function(&mut self, name: &str, init: &Expr, span: Span) -> InterpreterResult<Value> {
+        let mut value = self.evaluate_expr(init)?;
+        if let Value::Function { name: fn_name, line, .. } = &mut value {
+            *fn_name = Some(name.to_string());
+            *line = span.line;
+        }
+        Ok(value)
+    }
+
+    fn make_function(&mut self, params: &[String], body: &FuncBody) -> Value {
+        let id = self.next_function_id;
+        self.next_function_id += 1;
+        Value::Function {
+            id,
+            name: None,
+            line: 0,
+            params: params.to_vec(),
+            body: body.clone(),
+            closure: Rc::clone(&self.environment),  
+        }
+    }
+
+    fn evaluate_array(&mut self, elems: &[Expr]) -> InterpreterResult<Value> {
+        let mut values = Vec::with_capacity(elems.len());
+        for elem in elems {
+            match elem {
+                Expr::Spread(operand) => match &self.evaluate_expr(operand)? {
+                    Value::Array(items) => values.extend(items.iter().cloned()),
+                    other => return Err(InterpreterError::TypeError(messages::bad_spread("array", other.type_name()))),
+                },
+                _ => values.push(self.evaluate_expr(elem)?),
             }
+        }
+        Ok(Value::array(values))
+    }
 
-            Expr::Tuple(elems) => {
-                let mut tuple = HashMap::new();
-                for (i, elem) in elems.iter().enumerate() {
-                    let value = self.evaluate_expr(&elem.value)?;
-                    
-                    if let Some(ref name) = elem.name {
-                        tuple.insert(name.clone(), value.clone());
-                    }
-                    
-                    tuple.insert((i + 1).to_string(), value);
+    fn evaluate_tuple(&mut self, elems: &[TupleElement]) -> InterpreterResult<Value> {
+        let mut tuple = TupleFields::default();
+        let mut position = 0;
+        for elem in elems {
+            // a spread tuple is merged as by `+`: its positions come next, its names override
+            if let Expr::Spread(operand) = &elem.value {
+                match &self.evaluate_expr(operand)? {
+                    Value::Tuple(spread) => append_tuple(&mut tuple, spread),
+                    other => return Err(InterpreterError::TypeError(messages::bad_spread("tuple", other.type_name()))),
                 }
-                Ok(Value::Tuple(tuple))
+                position = tuple.position_count();
+                continue;
             }
-            
+            let value = self.evaluate_expr(&elem.value)?;
+
+            // a name that came with a spread keeps its position
+            if let Some(name) = &elem.name
+                && let Some(shared) = tuple.position_of(name)
+            {
+                tuple.fields.insert(shared.to_string(), value.clone());
+                tuple.fields.insert(name.clone(), value);
+                continue;
+            }
+            position += 1;
+            tuple.insert_element(position, elem.name.clone(), value);
+        }
+        Ok(Value::Tuple(tuple))
+    }
 
-            Expr::Range(low, high) => {
-                // Range is evaluated to produce a sequence for for loops
-                // For now, we'll handle it in iterable_to_vec
+    // A call whose result may be discarded; see `fell_through`
+    fn evaluate_call(&mut self, callee: &Expr, args: &[Expr]) -> InterpreterResult<Value> {
+        self.fell_through = None;
+        if let Expr::Ident(name) = callee
+            && is_builtin(name)
+            && self.globals.borrow().get(name).is_none()
+        {
+            // aggregate a range literal from its bounds instead of materializing it
+            if let [Expr::Range(low, high)] = args
+                && matches!(name.as_str(), "sum" | "minOf" | "maxOf" | "avg")
+            {
                 let low_val = self.evaluate_expr(low)?;
                 let high_val = self.evaluate_expr(high)?;
-                self.evaluate_range(&low_val, &high_val)
+                return self.aggregate_range(name, &low_val, &high_val);
             }
 
-            Expr::IsType { expr, type_ind } => {
-                let val = self.evaluate_expr(expr)?;
-                Ok(Value::Bool(self.check_type(&val, type_ind)))
+            // arity(sum) and params(sum) ask about a builtin, which is not a value
+            if let [Expr::Ident(builtin)] = args
+                && matches!(name.as_str(), "arity" | "params")
+                && is_builtin(builtin)
+                && self.globals.borrow().get(builtin).is_none()
+            {
+                return builtin_signature(name, builtin);
             }
 
-            Expr::Func { params, body } => {
-                Ok(Value::Function {
-                    params: params.clone(),
-                    body: body.clone(),
-                    closure: Rc::clone(&self.environment),  
-                })
+            let arg_values: Vec<Value> = args.iter()
+                .map(|arg| self.evaluate_expr(arg))
+                .collect::<Result<_, _>>()?;
+            return self.call_builtin(name, &arg_values);
+        }
+
+        let callee_val = self.evaluate_expr(callee)?;
+        let arg_values: Vec<Value> = args.iter()
+            .map(|arg| self.evaluate_expr(arg))
+            .collect::<Result<_, _>>()?;
+
+        self.call_function(callee, &callee_val, &arg_values)
+    }
+
+    // `a - b * c + d ...` evaluated left to right like the nested Binary
+    // nodes, but along the left operands with a loop, so a chain as long as
+    // a generated program makes it cannot exhaust the stack. Once a `+` makes
+    // the running value a string, every later `+` operand is appended to that
+    // one buffer instead of building a new string per `+`; adding to a
+    // string always appends and cannot fail, so errors stay where they were.
+    fn evaluate_binary_chain(&mut self, expr: &Expr) -> InterpreterResult<Value> {
+        let mut operations = Vec::new();
+        let mut node = expr;
+        while let Expr::Binary { left, op, right } = node {
+            operations.push((op, right.as_ref()));
+            node = left;
+        }
+
+        let mut value = self.evaluate_expr(node)?;
+        let mut text: Option<String> = None;  // the running value, while it is a string being appended to
+        for (op, right) in operations.into_iter().rev() {
+            if matches!(op, BinOp::OrElse | BinOp::AndThen) {
+                if let Some(buffer) = text.take() {
+                    value = Value::String(buffer.into());
+                }
+                // the right operand only runs when the left one does not decide
+                if self.is_truthy(&value) != (*op == BinOp::OrElse) {
+                    value = self.evaluate_expr(right)?;
+                }
+                continue;
             }
-            
+            let right_val = self.evaluate_expr(right)?;
+            if *op == BinOp::Add {
+                if text.is_none() && let Value::String(prefix) = &value {
+                    text = Some(prefix.to_string());
+                }
+                if let Some(buffer) = &mut text {
+                    match &right_val {
+                        Value::String(s) => buffer.push_str(s),
+                        other => buffer.push_str(&self.value_to_string(other)),
+                    }
+                    continue;
+                }
+            }
+            if let Some(buffer) = text.take() {
+                value = Value::String(buffer.into());
+            }
+            value = self.evaluate_binary_op(op, &value, &right_val)?;
+        }
+        Ok(match text {
+            Some(buffer) => Value::String(buffer.into()),
+            None => value,
+        })
+    }
+
+    // The shared text of a string literal, so evaluating it again (say, in a
+    // loop) does not copy it and equal literals compare by pointer
+    fn intern(&mut self, literal: &str) -> Rc<str> {
+        if let Some(text) = self.literals.get(literal) {
+            return Rc::clone(text);
         }
+        let text: Rc<str> = literal.into();
+        self.literals.insert(literal.to_string(), Rc::clone(&text));
+        text
     }
 
     fn evaluate_binary_op(&self, op: &BinOp, left: &Value, right: &Value) -> InterpreterResult<Value> {
@@ -500,33 +1883,46 @@ impl Interpreter {
             BinOp::Sub => self.sub_values(left, right),
             BinOp::Mul => self.mul_values(left, right),
             BinOp::Div => self.div_values(left, right),
+            BinOp::IntDiv => self.int_div_values(left, right),
             BinOp::Eq => Ok(Value::Bool(left == right)),
             BinOp::Ne => Ok(Value::Bool(left != right)),
-            BinOp::Lt => self.compare_values(left, right, |a, b| a < b),
-            BinOp::Le => self.compare_values(left, right, |a, b| a <= b),
-            BinOp::Gt => self.compare_values(left, right, |a, b| a > b),
-            BinOp::Ge => self.compare_values(left, right, |a, b| a >= b),
+            BinOp::Lt => self.compare_values(left, right, Ordering::is_lt),
+            BinOp::Le => self.compare_values(left, right, Ordering::is_le),
+            BinOp::Gt => self.compare_values(left, right, Ordering::is_gt),
+            BinOp::Ge => self.compare_values(left, right, Ordering::is_ge),
+            // two integers combine bitwise; anything else is logical
+            BinOp::And | BinOp::Or | BinOp::Xor if let (Value::Integer(a), Value::Integer(b)) = (left, right) => {
+                Ok(Value::Integer(match op {
+                    BinOp::And => a & b,
+                    BinOp::Or => a | b,
+                    _ => a ^ b,
+                }))
+            }
+            BinOp::Shl | BinOp::Shr => self.shift_values(op, left, right),
             BinOp::And => {
-                let left_bool = self.value_to_bool(left)?;
+                let left_bool = self.value_to_bool(left, "Operand of 'and'")?;
                 if !left_bool {
                     Ok(Value::Bool(false))
                 } else {
-                    Ok(Value::Bool(self.value_to_bool(right)?))
+                    Ok(Value::Bool(self.value_to_bool(right, "Operand of 'and'")?))
                 }
             }
             BinOp::Or => {
-                let left_bool = self.value_to_bool(left)?;
+                let left_bool = self.value_to_bool(left, "Operand of 'or'")?;
                 if left_bool {
                     Ok(Value::Bool(true))
                 } else {
-                    Ok(Value::Bool(self.value_to_bool(right)?))
+                    Ok(Value::Bool(self.value_to_bool(right, "Operand of 'or'")?))
                 }
             }
             BinOp::Xor => {
-                let left_bool = self.value_to_bool(left)?;
-                let right_bool = self.value_to_bool(right)?;
+                let left_bool = self.value_to_bool(left, "Operand of 'xor'")?;
+                let right_bool = self.value_to_bool(right, "Operand of 'xor'")?;
                 Ok(Value::Bool(left_bool ^ right_bool))
             }
+            BinOp::OrElse if self.is_truthy(left) => Ok(left.clone()),
+            BinOp::AndThen if !self.is_truthy(left) => Ok(left.clone()),
+            BinOp::OrElse | BinOp::AndThen => Ok(right.clone()),
             BinOp::Is => {
                 Err(InterpreterError::InvalidOperation("'is' operator should be used as 'expr is type'".to_string()))
             }
@@ -537,13 +1933,18 @@ impl Interpreter {
         match op {
             UnOp::Neg => {
                 match val {
-                    Value::Integer(n) => Ok(Value::Integer(-n)),
+                    Value::Integer(n) => n.checked_neg().map(Value::Integer)
+                        .ok_or_else(|| InterpreterError::RuntimeError("Integer overflow in negation".to_string())),
                     Value::Real(n) => Ok(Value::Real(-n)),
                     _ => Err(InterpreterError::TypeError("Cannot negate non-numeric value".to_string())),
                 }
             }
+            UnOp::Plus => match val {
+                Value::Integer(_) | Value::Real(_) => Ok(val.clone()),
+                _ => Err(InterpreterError::TypeError(messages::unary_plus_operand(val.type_name()))),
+            },
             UnOp::Not => {
-                let bool_val = self.value_to_bool(val)?;
+                let bool_val = self.value_to_bool(val, "Operand of 'not'")?;
                 Ok(Value::Bool(!bool_val))
             }
         }
@@ -552,17 +1953,17 @@ impl Interpreter {
     fn add_values(&self, left: &Value, right: &Value) -> InterpreterResult<Value> {
         match (left, right) {
             (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a + b)),
-            (Value::Real(a), Value::Real(b)) => Ok(Value::Real(a + b)),
-            (Value::Integer(a), Value::Real(b)) => Ok(Value::Real(*a as f64 + b)),
-            (Value::Real(a), Value::Integer(b)) => Ok(Value::Real(a + *b as f64)),
-            (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
+            (Value::Real(a), Value::Real(b)) => finite_real(a + b),
+            (Value::Integer(a), Value::Real(b)) => finite_real(*a as f64 + b),
+            (Value::Real(a), Value::Integer(b)) => finite_real(a + *b as f64),
+            (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b).into())),
             (Value::Tuple(a), Value::Tuple(b)) => {
                 let mut result = a.clone();
-                result.extend(b.clone());  // join two HashMap
+                append_tuple(&mut result, b);
                 Ok(Value::Tuple(result))
             }
-            (Value::String(a), b) => Ok(Value::String(format!("{}{}", a, self.value_to_string(b)))),
-            (a, Value::String(b)) => Ok(Value::String(format!("{}{}", self.value_to_string(a), b))),
+            (Value::String(a), b) => Ok(Value::String(format!("{}{}", a, self.value_to_string(b)).into())),
+            (a, Value::String(b)) => Ok(Value::String(format!("{}{}", self.value_to_string(a), b).into())),
             _ => Err(InterpreterError::TypeError("Invalid operands for addition".to_string())),
         }
     }
@@ -570,9 +1971,9 @@ impl Interpreter {
     fn sub_values(&self, left: &Value, right: &Value) -> InterpreterResult<Value> {
         match (left, right) {
             (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a - b)),
-            (Value::Real(a), Value::Real(b)) => Ok(Value::Real(a - b)),
-            (Value::Integer(a), Value::Real(b)) => Ok(Value::Real(*a as f64 - b)),
-            (Value::Real(a), Value::Integer(b)) => Ok(Value::Real(a - *b as f64)),
+            (Value::Real(a), Value::Real(b)) => finite_real(a - b),
+            (Value::Integer(a), Value::Real(b)) => finite_real(*a as f64 - b),
+            (Value::Real(a), Value::Integer(b)) => finite_real(a - *b as f64),
             _ => Err(InterpreterError::TypeError("Invalid operands for subtraction".to_string())),
         }
     }
@@ -580,54 +1981,136 @@ impl Interpreter {
     fn mul_values(&self, left: &Value, right: &Value) -> InterpreterResult<Value> {
         match (left, right) {
             (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a * b)),
-            (Value::Real(a), Value::Real(b)) => Ok(Value::Real(a * b)),
-            (Value::Integer(a), Value::Real(b)) => Ok(Value::Real(*a as f64 * b)),
-            (Value::Real(a), Value::Integer(b)) => Ok(Value::Real(a * *b as f64)),
+            (Value::Real(a), Value::Real(b)) => finite_real(a * b),
+            (Value::Integer(a), Value::Real(b)) => finite_real(*a as f64 * b),
+            (Value::Real(a), Value::Integer(b)) => finite_real(a * *b as f64),
+            (Value::String(s), Value::Integer(n)) | (Value::Integer(n), Value::String(s)) => {
+                repeat_string(s, *n, self.max_string_len)
+                    .map(|s| Value::String(s.into()))
+                    .map_err(InterpreterError::RuntimeError)
+            }
             _ => Err(InterpreterError::TypeError("Invalid operands for multiplication".to_string())),
         }
     }
 
     fn div_values(&self, left: &Value, right: &Value) -> InterpreterResult<Value> {
         match (left, right) {
-            (Value::Integer(a), Value::Integer(b)) => {
-                if *b == 0 {
-                    Err(InterpreterError::DivisionByZero)
-                } else {
-                    Ok(Value::Integer(a / b))
-                }
-            }
+            (Value::Integer(_), Value::Integer(_)) => self.int_div_values(left, right),
             (Value::Real(a), Value::Real(b)) => {
                 if *b == 0.0 {
                     Err(InterpreterError::DivisionByZero)
                 } else {
-                    Ok(Value::Real(a / b))
+                    finite_real(a / b)
                 }
             }
             (Value::Integer(a), Value::Real(b)) => {
                 if *b == 0.0 {
                     Err(InterpreterError::DivisionByZero)
                 } else {
-                    Ok(Value::Real(*a as f64 / b))
+                    finite_real(*a as f64 / b)
                 }
             }
             (Value::Real(a), Value::Integer(b)) => {
                 if *b == 0 {
                     Err(InterpreterError::DivisionByZero)
                 } else {
-                    Ok(Value::Real(a / *b as f64))
+                    finite_real(a / *b as f64)
                 }
             }
             _ => Err(InterpreterError::TypeError("Invalid operands for division".to_string())),
         }
     }
 
+    // `div` and integer `/`: truncating division
+    fn int_div_values(&self, left: &Value, right: &Value) -> InterpreterResult<Value> {
+        match (left, right) {
+            (Value::Integer(_), Value::Integer(0)) => Err(InterpreterError::DivisionByZero),
+            (Value::Integer(a), Value::Integer(b)) => a.checked_div(*b)
+                .map(Value::Integer)
+                .ok_or_else(|| InterpreterError::RuntimeError("Integer overflow in division".to_string())),
+            _ => Err(InterpreterError::TypeError("Operator 'div' expects integer operands".to_string())),
+        }
+    }
+
+    // Bits shifted out are dropped; `>>` keeps the sign
+    fn shift_values(&self, op: &BinOp, left: &Value, right: &Value) -> InterpreterResult<Value> {
+        match (left, right) {
+            (Value::Integer(a), Value::Integer(b)) if (0..64).contains(b) => Ok(Value::Integer(match op {
+                BinOp::Shl => a << b,
+                _ => a >> b,
+            })),
+            (Value::Integer(_), Value::Integer(b)) => {
+                Err(InterpreterError::RuntimeError(format!("Shift amount must be between 0 and 63, got {}", b)))
+            }
+            _ => Err(InterpreterError::TypeError(format!("Operator '{}' expects integer operands", op))),
+        }
+    }
+
     fn compare_values<F>(&self, left: &Value, right: &Value, cmp: F) -> InterpreterResult<Value>
     where
-        F: FnOnce(f64, f64) -> bool,
+        F: FnOnce(Ordering) -> bool,
     {
-        let left_num = self.value_to_number(left)?;
-        let right_num = self.value_to_number(right)?;
-        Ok(Value::Bool(cmp(left_num, right_num)))
+        Ok(Value::Bool(cmp(self.order_values(left, right)?)))
+    }
+
+    // The order behind `<` and friends, minOf and maxOf: numbers by value,
+    // arrays lexicographically (a proper prefix comes first). Walked with an
+    // explicit stack so deeply nested arrays cannot overflow the native one.
+    fn order_values(&self, left: &Value, right: &Value) -> InterpreterResult<Ordering> {
+        // arrays whose elements are being compared, with the next index
+        let mut pending: Vec<(&[Value], &[Value], usize)> = Vec::new();
+        let mut next = Some((left, right));
+        loop {
+            if let Some((left, right)) = next.take() {
+                let ordering = match (left, right) {
+                    (Value::Array(a), Value::Array(b)) => {
+                        pending.push((a, b, 0));
+                        Ordering::Equal
+                    }
+                    _ => self.order_scalars(left, right)?,
+                };
+                if ordering.is_ne() {
+                    return Ok(ordering);
+                }
+            }
+            let Some((a, b, index)) = pending.last_mut() else {
+                return Ok(Ordering::Equal);
+            };
+            let (a, b) = (*a, *b);
+            if *index < a.len() && *index < b.len() {
+                next = Some((&a[*index], &b[*index]));
+                *index += 1;
+            } else {
+                pending.pop();
+                if a.len() != b.len() {
+                    return Ok(a.len().cmp(&b.len()));
+                }
+            }
+        }
+    }
+
+    // integers compare exactly; anything involving a real agrees with `=`
+    fn order_scalars(&self, left: &Value, right: &Value) -> InterpreterResult<Ordering> {
+        match (left, right) {
+            (Value::Integer(a), Value::Integer(b)) => Ok(a.cmp(b)),
+            (Value::Integer(_) | Value::Real(_), Value::Integer(_) | Value::Real(_)) => {
+                let left_num = self.value_to_number(left)?;
+                let right_num = self.value_to_number(right)?;
+                Ok(if reals_equal(left_num, right_num) {
+                    Ordering::Equal
+                } else if left_num < right_num {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                })
+            }
+            (Value::Tuple(_), _) | (_, Value::Tuple(_)) => {
+                Err(InterpreterError::TypeError("Tuples are unordered; compare their fields instead".to_string()))
+            }
+            _ => Err(InterpreterError::TypeError(format!(
+                "Cannot order {} and {}", left.type_name(), right.type_name()
+            ))),
+        }
     }
 
     fn value_to_number(&self, val: &Value) -> InterpreterResult<f64> {
@@ -638,7 +2121,11 @@ impl Interpreter {
         }
     }
 
-    fn value_to_bool(&self, val: &Value) -> InterpreterResult<bool> {
+    // `construct` names what needed the bool, for the strict-mode error
+    fn value_to_bool(&self, val: &Value, construct: &str) -> InterpreterResult<bool> {
+        if self.strict_bool && !matches!(val, Value::Bool(_)) {
+            return Err(InterpreterError::TypeError(messages::not_a_bool(construct, val.type_name())));
+        }
         match val {
             Value::Bool(b) => Ok(*b),
             Value::Integer(n) => Ok(*n != 0),
@@ -647,63 +2134,115 @@ impl Interpreter {
             Value::String(s) => Ok(!s.is_empty()),
             Value::Array(arr) => Ok(!arr.is_empty()),
             Value::Tuple(tuple) => Ok(!tuple.is_empty()),
+            Value::Map(map) => Ok(!map.is_empty()),
             Value::Function { .. } => Ok(true),
         }
     }
 
     fn value_to_string(&self, val: &Value) -> String {
+        self.render_value(val, 0)
+    }
+
+    fn render_value(&self, val: &Value, depth: usize) -> String {
+        if val.has_children() && depth >= self.max_print_depth {
+            return "...".to_string();
+        }
         match val {
             Value::Integer(n) => n.to_string(),
-            Value::Real(n) => {
-                // Format to avoid unnecessary decimals
-                if n.fract() == 0.0 {
-                    format!("{:.0}", n)
-                } else {
-                    n.to_string()
-                }
-            }
+            Value::Real(n) => format_real(*n),
             Value::Bool(b) => b.to_string(),
-            Value::String(s) => s.clone(),
+            Value::String(s) => s.to_string(),
             Value::None => "none".to_string(),
             Value::Array(arr) => {
-                let elems: Vec<String> = arr.iter().map(|v| self.value_to_string(v)).collect();
-                format!("[{}]", elems.join(", "))
+                let elems: Vec<String> = arr.iter()
+                    .take(self.max_print_elements)
+                    .map(|v| self.render_value(v, depth + 1))
+                    .collect();
+                format!("[{}]", self.join_elided(elems, arr.len()))
             }
             Value::Tuple(tuple) => {
                 let mut pairs: Vec<String> = tuple.iter()
-                    .map(|(k, v)| format!("{}: {}", k, self.value_to_string(v)))
+                    .map(|(k, v)| format!("{}: {}", k, self.render_value(v, depth + 1)))
                     .collect();
                 pairs.sort();  // For consistent output
-                format!("{{{}}}", pairs.join(", "))
+                pairs.truncate(self.max_print_elements);
+                format!("{{{}}}", self.join_elided(pairs, tuple.len()))
+            }
+            Value::Map(map) => {
+                let pairs: Vec<String> = map.iter()
+                    .take(self.max_print_elements)
+                    .map(|(k, v)| format!("{}: {}", k, self.render_value(v, depth + 1)))
+                    .collect();
+                format!("map{{{}}}", self.join_elided(pairs, map.len()))
+            }
+            Value::Function { name, line, params, .. } => {
+                let signature = format!("{}({})", name.as_deref().unwrap_or(""), params.join(", "));
+                match (name, line) {
+                    (Some(_), 1..) => format!("<func {} at line {}>", signature, line),
+                    (Some(_), _) => format!("<func {}>", signature),
+                    (None, _) => format!("<func{}>", signature),
+                }
+            }
+        }
+    }
+
+    fn join_elided(&self, parts: Vec<String>, total: usize) -> String {
+        let shown = parts.len();
+        let mut joined = parts.join(", ");
+        if total > shown {
+            if shown > 0 {
+                joined.push_str(", ");
             }
-            Value::Function { .. } => "<function>".to_string(),
+            joined.push_str(&format!("... (+{} more)", total - shown));
         }
+        joined
+    }
+
+    // Locals are resolved to slots before running, so an unknown name can
+    // only be a misspelled global or builtin
+    fn undefined_variable(&self, name: &str) -> InterpreterError {
+        let globals = self.globals.borrow().names();
+        let candidates = globals.iter().map(String::as_str).chain(BUILTINS.iter().map(|(n, _, _)| *n));
+        let suggestion = suggest_name(name, candidates).map(str::to_string);
+        InterpreterError::UndefinedVariable { name: name.to_string(), suggestion }
     }
 
-    fn evaluate_index(&mut self, target: &Value, index: &Value) -> InterpreterResult<Value> {
+    // `target_expr` is only used to word the error for indexing a function
+    fn evaluate_index(&mut self, target_expr: &Expr, target: &Value, index: &Value) -> InterpreterResult<Value> {
+        if let Value::Function { .. } = target {
+            let hint = match target_expr {
+                Expr::Ident(name) => format!("did you mean '{}(...)' to call it?", name),
+                _ => "call it with parentheses instead".to_string(),
+            };
+            return Err(InterpreterError::TypeError(format!("Cannot index a function; {}", hint)));
+        }
+        if let Value::Map(map) = target {
+            let key = self.map_key(index)?;
+            return map.get(&key)
+                .cloned()
+                .ok_or_else(|| InterpreterError::RuntimeError(format!("Map key '{}' not found", key)));
+        }
+
         let index_num = match index {
             Value::Integer(n) => *n,
             _ => return Err(InterpreterError::TypeError("Array index must be an integer".to_string())),
         };
 
         match target {
-            Value::Array(arr) => {
-                // Arrays are 1-indexed
-                if index_num < 1 || index_num > arr.len() as i64 {
-                    Err(InterpreterError::IndexOutOfBounds {
-                        index: index_num,
-                        size: arr.len(),
-                    })
-                } else {
-                    Ok(arr[(index_num - 1) as usize].clone())
-                }
-            }
+            Value::Array(arr) => match self.index_base.offset(index_num, arr.len()) {
+                Some(offset) => Ok(arr[offset].clone()),
+                None => Err(InterpreterError::IndexOutOfBounds {
+                    index: index_num,
+                    size: arr.len(),
+                    base: self.index_base,
+                }),
+            },
             Value::Tuple(tuple) => {
                 // Tuples can be indexed by number (as string) or by name
                 let key = index_num.to_string();
                 tuple.get(&key)
                     .cloned()
-                    .ok_or_else(|| InterpreterError::RuntimeError(format!("Tuple field '{}' not found", key)))
+                    .ok_or_else(|| missing_tuple_field(tuple, &key))
             }
             _ => Err(InterpreterError::TypeError("Cannot index non-array/non-tuple value".to_string())),
         }
@@ -714,7 +2253,7 @@ impl Interpreter {
             Value::Tuple(tuple) => {
                 tuple.get(field)
                     .cloned()
-                    .ok_or_else(|| InterpreterError::RuntimeError(format!("Tuple field '{}' not found", field)))
+                    .ok_or_else(|| missing_tuple_field(tuple, field))
             }
             _ => Err(InterpreterError::TypeError("Cannot access member of non-tuple value".to_string())),
         }
@@ -724,11 +2263,11 @@ impl Interpreter {
         // Range evaluation: create an array of values from low to high (inclusive)
         let low_num = match low {
             Value::Integer(n) => *n,
-            _ => return Err(InterpreterError::TypeError("Range start must be an integer".to_string())),
+            _ => return Err(InterpreterError::TypeError(messages::range_bound_not_integer("start"))),
         };
         let high_num = match high {
             Value::Integer(n) => *n,
-            _ => return Err(InterpreterError::TypeError("Range end must be an integer".to_string())),
+            _ => return Err(InterpreterError::TypeError(messages::range_bound_not_integer("end"))),
         };
 
         let mut values = Vec::new();
@@ -742,12 +2281,13 @@ impl Interpreter {
                 values.push(Value::Integer(i));
             }
         }
-        Ok(Value::Array(values))
+        Ok(Value::array(values))
     }
 
     fn iterable_to_vec(&mut self, val: &Value) -> InterpreterResult<Vec<Value>> {
         match val {
-            Value::Array(arr) => Ok(arr.clone()),
+            Value::Array(arr) => Ok(arr.to_vec()),
+            Value::Map(map) => Ok(map.keys().map(|k| Value::String(k.as_str().into())).collect()),
             _ => Err(InterpreterError::TypeError("Cannot iterate over non-iterable value".to_string())),
         }
     }
@@ -766,29 +2306,33 @@ impl Interpreter {
         }
     }
 
-    fn call_function(&mut self, callee: &Value, args: &[Value]) -> InterpreterResult<Value> {
+    // `callee_expr` is only used to word the error for calling a non-function
+    fn call_function(&mut self, callee_expr: &Expr, callee: &Value, args: &[Value]) -> InterpreterResult<Value> {
         match callee {
-            Value::Function { params, body, closure } => {
+            Value::Function { name, line, params, body, closure, .. } => {
                 if params.len() != args.len() {
-                    return Err(InterpreterError::RuntimeError(format!(
-                        "Function expects {} arguments, got {}",
-                        params.len(),
-                        args.len()
-                    )));
+                    return Err(InterpreterError::RuntimeError(messages::arity(name.as_deref(), params, args.len())));
                 }
+                self.notify(|| HookEvent::Call { name: name.clone(), line: *line });
     
                 
-                let new_env = Rc::new(RefCell::new(Environment::new_with_parent(
-                    Rc::clone(closure)  
-                )));
+                if let Some(max) = self.max_call_depth && self.call_depth >= max {
+                    return Err(InterpreterError::RuntimeError(format!(
+                        "Maximum call depth of {} exceeded",
+                        max
+                    )));
+                }
+                self.call_depth += 1;
+
+                let new_env = self.new_scope(Rc::clone(closure));
                 
                 let old_env = std::mem::replace(&mut self.environment, new_env);
                 let prev_inside_function = self.inside_function;
                 self.inside_function = true;
     
                 // Bind parameters
-                for (param, arg) in params.iter().zip(args.iter()) {
-                    self.environment.borrow_mut().define(param.clone(), arg.clone());
+                for arg in args {
+                    self.define_local(arg.clone());
                 }
     
                 // Execute function body
@@ -801,30 +2345,46 @@ impl Interpreter {
                         }
                     }
                     FuncBody::Block(stmts) => {
-                        let mut return_val = Value::None;
+                        let mut return_val = None;
                         for stmt in stmts {
                             match self.execute_stmt(stmt) {
                                 Ok(()) => {}
                                 Err(InterpreterError::Return(val)) => {
-                                    return_val = val;
+                                    return_val = Some(val);
                                     break;
                                 }
                                 Err(e) => {
                                     self.environment = old_env;
                                     self.inside_function = prev_inside_function;
+                                    self.call_depth -= 1;
                                     return Err(e);
                                 }
                             }
                         }
-                        Ok(return_val)
+                        // statements in the body may have left their own calls' marks
+                        self.fell_through = match return_val {
+                            Some(_) => None,
+                            None => Some(name.as_ref().map_or_else(|| "function".to_string(), |n| format!("function '{}'", n))),
+                        };
+                        Ok(return_val.unwrap_or(Value::None))
                     }
                 };
     
                 self.environment = old_env;
                 self.inside_function = prev_inside_function;
+                self.call_depth -= 1;
                 result
             }
-            _ => Err(InterpreterError::TypeError("Cannot call non-function value".to_string())),
+            _ => {
+                // `arr(1)` for `arr[1]`
+                let hint = match (callee_expr, callee) {
+                    (Expr::Ident(name), Value::Array(_) | Value::Tuple(_) | Value::Map(_)) => {
+                        format!("; did you mean '{}[...]' for indexing?", name)
+                    }
+                    _ => String::new(),
+                };
+                Err(InterpreterError::TypeError(format!("Cannot call non-function value of type {}{}", callee.type_name(), hint)))
+            }
         }
     }
     
@@ -833,70 +2393,89 @@ impl Interpreter {
     fn assign_to_target(&mut self, target: &Expr, value: Value) -> InterpreterResult<()> {
         match target {
             Expr::Ident(name) => {
-                if !self.environment.borrow_mut().assign(name, value) {
-                    return Err(InterpreterError::UndefinedVariable(name.clone()));
+                if !self.globals.borrow_mut().assign(name, value) {
+                    return Err(self.undefined_variable(name));
+                }
+                Ok(())
+            }
+
+            Expr::Local { name, depth, slot } => {
+                if !self.environment.borrow_mut().assign_at(*depth, *slot, value) {
+                    return Err(self.undefined_variable(name));
                 }
                 Ok(())
             }
     
             Expr::Index { target: arr_expr, index } => {
-                let arr_val = self.evaluate_expr(arr_expr)?;
+                let mut arr_val = self.evaluate_expr(arr_expr)?;
                 let index_val = self.evaluate_expr(index)?;
     
-                match arr_val {
-                    Value::Array(mut arr) => {
+                match &mut arr_val {
+                    Value::Array(arr) => {
                         let index_num = match index_val {
                             Value::Integer(n) => n,
                             _ => return Err(InterpreterError::TypeError("Array index must be an integer".to_string())),
                         };
     
-                        if index_num < 1 || index_num > arr.len() as i64 {
-                            return Err(InterpreterError::IndexOutOfBounds {
+                        let offset = self.index_base.offset(index_num, arr.len()).ok_or(
+                            InterpreterError::IndexOutOfBounds {
                                 index: index_num,
                                 size: arr.len(),
-                            });
-                        }
+                                base: self.index_base,
+                            },
+                        )?;
     
-                        arr[(index_num - 1) as usize] = value;
-    
-                        if let Expr::Ident(name) = arr_expr.as_ref() {
-                            self.environment.borrow_mut().define(name.clone(), Value::Array(arr));
-                        } else {
+                        if !Self::is_variable(arr_expr) {
                             return Err(InterpreterError::RuntimeError("Cannot assign to non-variable array".to_string()));
                         }
-                        Ok(())
+                        // the variable lets go of its copy first, so when nothing
+                        // else shares the elements they are changed in place
+                        self.assign_to_target(arr_expr, Value::None)?;
+                        Rc::make_mut(arr)[offset] = value;
+                        self.assign_to_target(arr_expr, arr_val)
                     }
                     
-                    Value::Tuple(mut tuple) => {
-                        let key = match index_val {
+                    Value::Tuple(tuple) => {
+                        let key = match &index_val {
                             Value::Integer(n) => n.to_string(),
-                            Value::String(s) => s,
+                            Value::String(s) => s.to_string(),
                             _ => return Err(InterpreterError::TypeError("Tuple index must be integer or string".to_string())),
                         };
+                        set_tuple_field(tuple, &key, value)?;
     
-                        tuple.insert(key.clone(), value);
-    
-                        if let Expr::Ident(name) = arr_expr.as_ref() {
-                            self.environment.borrow_mut().define(name.clone(), Value::Tuple(tuple));
+                        if Self::is_variable(arr_expr) {
+                            self.assign_to_target(arr_expr, arr_val)?;
                         } else {
                             return Err(InterpreterError::RuntimeError("Cannot assign to non-variable tuple".to_string()));
                         }
                         Ok(())
                     }
+
+                    Value::Map(map) => {
+                        let key = self.map_key(&index_val)?;
+                        map.insert(key, value);
+
+                        if Self::is_variable(arr_expr) {
+                            self.assign_to_target(arr_expr, arr_val)?;
+                        } else {
+                            return Err(InterpreterError::RuntimeError("Cannot assign to non-variable map".to_string()));
+                        }
+                        Ok(())
+                    }
                     
                     _ => Err(InterpreterError::TypeError("Cannot assign to non-array/non-tuple value".to_string())),
                 }
             }
     
             Expr::Member { target, field } => {
-                let tuple_val = self.evaluate_expr(target)?;
+                let mut tuple_val = self.evaluate_expr(target)?;
     
-                match tuple_val {
-                    Value::Tuple(mut tuple) => {
-                        tuple.insert(field.clone(), value);
+                match &mut tuple_val {
+                    Value::Tuple(tuple) => {
+                        set_tuple_field(tuple, field, value)?;
     
-                        if let Expr::Ident(name) = target.as_ref() {
-                            self.environment.borrow_mut().define(name.clone(), Value::Tuple(tuple));
+                        if Self::is_variable(target) {
+                            self.assign_to_target(target, tuple_val)?;
                         } else {
                             return Err(InterpreterError::RuntimeError("Cannot assign to non-variable tuple".to_string()));
                         }
@@ -909,5 +2488,349 @@ impl Interpreter {
             _ => Err(InterpreterError::RuntimeError("Invalid assignment target".to_string())),
         }
     }
-    
+
+    // A swap operand with its index replaced by the index's value, so reading
+    // and then storing through it evaluates the index only once
+    fn settle_target(&mut self, target: &Expr) -> InterpreterResult<Expr> {
+        if !target.is_assignable() {
+            return Err(InterpreterError::RuntimeError(messages::not_swappable(&target.to_string())));
+        }
+        match target {
+            Expr::Index { target: container, index } => {
+                let index = match &self.evaluate_expr(index)? {
+                    Value::Integer(n) => Expr::Integer(*n),
+                    Value::String(s) => Expr::String(s.to_string()),
+                    Value::Bool(b) => Expr::Bool(*b),
+                    Value::Real(n) => Expr::Real(*n),
+                    Value::None => Expr::None,
+                    // not a key any container takes; reading through it reports that
+                    _ => return Ok(target.clone()),
+                };
+                Ok(Expr::Index { target: container.clone(), index: Box::new(index) })
+            }
+            _ => Ok(target.clone()),
+        }
+    }
+
+    fn is_variable(expr: &Expr) -> bool {
+        matches!(expr, Expr::Ident(_) | Expr::Local { .. })
+    }
+
+    // Builtin functions
+
+    fn call_builtin(&mut self, name: &str, args: &[Value]) -> InterpreterResult<Value> {
+        if let Some(msg) = builtin_arity_error(name, args.len()) {
+            return Err(InterpreterError::RuntimeError(msg));
+        }
+
+        match name {
+            "dict" => Ok(Value::Map(BTreeMap::new())),
+            "put" => {
+                let mut map = self.expect_map(name, &args[0])?;
+                map.insert(self.map_key(&args[1])?, args[2].clone());
+                Ok(Value::Map(map))
+            }
+            "getKey" => {
+                let map = self.expect_map(name, &args[0])?;
+                let key = self.map_key(&args[1])?;
+                map.get(&key)
+                    .cloned()
+                    .ok_or_else(|| InterpreterError::RuntimeError(format!("Map key '{}' not found", key)))
+            }
+            "has" => {
+                let map = self.expect_map(name, &args[0])?;
+                Ok(Value::Bool(map.contains_key(&self.map_key(&args[1])?)))
+            }
+            "keys" => {
+                let map = self.expect_map(name, &args[0])?;
+                Ok(Value::array(map.into_keys().map(|k| Value::String(k.into())).collect()))
+            }
+            "delete" => {
+                let mut map = self.expect_map(name, &args[0])?;
+                map.remove(&self.map_key(&args[1])?);
+                Ok(Value::Map(map))
+            }
+            "split" => {
+                let text = match &args[0] {
+                    Value::String(s) => s,
+                    _ => return Err(InterpreterError::TypeError("split expects a string".to_string())),
+                };
+                let parts: Vec<Value> = match args.get(1) {
+                    None => text.split_whitespace().map(|p| Value::String(p.into())).collect(),
+                    Some(Value::String(sep)) if !sep.is_empty() => {
+                        text.split(&**sep).map(|p| Value::String(p.into())).collect()
+                    }
+                    Some(_) => return Err(InterpreterError::TypeError("split separator must be a non-empty string".to_string())),
+                };
+                Ok(Value::array(parts))
+            }
+            "random" => {
+                // top 53 bits give a uniformly distributed real in [0, 1)
+                Ok(Value::Real((self.next_random() >> 11) as f64 / (1u64 << 53) as f64))
+            }
+            "randomInt" => {
+                let (lo, hi) = match (&args[0], &args[1]) {
+                    (Value::Integer(lo), Value::Integer(hi)) => (*lo, *hi),
+                    _ => return Err(InterpreterError::TypeError("randomInt bounds must be integers".to_string())),
+                };
+                if lo > hi {
+                    return Err(InterpreterError::RuntimeError(format!(
+                        "randomInt lower bound {} is greater than upper bound {}", lo, hi
+                    )));
+                }
+                let span = (hi as i128 - lo as i128 + 1) as u128;
+                let offset = (self.next_random() as u128 % span) as i128;
+                Ok(Value::Integer((lo as i128 + offset) as i64))
+            }
+            "now" => Ok(Value::Integer(self.start_time.elapsed().as_millis() as i64)),
+            // Values have copy semantics, so a clone is already a deep copy of
+            // arrays, tuples and maps; functions keep sharing their closure scope
+            "clone" => Ok(args[0].clone()),
+            "repeat" => match (&args[0], &args[1]) {
+                (Value::String(s), Value::Integer(n)) => repeat_string(s, *n, self.max_string_len)
+                    .map(|s| Value::String(s.into()))
+                    .map_err(InterpreterError::RuntimeError),
+                _ => Err(InterpreterError::TypeError("repeat expects a string and an integer".to_string())),
+            },
+            "sum" | "minOf" | "maxOf" | "avg" => {
+                let items = match &args[0] {
+                    Value::Array(arr) => arr,
+                    _ => return Err(InterpreterError::TypeError(format!("{} expects an array", name))),
+                };
+                self.aggregate(name, items)
+            }
+            // range(n) is 1..n and range(a, b) is a..b, but never counting
+            // down: an empty range is empty, so range(1, 0) loops zero times
+            "range" => {
+                let bounds = match args {
+                    [Value::Integer(n)] => (1, *n),
+                    [Value::Integer(a), Value::Integer(b)] => (*a, *b),
+                    _ => return Err(InterpreterError::TypeError("range bounds must be integers".to_string())),
+                };
+                Ok(Value::array((bounds.0..=bounds.1).map(Value::Integer).collect()))
+            }
+            "enumerate" => {
+                let items = match &args[0] {
+                    Value::Array(arr) => arr,
+                    _ => return Err(InterpreterError::TypeError("enumerate expects an array".to_string())),
+                };
+                let first = self.index_base.first();
+                let pairs = items.iter().enumerate().map(|(i, item)| {
+                    let index = Value::Integer(i as i64 + first);
+                    // named and positional fields, like a `{index := i, value := v}` literal
+                    let mut pair = TupleFields::default();
+                    pair.insert_element(1, Some("index".to_string()), index);
+                    pair.insert_element(2, Some("value".to_string()), item.clone());
+                    Value::Tuple(pair)
+                });
+                Ok(Value::array(pairs.collect()))
+            }
+            "arity" | "params" => match &args[0] {
+                Value::Function { params, .. } if name == "arity" => Ok(Value::Integer(params.len() as i64)),
+                Value::Function { params, .. } => {
+                    Ok(Value::array(params.iter().map(|p| Value::String(p.as_str().into())).collect()))
+                }
+                _ => Err(InterpreterError::TypeError(format!("{} expects a function", name))),
+            },
+            _ => Err(InterpreterError::UndefinedVariable { name: name.to_string(), suggestion: None }),
+        }
+    }
+
+    fn aggregate(&self, name: &str, items: &[Value]) -> InterpreterResult<Value> {
+        if items.is_empty() {
+            return match name {
+                "sum" => Ok(Value::Integer(0)),
+                _ => Err(InterpreterError::RuntimeError(format!("{} of an empty array", name))),
+            };
+        }
+
+        // stay in integers until a real shows up
+        let all_ints = items.iter().all(|v| matches!(v, Value::Integer(_)));
+        match name {
+            "sum" if all_ints => {
+                let mut total: i64 = 0;
+                for item in items {
+                    if let Value::Integer(n) = item {
+                        total = total.checked_add(*n)
+                            .ok_or_else(|| InterpreterError::RuntimeError("Integer overflow in sum".to_string()))?;
+                    }
+                }
+                Ok(Value::Integer(total))
+            }
+            "minOf" | "maxOf" => {
+                let better = if name == "minOf" { Ordering::Less } else { Ordering::Greater };
+                let mut best = &items[0];
+                for item in &items[1..] {
+                    if self.order_values(item, best)? == better {
+                        best = item;
+                    }
+                }
+                Ok(best.clone())
+            }
+            _ => {
+                let mut total = 0.0;
+                for item in items {
+                    total += self.value_to_number(item)?;
+                }
+                if name == "avg" {
+                    total /= items.len() as f64;
+                }
+                finite_real(total)
+            }
+        }
+    }
+
+    // Closed-form aggregation over an inclusive integer range
+    fn aggregate_range(&self, name: &str, low: &Value, high: &Value) -> InterpreterResult<Value> {
+        let (low, high) = match (low, high) {
+            (Value::Integer(a), Value::Integer(b)) => ((*a).min(*b), (*a).max(*b)),
+            // the same error as evaluating the range itself
+            (Value::Integer(_), _) => return Err(InterpreterError::TypeError(messages::range_bound_not_integer("end"))),
+            _ => return Err(InterpreterError::TypeError(messages::range_bound_not_integer("start"))),
+        };
+        match name {
+            "sum" => {
+                let count = high as i128 - low as i128 + 1;
+                let total = (low as i128 + high as i128) * count / 2;
+                i64::try_from(total)
+                    .map(Value::Integer)
+                    .map_err(|_| InterpreterError::RuntimeError("Integer overflow in sum".to_string()))
+            }
+            "minOf" => Ok(Value::Integer(low)),
+            "maxOf" => Ok(Value::Integer(high)),
+            _ => Ok(Value::Real((low as f64 + high as f64) / 2.0)),
+        }
+    }
+
+    fn expect_map(&self, builtin: &str, val: &Value) -> InterpreterResult<BTreeMap<String, Value>> {
+        match val {
+            Value::Map(map) => Ok(map.clone()),
+            _ => Err(InterpreterError::TypeError(format!("{} expects a map as its first argument", builtin))),
+        }
+    }
+
+    fn map_key(&self, key: &Value) -> InterpreterResult<String> {
+        match key {
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err(InterpreterError::TypeError("Map key must be a string".to_string())),
+        }
+    }
+}
+
+// ====
+// Snapshots: a deep copy of everything a program has defined, so a session
+// can be rolled back (e.g. after a failed notebook cell). Functions are
+// copied with their captured environments; environments shared between
+// closures stay shared in the copy.
+// ====
+
+pub struct InterpreterState {
+    environment: Rc<RefCell<Environment>>,
+    globals: Rc<RefCell<Environment>>,
+    next_function_id: Option<u64>,  // None when read from JSON: restoring keeps the current counter
+    rng_state: Option<u64>,
+}
+
+impl Interpreter {
+    pub fn snapshot(&self) -> InterpreterState {
+        let mut copier = EnvironmentCopier::default();
+        InterpreterState {
+            globals: copier.copy(&self.globals),
+            environment: copier.copy(&self.environment),
+            next_function_id: Some(self.next_function_id),
+            rng_state: Some(self.rng_state),
+        }
+    }
+
+    // Go back to a snapshot; limits, output and modes are left as they are
+    pub fn restore(&mut self, state: InterpreterState) {
+        self.environment = state.environment;
+        self.globals = state.globals;
+        if let Some(id) = state.next_function_id {
+            self.next_function_id = id;
+        }
+        if let Some(rng_state) = state.rng_state {
+            self.rng_state = rng_state;
+        }
+    }
+}
+
+impl InterpreterState {
+    // The top-level bindings of the snapshot
+    pub fn globals(&self) -> BTreeMap<String, Value> {
+        self.globals.borrow().variables.iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    // The globals as {"globals": {name: value, ...}} (see json.rs). Globals
+    // holding a function are left out; a function inside a container is an error.
+    pub fn to_json(&self) -> Result<String, JsonError> {
+        let globals = self.globals.borrow();
+        let plain: BTreeMap<&String, &Value> = globals.variables.iter()
+            .filter(|(_, value)| !matches!(value, Value::Function { .. }))
+            .collect();
+        let mut out = String::from("{\"globals\":");
+        write_object(plain, &mut out, 0)?;
+        out.push('}');
+        Ok(out)
+    }
+
+    pub fn from_json(text: &str) -> Result<InterpreterState, JsonError> {
+        let mut state = from_json(text)?;
+        let Value::Tuple(fields) = &mut state else {
+            return Err(JsonError("a state must be an object".to_string()));
+        };
+        let Some(Value::Tuple(variables)) = fields.get_mut("globals") else {
+            return Err(JsonError("a state needs a \"globals\" object".to_string()));
+        };
+        let variables = std::mem::take(variables).into_fields();
+        let globals = Rc::new(RefCell::new(Environment { variables, ..Environment::new() }));
+        Ok(InterpreterState {
+            environment: Rc::clone(&globals),
+            globals,
+            next_function_id: None,
+            rng_state: None,
+        })
+    }
+}
+
+// Copies environments, each one once, keyed by the address of the original
+#[derive(Default)]
+struct EnvironmentCopier {
+    copies: HashMap<*const RefCell<Environment>, Rc<RefCell<Environment>>>,
+}
+
+impl EnvironmentCopier {
+    fn copy(&mut self, env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        if let Some(copy) = self.copies.get(&Rc::as_ptr(env)) {
+            return Rc::clone(copy);
+        }
+        // registered before its contents are copied, so a closure stored in
+        // its own environment (any recursive function) finds it
+        let copy = Rc::new(RefCell::new(Environment::new()));
+        self.copies.insert(Rc::as_ptr(env), Rc::clone(&copy));
+
+        let mut contents = env.borrow().clone();
+        for value in contents.variables.values_mut().chain(contents.slots.iter_mut()) {
+            self.relink(value);
+        }
+        contents.parent = contents.parent.map(|parent| self.copy(&parent));
+        *copy.borrow_mut() = contents;
+        copy
+    }
+
+    // Value::clone shares the closures of functions; point them at the copies
+    fn relink(&mut self, value: &mut Value) {
+        let mut pending = vec![value];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Array(items) => pending.extend(Rc::make_mut(items).iter_mut()),
+                Value::Tuple(fields) => pending.extend(fields.values_mut()),
+                Value::Map(map) => pending.extend(map.values_mut()),
+                Value::Function { closure, .. } => *closure = self.copy(closure),
+                _ => {}
+            }
+        }
+    }
 }
@@ -0,0 +1,274 @@
+use crate::interpreter::Value;
+use std::collections::{BTreeMap, HashMap};
+
+// ====
+// JSON for plain values: ints, reals (always written with a '.' or an
+// exponent, so they read back as reals), strings, bools, none as null,
+// arrays, tuples as objects and maps as objects wrapped in {"$map": ...}
+// (no tuple field can be named "$map"). Functions have no JSON form.
+// ====
+
+// Containers nested deeper than this are refused rather than risking the native stack
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError(pub String);
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JSON error: {}", self.0)
+    }
+}
+
+pub fn to_json(value: &Value) -> Result<String, JsonError> {
+    let mut out = String::new();
+    write_value(value, &mut out, 0)?;
+    Ok(out)
+}
+
+pub fn from_json(text: &str) -> Result<Value, JsonError> {
+    let mut reader = Reader { text, pos: 0 };
+    let value = reader.value(0)?;
+    reader.skip_whitespace();
+    if reader.pos < text.len() {
+        return Err(reader.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+pub(crate) fn write_value(value: &Value, out: &mut String, depth: usize) -> Result<(), JsonError> {
+    if depth > MAX_DEPTH {
+        return Err(JsonError(format!("values nested more than {} levels deep", MAX_DEPTH)));
+    }
+    match value {
+        Value::Integer(n) => out.push_str(&n.to_string()),
+        Value::Real(n) if !n.is_finite() => return Err(JsonError(format!("{} has no JSON form", n))),
+        Value::Real(n) => out.push_str(&format!("{:?}", n)),
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::None => out.push_str("null"),
+        Value::String(s) => write_string(s, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(item, out, depth + 1)?;
+            }
+            out.push(']');
+        }
+        // sorted, so the same value always gives the same text
+        Value::Tuple(fields) => write_object(fields.iter().collect::<BTreeMap<_, _>>(), out, depth)?,
+        Value::Map(map) => {
+            out.push_str("{\"$map\":");
+            write_object(map.iter().collect(), out, depth + 1)?;
+            out.push('}');
+        }
+        Value::Function { .. } => return Err(JsonError("functions have no JSON form".to_string())),
+    }
+    Ok(())
+}
+
+pub(crate) fn write_object(fields: BTreeMap<&String, &Value>, out: &mut String, depth: usize) -> Result<(), JsonError> {
+    out.push('{');
+    for (i, (key, value)) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_string(key, out);
+        out.push(':');
+        write_value(value, out, depth + 1)?;
+    }
+    out.push('}');
+    Ok(())
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,  // byte offset
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError(format!("{} at offset {}", message, self.pos))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn eat(&mut self, expected: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, JsonError> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error(&format!("values nested more than {} levels deep", MAX_DEPTH)));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Value::None),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('"') => Ok(Value::String(self.string()?.into())),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Value::array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    if self.peek() == Some(',') {
+                        self.pos += 1;
+                    } else {
+                        self.eat(']')?;
+                        return Ok(Value::array(items));
+                    }
+                }
+            }
+            Some('{') => {
+                let mut fields = self.object(depth)?;
+                if fields.len() == 1 && fields[0].0 == "$map" {
+                    // Value has a Drop impl, so the entries are taken rather than moved out
+                    return match fields.last_mut() {
+                        Some((_, Value::Tuple(entries))) => Ok(Value::Map(std::mem::take(entries).into_fields().into_iter().collect())),
+                        _ => Err(self.error("\"$map\" must hold an object")),
+                    };
+                }
+                Ok(Value::Tuple(fields.into_iter().collect::<HashMap<_, _>>().into()))
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Vec<(String, Value)>, JsonError> {
+        self.eat('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(fields);
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a field name"));
+            }
+            let key = self.string()?;
+            self.eat(':')?;
+            fields.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            } else {
+                self.eat('}')?;
+                return Ok(fields);
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, JsonError> {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            self.pos += c.len_utf8();
+        }
+        let text = &self.text[start..self.pos];
+        if text.contains(['.', 'e', 'E']) {
+            match text.parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(Value::Real(n)),
+                _ => Err(self.error("invalid number")),
+            }
+        } else {
+            text.parse().map(Value::Integer).map_err(|_| self.error("invalid integer"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;  // opening quote
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 1;
+                    match escape {
+                        '"' | '\\' | '/' => s.push(escape),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => s.push(self.unicode_escape()?),
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    // After `\u`; a surrogate pair takes two escapes
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) && self.text[self.pos..].starts_with("\\u") {
+            self.pos += 2;
+            let low = self.hex4()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("invalid unicode escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
@@ -1,30 +1,69 @@
 use crate::token::Token;
+use std::ops::Range;
+
+// Default upper bounds (in bytes) on one string literal and one identifier;
+// a longer token is an error instead of a huge allocation
+pub const DEFAULT_MAX_STRING_LITERAL_LEN: usize = 1 << 20;
+pub const DEFAULT_MAX_IDENTIFIER_LEN: usize = 4096;
+
 //Lexer Struct
-pub struct Lexer {
-    input: Vec<char>,
-    pos: usize,
+// Reads the source in place; columns count characters, offsets count bytes
+pub struct Lexer<'a> {
+    input: &'a str,
     line: usize,
     col: usize,
+    token_start: (usize, usize),  // line/col where the last returned token began
+    byte_pos: usize,              // byte offset of the next character
+    token_start_byte: usize,      // byte offset where the last returned token began
+    max_string_literal_len: usize,
+    max_identifier_len: usize,
 }
 //Lexer Constructor
-impl Lexer {
-    pub fn new(input: &str) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
         Self {
-            input: input.chars().collect(),
-            pos: 0,
+            input,
             line: 1,
             col: 1,
+            token_start: (1, 1),
+            byte_pos: 0,
+            token_start_byte: 0,
+            max_string_literal_len: DEFAULT_MAX_STRING_LITERAL_LEN,
+            max_identifier_len: DEFAULT_MAX_IDENTIFIER_LEN,
         }
     }
+
+    pub fn set_max_string_literal_len(&mut self, max_len: usize) {
+        self.max_string_literal_len = max_len;
+    }
+
+    pub fn set_max_identifier_len(&mut self, max_len: usize) {
+        self.max_identifier_len = max_len;
+    }
+
+    // Position of the token most recently returned by next_token
+    pub fn token_start(&self) -> (usize, usize) {
+        self.token_start
+    }
+
+    // Bytes of the source covered by the token most recently returned by next_token
+    pub fn token_byte_range(&self) -> Range<usize> {
+        self.token_start_byte..self.byte_pos
+    }
     //Peeking and Advancing through the code
     fn peek(&self) -> Option<char> {
-        self.input.get(self.pos).cloned()
+        self.input[self.byte_pos..].chars().next()
+    }
+
+    // The character after peek()
+    fn peek_second(&self) -> Option<char> {
+        self.input[self.byte_pos..].char_indices().nth(1).map(|(_, c)| c)
     }
 
     fn advance(&mut self) -> Option<char> {
         let ch = self.peek();
         if let Some(c) = ch {
-            self.pos += 1;
+            self.byte_pos += c.len_utf8();
             if c == '\n' {
                 self.line += 1;
                 self.col = 1;
@@ -49,6 +88,8 @@ impl Lexer {
     //Main Tokenization Function
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
+        self.token_start = (self.line, self.col);
+        self.token_start_byte = self.byte_pos;
 
         let ch = match self.advance() {
             Some(c) => c,
@@ -67,12 +108,13 @@ impl Lexer {
                 } else if self.peek() == Some('/') {
                     // one-line comment
                     self.advance(); // skip the second '/'
-                    let mut s = String::new();
+                    let start = self.byte_pos;
                     while let Some(c) = self.peek() {
-                        if c == '\n' { break; }
-                        s.push(self.advance().unwrap());
+                        // the line break, CRLF included, is not part of the comment
+                        if c == '\n' || (c == '\r' && self.peek_second() == Some('\n')) { break; }
+                        self.advance();
                     }
-                    Token::Comment(s)
+                    Token::Comment(self.input[start..self.byte_pos].to_string())
                 } else if self.peek() == Some('*') {
                     // Multi-line comment
                     self.advance(); // skip '*'
@@ -102,6 +144,9 @@ impl Lexer {
                 if self.peek() == Some('=') {
                     self.advance();
                     Token::LessEqual
+                } else if self.peek() == Some('<') {
+                    self.advance();
+                    Token::ShiftLeft
                 } else {
                     Token::Less
                 }
@@ -110,6 +155,9 @@ impl Lexer {
                 if self.peek() == Some('=') {
                     self.advance();
                     Token::GreaterEqual
+                } else if self.peek() == Some('>') {
+                    self.advance();
+                    Token::ShiftRight
                 } else {
                     Token::Greater
                 }
@@ -119,11 +167,7 @@ impl Lexer {
                     self.advance();
                     Token::Assign
                 } else {
-                    Token::Error {
-                        message: "Unexpected ':'".into(),
-                        line: self.line,
-                        col: self.col,
-                    }
+                    Token::Colon
                 }
             }
             '(' => Token::LParen,
@@ -137,7 +181,13 @@ impl Lexer {
             '.' => {
                 if self.peek() == Some('.') {
                     self.advance();
-                    Token::Range
+                    // the longest match wins: `...` is a spread, never `..` then `.`
+                    if self.peek() == Some('.') {
+                        self.advance();
+                        Token::Spread
+                    } else {
+                        Token::Range
+                    }
                 } else {
                     Token::Dot
                 }
@@ -145,6 +195,8 @@ impl Lexer {
             '"' | '\'' => self.lex_string(ch),
             c if c.is_ascii_digit() => self.lex_number(c),
             c if c.is_alphabetic() => self.lex_identifier(c),
+            // `_name` marks something unused on purpose; a bare `_` is not a name
+            '_' if self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') => self.lex_identifier(ch),
             _ => Token::Error {
                 message: format!("Unexpected character: '{}'", ch),
                 line: self.line,
@@ -160,19 +212,22 @@ impl Lexer {
         
         while let Some(c) = self.peek() {
             if c.is_ascii_digit() {
-                s.push(self.advance().unwrap());
+                s.push(c);
+                self.advance();
             } else if c == '.' && !is_real {
                 
-                if let Some(&next_ch) = self.input.get(self.pos + 1) {
+                if let Some(next_ch) = self.peek_second() {
                     if next_ch.is_ascii_digit() {
                         
-                        s.push(self.advance().unwrap()); 
+                        s.push(c);
+                        self.advance();
                         is_real = true;
                         
                         
                         while let Some(c2) = self.peek() {
                             if c2.is_ascii_digit() {
-                                s.push(self.advance().unwrap());
+                                s.push(c2);
+                                self.advance();
                             } else {
                                 break;
                             }
@@ -194,64 +249,180 @@ impl Lexer {
             }
         }
         
-        if is_real {
-            Token::Real(s.parse().unwrap())
+        let parsed = if is_real {
+            s.parse().ok().filter(|n: &f64| n.is_finite()).map(Token::Real)
         } else {
-            Token::Integer(s.parse().unwrap())
-        }
+            s.parse().ok().map(Token::Integer)
+        };
+        parsed.unwrap_or_else(|| Token::Error {
+            message: format!("Number literal out of range: {}", s),
+            line: self.token_start.0,
+            col: self.token_start.1,
+        })
     }
     
     //Lexing Identifiers/VarNames
     fn lex_identifier(&mut self, first: char) -> Token {
-        let mut s = first.to_string();
-        while let Some(c) = self.peek() {
-            if c.is_alphanumeric() || c == '_' {
-                s.push(self.advance().unwrap());
-            } else {
-                break;
-            }
+        let start = self.byte_pos - first.len_utf8();
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.advance();
         }
-
-        match s.as_str() {
-            "var" => Token::Var,
-            "if" => Token::If,
-            "func" => Token::Func,
-            "is" => Token::Is,
-            "then" => Token::Then,
-            "else" => Token::Else,
-            "end" => Token::End,
-            "while" => Token::While,
-            "for" => Token::For,
-            "loop" => Token::Loop,
-            "exit" => Token::Exit,
-            "return" => Token::Return,
-            "print" => Token::Print,
-            "true" => Token::True,
-            "false" => Token::False,
-            "none" => Token::None,
-            "and" => Token::And,
-            "or" => Token::Or,
-            "xor" => Token::Xor,
-            "not" => Token::Not,
-            "in" => Token::In,
-            "int" => Token::TypeInt,
-            "real" => Token::TypeReal,
-            "bool" => Token::TypeBool,
-            "string" => Token::TypeString,
-            _ => Token::Identifier(s),
+        // the whole name is skipped, so lexing goes on after it
+        if self.byte_pos - start > self.max_identifier_len {
+            return self.too_long("Identifier", self.max_identifier_len);
         }
+
+        let s = &self.input[start..self.byte_pos];
+        Token::from_keyword(s).unwrap_or_else(|| Token::Identifier(s.to_string()))
     }
 
     //Lexing Strings
     fn lex_string(&mut self, quote: char) -> Token {
-        let mut s = String::new();
-        while let Some(c) = self.peek() {
-            self.advance();
+        let start = self.byte_pos;
+        let mut end = start;
+        while let Some(c) = self.advance() {
             if c == quote {
                 break;
             }
-            s.push(c);
+            end = self.byte_pos;
+        }
+        // an over-long literal is still read to its end, so lexing goes on after it
+        if end - start > self.max_string_literal_len {
+            return self.too_long("String literal", self.max_string_literal_len);
+        }
+        Token::String(self.input[start..end].to_string())
+    }
+
+    // Error for a token over its limit, at the token's start
+    fn too_long(&self, what: &str, max_len: usize) -> Token {
+        Token::Error {
+            message: format!("{} is longer than the limit of {} bytes", what, max_len),
+            line: self.token_start.0,
+            col: self.token_start.1,
+        }
+    }
+}
+
+// ====
+// Lossless token stream for formatters and refactoring tools: the tokens
+// with the blanks between them, so every byte of the source is accounted
+// for. The parser keeps to next_token, which skips the blanks.
+// ====
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriviaOrToken {
+    Whitespace { text: String, range: Range<usize> },  // a run of spaces, tabs and carriage returns
+    Token { token: Token, text: String, range: Range<usize> },  // newlines and comments included
+}
+
+impl TriviaOrToken {
+    // The source text it was read from
+    pub fn text(&self) -> &str {
+        match self {
+            TriviaOrToken::Whitespace { text, .. } | TriviaOrToken::Token { text, .. } => text,
+        }
+    }
+
+    // Byte offsets into the source
+    pub fn range(&self) -> Range<usize> {
+        match self {
+            TriviaOrToken::Whitespace { range, .. } | TriviaOrToken::Token { range, .. } => range.clone(),
+        }
+    }
+}
+
+impl Lexer<'_> {
+    // Everything in `source` in order, EOF left out; reconstruct() gives the source back
+    pub fn tokenize_with_trivia(source: &str) -> Vec<TriviaOrToken> {
+        let mut lexer = Lexer::new(source);
+        let mut items = Vec::new();
+        let mut covered = 0;
+        loop {
+            let token = lexer.next_token();
+            let range = lexer.token_byte_range();
+            if range.start > covered {
+                items.push(TriviaOrToken::Whitespace { text: source[covered..range.start].to_string(), range: covered..range.start });
+            }
+            covered = range.end;
+            if token == Token::EOF {
+                break;
+            }
+            items.push(TriviaOrToken::Token { token, text: source[range.clone()].to_string(), range });
         }
-        Token::String(s)
+        if covered < source.len() {
+            items.push(TriviaOrToken::Whitespace { text: source[covered..].to_string(), range: covered..source.len() });
+        }
+        items
+    }
+}
+
+// The source tokenize_with_trivia read `items` from
+pub fn reconstruct(items: &[TriviaOrToken]) -> String {
+    items.iter().map(TriviaOrToken::text).collect()
+}
+
+// ====
+// Highlighting: every byte of the source classified for an editor,
+// whitespace and comments included
+// ====
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighlightCategory {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Comment,
+    Operator,
+    Punctuation,
+    Error,
+    Whitespace,  // blanks and line breaks between tokens
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightSpan {
+    pub range: Range<usize>,  // byte offsets into the source
+    pub category: HighlightCategory,
+}
+
+// Spans in source order that tile `source` exactly, with no gaps or overlaps
+pub fn highlight(source: &str) -> Vec<HighlightSpan> {
+    let mut spans = Vec::new();
+    for item in Lexer::tokenize_with_trivia(source) {
+        match item {
+            TriviaOrToken::Whitespace { range, .. } => push_span(&mut spans, range, HighlightCategory::Whitespace),
+            TriviaOrToken::Token { token, range, .. } => push_span(&mut spans, range, highlight_category(&token)),
+        }
+    }
+    spans
+}
+
+// Neighbouring whitespace is one span, so a CRLF is not split in two
+fn push_span(spans: &mut Vec<HighlightSpan>, range: Range<usize>, category: HighlightCategory) {
+    match spans.last_mut() {
+        Some(last) if category == HighlightCategory::Whitespace && last.category == category => last.range.end = range.end,
+        _ => spans.push(HighlightSpan { range, category }),
+    }
+}
+
+fn highlight_category(token: &Token) -> HighlightCategory {
+    use HighlightCategory::*;
+    match token {
+        Token::Var | Token::If | Token::Then | Token::Else | Token::End | Token::While | Token::For
+        | Token::Loop | Token::Func | Token::Is | Token::Exit | Token::Return | Token::Print
+        | Token::True | Token::False | Token::None | Token::Include | Token::Swap | Token::And | Token::Or
+        | Token::Xor | Token::AndThen | Token::OrElse | Token::Not | Token::In | Token::TypeInt | Token::TypeReal | Token::TypeBool
+        | Token::TypeString => Keyword,
+        Token::Identifier(_) => Identifier,
+        Token::Integer(_) | Token::Real(_) => Number,
+        Token::String(_) => String,
+        Token::Comment(_) => Comment,
+        Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Assign | Token::Equal
+        | Token::NotEqual | Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual
+        | Token::ShiftLeft | Token::ShiftRight | Token::Range | Token::Spread | Token::Arrow => Operator,
+        Token::LParen | Token::RParen | Token::LBrace | Token::RBrace | Token::LBracket
+        | Token::RBracket | Token::Comma | Token::Semicolon | Token::Colon | Token::Dot => Punctuation,
+        Token::Newline | Token::EOF => Whitespace,
+        Token::Error { .. } => Error,
     }
 }
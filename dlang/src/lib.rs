@@ -3,14 +3,31 @@ pub mod lexer;
 pub mod ast;
 pub mod parser;
 pub mod analyzer;
+pub mod resolver;
+pub mod robust;
+pub mod pipeline;
+pub mod loader;
+pub mod stdlib;
+pub mod diagnostics;
+pub mod incremental;
+pub mod json;
+pub mod verify;
+pub mod cache;
+pub mod stats;
 pub mod interpreter;
+pub mod coverage;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 
 pub use parser::Parser;
-pub use analyzer::{SemanticChecker, Optimizer, AnalysisError, AnalysisResult};
-pub use interpreter::{Interpreter, InterpreterError, InterpreterResult};
+pub use cache::ParseCache;
+pub use analyzer::{SemanticChecker, ChangeSet, DiagnosticSite, SymbolTable, Optimizer, OptReport, AnalysisError, AnalysisResult, AstChange, diff_programs};
+pub use interpreter::{HookEvent, IndexBase, Interpreter, InterpreterError, InterpreterResult, InterpreterState, InterpreterStats};
+pub use coverage::Coverage;
+pub use pipeline::{check, check_file, check_with, fold_expr_str, run, run_file, run_parallel, run_with, CheckReport, PipelineError, ProgramRunner, RunnerError, RunOptions, RunOutput, Stage};
 
-pub use ast::{Program, Stmt, Expr, BinOp, UnOp};
+pub use ast::{Program, Stmt, Expr, BinOp, UnOp, pretty_print};
 
 
 #[cfg(test)]
@@ -111,5 +128,127 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_leading_underscore_identifiers() {
+        let mut lexer = Lexer::new("_unused __x _");
+        use Token::*;
+        assert_eq!(lexer.next_token(), Identifier("_unused".into()));
+        assert_eq!(lexer.next_token(), Identifier("__x".into()));
+        assert!(matches!(lexer.next_token(), Error { .. }), "a bare underscore is not a name");
+    }
+
+    #[test]
+    fn test_spread_and_range_tokens() {
+        let mut lexer = Lexer::new("1..3 ...xs 1...3 ....");
+        use Token::*;
+        assert_eq!(lexer.next_token(), Integer(1));
+        assert_eq!(lexer.next_token(), Range);
+        assert_eq!(lexer.next_token(), Integer(3));
+        assert_eq!(lexer.next_token(), Spread);
+        assert_eq!(lexer.next_token(), Identifier("xs".into()));
+        assert_eq!(lexer.next_token(), Integer(1));
+        assert_eq!(lexer.next_token(), Spread, "the longest match wins");
+        assert_eq!(lexer.next_token(), Integer(3));
+        assert_eq!(lexer.next_token(), Spread);
+        assert_eq!(lexer.next_token(), Dot);
+    }
+
+    #[test]
+    fn test_token_length_limits() {
+        use Token::*;
+        let source = format!("print \"{}\" x\nvar {} := 1", "a".repeat(20), "n".repeat(11));
+        let mut lexer = Lexer::new(&source);
+        lexer.set_max_string_literal_len(10);
+        lexer.set_max_identifier_len(10);
+        assert_eq!(lexer.next_token(), Print);
+        match lexer.next_token() {
+            Error { message, line, col } => {
+                assert_eq!(message, "String literal is longer than the limit of 10 bytes");
+                assert_eq!((line, col), (1, 7));
+            }
+            other => panic!("Expected error token, got {:?}", other),
+        }
+        // the whole literal is skipped and lexing carries on after it
+        assert_eq!(lexer.next_token(), Identifier("x".into()));
+        assert_eq!(lexer.next_token(), Newline);
+        assert_eq!(lexer.next_token(), Var);
+        assert!(matches!(lexer.next_token(), Error { message, .. } if message.contains("Identifier is longer than the limit of 10 bytes")));
+        assert_eq!(lexer.next_token(), Assign);
+
+        // the defaults leave room for long literals
+        let long = "b".repeat(100_000);
+        let source = format!("\"{}\"", long);
+        let mut lexer = Lexer::new(&source);
+        assert_eq!(lexer.next_token(), String(long));
+    }
+
+    #[test]
+    fn test_multibyte_utf8_around_operators() {
+        use Token::*;
+        let mut lexer = Lexer::new("var café:=\"ñ→ü\"/*é*/+ü1//ö\r\nprint café");
+        assert_eq!(lexer.next_token(), Var);
+        assert_eq!(lexer.next_token(), Identifier("café".into()));
+        assert_eq!(lexer.next_token(), Assign);
+        assert_eq!(lexer.token_start(), (1, 9), "columns count characters");
+        assert_eq!(lexer.next_token(), String("ñ→ü".into()));
+        assert_eq!(lexer.next_token(), Comment("é".into()));
+        assert_eq!(lexer.next_token(), Plus);
+        assert_eq!(lexer.token_start(), (1, 21));
+        assert_eq!(lexer.token_byte_range(), 26..27, "offsets count bytes");
+        assert_eq!(lexer.next_token(), Identifier("ü1".into()));
+        assert_eq!(lexer.next_token(), Comment("ö".into()));
+        assert_eq!(lexer.next_token(), Newline);
+        assert_eq!(lexer.next_token(), Print);
+        assert_eq!(lexer.next_token(), Identifier("café".into()));
+        assert_eq!(lexer.token_start(), (2, 7));
+        assert_eq!(lexer.next_token(), EOF);
+    }
+
+    #[test]
+    fn test_highlight_spans_tile_input() {
+        use super::lexer::{highlight, HighlightCategory::*};
+        let source = "var s := \"a\r\nb\" //c\r\nprint s é";
+        let spans = highlight(source);
+
+        let mut next = 0;
+        for span in &spans {
+            assert_eq!(span.range.start, next, "gap or overlap before {:?}", span);
+            next = span.range.end;
+        }
+        assert_eq!(next, source.len());
+
+        let text = |category| -> Vec<&str> {
+            spans.iter().filter(|s| s.category == category).map(|s| &source[s.range.clone()]).collect()
+        };
+        assert_eq!(text(Keyword), vec!["var", "print"]);
+        assert_eq!(text(String), vec!["\"a\r\nb\""]);  // the string spans the CRLF
+        assert_eq!(text(Comment), vec!["//c"]);
+        assert_eq!(text(Operator), vec![":="]);
+        assert_eq!(text(Identifier), vec!["s", "s", "é"]);
+        assert!(text(Whitespace).contains(&"\r\n"));
+    }
+
+    #[test]
+    fn test_keyword_and_operator_tables() {
+        for table in [Token::keywords(), Token::operators()] {
+            // sorted and without duplicates, so lookups can binary search
+            assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0), "{:?}", table);
+        }
+        for (spelling, token) in Token::keywords() {
+            let mut lexer = Lexer::new(spelling);
+            assert_eq!(&lexer.next_token(), token, "{}", spelling);
+            assert_eq!(lexer.next_token(), Token::EOF);
+            assert_eq!(token.keyword(), Some(*spelling));
+        }
+        for (spelling, token) in Token::operators() {
+            let mut lexer = Lexer::new(spelling);
+            assert_eq!(&lexer.next_token(), token, "{}", spelling);
+            assert_eq!(lexer.next_token(), Token::EOF);
+            assert_eq!(token.keyword(), None);
+        }
+        assert_eq!(Lexer::new("div").next_token(), Token::Identifier("div".into()));
+        assert_eq!(Token::Assign.describe(), "':=' assignment operator");
+    }
+
 
 }
@@ -0,0 +1,97 @@
+use crate::ast::{NodeId, Program, Stmt};
+use crate::parser::{ParseError, Parser};
+use crate::robust::Diagnostic;
+use std::path::{Path, PathBuf};
+
+// ====
+// Loader: reads a program from disk and splices the statements of every
+// top-level `include "path"` in place of it, so the checker and the
+// interpreter only ever see one flat program.
+// Included paths are relative to the file that includes them.
+// ====
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    Io { path: PathBuf, included_from: Option<PathBuf>, message: String },
+    Parse { path: PathBuf, error: ParseError },
+    Cycle(Vec<PathBuf>),  // the include chain, starting and ending with the same file
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io { path, included_from: Some(from), message } => {
+                write!(f, "Cannot read '{}' included from '{}': {}", path.display(), from.display(), message)
+            }
+            LoadError::Io { path, included_from: None, message } => {
+                write!(f, "Cannot read '{}': {}", path.display(), message)
+            }
+            LoadError::Parse { path, error } => write!(f, "In '{}': {}", path.display(), error),
+            LoadError::Cycle(chain) => {
+                let files: Vec<String> = chain.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "Include cycle: {}", files.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// Parse errors keep their position; the file goes in front of the message
+impl From<LoadError> for Diagnostic {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Parse { path, error } => Diagnostic {
+                message: format!("In '{}': {}", path.display(), error.message),
+                line: error.line,
+                col: error.col,
+            },
+            e => Diagnostic { message: e.to_string(), line: 0, col: 0 },
+        }
+    }
+}
+
+// Read and parse `path` with all of its includes resolved
+pub fn load_file(path: &Path) -> Result<Program, LoadError> {
+    let mut chain = Vec::new();
+    let stmts = load(path, None, &mut chain, &mut 1)?;
+    Ok(Program::Stmts(stmts))
+}
+
+// `chain` holds the files currently being loaded, outermost first; the
+// statements of every file are numbered from `next_id` on
+fn load(path: &Path, included_from: Option<&Path>, chain: &mut Vec<PathBuf>, next_id: &mut NodeId) -> Result<Vec<Stmt>, LoadError> {
+    let io_error = |e: std::io::Error| LoadError::Io {
+        path: path.to_path_buf(),
+        included_from: included_from.map(Path::to_path_buf),
+        message: e.to_string(),
+    };
+    // the same file reached through different relative paths is still a cycle
+    let canonical = path.canonicalize().map_err(io_error)?;
+    if let Some(start) = chain.iter().position(|p| p.canonicalize().ok().as_ref() == Some(&canonical)) {
+        let mut cycle = chain[start..].to_vec();
+        cycle.push(path.to_path_buf());
+        return Err(LoadError::Cycle(cycle));
+    }
+
+    let source = std::fs::read_to_string(path).map_err(io_error)?;
+    let mut parser = Parser::new(&source).with_first_id(*next_id);
+    let Program::Stmts(stmts) = parser
+        .parse_program()
+        .map_err(|error| LoadError::Parse { path: path.to_path_buf(), error })?;
+    *next_id = parser.next_id();
+
+    chain.push(path.to_path_buf());
+    let mut spliced = Vec::with_capacity(stmts.len());
+    for stmt in stmts {
+        match stmt {
+            Stmt::Include(included, _) => {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                spliced.extend(load(&dir.join(included), Some(path), chain, next_id)?);
+            }
+            stmt => spliced.push(stmt),
+        }
+    }
+    chain.pop();
+    Ok(spliced)
+}
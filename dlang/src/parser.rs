@@ -1,4 +1,5 @@
 use crate::ast::*;
+use crate::interpreter::DEFAULT_MAX_EXPR_DEPTH;
 use crate::lexer::Lexer;
 use crate::token::Token;
 
@@ -11,32 +12,220 @@ impl std::fmt::Display for ParseError {
     }
 }
 
+impl std::error::Error for ParseError {}
+
 pub type ParseResult<T> = Result<T, ParseError>;
 
-fn err_from_token<T>(message: String, tok: &Token) -> ParseResult<T> {
-    let (line, col) = match tok { Token::Error { line, col, .. } => (*line, *col), _ => (0, 0) };
-    Err(ParseError { message, line, col })
+// ====
+// What the parser builds: it hands each node, with its children already
+// built, to a Build. `Boxed` makes the ast.rs tree; a ParseArena stores the
+// node in place (see ast/arena.rs), so arena mode allocates no boxes and
+// no vector per list.
+// ====
+
+pub(crate) trait Build: Sized {
+    type Expr;
+    type Stmt;
+    type Exprs;  // a list being built, e.g. the arguments of a call
+    type Stmts;  // a block being built
+    fn expr(&mut self, node: ExprNode<Self>) -> Self::Expr;
+    fn stmt(&mut self, node: StmtNode<Self>) -> Self::Stmt;
+    // Lists are finished (built into their node) in the reverse order they were started
+    fn exprs(&mut self) -> Self::Exprs;
+    fn push_expr(&mut self, list: &mut Self::Exprs, expr: Self::Expr);
+    fn stmts(&mut self) -> Self::Stmts;
+    fn push_stmt(&mut self, list: &mut Self::Stmts, stmt: Self::Stmt);
+    // A literal, which cannot be called, indexed or have members
+    fn is_literal(&self, expr: &Self::Expr) -> bool;
+}
+
+// An Expr over children built by `B`
+pub(crate) enum ExprNode<B: Build> {
+    Leaf(Expr),  // a literal or a name
+    Range(B::Expr, B::Expr),
+    Binary { left: B::Expr, op: BinOp, right: B::Expr },
+    Unary { op: UnOp, expr: B::Expr },
+    Call { callee: B::Expr, args: B::Exprs },
+    Index { target: B::Expr, index: B::Expr },
+    Member { target: B::Expr, field: String },
+    Array(B::Exprs),
+    Tuple(Vec<(Option<String>, B::Expr)>),
+    Spread(B::Expr),
+    IsType { expr: B::Expr, type_ind: TypeIndicator },
+    Func { params: Vec<String>, body: FuncBodyNode<B> },
+}
+
+pub(crate) enum FuncBodyNode<B: Build> {
+    Expr(B::Expr),
+    Block(B::Stmts),
+}
+
+// A Stmt over children built by `B`
+pub(crate) enum StmtNode<B: Build> {
+    VarDecl { name: String, init: Option<B::Expr>, span: Span },
+    Assign { target: B::Expr, value: B::Expr, span: Span },
+    Swap { left: B::Expr, right: B::Expr, span: Span },
+    Print { args: B::Exprs, span: Span },
+    If { cond: B::Expr, then_branch: B::Stmts, else_branch: Option<B::Stmts>, span: Span },
+    While { label: Option<String>, cond: B::Expr, body: B::Stmts, span: Span },
+    RepeatUntil { body: B::Stmts, cond: B::Expr, span: Span },
+    For { label: Option<String>, var: LoopPattern, iterable: B::Expr, body: B::Stmts, span: Span },
+    Return(Option<B::Expr>, Span),
+    Exit(Option<String>, Span),
+    Expr(B::Expr, Span),
+    Include(String, Span),
+}
+
+struct Boxed;
+
+impl Build for Boxed {
+    type Expr = Expr;
+    type Stmt = Stmt;
+    type Exprs = Vec<Expr>;
+    type Stmts = Vec<Stmt>;
+
+    fn expr(&mut self, node: ExprNode<Self>) -> Expr {
+        match node {
+            ExprNode::Leaf(expr) => expr,
+            ExprNode::Range(low, high) => Expr::Range(Box::new(low), Box::new(high)),
+            ExprNode::Binary { left, op, right } => Expr::Binary { left: Box::new(left), op, right: Box::new(right) },
+            ExprNode::Unary { op, expr } => Expr::Unary { op, expr: Box::new(expr) },
+            ExprNode::Call { callee, args } => Expr::Call { callee: Box::new(callee), args },
+            ExprNode::Index { target, index } => Expr::Index { target: Box::new(target), index: Box::new(index) },
+            ExprNode::Member { target, field } => Expr::Member { target: Box::new(target), field },
+            ExprNode::Array(items) => Expr::Array(items),
+            ExprNode::Tuple(elements) => Expr::Tuple(elements.into_iter().map(|(name, value)| TupleElement { name, value }).collect()),
+            ExprNode::Spread(expr) => Expr::Spread(Box::new(expr)),
+            ExprNode::IsType { expr, type_ind } => Expr::IsType { expr: Box::new(expr), type_ind },
+            ExprNode::Func { params, body: FuncBodyNode::Expr(body) } => Expr::Func { params, body: FuncBody::Expr(Box::new(body)) },
+            ExprNode::Func { params, body: FuncBodyNode::Block(body) } => Expr::Func { params, body: FuncBody::Block(body) },
+        }
+    }
+
+    fn stmt(&mut self, node: StmtNode<Self>) -> Stmt {
+        match node {
+            StmtNode::VarDecl { name, init, span } => Stmt::VarDecl { name, init, span },
+            StmtNode::Assign { target, value, span } => Stmt::Assign { target, value, span },
+            StmtNode::Swap { left, right, span } => Stmt::Swap { left, right, span },
+            StmtNode::Print { args, span } => Stmt::Print { args, span },
+            StmtNode::If { cond, then_branch, else_branch, span } => Stmt::If { cond, then_branch, else_branch, span },
+            StmtNode::While { label, cond, body, span } => Stmt::While { label, cond, body, span },
+            StmtNode::RepeatUntil { body, cond, span } => Stmt::RepeatUntil { body, cond, span },
+            StmtNode::For { label, var, iterable, body, span } => Stmt::For { label, var, iterable, body, span },
+            StmtNode::Return(value, span) => Stmt::Return(value, span),
+            StmtNode::Exit(label, span) => Stmt::Exit(label, span),
+            StmtNode::Expr(expr, span) => Stmt::Expr(expr, span),
+            StmtNode::Include(path, span) => Stmt::Include(path, span),
+        }
+    }
+
+    fn exprs(&mut self) -> Vec<Expr> {
+        Vec::new()
+    }
+
+    fn push_expr(&mut self, list: &mut Vec<Expr>, expr: Expr) {
+        list.push(expr);
+    }
+
+    fn stmts(&mut self) -> Vec<Stmt> {
+        Vec::new()
+    }
+
+    fn push_stmt(&mut self, list: &mut Vec<Stmt>, stmt: Stmt) {
+        list.push(stmt);
+    }
+
+    fn is_literal(&self, expr: &Expr) -> bool {
+        matches!(expr, Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::String(_) | Expr::None)
+    }
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
+    positions: Vec<(usize, usize)>,  // start line/col of each token
     pos: usize,
+    nesting: usize,  // open ( [ { around the current token; newlines are insignificant inside them
+    depth: usize,    // statements and expressions being parsed around the current one, see nested()
+    errors: Vec<ParseError>,  // errors parsed past, then the one that stopped the parse
+    next_id: NodeId,  // for the next statement parsed
 }
 
 impl Parser {
     pub fn new(source: &str) -> Self {
         let mut lexer = Lexer::new(source);
         let mut tokens = Vec::new();
-        loop { let t = lexer.next_token(); let end = t == Token::EOF; tokens.push(t); if end { break; } }
-        Self { tokens, pos: 0 }
+        let mut positions = Vec::new();
+        loop { let t = lexer.next_token(); let end = t == Token::EOF; tokens.push(t); positions.push(lexer.token_start()); if end { break; } }
+        Self::from_tokens(tokens, positions)
+    }
+
+    // Parse already lexed tokens; the last one must be EOF
+    pub(crate) fn from_tokens(tokens: Vec<Token>, positions: Vec<(usize, usize)>) -> Self {
+        Self { tokens, positions, pos: 0, nesting: 0, depth: 0, errors: Vec::new(), next_id: 1 }
+    }
+
+    // Number statements from `id` on, e.g. to keep going after another parse
+    pub fn with_first_id(mut self, id: NodeId) -> Self {
+        self.next_id = id.max(1);
+        self
+    }
+
+    // The NodeId the next statement would get
+    pub fn next_id(&self) -> NodeId {
+        self.next_id
+    }
+
+    // Position of the current token, with a fresh NodeId for the statement starting there
+    fn span(&mut self) -> Span {
+        let (line, col) = self.positions.get(self.pos).or(self.positions.last()).copied().unwrap_or((0, 0));
+        let id = self.next_id;
+        self.next_id += 1;
+        Span::new(line, col).with_id(id)
     }
 
     fn peek(&self) -> &Token { self.tokens.get(self.pos).unwrap_or(&Token::EOF) }
-    fn advance(&mut self) -> Token { let t = self.peek().clone(); if self.pos < self.tokens.len() { self.pos += 1; } t }
+    // Token n positions past the current one (peek_ahead(0) == peek())
+    fn peek_ahead(&self, n: usize) -> &Token { self.tokens.get(self.pos + n).unwrap_or(&Token::EOF) }
+    fn advance(&mut self) -> Token {
+        let t = self.peek().clone();
+        if self.pos < self.tokens.len() { self.pos += 1; }
+        match t {
+            Token::LParen | Token::LBracket | Token::LBrace => self.nesting += 1,
+            Token::RParen | Token::RBracket | Token::RBrace => self.nesting = self.nesting.saturating_sub(1),
+            _ => {}
+        }
+        self.skip_nested_trivia();
+        t
+    }
+
+    // A postfix `(` or `[` only continues an expression on the line of the token before it,
+    // so `f(1)` followed by `(2 + 3)` on the next line stays two statements. Same-line
+    // comments in between are skipped; inside brackets lines don't matter.
+    fn postfix_on_same_line(&mut self) -> bool {
+        let mut ahead = 0;
+        while matches!(self.peek_ahead(ahead), Token::Comment(_)) { ahead += 1; }
+        if ahead == 0 || !matches!(self.peek_ahead(ahead), Token::LParen | Token::LBracket) {
+            return true;
+        }
+        let line_of = |i: usize| self.positions.get(i).map(|p| p.0);
+        if self.nesting > 0 || (self.pos > 0 && line_of(self.pos - 1) == line_of(self.pos + ahead)) {
+            self.pos += ahead;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Inside brackets an expression may span lines, so newlines and comments are skipped
+    fn skip_nested_trivia(&mut self) {
+        while self.nesting > 0 && matches!(self.peek(), Token::Newline | Token::Comment(_)) {
+            self.pos += 1;
+        }
+    }
     fn match_token(&mut self, expected: &Token) -> bool { if self.peek() == expected { self.advance(); true } else { false } }
 
     fn expect(&mut self, expected: &Token) -> ParseResult<()> {
-        if self.match_token(expected) { Ok(()) } else { err_from_token(format!("Expected {:?}, got {:?}", expected, self.peek()), self.peek()) }
+        if self.match_token(expected) { Ok(()) } else { self.error_here(format!("Expected {}, got {}", token_to_string(expected), token_to_string(self.peek()))) }
     }
 
     fn consume_trivia(&mut self) {
@@ -51,208 +240,480 @@ impl Parser {
     }
 
     pub fn parse_program(&mut self) -> ParseResult<Program> {
+        let stmts = self.parse_top_level()?;
+        Ok(Program::Stmts(stmts.into_iter().map(|(stmt, _)| stmt).collect()))
+    }
+
+    // A single expression on its own, e.g. to fold it in isolation; anything
+    // after it is an error
+    pub fn parse_standalone_expression(&mut self) -> ParseResult<Expr> {
+        let mut expr = Expr::None;
+        self.consume_trivia();
+        let result = self.parse_expression(&mut Boxed).map(|e| expr = e).and_then(|()| {
+            self.consume_trivia();
+            match self.peek() {
+                Token::EOF => Ok(()),
+                t => {
+                    let (line, col) = self.positions.get(self.pos).copied().unwrap_or((0, 0));
+                    Err(ParseError { message: format!("Unexpected {} after the expression", token_to_string(t)), line, col })
+                }
+            }
+        });
+        self.finish(result).map(|()| expr)
+    }
+
+    // Arena mode: nodes are built in the arena as they are parsed, so no
+    // boxed node is allocated along the way
+    pub fn parse_arena(&mut self) -> ParseResult<arena::ParseArena> {
+        let mut arena = arena::ParseArena::new();
+        let result = self.each_top_level(&mut arena, |arena, stmt, _| arena.add_top_level(stmt));
+        self.finish(result).map(|()| arena)
+    }
+
+    // Top-level statements with the indices of the tokens each one was parsed from
+    pub(crate) fn parse_top_level(&mut self) -> ParseResult<Vec<(Stmt, std::ops::Range<usize>)>> {
         let mut stmts = Vec::new();
+        let result = self.each_top_level(&mut Boxed, |_, stmt, tokens| stmts.push((stmt, tokens)));
+        self.finish(result).map(|()| stmts)
+    }
+
+    fn each_top_level<B: Build>(&mut self, b: &mut B, mut f: impl FnMut(&mut B, B::Stmt, std::ops::Range<usize>)) -> ParseResult<()> {
         self.consume_trivia();
         while self.peek() != &Token::EOF {
-            stmts.push(self.parse_stmt()?);
+            let start = self.pos;
+            let stmt = self.parse_stmt(b)?;
+            f(b, stmt, start..self.pos);
             self.consume_trivia();
         }
-        Ok(Program::Stmts(stmts))
+        Ok(())
+    }
+
+    // Every error of the last parse in source order: those the parser
+    // recovered from and the one that stopped it, if any
+    pub fn errors(&self) -> &[ParseError] { &self.errors }
+
+    // Fails at the current token when it is a keyword where a name belongs
+    fn refuse_keyword(&self) -> ParseResult<()> {
+        match self.peek().keyword() { Some(word) => self.reserved(word), None => Ok(()) }
+    }
+
+    // Fails at the current token
+    fn error_here<T>(&self, message: String) -> ParseResult<T> {
+        self.error_at(self.pos, message)
+    }
+
+    // Fails at the token advance() just returned
+    fn error_behind<T>(&self, message: String) -> ParseResult<T> {
+        self.error_at(self.pos.saturating_sub(1), message)
+    }
+
+    fn error_at<T>(&self, index: usize, message: String) -> ParseResult<T> {
+        let (line, col) = self.positions.get(index).or(self.positions.last()).copied().unwrap_or((0, 0));
+        Err(ParseError { message, line, col })
     }
 
-    fn parse_stmt(&mut self) -> ParseResult<Stmt> {
+    fn reserved<T>(&self, word: &str) -> ParseResult<T> {
+        let (line, col) = self.positions.get(self.pos).copied().unwrap_or((0, 0));
+        Err(ParseError { message: format!("'{}' is a reserved keyword and cannot be used as an identifier", word), line, col })
+    }
+
+    // Records an error at the current token and carries on parsing
+    fn recover(&mut self, message: &str) {
+        let (line, col) = self.positions.get(self.pos).copied().unwrap_or((0, 0));
+        self.errors.push(ParseError { message: message.to_string(), line, col });
+    }
+
+    // A parse with errors fails with the first of them, even when it got to the end
+    fn finish(&mut self, result: ParseResult<()>) -> ParseResult<()> {
+        if let Err(e) = result { self.errors.push(e); }
+        match self.errors.first() { Some(first) => Err(first.clone()), None => Ok(()) }
+    }
+
+    // Runs `parse` one level deeper. The parser recurses once per level, so
+    // past DEFAULT_MAX_EXPR_DEPTH levels it fails rather than overflow the stack.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        if self.depth >= DEFAULT_MAX_EXPR_DEPTH {
+            return self.error_here(format!("Statements and expressions nest more than {} levels deep", DEFAULT_MAX_EXPR_DEPTH));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_stmt<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        self.nested(|p| p.parse_stmt_here(b))
+    }
+
+    fn parse_stmt_here<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
         match self.peek() {
-            Token::Var => self.parse_var_decl(),
-            Token::Print => self.parse_print(),
-            Token::If => self.parse_if(),
-            Token::While => self.parse_while(),
-            Token::For => self.parse_for(),
-            Token::Return => self.parse_return(),
-            Token::Exit => { self.advance(); Ok(Stmt::Exit) }
+            Token::Var => self.parse_var_decl(b),
+            Token::Print => self.parse_print(b),
+            Token::If => self.parse_if(b),
+            Token::While => { let span = self.span(); self.parse_while(b, None, span) }
+            Token::For => { let span = self.span(); self.parse_for(b, None, span) }
+            Token::Return => self.parse_return(b),
+            Token::Exit => {
+                let span = self.span();
+                self.advance();
+                let label = match self.peek().clone() { Token::Identifier(name) => { self.advance(); Some(name) } _ => None };
+                Ok(b.stmt(StmtNode::Exit(label, span)))
+            }
+            Token::Identifier(_) if self.peek_ahead(1) == &Token::Colon => self.parse_labelled_loop(b),
+            Token::Include => self.parse_include(b),
+            Token::Swap => self.parse_swap(b),
+            _ if self.at_loop_word("repeat") => self.parse_repeat(b),
             _ => {
-                let expr = self.parse_expression()?;
+                let span = self.span();
+                let expr = self.parse_expression(b)?;
                 if self.match_token(&Token::Assign) {
-                    let value = self.parse_expression()?;
-                    Ok(Stmt::Assign { target: expr, value })
+                    let value = self.parse_expression(b)?;
+                    Ok(b.stmt(StmtNode::Assign { target: expr, value, span }))
                 } else {
-                    Ok(Stmt::Expr(expr))
+                    Ok(b.stmt(StmtNode::Expr(expr, span)))
                 }
             }
         }
     }
 
-    fn parse_var_decl(&mut self) -> ParseResult<Stmt> {
+    fn parse_var_decl<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
         self.expect(&Token::Var)?;
-        let name = match self.advance() { Token::Identifier(s) => s, t => return err_from_token(format!("Expected identifier after var, got {:?}", t), &t) };
-        let init = if self.match_token(&Token::Assign) { self.parse_expression()? } else { Expr::None };
-        Ok(Stmt::VarDecl { name, init })
+        self.refuse_keyword()?;
+        let name = match self.advance() { Token::Identifier(s) => s, t => return self.error_behind(format!("Expected identifier after var, got {}", token_to_string(&t))) };
+        // `var x = 5` is reported, then read as `:=` like parse_condition does
+        if self.peek() == &Token::Equal { self.recover("use ':=' to initialize a variable"); }
+        let init = if self.match_token(&Token::Assign) || self.match_token(&Token::Equal) { Some(self.parse_expression(b)?) } else { None };
+        Ok(b.stmt(StmtNode::VarDecl { name, init, span }))
     }
 
-    fn parse_print(&mut self) -> ParseResult<Stmt> {
+    fn parse_print<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
         self.expect(&Token::Print)?;
-        let mut args = Vec::new();
-        args.push(self.parse_expression()?);
-        while self.match_token(&Token::Comma) { args.push(self.parse_expression()?); }
-        Ok(Stmt::Print { args })
+        let mut args = b.exprs();
+        // a bare `print` prints an empty line
+        if matches!(self.peek(), Token::Newline | Token::Semicolon | Token::EOF | Token::End | Token::Else | Token::Comment(_)) {
+            return Ok(b.stmt(StmtNode::Print { args, span }));
+        }
+        self.parse_into(b, &mut args, Self::parse_expression)?;
+        while self.match_token(&Token::Comma) { self.parse_into(b, &mut args, Self::parse_expression)?; }
+        Ok(b.stmt(StmtNode::Print { args, span }))
     }
 
-    fn parse_if(&mut self) -> ParseResult<Stmt> {
+    fn parse_if<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
         self.expect(&Token::If)?;
-        let cond = self.parse_expression()?;
+        let cond = self.parse_condition(b)?;
         if self.match_token(&Token::Arrow) {
-            let then_branch = vec![ self.parse_stmt()? ];
-            Ok(Stmt::If { cond, then_branch, else_branch: None })
+            let mut then_branch = b.stmts();
+            let stmt = self.parse_stmt(b)?;
+            b.push_stmt(&mut then_branch, stmt);
+            Ok(b.stmt(StmtNode::If { cond, then_branch, else_branch: None, span }))
         } else {
             self.expect(&Token::Then)?;
-            let then_branch = self.parse_block_until(&[Token::Else, Token::End])?;
-            let else_branch = if self.match_token(&Token::Else) { Some(self.parse_block_until(&[Token::End])?) } else { None };
+            let then_branch = self.parse_block_until(b, &[Token::Else, Token::End])?;
+            let else_branch = if self.match_token(&Token::Else) { Some(self.parse_block_until(b, &[Token::End])?) } else { None };
             self.expect(&Token::End)?;
-            Ok(Stmt::If { cond, then_branch, else_branch })
+            Ok(b.stmt(StmtNode::If { cond, then_branch, else_branch, span }))
         }
     }
 
-    fn parse_while(&mut self) -> ParseResult<Stmt> {
+    fn parse_while<B: Build>(&mut self, b: &mut B, label: Option<String>, span: Span) -> ParseResult<B::Stmt> {
         self.expect(&Token::While)?;
-        let cond = self.parse_expression()?;
+        let cond = self.parse_condition(b)?;
         self.expect(&Token::Loop)?;
-        let body = self.parse_block_until(&[Token::End])?;
+        let body = self.parse_block_until(b, &[Token::End])?;
         self.expect(&Token::End)?;
-        Ok(Stmt::While { cond, body })
+        Ok(b.stmt(StmtNode::While { label, cond, body, span }))
+    }
+
+    // `outer: for ...` or `outer: while ...`; the loop's span starts at the label
+    fn parse_labelled_loop<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
+        let name = match self.advance() { Token::Identifier(name) => name, t => return self.error_behind(format!("Expected a loop label, got {}", token_to_string(&t))) };
+        self.expect(&Token::Colon)?;
+        match self.peek() {
+            Token::While => self.parse_while(b, Some(name), span),
+            Token::For => self.parse_for(b, Some(name), span),
+            t => self.error_here(format!("Expected 'for' or 'while' after the label '{}', got {}", name, token_to_string(t))),
+        }
+    }
+
+    // `repeat` and `until` are not reserved (`repeat` is also a builtin): they
+    // are loop words unless what follows makes them a name, as in `repeat(s, 2)`
+    fn at_loop_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Token::Identifier(name) if name == word)
+            && !matches!(self.peek_ahead(1), Token::LParen | Token::LBracket | Token::Dot | Token::Assign)
+    }
+
+    fn parse_repeat<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
+        self.advance();
+        let mut body = b.stmts();
+        self.consume_trivia();
+        while !self.at_loop_word("until") && self.peek() != &Token::EOF {
+            let stmt = self.parse_stmt(b)?;
+            b.push_stmt(&mut body, stmt);
+            self.consume_trivia();
+        }
+        if !self.at_loop_word("until") { return self.error_here(format!("Expected 'until' after the body of 'repeat', got {}", token_to_string(self.peek()))); }
+        self.advance();
+        let cond = self.parse_condition(b)?;
+        Ok(b.stmt(StmtNode::RepeatUntil { body, cond, span }))
     }
 
-    fn parse_for(&mut self) -> ParseResult<Stmt> {
+    // `if x := 5 then` is nearly always a comparison gone wrong: the `:=` is
+    // reported and read as `=`, so errors after it are still found
+    fn parse_condition<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let cond = self.parse_expression(b)?;
+        if self.peek() != &Token::Assign { return Ok(cond); }
+        self.recover("assignment ':=' is not allowed in a condition; did you mean '='?");
+        self.advance();
+        let right = self.parse_expression(b)?;
+        Ok(b.expr(ExprNode::Binary { left: cond, op: BinOp::Eq, right }))
+    }
+
+    fn parse_for<B: Build>(&mut self, b: &mut B, label: Option<String>, span: Span) -> ParseResult<B::Stmt> {
         self.expect(&Token::For)?;
         
     
         let (var, iterable) = if self.peek() == &Token::Loop {
             // Infinite loop: loop ... end
-            ("_".to_string(), Expr::None)
+            (LoopPattern::Name("_".to_string()), b.expr(ExprNode::Leaf(Expr::None)))
+        } else if let (Some(word), Token::In) = (self.peek().keyword(), self.peek_ahead(1)) {
+            return self.reserved(word);
+        } else if let (Token::Identifier(name), Token::In) = (self.peek().clone(), self.peek_ahead(1)) {
+            self.advance();
+            self.advance();
+            (LoopPattern::Name(name), self.parse_expression(b)?)
+        } else if self.tuple_pattern_ahead() {
+            let pattern = self.parse_tuple_pattern()?;
+            self.expect(&Token::In)?;
+            (pattern, self.parse_expression(b)?)
         } else {
-            let var_name = if let Token::Identifier(name) = self.peek().clone() {  
-                self.advance();
-                name  
-            } else {
-                "_".to_string()
-            };
-            
-            // check 'in'
-            if self.match_token(&Token::In) {
-                let iterable_expr = self.parse_expression()?;
-                (var_name, iterable_expr)
-            } else {
-                // only expressions without 'in'
-                let iterable_expr = self.parse_expression()?;
-                ("_".to_string(), iterable_expr)
-            }
+            // only an expression without 'in', which may start with a name (`for n..m loop`)
+            (LoopPattern::Name("_".to_string()), self.parse_expression(b)?)
         };
         
         self.expect(&Token::Loop)?;
-        let body = self.parse_block_until(&[Token::End])?;
+        let body = self.parse_block_until(b, &[Token::End])?;
         self.expect(&Token::End)?;
         
-        Ok(Stmt::For { var, iterable, body })
+        Ok(b.stmt(StmtNode::For { label, var, iterable, body, span }))
     }
     
-    fn parse_block_until(&mut self, end_tokens: &[Token]) -> ParseResult<Vec<Stmt>> {
-        let mut stmts = Vec::new();
+    // `{...} in`: braces followed by 'in' are a pattern, otherwise a tuple to iterate
+    fn tuple_pattern_ahead(&self) -> bool {
+        if self.peek() != &Token::LBrace { return false; }
+        let mut depth = 0;
+        for n in 0.. {
+            match self.peek_ahead(n) {
+                Token::LBrace => depth += 1,
+                Token::RBrace => {
+                    depth -= 1;
+                    if depth == 0 { return self.peek_ahead(n + 1) == &Token::In; }
+                }
+                Token::EOF => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // `{x, y}` binds fields by name; `{first := 1, label := name}` picks the field or position to bind
+    fn parse_tuple_pattern(&mut self) -> ParseResult<LoopPattern> {
+        let (line, col) = self.positions.get(self.pos).copied().unwrap_or((0, 0));
+        self.expect(&Token::LBrace)?;
+        let mut fields: Vec<PatternField> = Vec::new();
+        loop {
+            self.refuse_keyword()?;
+            let name = match self.advance() {
+                Token::Identifier(name) => name,
+                other => return self.error_behind(format!("Expected a name in the loop pattern, got {}", token_to_string(&other))),
+            };
+            let field = if self.match_token(&Token::Assign) {
+                match self.advance() {
+                    Token::Identifier(field) => field,
+                    Token::Integer(position) if position >= 1 => position.to_string(),
+                    other => return self.error_behind(format!("Expected a field name or a position from 1 in the loop pattern, got {}", token_to_string(&other))),
+                }
+            } else {
+                name.clone()
+            };
+            if fields.iter().any(|bound| bound.name == name) {
+                return Err(ParseError { message: format!("'{}' is bound twice in the loop pattern", name), line, col });
+            }
+            fields.push(PatternField { name, field });
+            if !self.match_token(&Token::Comma) { break; }
+        }
+        self.expect(&Token::RBrace)?;
+        Ok(LoopPattern::Tuple(fields))
+    }
+
+    fn parse_block_until<B: Build>(&mut self, b: &mut B, end_tokens: &[Token]) -> ParseResult<B::Stmts> {
+        let mut stmts = b.stmts();
         self.consume_trivia();
-        while !end_tokens.contains(self.peek()) && self.peek() != &Token::EOF {
-            stmts.push(self.parse_stmt()?);
+        // `end := 1` is a statement (refused as such), not the end of the block
+        while (!end_tokens.contains(self.peek()) || self.peek_ahead(1) == &Token::Assign) && self.peek() != &Token::EOF {
+            let stmt = self.parse_stmt(b)?;
+            b.push_stmt(&mut stmts, stmt);
             self.consume_trivia();
         }
         Ok(stmts)
     }
 
-    fn parse_return(&mut self) -> ParseResult<Stmt> {
+    fn parse_return<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
         self.expect(&Token::Return)?;
         match self.peek() {
-            Token::End | Token::Else | Token::Loop | Token::Newline | Token::Semicolon => Ok(Stmt::Return(None)),
-            _ => Ok(Stmt::Return(Some(self.parse_expression()?)))
+            Token::End | Token::Else | Token::Loop | Token::Newline | Token::Semicolon => Ok(b.stmt(StmtNode::Return(None, span))),
+            _ => {
+                let value = self.parse_expression(b)?;
+                Ok(b.stmt(StmtNode::Return(Some(value), span)))
+            }
         }
     }
 
-    // Expression hierarchy methods per grammar
-    fn parse_expression(&mut self) -> ParseResult<Expr> {
-        let mut node = self.parse_relation()?;
-        loop {
-            match self.peek() {
-                Token::Or => { self.advance(); let rhs = self.parse_relation()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Or, right: Box::new(rhs) }; }
-                Token::And => { self.advance(); let rhs = self.parse_relation()?; node = Expr::Binary { left: Box::new(node), op: BinOp::And, right: Box::new(rhs) }; }
-                Token::Xor => { self.advance(); let rhs = self.parse_relation()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Xor, right: Box::new(rhs) }; }
-                _ => break,
-            }
+    // The operands are checked to be assignable by the checker, as `:=` targets are
+    fn parse_swap<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
+        self.expect(&Token::Swap)?;
+        let left = self.parse_expression(b)?;
+        self.expect(&Token::Comma)?;
+        let right = self.parse_expression(b)?;
+        Ok(b.stmt(StmtNode::Swap { left, right, span }))
+    }
+
+    fn parse_include<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Stmt> {
+        let span = self.span();
+        self.expect(&Token::Include)?;
+        match self.advance() {
+            Token::String(path) => Ok(b.stmt(StmtNode::Include(path, span))),
+            t => self.error_behind(format!("Expected file path string after include, got {}", token_to_string(&t))),
         }
-        Ok(node)
     }
 
-    fn parse_relation(&mut self) -> ParseResult<Expr> {
-        let mut node = self.parse_range()?;
-        match self.peek() {
-            Token::Less => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Lt, right: Box::new(rhs) }; }
-            Token::LessEqual => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Le, right: Box::new(rhs) }; }
-            Token::Greater => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Gt, right: Box::new(rhs) }; }
-            Token::GreaterEqual => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Ge, right: Box::new(rhs) }; }
-            Token::Equal => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Eq, right: Box::new(rhs) }; }
-            Token::NotEqual => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Ne, right: Box::new(rhs) }; }
-            Token::Is => { self.advance(); let rhs = self.parse_factor()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Is, right: Box::new(rhs) }; }
-            _ => {}
+    // Parse one more element of `list` with `parse`
+    fn parse_into<B: Build>(&mut self, b: &mut B, list: &mut B::Exprs, parse: fn(&mut Self, &mut B) -> ParseResult<B::Expr>) -> ParseResult<()> {
+        let expr = parse(self, b)?;
+        b.push_expr(list, expr);
+        Ok(())
+    }
+
+    // Expression hierarchy methods per grammar
+    fn parse_expression<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        self.nested(|p| p.parse_logical(b))
+    }
+
+    fn parse_logical<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let mut node = self.parse_relation(b)?;
+        while let Some(op) = match self.peek() {
+            Token::Or => Some(BinOp::Or),
+            Token::And => Some(BinOp::And),
+            Token::Xor => Some(BinOp::Xor),
+            Token::OrElse => Some(BinOp::OrElse),
+            Token::AndThen => Some(BinOp::AndThen),
+            _ => None,
+        } {
+            self.advance();
+            let rhs = self.parse_relation(b)?;
+            node = b.expr(ExprNode::Binary { left: node, op, right: rhs });
         }
         Ok(node)
     }
 
-    fn parse_range(&mut self) -> ParseResult<Expr> {
-        let mut node = self.parse_factor()?;
+    fn parse_relation<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let node = self.parse_range(b)?;
+        let op = match self.peek() {
+            Token::Less => BinOp::Lt,
+            Token::LessEqual => BinOp::Le,
+            Token::Greater => BinOp::Gt,
+            Token::GreaterEqual => BinOp::Ge,
+            Token::Equal => BinOp::Eq,
+            Token::NotEqual => BinOp::Ne,
+            Token::Is => BinOp::Is,
+            _ => return Ok(node),
+        };
+        self.advance();
+        let rhs = self.parse_shift(b)?;
+        Ok(b.expr(ExprNode::Binary { left: node, op, right: rhs }))
+    }
+
+    fn parse_range<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let mut node = self.parse_shift(b)?;
         
         if self.match_token(&Token::Range) {
-            let end = self.parse_factor()?;
-            node = Expr::Range(Box::new(node), Box::new(end));
+            let end = self.parse_shift(b)?;
+            node = b.expr(ExprNode::Range(node, end));
         }
         
         Ok(node)
     }
-    
 
-    fn parse_factor(&mut self) -> ParseResult<Expr> {
-        let mut node = self.parse_term()?;
-        loop {
-            match self.peek() {
-                Token::Plus => { self.advance(); let rhs = self.parse_term()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Add, right: Box::new(rhs) }; }
-                Token::Minus => { self.advance(); let rhs = self.parse_term()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Sub, right: Box::new(rhs) }; }
-                _ => break,
-            }
+    fn parse_shift<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let mut node = self.parse_factor(b)?;
+        while let Some(op) = match self.peek() {
+            Token::ShiftLeft => Some(BinOp::Shl),
+            Token::ShiftRight => Some(BinOp::Shr),
+            _ => None,
+        } {
+            self.advance();
+            let rhs = self.parse_factor(b)?;
+            node = b.expr(ExprNode::Binary { left: node, op, right: rhs });
         }
         Ok(node)
     }
 
-    fn parse_term(&mut self) -> ParseResult<Expr> {
-        let mut node = self.parse_unary()?;
-        loop {
-            match self.peek() {
-                Token::Star => { self.advance(); let rhs = self.parse_unary()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Mul, right: Box::new(rhs) }; }
-                Token::Slash => { self.advance(); let rhs = self.parse_unary()?; node = Expr::Binary { left: Box::new(node), op: BinOp::Div, right: Box::new(rhs) }; }
-                _ => break,
-            }
+    fn parse_factor<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let mut node = self.parse_term(b)?;
+        while let Some(op) = match self.peek() {
+            Token::Plus => Some(BinOp::Add),
+            Token::Minus => Some(BinOp::Sub),
+            _ => None,
+        } {
+            self.advance();
+            let rhs = self.parse_term(b)?;
+            node = b.expr(ExprNode::Binary { left: node, op, right: rhs });
         }
         Ok(node)
     }
 
-    fn parse_unary(&mut self) -> ParseResult<Expr> {
-        match self.peek() {
-            Token::Plus => { self.advance(); self.parse_unary() }
-            Token::Minus => { self.advance(); Ok(Expr::Unary { op: UnOp::Neg, expr: Box::new(self.parse_unary()?) }) }
-            Token::Not => { self.advance(); Ok(Expr::Unary { op: UnOp::Not, expr: Box::new(self.parse_unary()?) }) }
+    fn parse_term<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let mut node = self.parse_unary(b)?;
+        while let Some(op) = match self.peek() {
+            Token::Star => Some(BinOp::Mul),
+            Token::Slash => Some(BinOp::Div),
+            // `div` is only an operator between operands, so it stays usable as a name
+            Token::Identifier(name) if name == "div" => Some(BinOp::IntDiv),
+            _ => None,
+        } {
+            self.advance();
+            let rhs = self.parse_unary(b)?;
+            node = b.expr(ExprNode::Binary { left: node, op, right: rhs });
+        }
+        Ok(node)
+    }
+
+    fn parse_unary<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let op = match self.peek() {
+            Token::Plus => UnOp::Plus,
+            Token::Minus => UnOp::Neg,
+            Token::Not => UnOp::Not,
             _ => {
-                let expr = self.parse_reference_primary()?;
+                let expr = self.parse_reference_primary(b)?;
                 
                 // check operator 'is' after expression
-                if self.match_token(&Token::Is) {
+                return if self.match_token(&Token::Is) {
                     let type_ind = self.parse_type_indicator()?;
-                    Ok(Expr::IsType { expr: Box::new(expr), type_ind })
+                    Ok(b.expr(ExprNode::IsType { expr, type_ind }))
                 } else {
                     Ok(expr)
-                }
+                };
             }
-        }
+        };
+        self.advance();
+        let expr = self.nested(|p| p.parse_unary(b))?;
+        Ok(b.expr(ExprNode::Unary { op, expr }))
     }
 
     fn parse_type_indicator(&mut self) -> ParseResult<TypeIndicator> {
@@ -271,91 +732,105 @@ impl Parser {
                 Ok(TypeIndicator::Tuple)
             }
             Token::Func => Ok(TypeIndicator::Func),
-            t => err_from_token(format!("Expected type indicator, got {:?}", t), &t),
+            t => self.error_behind(format!("Expected type indicator, got {}", token_to_string(&t))),
         }
     }
 
-    fn parse_reference_primary(&mut self) -> ParseResult<Expr> {
-        let mut expr = match self.peek().clone() {
-            Token::Integer(n) => { self.advance(); Expr::Integer(n) }
-            Token::Real(r) => { self.advance(); Expr::Real(r) }
-            Token::True => { self.advance(); Expr::Bool(true) }
-            Token::False => { self.advance(); Expr::Bool(false) }
-            Token::None => { self.advance(); Expr::None }
-            Token::String(s) => { self.advance(); Expr::String(s) }
-            Token::Identifier(s) => { self.advance(); Expr::Ident(s) }
-            Token::LParen => { 
-                self.advance(); 
-                let e = self.parse_expression()?; 
-                self.expect(&Token::RParen)?; 
-                e 
+    fn parse_reference_primary<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let expr = self.parse_primary(b)?;
+        self.parse_postfix(b, expr)
+    }
+
+    // Only the bracketing tokens recurse, so the literals and names are left to
+    // parse_atom to keep this frame small for deeply nested input
+    fn parse_primary<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        match self.peek() {
+            Token::LParen => {
+                self.advance();
+                let e = self.parse_expression(b)?;
+                self.expect(&Token::RParen)?;
+                Ok(e)
             }
-            Token::LBracket => self.parse_array_literal()?,
-            Token::LBrace => self.parse_tuple_literal()?,
-            Token::Func => self.parse_func_literal()?,
-            t => return err_from_token(format!("Unexpected token in expression: {:?}", t), &t),
+            Token::LBracket => self.parse_array_literal(b),
+            Token::LBrace => self.parse_tuple_literal(b),
+            Token::Func => self.parse_func_literal(b),
+            _ => self.parse_atom(b),
+        }
+    }
+
+    fn parse_atom<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        let expr = match self.peek().clone() {
+            Token::Integer(n) => { self.advance(); b.expr(ExprNode::Leaf(Expr::Integer(n))) }
+            Token::Real(r) => { self.advance(); b.expr(ExprNode::Leaf(Expr::Real(r))) }
+            Token::True => { self.advance(); b.expr(ExprNode::Leaf(Expr::Bool(true))) }
+            Token::False => { self.advance(); b.expr(ExprNode::Leaf(Expr::Bool(false))) }
+            Token::None => { self.advance(); b.expr(ExprNode::Leaf(Expr::None)) }
+            Token::String(s) => { self.advance(); b.expr(ExprNode::Leaf(Expr::String(s))) }
+            Token::Identifier(s) => { self.advance(); b.expr(ExprNode::Leaf(Expr::Ident(s))) }
+            Token::Error { message, line, col } => return Err(ParseError { message, line, col }),
+            t => return match t.keyword() {
+                // `loop := 1`
+                Some(word) if self.peek_ahead(1) == &Token::Assign => self.reserved(word),
+                _ => self.error_here(format!("Unexpected token in expression: {}", token_to_string(&t))),
+            },
         };
-    
+        Ok(expr)
+    }
+
+    // Calls, indexing and member access after a primary
+    fn parse_postfix<B: Build>(&mut self, b: &mut B, mut expr: B::Expr) -> ParseResult<B::Expr> {
         loop {
-            let is_literal = matches!(
-                expr, 
-                Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | 
-                Expr::String(_) | Expr::None
-            );
+            let is_literal = b.is_literal(&expr);
             
+            if !self.postfix_on_same_line() {
+                break;
+            }
+
             match self.peek() {
                 Token::LParen => {
                     if is_literal {
-                        return err_from_token(
-                            "Cannot call a literal value".to_string(),
-                            self.peek()
-                        );
+                        return self.error_here("Cannot call a literal value".to_string());
                     }
                     
                     self.advance();
-                    let mut args = Vec::new();
+                    let mut args = b.exprs();
                     if self.peek() != &Token::RParen { 
-                        args.push(self.parse_expression()?); 
+                        self.parse_into(b, &mut args, Self::parse_expression)?; 
                         while self.match_token(&Token::Comma) { 
-                            args.push(self.parse_expression()?); 
+                            self.parse_into(b, &mut args, Self::parse_expression)?; 
                         } 
                     }
                     self.expect(&Token::RParen)?;
-                    expr = Expr::Call { callee: Box::new(expr), args };
+                    expr = b.expr(ExprNode::Call { callee: expr, args });
                 }
                 Token::LBracket => {
                     if is_literal {
-                        return err_from_token(
-                            "Cannot index a literal value".to_string(),
-                            self.peek()
-                        );
+                        return self.error_here("Cannot index a literal value".to_string());
                     }
                     
                     self.advance();
-                    let index = self.parse_expression()?;
+                    let index = self.parse_expression(b)?;
                     self.expect(&Token::RBracket)?;
-                    expr = Expr::Index { target: Box::new(expr), index: Box::new(index) };
+                    expr = b.expr(ExprNode::Index { target: expr, index });
                 }
                 Token::Dot => {
                     if is_literal {
-                        return err_from_token(
-                            "Cannot access member of a literal value".to_string(),
-                            self.peek()
-                        );
+                        return self.error_here("Cannot access member of a literal value".to_string());
                     }
                     
                     self.advance();
                     match self.advance() {
                         Token::Identifier(field) => { 
-                            expr = Expr::Member { target: Box::new(expr), field }; 
+                            expr = b.expr(ExprNode::Member { target: expr, field }); 
                         }
                         Token::Integer(n) => { 
-                            expr = Expr::Member { target: Box::new(expr), field: n.to_string() }; 
+                            expr = b.expr(ExprNode::Member { target: expr, field: n.to_string() }); 
                         }
-                        t => return err_from_token(
-                            format!("Expected identifier or integer after '.', got {:?}", t), 
-                            &t
-                        ),
+                        // fields are not variables, so `t.end` is fine
+                        t => match t.keyword() {
+                            Some(word) => expr = b.expr(ExprNode::Member { target: expr, field: word.to_string() }),
+                            None => return self.error_behind(format!("Expected identifier or integer after '.', got {}", token_to_string(&t))),
+                        },
                     }
                 }
                 _ => break,
@@ -366,57 +841,67 @@ impl Parser {
     }
     
 
-    fn parse_array_literal(&mut self) -> ParseResult<Expr> {
+    fn parse_array_literal<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
         self.expect(&Token::LBracket)?;
-        let mut elems = Vec::new();
-        if self.peek() != &Token::RBracket { elems.push(self.parse_expression()?); while self.match_token(&Token::Comma) { elems.push(self.parse_expression()?); } }
+        let mut elems = b.exprs();
+        if self.peek() != &Token::RBracket { self.parse_into(b, &mut elems, Self::parse_element)?; while self.match_token(&Token::Comma) { self.parse_into(b, &mut elems, Self::parse_element)?; } }
         self.expect(&Token::RBracket)?;
-        Ok(Expr::Array(elems))
+        Ok(b.expr(ExprNode::Array(elems)))
+    }
+
+    // An element of an array or tuple literal, which may spread another one: `...xs`
+    fn parse_element<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
+        if self.match_token(&Token::Spread) { let expr = self.parse_expression(b)?; Ok(b.expr(ExprNode::Spread(expr))) } else { self.parse_expression(b) }
     }
 
-    fn parse_tuple_literal(&mut self) -> ParseResult<Expr> {
+    fn parse_tuple_literal<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
         self.expect(&Token::LBrace)?;
         let mut elements = Vec::new();
         
         if self.peek() != &Token::RBrace {
             loop {
-                // Check if the element is named (IDENT :=)
-                let name = if let Token::Identifier(id) = self.peek() {
-                    let id_clone = id.clone();
-                    self.advance();
-                    if self.match_token(&Token::Assign) {
-                        Some(id_clone)  // named el
-                    } else {
-                        // beginning of the expression, roll back
-                        self.pos -= 1;
-                        None
-                    }
-                } else {
-                    None
+                // Check if the element is named (IDENT :=) without consuming anything;
+                // like after `.`, a keyword can name a field
+                let name = match (self.peek(), self.peek_ahead(1)) {
+                    (Token::Identifier(id), Token::Assign) => Some(id.clone()),
+                    (t, Token::Assign) => t.keyword().map(str::to_string),
+                    _ => None,
                 };
+                if name.is_some() {
+                    self.advance();
+                    self.advance();
+                }
                 
-                let value = self.parse_expression()?;
-                elements.push(TupleElement { name, value });
+                let value = if name.is_none() { self.parse_element(b)? } else { self.parse_expression(b)? };
+                elements.push((name, value));
                 
                 if !self.match_token(&Token::Comma) { break; }
             }
         }
         
         self.expect(&Token::RBrace)?;
-        Ok(Expr::Tuple(elements))
+        Ok(b.expr(ExprNode::Tuple(elements)))
     }
     
 
-    fn parse_func_literal(&mut self) -> ParseResult<Expr> {
+    fn parse_func_literal<B: Build>(&mut self, b: &mut B) -> ParseResult<B::Expr> {
         self.expect(&Token::Func)?;
         self.expect(&Token::LParen)?;
         let mut params = Vec::new();
         if self.peek() != &Token::RParen { params.push(self.expect_ident()?); while self.match_token(&Token::Comma) { params.push(self.expect_ident()?); } }
         self.expect(&Token::RParen)?;
-        if self.match_token(&Token::Arrow) { let body_expr = self.parse_expression()?; Ok(Expr::Func { params, body: FuncBody::Expr(Box::new(body_expr)) }) }
-        else if self.match_token(&Token::Is) { let body = self.parse_block_until(&[Token::End])?; self.expect(&Token::End)?; Ok(Expr::Func { params, body: FuncBody::Block(body) }) }
-        else { err_from_token(format!("Expected '=>' or 'is' after func params, got {:?}", self.peek()), self.peek()) }
+        if self.match_token(&Token::Arrow) { let body_expr = self.parse_expression(b)?; Ok(b.expr(ExprNode::Func { params, body: FuncBodyNode::Expr(body_expr) })) }
+        else if self.match_token(&Token::Is) {
+            // a block body separates statements by newlines even when the literal sits inside brackets
+            let nesting = std::mem::replace(&mut self.nesting, 0);
+            let body = self.parse_block_until(b, &[Token::End]);
+            self.nesting = nesting;
+            let body = body?;
+            self.expect(&Token::End)?;
+            Ok(b.expr(ExprNode::Func { params, body: FuncBodyNode::Block(body) }))
+        }
+        else { self.error_here(format!("Expected '=>' or 'is' after func params, got {}", token_to_string(self.peek()))) }
     }
 
-    fn expect_ident(&mut self) -> ParseResult<String> { match self.advance() { Token::Identifier(s) => Ok(s), t => err_from_token(format!("Expected identifier, got {:?}", t), &t) } }
+    fn expect_ident(&mut self) -> ParseResult<String> { self.refuse_keyword()?; match self.advance() { Token::Identifier(s) => Ok(s), t => self.error_behind(format!("Expected identifier, got {}", token_to_string(&t))) } }
 }
@@ -1,7 +1,8 @@
 use crate::ast::*;
 use crate::parser::*;
 
-
+// Statement levels shown when printing trees
+const AST_SUMMARY_DEPTH: usize = 4;
 
 // Auxiliary function for checking successful parsing
 fn parse_ok(input: &str) -> Program {
@@ -9,7 +10,7 @@ fn parse_ok(input: &str) -> Program {
     println!("\n========== INPUT ==========");
     println!("{}", input);
     println!("========== AST ==========");
-    println!("{:#?}", result);
+    print!("{}", result.summary(AST_SUMMARY_DEPTH));
     println!("==========================\n");
     result
 }
@@ -32,7 +33,7 @@ fn test_var_decl_with_init() {
         Program::Stmts(stmts) => {
             assert_eq!(stmts.len(), 1);
             match &stmts[0] {
-                Stmt::VarDecl { name, init } => {
+                Stmt::VarDecl { name, init: Some(init), .. } => {
                     assert_eq!(name, "x");
                     assert_eq!(init, &Expr::Integer(42));
                 }
@@ -49,9 +50,9 @@ fn test_var_decl_without_init() {
         Program::Stmts(stmts) => {
             assert_eq!(stmts.len(), 1);
             match &stmts[0] {
-                Stmt::VarDecl { name, init } => {
+                Stmt::VarDecl { name, init, .. } => {
                     assert_eq!(name, "y");
-                    assert_eq!(init, &Expr::None);
+                    assert_eq!(init, &None, "`var y` has no initializer, unlike `var y := none`");
                 }
                 _ => panic!("Expected VarDecl"),
             }
@@ -66,7 +67,7 @@ fn test_assignment() {
         Program::Stmts(stmts) => {
             assert_eq!(stmts.len(), 1);
             match &stmts[0] {
-                Stmt::Assign { target, value } => {
+                Stmt::Assign { target, value, .. } => {
                     assert!(matches!(target, Expr::Ident(_)));
                     assert_eq!(value, &Expr::Integer(10));
                 }
@@ -82,7 +83,7 @@ fn test_print_single_arg() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::Print { args } => {
+                Stmt::Print { args, .. } => {
                     assert_eq!(args.len(), 1);
                     assert_eq!(args[0], Expr::String("hello".into()));
                 }
@@ -98,7 +99,7 @@ fn test_print_multiple_args() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::Print { args } => {
+                Stmt::Print { args, .. } => {
                     assert_eq!(args.len(), 3);
                 }
                 _ => panic!("Expected Print"),
@@ -107,13 +108,27 @@ fn test_print_multiple_args() {
     }
 }
 
+#[test]
+fn test_print_without_args() {
+    let prog = parse_ok("print 1\nprint\nprint 2");
+    let Program::Stmts(stmts) = &prog;
+    assert_eq!(stmts.len(), 3);
+    assert!(matches!(&stmts[1], Stmt::Print { args, .. } if args.is_empty()));
+    assert_eq!(pretty_print(&prog), "print 1\nprint\nprint 2\n");
+
+    // last in a block, before `else` and `end`
+    let prog = parse_ok("if x then print else print end\nwhile x loop print; print end");
+    let Program::Stmts(stmts) = &prog;
+    assert_eq!(stmts.len(), 2);
+}
+
 #[test]
 fn test_if_then_end() {
     let prog = parse_ok("if x < 10 then print x end");
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::If { cond, then_branch, else_branch } => {
+                Stmt::If { cond, then_branch, else_branch, .. } => {
                     assert!(matches!(cond, Expr::Binary { .. }));
                     assert_eq!(then_branch.len(), 1);
                     assert!(else_branch.is_none());
@@ -130,7 +145,7 @@ fn test_if_then_else_end() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::If { cond, then_branch, else_branch } => {
+                Stmt::If { cond, then_branch, else_branch, .. } => {
                     assert!(matches!(cond, Expr::Binary { .. }));
                     assert_eq!(then_branch.len(), 1);
                     assert!(else_branch.is_some());
@@ -148,7 +163,7 @@ fn test_if_arrow_short_form() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::If { cond, then_branch, else_branch } => {
+                Stmt::If { cond, then_branch, else_branch, .. } => {
                     assert!(matches!(cond, Expr::Binary { .. }));
                     assert_eq!(then_branch.len(), 1);
                     assert!(else_branch.is_none());
@@ -165,7 +180,7 @@ fn test_while_loop() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::While { cond, body } => {
+                Stmt::While { cond, body, .. } => {
                     assert!(matches!(cond, Expr::Binary { .. }));
                     assert_eq!(body.len(), 1);
                 }
@@ -181,8 +196,8 @@ fn test_for_loop_with_array() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::For { var, iterable, body } => {
-                    assert_eq!(var, "i");
+                Stmt::For { var, iterable, body, .. } => {
+                    assert_eq!(var, &LoopPattern::Name("i".to_string()));
                     assert!(matches!(iterable, Expr::Array(_)));
                     assert_eq!(body.len(), 1);
                 }
@@ -198,7 +213,7 @@ fn test_return_with_value() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::Return(Some(expr)) => {
+                Stmt::Return(Some(expr), _) => {
                     assert_eq!(expr, &Expr::Integer(42));
                 }
                 _ => panic!("Expected Return with value"),
@@ -212,7 +227,7 @@ fn test_exit() {
     let prog = parse_ok("exit");
     match &prog {
         Program::Stmts(stmts) => {
-            assert!(matches!(stmts[0], Stmt::Exit));
+            assert!(matches!(stmts[0], Stmt::Exit(None, _)));
         }
     }
 }
@@ -288,7 +303,7 @@ fn test_array_literal() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::VarDecl { init, .. } => {
+                Stmt::VarDecl { init: Some(init), .. } => {
                     match init {
                         Expr::Array(elems) => {
                             assert_eq!(elems.len(), 3);
@@ -308,7 +323,7 @@ fn test_empty_array() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::VarDecl { init, .. } => {
+                Stmt::VarDecl { init: Some(init), .. } => {
                     match init {
                         Expr::Array(elems) => {
                             assert_eq!(elems.len(), 0);
@@ -328,7 +343,7 @@ fn test_tuple_literal() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::VarDecl { init, .. } => {
+                Stmt::VarDecl { init: Some(init), .. } => {
                     match init {
                         Expr::Tuple(elements) => {  
                             assert_eq!(elements.len(), 2);
@@ -349,7 +364,7 @@ fn test_func_arrow_syntax() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::VarDecl { init, .. } => {
+                Stmt::VarDecl { init: Some(init), .. } => {
                     match init {
                         Expr::Func { params, body } => {
                             assert_eq!(params.len(), 1);
@@ -370,7 +385,7 @@ fn test_func_block_syntax() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::VarDecl { init, .. } => {
+                Stmt::VarDecl { init: Some(init), .. } => {
                     match init {
                         Expr::Func { params, body } => {
                             assert_eq!(params.len(), 2);
@@ -391,7 +406,7 @@ fn test_function_call() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::Expr(expr) => {
+                Stmt::Expr(expr, _) => {
                     match expr {
                         Expr::Call { callee, args } => {
                             assert!(matches!(callee.as_ref(), Expr::Ident(_)));
@@ -504,6 +519,86 @@ fn test_error_invalid_syntax() {
     assert!(err.message.contains("identifier"));
 }
 
+#[test]
+fn test_error_assignment_in_condition() {
+    let message = "assignment ':=' is not allowed in a condition; did you mean '='?";
+    let err = parse_err("if x := 5 then print x end");
+    assert_eq!((err.message.as_str(), err.line, err.col), (message, 1, 6));
+
+    let err = parse_err("if x := 5 => print x");
+    assert_eq!((err.message.as_str(), err.line, err.col), (message, 1, 6));
+
+    let err = parse_err("var i := 0\nwhile i := 3 loop\n    i := i + 1\nend");
+    assert_eq!((err.message.as_str(), err.line, err.col), (message, 2, 9));
+}
+
+#[test]
+fn test_error_equal_in_var_decl() {
+    let err = parse_err("var total = 5\nprint total");
+    assert_eq!((err.message.as_str(), err.line, err.col), ("use ':=' to initialize a variable", 1, 11));
+
+    // parsed on as `:=`, so only the one error
+    let mut parser = Parser::new("var total = 5\nprint total");
+    assert!(parser.parse_program().is_err());
+    assert_eq!(parser.errors().len(), 1, "{:?}", parser.errors());
+}
+
+#[test]
+fn test_error_keyword_as_identifier() {
+    let cases = [
+        ("var end := 5", "end", (1, 5)),
+        ("var loop := 1", "loop", (1, 5)),
+        ("var int := 3", "int", (1, 5)),
+        ("for then in [1] loop print 1 end", "then", (1, 5)),
+        ("var f := func(x, return) => x", "return", (1, 18)),
+        ("if true then\n  end := 2\nend", "end", (2, 3)),
+        ("string := \"s\"", "string", (1, 1)),
+    ];
+    for (input, word, position) in cases {
+        let err = parse_err(input);
+        let expected = format!("'{}' is a reserved keyword and cannot be used as an identifier", word);
+        assert_eq!((err.message, (err.line, err.col)), (expected, position), "{}", input);
+    }
+}
+
+#[test]
+fn test_keywords_as_field_names() {
+    let prog = parse_ok("var t := {end := 1, in := 2}\nt.end := t.in + 1\nprint t.end");
+    let Program::Stmts(stmts) = prog;
+    let Stmt::VarDecl { init: Some(Expr::Tuple(elems)), .. } = &stmts[0] else { panic!("Expected a tuple") };
+    assert_eq!(elems.iter().map(|elem| elem.name.as_deref()).collect::<Vec<_>>(), vec![Some("end"), Some("in")]);
+    let member = |field: &str| Expr::Member { target: Box::new(Expr::Ident("t".into())), field: field.into() };
+    assert!(matches!(&stmts[1], Stmt::Assign { target, .. } if *target == member("end")));
+    assert!(matches!(&stmts[2], Stmt::Print { args, .. } if args[0] == member("end")));
+}
+
+#[test]
+fn test_div_is_not_reserved() {
+    let prog = parse_ok("var div := 4\nprint div div 2");
+    let Program::Stmts(stmts) = prog;
+    assert!(matches!(&stmts[1], Stmt::Print { args, .. } if matches!(&args[0], Expr::Binary { op: BinOp::IntDiv, .. })));
+}
+
+#[test]
+fn test_assignment_in_condition_does_not_hide_later_errors() {
+    let mut parser = Parser::new("if x := 5 then print x end\nwhile y := 1 loop print y end\nvar := 42");
+    let first = parser.parse_program().expect_err("Parse should fail");
+    let errors = parser.errors();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert_eq!(errors[0], first);
+    assert_eq!((errors[1].line, errors[1].col), (2, 9));
+    assert!(errors[2].message.contains("identifier"));
+}
+
+#[test]
+fn test_equality_in_condition_still_parses() {
+    let prog = parse_ok("if x = 5 then print x end\nwhile x = 5 loop exit end");
+    let Program::Stmts(stmts) = prog;
+    let eq = Expr::Binary { left: Box::new(Expr::Ident("x".into())), op: BinOp::Eq, right: Box::new(Expr::Integer(5)) };
+    assert!(matches!(&stmts[0], Stmt::If { cond, .. } if *cond == eq));
+    assert!(matches!(&stmts[1], Stmt::While { cond, .. } if *cond == eq));
+}
+
 #[test]
 fn test_range_in_for_loop_basic() {
     let input = r#"
@@ -517,8 +612,8 @@ end
         Program::Stmts(stmts) => {
             assert_eq!(stmts.len(), 1);
             match &stmts[0] {
-                Stmt::For { var, iterable, body } => {
-                    assert_eq!(var, "i");
+                Stmt::For { var, iterable, body, .. } => {
+                    assert_eq!(var, &LoopPattern::Name("i".to_string()));
                     
                 
                     match iterable {
@@ -548,7 +643,7 @@ fn test_range_as_expression_in_variable() {
         Program::Stmts(stmts) => {
             assert_eq!(stmts.len(), 1);
             match &stmts[0] {
-                Stmt::VarDecl { name, init } => {
+                Stmt::VarDecl { name, init: Some(init), .. } => {
                     assert_eq!(name, "range");
                    
                     match init {
@@ -575,7 +670,7 @@ fn test_range_in_function_call() {
     match &prog {
         Program::Stmts(stmts) => {
             match &stmts[0] {
-                Stmt::Expr(expr) => {
+                Stmt::Expr(expr, _) => {
                     match expr {
                         Expr::Call { callee, args } => {
                             assert_eq!(args.len(), 1);
@@ -645,7 +740,7 @@ fn test_is_operator_with_all_basic_types() {
         match &prog {
             Program::Stmts(stmts) => {
                 match &stmts[0] {
-                    Stmt::Expr(expr) => {
+                    Stmt::Expr(expr, _) => {
                         match expr {
                             Expr::IsType { type_ind, .. } => {
                                 assert_eq!(type_ind, &expected_type, "Failed for input: {}", input);
@@ -659,3 +754,497 @@ fn test_is_operator_with_all_basic_types() {
         }
     }
 }
+
+#[test]
+fn test_statement_spans() {
+    let prog = parse_ok("var x := 1\nif x = 1 then\n    print x\nend");
+    match &prog {
+        Program::Stmts(stmts) => {
+            assert_eq!((stmts[0].span().line, stmts[0].span().col), (1, 1));
+            assert_eq!((stmts[1].span().line, stmts[1].span().col), (2, 1));
+            match &stmts[1] {
+                Stmt::If { then_branch, .. } => {
+                    assert_eq!((then_branch[0].span().line, then_branch[0].span().col), (3, 5));
+                }
+                _ => panic!("Expected If"),
+            }
+        }
+    }
+}
+
+// Names of the tuple elements, None for unnamed ones
+fn tuple_names(input: &str) -> Vec<Option<String>> {
+    match parse_ok(input) {
+        Program::Stmts(stmts) => match &stmts[0] {
+            Stmt::VarDecl { init: Some(Expr::Tuple(elements)), .. } => {
+                elements.iter().map(|e| e.name.clone()).collect()
+            }
+            _ => panic!("Expected VarDecl with Tuple literal"),
+        },
+    }
+}
+
+#[test]
+fn test_tuple_unnamed_identifier_first() {
+    assert_eq!(tuple_names("var t := {x, y := 2}"), vec![None, Some("y".to_string())]);
+}
+
+#[test]
+fn test_tuple_unnamed_call_first() {
+    let prog = parse_ok("var t := {f(1), a := 2}");
+    match &prog {
+        Program::Stmts(stmts) => match &stmts[0] {
+            Stmt::VarDecl { init: Some(Expr::Tuple(elements)), .. } => {
+                assert_eq!(elements[0].name, None);
+                assert!(matches!(&elements[0].value, Expr::Call { args, .. } if args.len() == 1));
+                assert_eq!(elements[1].name.as_deref(), Some("a"));
+            }
+            _ => panic!("Expected VarDecl with Tuple literal"),
+        },
+    }
+}
+
+#[test]
+fn test_tuple_nested_unnamed() {
+    let prog = parse_ok("var t := {{x, b := 1}, {}, c := {d := 2}}");
+    match &prog {
+        Program::Stmts(stmts) => match &stmts[0] {
+            Stmt::VarDecl { init: Some(Expr::Tuple(elements)), .. } => {
+                assert_eq!(elements.len(), 3);
+                assert_eq!(elements[0].name, None);
+                assert_eq!(elements[1].name, None);
+                assert_eq!(elements[2].name.as_deref(), Some("c"));
+                match &elements[0].value {
+                    Expr::Tuple(inner) => {
+                        assert_eq!(inner[0].name, None);
+                        assert_eq!(inner[1].name.as_deref(), Some("b"));
+                    }
+                    _ => panic!("Expected nested Tuple"),
+                }
+            }
+            _ => panic!("Expected VarDecl with Tuple literal"),
+        },
+    }
+}
+
+#[test]
+fn test_call_arguments_across_lines() {
+    let prog = parse_ok("print max(\n    1,\n    2\n)");
+    match &prog {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 1);
+            match &stmts[0] {
+                Stmt::Print { args, .. } => {
+                    assert!(matches!(&args[0], Expr::Call { args, .. } if args.len() == 2));
+                }
+                _ => panic!("Expected Print"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_array_literal_one_element_per_line() {
+    let prog = parse_ok("var xs := [\n    1, // first\n    2,\n    3\n]\nprint xs");
+    match &prog {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 2);
+            match &stmts[0] {
+                Stmt::VarDecl { init: Some(Expr::Array(elems)), .. } => assert_eq!(elems.len(), 3),
+                _ => panic!("Expected VarDecl with Array literal"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_newlines_still_separate_statements() {
+    let prog = parse_ok("var x := (1 +\n 2)\nprint x\nvar f := g(func() is\n    print 1\n    print 2\nend)\nprint f");
+    match &prog {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 4);
+            assert!(matches!(&stmts[1], Stmt::Print { .. }));
+            match &stmts[2] {
+                Stmt::VarDecl { init: Some(Expr::Call { args, .. }), .. } => match &args[0] {
+                    Expr::Func { body: FuncBody::Block(body), .. } => assert_eq!(body.len(), 2),
+                    _ => panic!("Expected block function argument"),
+                },
+                _ => panic!("Expected VarDecl with Call"),
+            }
+            assert!(matches!(&stmts[3], Stmt::Print { .. }));
+        }
+    }
+}
+
+#[test]
+fn test_call_on_next_line_is_separate_statement() {
+    let prog = parse_ok("print f(1)\n(2+3)");
+    match &prog {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 2);
+            match &stmts[0] {
+                Stmt::Print { args, .. } => {
+                    assert!(matches!(&args[0], Expr::Call { callee, .. } if matches!(callee.as_ref(), Expr::Ident(_))));
+                }
+                _ => panic!("Expected Print"),
+            }
+            assert!(matches!(&stmts[1], Stmt::Expr(Expr::Binary { .. }, _)));
+        }
+    }
+}
+
+#[test]
+fn test_split_call_inside_parentheses() {
+    let prog = parse_ok("print (f\n(1))");
+    match &prog {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 1);
+            match &stmts[0] {
+                Stmt::Print { args, .. } => assert!(matches!(&args[0], Expr::Call { .. })),
+                _ => panic!("Expected Print"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_postfix_across_comments() {
+    // a comment on the same line doesn't break the call
+    match &parse_ok("var g := f /* note */ (2)") {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 1);
+            assert!(matches!(&stmts[0], Stmt::VarDecl { init: Some(Expr::Call { .. }), .. }));
+        }
+    }
+    // a comment spanning lines does, like a newline would
+    match &parse_ok("var g := f /* note\n */ (2)\nvar h := a /* x\n */ [1]") {
+        Program::Stmts(stmts) => {
+            assert_eq!(stmts.len(), 4);
+            assert!(matches!(&stmts[0], Stmt::VarDecl { init: Some(Expr::Ident(_)), .. }));
+            assert!(matches!(&stmts[2], Stmt::VarDecl { init: Some(Expr::Ident(_)), .. }));
+            assert!(matches!(&stmts[3], Stmt::Expr(Expr::Array(_), _)));
+        }
+    }
+}
+
+#[test]
+fn test_unary_chains_and_plus() {
+    let unary = |op: UnOp, e: Expr| Expr::Unary { op, expr: Box::new(e) };
+    let print_arg = |source: &str| match parse_ok(source) {
+        Program::Stmts(stmts) => match &stmts[0] {
+            Stmt::Print { args, .. } => args[0].clone(),
+            other => panic!("Expected print, got {:?}", other),
+        },
+    };
+    assert_eq!(print_arg("print - - 5"), unary(UnOp::Neg, unary(UnOp::Neg, Expr::Integer(5))));
+    let x = Expr::Ident("x".to_string());
+    assert_eq!(print_arg("print not not not x"), unary(UnOp::Not, unary(UnOp::Not, unary(UnOp::Not, x.clone()))));
+    assert!(matches!(&print_arg("print -(3 - 10)"), Expr::Unary { op: UnOp::Neg, expr } if matches!(expr.as_ref(), Expr::Binary { .. })));
+    // unary plus stays in the tree so its operand can be checked
+    assert_eq!(print_arg("print +x"), unary(UnOp::Plus, x));
+    assert_eq!(print_arg("print +\"abc\""), unary(UnOp::Plus, Expr::String("abc".to_string())));
+    assert_eq!(print_arg("print - - 5").to_string(), "-(-5)");
+}
+
+#[test]
+fn test_unary_minus_in_operand_positions() {
+    let neg = |e: Expr| Expr::Unary { op: UnOp::Neg, expr: Box::new(e) };
+    match &parse_ok("for i in -3..-1 loop\nend\nprint arr[-1], f(-x), {a := -1}") {
+        Program::Stmts(stmts) => {
+            match &stmts[0] {
+                Stmt::For { iterable: Expr::Range(low, high), .. } => {
+                    assert_eq!(low.as_ref(), &neg(Expr::Integer(3)));
+                    assert_eq!(high.as_ref(), &neg(Expr::Integer(1)));
+                }
+                _ => panic!("Expected For over a range"),
+            }
+            match &stmts[1] {
+                Stmt::Print { args, .. } => {
+                    assert!(matches!(&args[0], Expr::Index { index, .. } if index.as_ref() == &neg(Expr::Integer(1))));
+                    assert!(matches!(&args[1], Expr::Call { args, .. } if args[0] == neg(Expr::Ident("x".to_string()))));
+                    assert!(matches!(&args[2], Expr::Tuple(elems) if elems[0].value == neg(Expr::Integer(1))));
+                }
+                _ => panic!("Expected Print"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_include_statement() {
+    match &parse_ok("include \"lib/helpers.dl\"\nprint 1") {
+        Program::Stmts(stmts) => {
+            assert!(matches!(&stmts[0], Stmt::Include(path, _) if path == "lib/helpers.dl"));
+            assert_eq!(stmts[0].span().line, 1);
+        }
+    }
+    let err = parse_err("include helpers");
+    assert!(err.message.contains("Expected file path string after include"));
+}
+
+#[test]
+fn test_swap_statement() {
+    let prog = parse_ok("swap a, b\nswap arr[i], t.x");
+    let Program::Stmts(stmts) = &prog;
+    assert!(matches!(&stmts[0], Stmt::Swap { left: Expr::Ident(a), right: Expr::Ident(b), .. } if a == "a" && b == "b"));
+    assert!(matches!(&stmts[1], Stmt::Swap { left: Expr::Index { .. }, right: Expr::Member { .. }, .. }));
+    assert_eq!(pretty_print(&prog), "swap a, b\nswap arr[i], t.x\n");
+
+    assert!(parse_err("swap a b").message.contains("Expected ',' comma"));
+    assert!(parse_err("var swap := 1").message.contains("reserved keyword"));
+}
+
+#[test]
+fn test_for_over_range_starting_with_name() {
+    let prog = parse_ok("var n := 3\nfor n..5 loop print 1 end");
+    let Program::Stmts(stmts) = prog;
+    assert!(matches!(&stmts[1], Stmt::For { var, iterable: Expr::Range(..), .. } if var.is_placeholder()));
+}
+
+#[test]
+fn test_for_tuple_pattern() {
+    let prog = parse_ok("for {x, second := 2} in points loop print x end");
+    assert_eq!(pretty_print(&prog), "for {x, second := 2} in points loop\n    print x\nend\n");
+    let Program::Stmts(stmts) = prog;
+    let Stmt::For { var: LoopPattern::Tuple(fields), iterable: Expr::Ident(name), .. } = &stmts[0] else {
+        panic!("Expected a for loop with a tuple pattern, got {:?}", stmts[0]);
+    };
+    assert_eq!(name, "points");
+    assert_eq!(fields, &vec![
+        PatternField { name: "x".to_string(), field: "x".to_string() },
+        PatternField { name: "second".to_string(), field: "2".to_string() },
+    ]);
+
+    // without 'in' the braces are a tuple to iterate
+    let Program::Stmts(stmts) = parse_ok("for {a := 1} loop print 1 end");
+    assert!(matches!(&stmts[0], Stmt::For { var, iterable: Expr::Tuple(_), .. } if var.is_placeholder()));
+
+    assert!(parse_err("for {x, x} in points loop end").message.contains("'x' is bound twice in the loop pattern"));
+    assert!(parse_err("for {first := 0} in points loop end").message.contains("position from 1"));
+}
+
+#[test]
+fn test_spread_elements() {
+    let prog = parse_ok("var xs := [1, ...rest, 9]\nvar t := {...base, extra := 1}");
+    assert_eq!(pretty_print(&prog), "var xs := [1, ...rest, 9]\nvar t := {...base, extra := 1}\n");
+    let Program::Stmts(stmts) = prog;
+    let Stmt::VarDecl { init: Some(Expr::Array(elems)), .. } = &stmts[0] else { panic!("Expected an array literal") };
+    assert_eq!(elems[1], Expr::Spread(Box::new(Expr::Ident("rest".to_string()))));
+
+    // only literal elements spread
+    parse_err("print ...xs");
+    parse_err("var t := {name := ...base}");
+}
+
+#[test]
+fn test_repeat_until() {
+    let prog = parse_ok("repeat\n    print 1\nuntil x > 2\nprint repeat(\"a\", 2)\nrepeat := 1");
+    assert_eq!(pretty_print(&prog), "repeat\n    print 1\nuntil x > 2\nprint repeat(\"a\", 2)\nrepeat := 1\n");
+    let Program::Stmts(stmts) = prog;
+    let Stmt::RepeatUntil { body, cond: Expr::Binary { .. }, .. } = &stmts[0] else {
+        panic!("Expected repeat ... until, got {:?}", stmts[0]);
+    };
+    assert_eq!(body.len(), 1);
+
+    assert!(parse_err("repeat\n    print 1\n").message.contains("Expected 'until'"));
+}
+
+#[test]
+fn test_loop_labels() {
+    let source = "outer: for i in 1..3 loop\n    inner: while true loop\n        exit outer\n    end\nend\nexit";
+    let prog = parse_ok(source);
+    assert_eq!(pretty_print(&prog), format!("{}\n", source));
+    let Program::Stmts(stmts) = prog;
+    let Stmt::For { label: Some(outer), body, .. } = &stmts[0] else {
+        panic!("Expected a labelled for, got {:?}", stmts[0]);
+    };
+    assert_eq!(outer, "outer");
+    let Stmt::While { label: Some(inner), body, .. } = &body[0] else {
+        panic!("Expected a labelled while, got {:?}", body[0]);
+    };
+    assert_eq!(inner, "inner");
+    assert!(matches!(&body[0], Stmt::Exit(Some(label), _) if label == "outer"));
+    assert!(matches!(stmts[1], Stmt::Exit(None, _)));
+
+    assert!(parse_err("outer: print 1").message.contains("Expected 'for' or 'while' after the label 'outer'"));
+}
+
+#[test]
+fn test_long_tokens_are_shortened_in_errors() {
+    let message = parse_err(&format!("print (1 \"{}\")", "z".repeat(1000))).message;
+    assert!(message.len() < 200, "{}", message);
+    assert!(message.contains("string literal \"zzz") && message.ends_with("..."), "{}", message);
+}
+
+#[test]
+fn test_errors_name_tokens_in_words() {
+    assert_eq!(parse_err("print (1 2)").message, "Expected ')' closing parenthesis, got integer literal 2");
+    assert_eq!(parse_err("var := 1").message, "Expected identifier after var, got ':=' assignment operator");
+    assert_eq!(parse_err("print (x").message, "Expected ')' closing parenthesis, got end of input");
+    assert_eq!(parse_err("print 1 +").message, "Unexpected token in expression: end of input");
+    assert!(!parse_err("print (x y)").message.contains("Identifier("));
+}
+
+#[test]
+fn test_statements_get_unique_node_ids() {
+    let source = "var f := func(x) is\n    if x > 0 then\n        return x\n    end\n    return 0\nend\nwhile true loop\n    exit\nend\nprint f(1)";
+    let program = parse_ok(source);
+    let mut ids = Vec::new();
+    let mut pending: Vec<&Stmt> = match &program { Program::Stmts(stmts) => stmts.iter().collect() };
+    while let Some(stmt) = pending.pop() {
+        ids.push(stmt.span().id);
+        match stmt {
+            Stmt::VarDecl { init: Some(Expr::Func { body: FuncBody::Block(body), .. }), .. } | Stmt::While { body, .. } => pending.extend(body),
+            Stmt::If { then_branch, .. } => pending.extend(then_branch),
+            _ => {}
+        }
+    }
+    assert_eq!(ids.len(), 7);
+    assert!(!ids.contains(&0));
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 7, "{:?}", ids);
+
+    // a parse that goes on from another one's ids
+    let mut parser = Parser::new("print 1").with_first_id(100);
+    let Program::Stmts(stmts) = parser.parse_program().unwrap();
+    assert_eq!(stmts[0].span().id, 100);
+    assert_eq!(parser.next_id(), 101);
+}
+
+#[test]
+fn test_find_by_id_reaches_nested_statements() {
+    let program = parse_ok("var f := func() is\n    for i in 1..3 loop\n        print i\n    end\nend\nprint 2");
+    let Program::Stmts(stmts) = &program;
+    let Stmt::VarDecl { init: Some(Expr::Func { body: FuncBody::Block(body), .. }), .. } = &stmts[0] else {
+        panic!("Expected a function declaration, got {:?}", stmts[0]);
+    };
+    let Stmt::For { body: loop_body, .. } = &body[0] else {
+        panic!("Expected a for loop, got {:?}", body[0]);
+    };
+    let print = &loop_body[0];
+    let found = program.find_by_id(print.span().id).expect("The nested print should be found");
+    assert!(std::ptr::eq(found, print));
+    assert_eq!(found.span().line, 3);
+    assert!(program.find_by_id(0).is_none());
+    assert!(program.find_by_id(1000).is_none());
+}
+
+#[test]
+fn test_summary_truncates_at_depth() {
+    let program = parse_ok(r#"var f := func(n) is
+    if n > 0 then
+        while n > 0 loop
+            n := n - 1
+        end
+        return f(n)
+    else
+        print "done", n
+    end
+end
+print f(3)"#);
+    assert_eq!(program.summary(0), "[2 statements]\n");
+    assert_eq!(
+        program.summary(1),
+        "VarDecl f := Func(n) [1 statement]\nPrint Call(f, 1 arg)\n"
+    );
+    assert_eq!(
+        program.summary(3),
+        "VarDecl f := Func(n)\n    If Binary(Gt)\n        While Binary(Gt) [1 statement]\n        Return Call(f, 1 arg)\n    Else\n        Print String(\"done\"), Ident(n)\nPrint Call(f, 1 arg)\n"
+    );
+    assert_eq!(
+        program.summary(10),
+        "VarDecl f := Func(n)\n    If Binary(Gt)\n        While Binary(Gt)\n            Assign Ident(n) := Binary(Sub)\n        Return Call(f, 1 arg)\n    Else\n        Print String(\"done\"), Ident(n)\nPrint Call(f, 1 arg)\n"
+    );
+}
+
+#[test]
+fn test_pretty_print_round_trips_generated_programs() {
+    use crate::analyzer::SemanticChecker;
+    use crate::testing::AstGenerator;
+
+    for seed in 1..=500 {
+        let program = AstGenerator::new(seed).with_max_depth(4).program();
+        let source = pretty_print(&program);
+        let reparsed = Parser::new(&source)
+            .parse_program()
+            .unwrap_or_else(|e| panic!("seed {}: {}\n{}", seed, e, source));
+        assert_eq!(reparsed.without_spans(), program.without_spans(), "seed {}:\n{}", seed, source);
+        // printing is stable too
+        assert_eq!(pretty_print(&reparsed), source, "seed {}", seed);
+        if let Err(e) = SemanticChecker::new().check(&program) {
+            panic!("seed {}: {}\n{}", seed, e, source);
+        }
+    }
+}
+
+#[test]
+fn test_pretty_print_precedence() {
+    for source in [
+        "print (a < b) = c\n",
+        "print a - (b - c)\n",
+        "print (-a)(1), (-a).x, (-a) is int\n",
+        "print (t.1).2\n",
+        "print (func(x) => x) + 1\n",
+        "print '\"quoted\"'\n",
+        "print a << b + c, (a << b) + c, a < b >> 2, a and b << 1\n",
+    ] {
+        let program = parse_ok(source);
+        assert_eq!(pretty_print(&program), source);
+    }
+}
+
+#[test]
+fn test_arena_round_trips_generated_programs() {
+    use crate::testing::AstGenerator;
+
+    for seed in 1..=200 {
+        let program = AstGenerator::new(seed).with_max_depth(4).program();
+        let source = pretty_print(&program);
+        let arena = Parser::new(&source).parse_arena().unwrap_or_else(|e| panic!("seed {}: {}", seed, e));
+        let converted = arena.to_program();
+        assert_eq!(converted.without_spans(), program.without_spans(), "seed {}:\n{}", seed, source);
+        // positions and ids too, as the tree parser gives them
+        assert_eq!(converted, Parser::new(&source).parse_program().unwrap(), "seed {}", seed);
+    }
+}
+
+#[test]
+fn test_arena_parses_large_program() {
+    let mut source = String::from("var total := 0\nvar f := func(x) => x * 2\n");
+    for i in 0..50_000 {
+        source.push_str(&match i % 4 {
+            0 => format!("var v{} := [{}, {} + 1, f({})]\n", i, i, i, i),
+            1 => format!("total := total + v{}[1] * {}\n", i - 1, i % 7),
+            2 => format!("if total > {} then print total else total := total - 1 end\n", i),
+            _ => "for j in 1..3 loop total := total + j end\n".to_string(),
+        });
+    }
+
+    let started = std::time::Instant::now();
+    let arena = Parser::new(&source).parse_arena().expect("generated program should parse");
+    let elapsed = started.elapsed();
+    assert_eq!(arena.top_level().len(), 50_002);
+    assert!(elapsed.as_secs() < 20, "parsing took {:?}", elapsed);
+    // a few hundred bytes per statement at most
+    assert!(arena.heap_bytes() < 64 << 20, "arena holds {} bytes", arena.heap_bytes());
+
+    let boxed = Parser::new(&source).parse_program().unwrap();
+    assert_eq!(arena.to_program(), boxed);
+}
+
+#[test]
+fn test_optimizer_dirty_tracking_on_generated_programs() {
+    use crate::analyzer::Optimizer;
+    use crate::testing::AstGenerator;
+
+    for seed in 1..=500 {
+        let program = AstGenerator::new(seed).with_max_depth(4).program();
+        let (mut tracked, mut naive) = (program.clone(), program.clone());
+        let mut tracking = Optimizer::new();
+        let mut walking = Optimizer::new().with_dirty_tracking(false);
+        assert_eq!(tracking.optimize(&mut tracked), walking.optimize(&mut naive));
+        assert_eq!(tracked, naive, "seed {}:\n{}", seed, pretty_print(&program));
+        assert_eq!(tracking.warnings(), walking.warnings(), "seed {}", seed);
+    }
+}
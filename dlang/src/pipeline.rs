@@ -0,0 +1,378 @@
+use crate::analyzer::{AnalysisError, Optimizer, SemanticChecker};
+use crate::ast::{Expr, Program, Stmt};
+use crate::interpreter::{check_nesting, IndexBase, Interpreter, InterpreterError, Value, DEFAULT_MAX_EXPR_DEPTH};
+use crate::loader::{load_file, LoadError};
+use crate::parser::{ParseError, Parser};
+use crate::robust::{semantic_diagnostics, Diagnostic};
+use crate::stdlib::prelude;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// ====
+// Pipeline entry points for library users (graders, editor plugins).
+// Nothing here prints; reporting is left to the caller (see main.rs).
+// ====
+
+// Everything learned about a program without running it
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub program: Option<Program>,             // None when parsing failed
+    pub parse_errors: Vec<Diagnostic>,
+    pub semantic_errors: Vec<Diagnostic>,
+    pub semantic_warnings: Vec<String>,
+    pub semantic_notes: Vec<String>,          // see SemanticChecker::notes
+    pub optimized: Option<Program>,           // the optimizer's result, when it changed anything
+    pub optimizer_warnings: Vec<String>,
+    pub known_constants: BTreeMap<String, Expr>,  // see Optimizer::known_constants, sorted by name
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.parse_errors.is_empty() && self.semantic_errors.is_empty()
+    }
+
+    pub fn optimizable(&self) -> bool {
+        self.optimized.is_some()
+    }
+
+    // The checked program, or the first error that stopped it as a PipelineError
+    pub fn into_result(self) -> Result<Program, PipelineError> {
+        if let Some(e) = self.parse_errors.into_iter().next() {
+            return Err(PipelineError::Parse(ParseError { message: e.message, line: e.line, col: e.col }));
+        }
+        if !self.semantic_errors.is_empty() {
+            return Err(PipelineError::Semantic(self.semantic_errors));
+        }
+        self.program.ok_or_else(|| PipelineError::Parse(ParseError { message: "no program".to_string(), line: 0, col: 0 }))
+    }
+}
+
+// Lex, parse and semantically check `source` without executing it
+pub fn check(source: &str) -> CheckReport {
+    check_with(source, &RunOptions::default())
+}
+
+// Same as `check`, with the checker configured as `run` would configure it
+// for `options` (prelude, strict bools, redeclaration, index base)
+pub fn check_with(source: &str, options: &RunOptions) -> CheckReport {
+    match Parser::new(source).parse_program() {
+        Ok(program) => check_program(program, options),
+        Err(e) => CheckReport { parse_errors: vec![Diagnostic::from(e)], ..CheckReport::default() },
+    }
+}
+
+// Same as `check` for a file on disk, with its includes resolved
+pub fn check_file(path: &Path) -> CheckReport {
+    match load_file(path) {
+        Ok(program) => check_program(program, &RunOptions::default()),
+        Err(e) => CheckReport { parse_errors: vec![Diagnostic::from(e)], ..CheckReport::default() },
+    }
+}
+
+// Parse a standalone expression such as "2*(3+4)" and constant-fold it;
+// use Optimizer::fold_expression directly to see what folding noticed
+pub fn fold_expr_str(source: &str) -> Result<Expr, ParseError> {
+    let mut expr = Parser::new(source).parse_standalone_expression()?;
+    Optimizer::new().fold_expression(&mut expr);
+    Ok(expr)
+}
+
+// A checker set up for `options`, knowing the prelude's names if one is given
+pub(crate) fn checker_for(options: &RunOptions, prelude: Option<&Program>) -> SemanticChecker {
+    let mut checker = SemanticChecker::new();
+    if let Some(prelude) = prelude {
+        checker.declare_prelude(prelude);
+    }
+    checker.set_strict_bool(options.strict_bool);
+    checker.set_allow_redeclaration(options.allow_redeclaration);
+    checker.set_index_base(options.index_base);
+    checker
+}
+
+pub(crate) fn check_program(program: Program, options: &RunOptions) -> CheckReport {
+    let mut report = CheckReport::default();
+    if let Err(e) = check_nesting(&program, DEFAULT_MAX_EXPR_DEPTH) {
+        report.semantic_errors = vec![Diagnostic { message: e.message(), line: 0, col: 0 }];
+        report.program = Some(program);
+        return report;
+    }
+    let prelude = (!options.no_prelude).then(prelude);
+    let mut checker = checker_for(options, prelude.as_ref());
+    if let Err(e) = checker.check(&program) {
+        report.semantic_errors = semantic_diagnostics(e, checker.error_sites());
+    } else {
+        report.semantic_warnings = checker.warnings().to_vec();
+        report.semantic_notes = checker.notes().to_vec();
+        // only meaningful for programs that would actually be optimized and run
+        let mut optimized = program.clone();
+        let mut optimizer = Optimizer::new();
+        if optimizer.optimize(&mut optimized) {
+            report.optimized = Some(optimized);
+        }
+        report.optimizer_warnings = optimizer.warnings().to_vec();
+        report.known_constants = optimizer.known_constants().clone().into_iter().collect();
+    }
+    report.program = Some(program);
+    report
+}
+
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub optimize: bool,                // run the optimizer before executing
+    pub strict: bool,                  // semantic errors stop the run (otherwise they are ignored)
+    pub max_steps: Option<u64>,        // see Interpreter::set_max_steps
+    pub max_call_depth: Option<usize>, // see Interpreter::set_max_call_depth
+    pub memory_limit: Option<usize>,   // see Interpreter::set_memory_limit
+    pub capture_output: bool,          // collect printed lines instead of writing to stdout
+    pub strict_bool: bool,             // conditions must be bools, checked statically and at run time
+    pub strict_unset: bool,            // see Interpreter::with_strict_unset
+    pub index_base: IndexBase,         // first array index, for both the checker and the interpreter
+    pub no_prelude: bool,              // leave out the standard definitions of stdlib.rs
+    pub check_after_optimize: bool,    // check the optimized program, so code the optimizer removes is not reported
+    pub allow_redeclaration: bool,     // see SemanticChecker::set_allow_redeclaration
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            optimize: true,
+            strict: true,
+            max_steps: None,
+            max_call_depth: None,
+            memory_limit: None,
+            capture_output: true,
+            strict_bool: false,
+            strict_unset: false,
+            index_base: IndexBase::default(),
+            no_prelude: false,
+            check_after_optimize: false,
+            allow_redeclaration: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RunOutput {
+    pub output: Vec<String>,             // printed lines (empty unless captured)
+    pub globals: BTreeMap<String, Value>, // top-level bindings after the program finished
+}
+
+// Which part of the pipeline rejected the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Load,
+    Parse,
+    Semantic,
+    Runtime,
+}
+
+// Any error the pipeline can stop with; each concrete error converts into it
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineError {
+    Parse(ParseError),
+    Load(LoadError),
+    Semantic(Vec<Diagnostic>),
+    Runtime { error: InterpreterError, output: Vec<String> },  // output printed before the failure
+}
+
+impl PipelineError {
+    pub fn stage(&self) -> Stage {
+        match self {
+            PipelineError::Parse(_) => Stage::Parse,
+            PipelineError::Load(_) => Stage::Load,
+            PipelineError::Semantic(_) => Stage::Semantic,
+            PipelineError::Runtime { .. } => Stage::Runtime,
+        }
+    }
+
+    // (line, column) of the error, or of the first semantic error that has one
+    pub fn span(&self) -> Option<(usize, usize)> {
+        let known = |line: usize, col: usize| (line > 0).then_some((line, col));
+        match self {
+            PipelineError::Parse(e) | PipelineError::Load(LoadError::Parse { error: e, .. }) => known(e.line, e.col),
+            PipelineError::Load(_) => None,
+            PipelineError::Semantic(errors) => errors.iter().find_map(|e| known(e.line, e.col)),
+            PipelineError::Runtime { error, .. } => error.position(),
+        }
+    }
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::Parse(e) => write!(f, "Parse error: {}", e),
+            PipelineError::Load(e) => write!(f, "Load error: {}", e),
+            PipelineError::Semantic(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "Semantic error: {}", messages.join("\n"))
+            }
+            PipelineError::Runtime { error, .. } => write!(f, "Runtime error: {}", error),
+        }
+    }
+}
+
+// Display already includes the wrapped error, so there is no source()
+impl std::error::Error for PipelineError {}
+
+impl From<ParseError> for PipelineError {
+    fn from(e: ParseError) -> Self {
+        PipelineError::Parse(e)
+    }
+}
+
+impl From<LoadError> for PipelineError {
+    fn from(e: LoadError) -> Self {
+        PipelineError::Load(e)
+    }
+}
+
+impl From<AnalysisError> for PipelineError {
+    fn from(e: AnalysisError) -> Self {
+        PipelineError::Semantic(semantic_diagnostics(e, &[]))
+    }
+}
+
+// Without the printed lines; run() fills those in
+impl From<InterpreterError> for PipelineError {
+    fn from(error: InterpreterError) -> Self {
+        PipelineError::Runtime { error, output: Vec::new() }
+    }
+}
+
+// Parse, check, optimize and interpret `source`
+pub fn run(source: &str, options: RunOptions) -> Result<RunOutput, PipelineError> {
+    run_with(source, options, Interpreter::new())
+}
+
+// Same as `run`, for callers that need a preconfigured interpreter (seeded
+// RNG, print limits, ...); the options override its limits, output mode
+// and index base
+pub fn run_with(source: &str, options: RunOptions, interpreter: Interpreter) -> Result<RunOutput, PipelineError> {
+    let program = Parser::new(source).parse_program()?;
+    run_program(program, options, interpreter)
+}
+
+// Same as `run` for a file on disk, with its includes resolved
+pub fn run_file(path: &Path, options: RunOptions) -> Result<RunOutput, PipelineError> {
+    let program = load_file(path)?;
+    run_program(program, options, Interpreter::new())
+}
+
+pub(crate) fn run_program(mut program: Program, options: RunOptions, mut interpreter: Interpreter) -> Result<RunOutput, PipelineError> {
+    // refused before the checker and optimizer get to recurse into it
+    check_nesting(&program, interpreter.max_expr_depth())?;
+    let prelude = (!options.no_prelude).then(prelude);
+    let check = |program: &Program| {
+        let mut checker = checker_for(&options, prelude.as_ref());
+        checker.check(program).map(|_| ()).map_err(|e| PipelineError::Semantic(semantic_diagnostics(e, checker.error_sites())))
+    };
+    // e.g. a division by zero in a branch on a constant is gone after optimizing
+    let check_after = options.optimize && options.check_after_optimize;
+    if options.strict && !check_after {
+        check(&program)?;
+    }
+    if options.optimize {
+        Optimizer::new().optimize(&mut program);
+    }
+    if options.strict && check_after {
+        check(&program)?;
+    }
+
+    // a strict interpreter passed in stays strict
+    if options.strict_bool {
+        interpreter = interpreter.with_strict_bool(true);
+    }
+    if options.strict_unset {
+        interpreter = interpreter.with_strict_unset(true);
+    }
+    interpreter = interpreter.with_index_base(options.index_base);
+    interpreter.set_output_capture(options.capture_output);
+    interpreter.set_max_steps(options.max_steps);
+    interpreter.set_max_call_depth(options.max_call_depth);
+    interpreter.set_memory_limit(options.memory_limit);
+
+    // the prelude's bindings, so they can be left out of the globals unless
+    // the program replaced them
+    let mut prelude_globals = BTreeMap::new();
+    let result = match &prelude {
+        Some(prelude) => interpreter.load_prelude(prelude).and_then(|()| {
+            let Program::Stmts(stmts) = prelude;
+            let globals = interpreter.globals();
+            for stmt in stmts {
+                if let Stmt::VarDecl { name, .. } = stmt
+                    && let Some(value) = globals.get(name)
+                {
+                    prelude_globals.insert(name.clone(), value.clone());
+                }
+            }
+            interpreter.interpret(&program)
+        }),
+        None => interpreter.interpret(&program),
+    };
+    let output = interpreter.take_output();
+    match result {
+        Ok(()) => {
+            let mut globals = interpreter.globals();
+            globals.retain(|name, value| prelude_globals.get(name) != Some(value));
+            Ok(RunOutput { output, globals })
+        }
+        Err(error) => Err(PipelineError::Runtime { error, output }),
+    }
+}
+
+// ====
+// Running on other threads. Values share environments through Rc, so an
+// Interpreter and its results never leave the thread that made them; a
+// ProgramRunner only holds the parsed program and the options, builds the
+// interpreter inside `run` and hands back plain text.
+// ====
+
+#[derive(Debug, Clone)]
+pub struct ProgramRunner {
+    program: Program,
+    options: RunOptions,
+}
+
+// A failed run as text: the error and the lines printed before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunnerError {
+    pub message: String,
+    pub output: Vec<String>,
+}
+
+impl std::fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ProgramRunner {
+    // Printed lines are always captured, whatever `options.capture_output` says
+    pub fn new(source: &str, options: RunOptions) -> Result<Self, Diagnostic> {
+        let program = Parser::new(source).parse_program().map_err(Diagnostic::from)?;
+        Ok(Self::from_program(program, options))
+    }
+
+    pub fn from_program(program: Program, options: RunOptions) -> Self {
+        Self { program, options: RunOptions { capture_output: true, ..options } }
+    }
+
+    // Check, optimize and interpret on the calling thread
+    pub fn run(self) -> Result<Vec<String>, RunnerError> {
+        match run_program(self.program, self.options, Interpreter::new()) {
+            Ok(run) => Ok(run.output),
+            Err(PipelineError::Runtime { error, output }) => Err(RunnerError { message: format!("Runtime error: {}", error), output }),
+            Err(e) => Err(RunnerError { message: e.to_string(), output: Vec::new() }),
+        }
+    }
+}
+
+// Each runner on a thread of its own; results come back in the same order
+pub fn run_parallel(runners: Vec<ProgramRunner>) -> Vec<Result<Vec<String>, RunnerError>> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = runners.into_iter().map(|runner| scope.spawn(move || runner.run())).collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(RunnerError { message: "interpreter thread panicked".to_string(), output: Vec::new() })))
+            .collect()
+    })
+}
@@ -0,0 +1,207 @@
+use crate::ast::*;
+use std::collections::HashMap;
+
+// ====
+// Resolver: binds every local identifier to a (depth, slot) pair so the
+// interpreter can read frames by index instead of hashing names.
+// Top-level declarations stay global and are still looked up by name.
+// ====
+
+// The scopes pushed here must mirror the environments the interpreter creates:
+// if/else branches, one per loop iteration plus one for the loop body,
+// and one per function call holding the parameters and the block body.
+// Branches and loop bodies that declare nothing get no scope.
+#[derive(Default)]
+pub struct Resolver {
+    scopes: Vec<HashMap<String, usize>>,  // name -> slot, innermost scope last
+    slot_counts: Vec<usize>,              // slots handed out per scope
+}
+
+// Whether a block declares variables of its own (nested blocks aside)
+pub(crate) fn declares_locals(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| matches!(stmt, Stmt::VarDecl { .. }))
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: Vec::new(),
+            slot_counts: Vec::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, program: &mut Program) {
+        match program {
+            Program::Stmts(stmts) => self.resolve_stmts(stmts),
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+        self.slot_counts.push(0);
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+        self.slot_counts.pop();
+    }
+
+    // a redeclaration in the same scope gets a fresh slot, matching the
+    // interpreter which pushes a new slot for every executed `var`
+    fn declare(&mut self, name: &str) {
+        if let (Some(scope), Some(count)) = (self.scopes.last_mut(), self.slot_counts.last_mut()) {
+            scope.insert(name.to_string(), *count);
+            *count += 1;
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<(usize, usize)> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&slot) = scope.get(name) {
+                return Some((depth, slot));
+            }
+        }
+        None
+    }
+
+    fn resolve_stmts(&mut self, stmts: &mut [Stmt]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_block(&mut self, stmts: &mut [Stmt]) {
+        if !declares_locals(stmts) {
+            return self.resolve_stmts(stmts);
+        }
+        self.begin_scope();
+        self.resolve_stmts(stmts);
+        self.end_scope();
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::VarDecl { name, init, .. } => {
+                // functions are declared first so they can call themselves
+                match init {
+                    Some(init @ Expr::Func { .. }) => {
+                        self.declare(name);
+                        self.resolve_expr(init);
+                    }
+                    _ => {
+                        if let Some(init) = init {
+                            self.resolve_expr(init);
+                        }
+                        self.declare(name);
+                    }
+                }
+            }
+            Stmt::Assign { target, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(target);
+            }
+            Stmt::Swap { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Stmt::Print { args, .. } => {
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Stmt::If { cond, then_branch, else_branch, .. } => {
+                self.resolve_expr(cond);
+                self.resolve_block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_block(else_branch);
+                }
+            }
+            Stmt::While { cond, body, .. } => {
+                self.resolve_expr(cond);
+                self.resolve_block(body);
+            }
+            // the condition is outside the body's scope
+            Stmt::RepeatUntil { body, cond, .. } => {
+                self.resolve_block(body);
+                self.resolve_expr(cond);
+            }
+            Stmt::For { var, iterable, body, .. } => {
+                // infinite loops (`loop ... end`) never bind their variable
+                let binds_var = !matches!(iterable, Expr::None) || !var.is_placeholder();
+                self.resolve_expr(iterable);
+
+                self.begin_scope();
+                if binds_var {
+                    var.names().into_iter().for_each(|name| self.declare(name));
+                }
+                self.resolve_block(body);
+                self.end_scope();
+            }
+            Stmt::Return(Some(expr), _) | Stmt::Expr(expr, _) => self.resolve_expr(expr),
+            Stmt::Return(None, _) | Stmt::Exit(..) | Stmt::Include(..) => {}
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Ident(name) => {
+                if let Some((depth, slot)) = self.lookup(name) {
+                    *expr = Expr::Local { name: std::mem::take(name), depth, slot };
+                }
+            }
+            Expr::Integer(_) | Expr::Real(_) | Expr::Bool(_) | Expr::String(_) | Expr::None
+            | Expr::Local { .. } => {}
+            Expr::Range(low, high) => {
+                self.resolve_expr(low);
+                self.resolve_expr(high);
+            }
+            Expr::Binary { .. } => {
+                // along the left operands of a chain with a loop, however long it is
+                let mut rights = Vec::new();
+                let mut node = expr;
+                while let Expr::Binary { left, right, .. } = node {
+                    rights.push(right);
+                    node = left;
+                }
+                self.resolve_expr(node);
+                for right in rights.into_iter().rev() {
+                    self.resolve_expr(right);
+                }
+            }
+            Expr::Unary { expr, .. } | Expr::IsType { expr, .. } | Expr::Spread(expr) => self.resolve_expr(expr),
+            Expr::Call { callee, args } => {
+                self.resolve_expr(callee);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Index { target, index } => {
+                self.resolve_expr(target);
+                self.resolve_expr(index);
+            }
+            Expr::Member { target, .. } => self.resolve_expr(target),
+            Expr::Array(elems) => {
+                for elem in elems {
+                    self.resolve_expr(elem);
+                }
+            }
+            Expr::Tuple(elems) => {
+                for elem in elems {
+                    self.resolve_expr(&mut elem.value);
+                }
+            }
+            Expr::Func { params, body } => {
+                // parameters and a block body share the call's environment
+                self.begin_scope();
+                for param in params.iter() {
+                    self.declare(param);
+                }
+                match body {
+                    FuncBody::Expr(expr) => self.resolve_expr(expr),
+                    FuncBody::Block(stmts) => self.resolve_stmts(stmts),
+                }
+                self.end_scope();
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::analyzer::{AnalysisError, DiagnosticSite};
+use crate::ast::Program;
+use crate::parser::{ParseError, Parser};
+use crate::pipeline::{checker_for, RunOptions};
+use crate::stdlib::prelude;
+
+// ====
+// Robust front end: parse and check untrusted source, reporting every
+// problem as a diagnostic. Must never panic, whatever the input.
+// ====
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,  // 0 when the position is unknown
+    pub col: usize,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 0 { write!(f, "{} (at {}:{})", self.message, self.line, self.col) } else { write!(f, "{}", self.message) }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Diagnostic { message: e.message, line: e.line, col: e.col }
+    }
+}
+
+// The checker reports all of its errors joined by newlines
+// `sites` are the checker's error_sites(), one per line of the message
+pub(crate) fn semantic_diagnostics(e: AnalysisError, sites: &[DiagnosticSite]) -> Vec<Diagnostic> {
+    let AnalysisError::Message(msg) = e;
+    msg.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let span = sites.get(i).map(|site| site.span).unwrap_or_default();
+            Diagnostic { message: line.to_string(), line: span.line, col: span.col }
+        })
+        .collect()
+}
+
+pub fn parse_and_check(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    let mut parser = Parser::new(source);
+    let program = parser.parse_program().map_err(|_| parser.errors().iter().cloned().map(Diagnostic::from).collect::<Vec<_>>())?;
+    // checked as dlang::run would check it, so the prelude's functions are known
+    let mut checker = checker_for(&RunOptions::default(), Some(&prelude()));
+    checker.check(&program).map_err(|e| semantic_diagnostics(e, checker.error_sites()))?;
+    Ok(program)
+}
@@ -0,0 +1,119 @@
+use crate::ast::{Expr, FuncBody, Program, Stmt};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::Token;
+use std::collections::BTreeMap;
+
+// ====
+// Counts for showing what the lexer and parser make of a program (--stats):
+// tokens by kind, AST nodes by variant, how deep the tree gets.
+// ====
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceStats {
+    pub tokens: BTreeMap<String, usize>,                // by kind, e.g. "Identifier"; EOF left out
+    pub statements: BTreeMap<&'static str, usize>,      // by Stmt variant
+    pub expressions: BTreeMap<&'static str, usize>,     // by Expr variant
+    pub max_depth: usize,          // longest chain of nested nodes; a top-level statement is at depth 1
+    pub function_literals: usize,
+    pub parse_error: Option<String>,  // when set, only the token counts are filled in
+}
+
+impl SourceStats {
+    pub fn token_count(&self) -> usize {
+        self.tokens.values().sum()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.statements.values().sum::<usize>() + self.expressions.values().sum::<usize>()
+    }
+}
+
+pub fn source_stats(source: &str) -> SourceStats {
+    let mut stats = SourceStats::default();
+    let mut lexer = Lexer::new(source);
+    loop {
+        let token = lexer.next_token();
+        if token == Token::EOF {
+            break;
+        }
+        *stats.tokens.entry(token_kind(&token)).or_default() += 1;
+    }
+
+    match Parser::new(source).parse_program() {
+        Ok(Program::Stmts(stmts)) => stmts.iter().for_each(|stmt| count_stmt(stmt, 1, &mut stats)),
+        Err(e) => stats.parse_error = Some(e.to_string()),
+    }
+    stats
+}
+
+// Variant name without the token's payload
+fn token_kind(token: &Token) -> String {
+    match token {
+        Token::Identifier(_) => "Identifier".to_string(),
+        Token::Integer(_) => "Integer".to_string(),
+        Token::Real(_) => "Real".to_string(),
+        Token::String(_) => "String".to_string(),
+        Token::Comment(_) => "Comment".to_string(),
+        Token::Error { .. } => "Error".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+fn count_stmt(stmt: &Stmt, depth: usize, stats: &mut SourceStats) {
+    stats.max_depth = stats.max_depth.max(depth);
+    let (name, exprs, blocks): (_, Vec<&Expr>, Vec<&[Stmt]>) = match stmt {
+        Stmt::VarDecl { init, .. } => ("VarDecl", init.iter().collect(), vec![]),
+        Stmt::Assign { target, value, .. } => ("Assign", vec![target, value], vec![]),
+        Stmt::Swap { left, right, .. } => ("Swap", vec![left, right], vec![]),
+        Stmt::Print { args, .. } => ("Print", args.iter().collect(), vec![]),
+        Stmt::If { cond, then_branch, else_branch, .. } => {
+            ("If", vec![cond], [Some(then_branch), else_branch.as_ref()].into_iter().flatten().map(Vec::as_slice).collect())
+        }
+        Stmt::While { cond, body, .. } => ("While", vec![cond], vec![body]),
+        Stmt::RepeatUntil { body, cond, .. } => ("RepeatUntil", vec![cond], vec![body]),
+        Stmt::For { iterable, body, .. } => ("For", vec![iterable], vec![body]),
+        Stmt::Return(value, _) => ("Return", value.iter().collect(), vec![]),
+        Stmt::Exit(..) => ("Exit", vec![], vec![]),
+        Stmt::Expr(expr, _) => ("Expr", vec![expr], vec![]),
+        Stmt::Include(..) => ("Include", vec![], vec![]),
+    };
+    *stats.statements.entry(name).or_default() += 1;
+    exprs.into_iter().for_each(|expr| count_expr(expr, depth + 1, stats));
+    blocks.into_iter().flatten().for_each(|stmt| count_stmt(stmt, depth + 1, stats));
+}
+
+fn count_expr(expr: &Expr, depth: usize, stats: &mut SourceStats) {
+    stats.max_depth = stats.max_depth.max(depth);
+    let (name, children): (_, Vec<&Expr>) = match expr {
+        Expr::Integer(_) => ("Integer", vec![]),
+        Expr::Real(_) => ("Real", vec![]),
+        Expr::Bool(_) => ("Bool", vec![]),
+        Expr::None => ("None", vec![]),
+        Expr::String(_) => ("String", vec![]),
+        Expr::Ident(_) => ("Ident", vec![]),
+        Expr::Local { .. } => ("Local", vec![]),
+        Expr::Range(low, high) => ("Range", vec![low, high]),
+        Expr::Binary { left, right, .. } => ("Binary", vec![left, right]),
+        Expr::Unary { expr, .. } => ("Unary", vec![expr]),
+        Expr::Call { callee, args } => ("Call", std::iter::once(callee.as_ref()).chain(args).collect()),
+        Expr::Index { target, index } => ("Index", vec![target, index]),
+        Expr::Member { target, .. } => ("Member", vec![target]),
+        Expr::Array(elems) => ("Array", elems.iter().collect()),
+        Expr::Tuple(elems) => ("Tuple", elems.iter().map(|elem| &elem.value).collect()),
+        Expr::Spread(expr) => ("Spread", vec![expr]),
+        Expr::IsType { expr, .. } => ("IsType", vec![expr]),
+        Expr::Func { body, .. } => {
+            stats.function_literals += 1;
+            match body {
+                FuncBody::Expr(body) => ("Func", vec![body]),
+                FuncBody::Block(stmts) => {
+                    stmts.iter().for_each(|stmt| count_stmt(stmt, depth + 1, stats));
+                    ("Func", vec![])
+                }
+            }
+        }
+    };
+    *stats.expressions.entry(name).or_default() += 1;
+    children.into_iter().for_each(|child| count_expr(child, depth + 1, stats));
+}
@@ -0,0 +1,14 @@
+use crate::ast::Program;
+use crate::parser::Parser;
+
+// ====
+// Prelude: library functions written in dlang itself. They are run before
+// the user's program and declared in a scope around it, so user code can
+// call them without declaring them and may shadow any of them.
+// ====
+
+pub const PRELUDE_SOURCE: &str = include_str!("prelude.dl");
+
+pub fn prelude() -> Program {
+    Parser::new(PRELUDE_SOURCE).parse_program().expect("the prelude is valid dlang")
+}
@@ -0,0 +1,335 @@
+use crate::ast::*;
+
+// ====
+// Random programs for property tests (available to the crate's own tests
+// and with the `testing` feature). Every generated program is valid: it
+// parses back from its pretty-printed source and the semantic checker
+// accepts it, since names are only used once declared and in scope, and
+// literals that the checker rejects (a zero divisor, real range bounds,
+// constant out-of-bounds indices) are never produced.
+// ====
+
+#[derive(Debug, Clone)]
+struct Name {
+    ident: String,
+    arity: Option<usize>,  // Some for functions
+}
+
+pub struct AstGenerator {
+    state: u64,                // xorshift64*, as in Interpreter::seed_rng
+    max_depth: usize,          // nesting of expressions and blocks
+    scopes: Vec<Vec<Name>>,
+    next_name: usize,
+    in_function: bool,
+    in_loop: bool,
+}
+
+// `end` is a keyword, which fields may still be named
+const TUPLE_FIELDS: &[&str] = &["x", "y", "first", "end"];
+const TYPES: &[TypeIndicator] = &[
+    TypeIndicator::Int,
+    TypeIndicator::Real,
+    TypeIndicator::Bool,
+    TypeIndicator::String,
+    TypeIndicator::None,
+    TypeIndicator::Array,
+    TypeIndicator::Tuple,
+    TypeIndicator::Func,
+];
+const OPS: &[BinOp] = &[
+    BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div, BinOp::IntDiv,
+    BinOp::Eq, BinOp::Ne, BinOp::Lt, BinOp::Le, BinOp::Gt, BinOp::Ge,
+    BinOp::Shl, BinOp::Shr, BinOp::And, BinOp::Or, BinOp::Xor,
+    BinOp::OrElse, BinOp::AndThen,
+];
+
+impl AstGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+            max_depth: 3,
+            scopes: Vec::new(),
+            next_name: 0,
+            in_function: false,
+            in_loop: false,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // A fresh program; each call continues the same random sequence
+    pub fn program(&mut self) -> Program {
+        self.scopes = vec![Vec::new()];
+        self.next_name = 0;
+        let count = 1 + self.below(6);
+        Program::Stmts(self.block(count, self.max_depth))
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    // ===
+    // names
+    // ===
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    fn declare(&mut self, ident: String, arity: Option<usize>) {
+        self.scopes.last_mut().expect("a scope").push(Name { ident, arity });
+    }
+
+    fn visible(&self, functions: bool) -> Vec<Name> {
+        self.scopes.iter().flatten().filter(|name| name.arity.is_some() == functions).cloned().collect()
+    }
+
+    fn variable(&mut self) -> Option<String> {
+        let names = self.visible(false);
+        (!names.is_empty()).then(|| self.pick(&names).ident.clone())
+    }
+
+    // Statements in a scope of their own
+    fn scoped_block(&mut self, depth: usize) -> Vec<Stmt> {
+        self.scopes.push(Vec::new());
+        let count = self.below(4);
+        let stmts = self.block(count, depth);
+        self.scopes.pop();
+        stmts
+    }
+
+    // ===
+    // statements
+    // ===
+
+    fn block(&mut self, count: usize, depth: usize) -> Vec<Stmt> {
+        (0..count).map(|_| self.stmt(depth)).collect()
+    }
+
+    fn stmt(&mut self, depth: usize) -> Stmt {
+        let span = Span::default();
+        let nested = depth.saturating_sub(1);
+        match self.below(if depth == 0 { 4 } else { 9 }) {
+            0 => self.var_decl(nested),
+            1 => match self.variable() {
+                Some(name) => {
+                    let target = if self.chance(30) {
+                        Expr::Index { target: Box::new(Expr::Ident(name)), index: Box::new(self.index(nested)) }
+                    } else {
+                        Expr::Ident(name)
+                    };
+                    Stmt::Assign { target, value: self.expr(nested), span }
+                }
+                None => self.var_decl(nested),
+            },
+            2 => {
+                let count = 1 + self.below(3);
+                Stmt::Print { args: (0..count).map(|_| self.expr(nested)).collect(), span }
+            }
+            3 if self.in_function => Stmt::Return(self.chance(80).then(|| self.expr(nested)), span),
+            3 if self.in_loop => Stmt::Exit(None, span),
+            3 => Stmt::Expr(self.call(nested).unwrap_or(Expr::Integer(0)), span),
+            4 | 5 => {
+                let cond = self.expr(nested);
+                let then_branch = self.scoped_block(nested);
+                let else_branch = self.chance(50).then(|| self.scoped_block(nested));
+                Stmt::If { cond, then_branch, else_branch, span }
+            }
+            6 => {
+                let cond = self.expr(nested);
+                let body = self.loop_body(None, nested);
+                Stmt::While { label: None, cond, body, span }
+            }
+            7 => {
+                let (var, iterable) = match self.below(3) {
+                    0 => ("_".to_string(), Expr::None),
+                    1 => ("_".to_string(), self.range(nested)),
+                    _ => (self.fresh("i"), self.range(nested)),
+                };
+                let body = self.loop_body(Some(&var), nested);
+                Stmt::For { label: None, var: LoopPattern::Name(var), iterable, body, span }
+            }
+            _ => Stmt::Expr(self.call(nested).unwrap_or_else(|| self.expr(nested)), span),
+        }
+    }
+
+    fn var_decl(&mut self, depth: usize) -> Stmt {
+        let init = if self.chance(25) { self.func(depth) } else { self.expr(depth) };
+        let arity = match &init {
+            Expr::Func { params, .. } => Some(params.len()),
+            _ => None,
+        };
+        let name = self.fresh(if arity.is_some() { "f" } else { "v" });
+        self.declare(name.clone(), arity);
+        Stmt::VarDecl { name, init: Some(init), span: Span::default() }
+    }
+
+    fn loop_body(&mut self, var: Option<&str>, depth: usize) -> Vec<Stmt> {
+        let in_loop = std::mem::replace(&mut self.in_loop, true);
+        self.scopes.push(Vec::new());
+        if let Some(var) = var.filter(|var| *var != "_") {
+            self.declare(var.to_string(), None);
+        }
+        let count = self.below(4);
+        let body = self.block(count, depth);
+        self.scopes.pop();
+        self.in_loop = in_loop;
+        body
+    }
+
+    // ===
+    // expressions
+    // ===
+
+    fn expr(&mut self, depth: usize) -> Expr {
+        if depth == 0 || self.chance(25) {
+            return self.atom();
+        }
+        let nested = depth - 1;
+        let boxed = |e: Expr| Box::new(e);
+        match self.below(10) {
+            0..=2 => {
+                let op = self.pick(OPS).clone();
+                let left = self.expr(nested);
+                let right = if matches!(op, BinOp::Div | BinOp::IntDiv) {
+                    // never a literal zero; a variable may still hold one
+                    self.variable().map(Expr::Ident).unwrap_or(Expr::Integer(1 + self.below(9) as i64))
+                } else {
+                    self.expr(nested)
+                };
+                Expr::Binary { left: boxed(left), op, right: boxed(right) }
+            }
+            3 => {
+                let op = if self.chance(50) { UnOp::Neg } else { UnOp::Not };
+                Expr::Unary { op, expr: boxed(self.expr(nested)) }
+            }
+            4 => self.call(nested).unwrap_or_else(|| self.atom()),
+            5 => match self.variable() {
+                Some(name) if self.chance(50) => Expr::Index { target: boxed(Expr::Ident(name)), index: boxed(self.index(nested)) },
+                Some(name) => Expr::Member { target: boxed(Expr::Ident(name)), field: self.pick(TUPLE_FIELDS).to_string() },
+                None => self.atom(),
+            },
+            6 => {
+                let count = self.below(4);
+                Expr::Array((0..count).map(|_| self.expr(nested)).collect())
+            }
+            7 => {
+                let count = self.below(3);
+                let mut elems: Vec<TupleElement> = Vec::new();
+                for _ in 0..count {
+                    // a name used twice is an error
+                    let name = self.chance(50).then(|| self.pick(TUPLE_FIELDS).to_string())
+                        .filter(|name| elems.iter().all(|elem| elem.name.as_ref() != Some(name)));
+                    elems.push(TupleElement { name, value: self.expr(nested) });
+                }
+                Expr::Tuple(elems)
+            }
+            8 => Expr::IsType { expr: boxed(self.expr(nested)), type_ind: self.pick(TYPES).clone() },
+            _ => self.func(nested),
+        }
+    }
+
+    fn atom(&mut self) -> Expr {
+        match self.below(7) {
+            0 => Expr::Integer(self.below(100) as i64),
+            // quarters print exactly, e.g. 2.25
+            1 => Expr::Real(self.below(40) as f64 / 4.0),
+            2 => Expr::Bool(self.chance(50)),
+            3 => Expr::None,
+            4 => {
+                let len = self.below(6);
+                Expr::String((0..len).map(|_| *self.pick(&['a', 'b', 'z', ' ', '"', '1'])).collect())
+            }
+            _ => self.variable().map(Expr::Ident).unwrap_or(Expr::Integer(0)),
+        }
+    }
+
+    // An index the checker cannot evaluate, so it never reports it out of bounds
+    fn index(&mut self, depth: usize) -> Expr {
+        match self.variable() {
+            Some(name) if depth == 0 || self.chance(50) => Expr::Ident(name),
+            Some(name) => Expr::Binary {
+                left: Box::new(Expr::Ident(name)),
+                op: BinOp::Add,
+                right: Box::new(self.expr(depth - 1)),
+            },
+            None => Expr::Call { callee: Box::new(Expr::Ident("randomInt".into())), args: vec![Expr::Integer(1), Expr::Integer(3)] },
+        }
+    }
+
+    fn range(&mut self, depth: usize) -> Expr {
+        let bound = |g: &mut Self| match g.variable() {
+            Some(name) if g.chance(40) => Expr::Ident(name),
+            _ if depth > 0 && g.chance(30) => Expr::Binary {
+                left: Box::new(Expr::Integer(g.below(5) as i64)),
+                op: BinOp::Add,
+                right: Box::new(Expr::Integer(g.below(5) as i64)),
+            },
+            _ => Expr::Integer(g.below(10) as i64),
+        };
+        let low = bound(self);
+        let high = bound(self);
+        Expr::Range(Box::new(low), Box::new(high))
+    }
+
+    // A call of a declared function with the right number of arguments, or
+    // of a function literal
+    fn call(&mut self, depth: usize) -> Option<Expr> {
+        let functions = self.visible(true);
+        let (callee, arity) = if !functions.is_empty() && self.chance(80) {
+            let name = self.pick(&functions).clone();
+            (Expr::Ident(name.ident), name.arity.expect("a function"))
+        } else if depth > 0 {
+            let func = self.func(depth - 1);
+            let Expr::Func { params, .. } = &func else { unreachable!() };
+            let arity = params.len();
+            (func, arity)
+        } else {
+            return None;
+        };
+        let args = (0..arity).map(|_| self.expr(depth.saturating_sub(1))).collect();
+        Some(Expr::Call { callee: Box::new(callee), args })
+    }
+
+    fn func(&mut self, depth: usize) -> Expr {
+        let count = self.below(3);
+        let params: Vec<String> = (0..count).map(|_| self.fresh("p")).collect();
+        self.scopes.push(params.iter().map(|p| Name { ident: p.clone(), arity: None }).collect());
+        let body = if self.chance(50) {
+            FuncBody::Expr(Box::new(self.expr(depth)))
+        } else {
+            let in_function = std::mem::replace(&mut self.in_function, true);
+            // a loop outside the function does not make `exit` valid inside it
+            let in_loop = std::mem::replace(&mut self.in_loop, false);
+            let count = self.below(4);
+            let body = self.block(count, depth);
+            self.in_function = in_function;
+            self.in_loop = in_loop;
+            FuncBody::Block(body)
+        };
+        self.scopes.pop();
+        Expr::Func { params, body }
+    }
+}
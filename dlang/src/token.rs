@@ -1,14 +1,15 @@
 #[derive (Debug, Clone, PartialEq)]
 pub enum Token{
   Var, If, Then, Else, End, While, For, Loop, Func, Is,
-  Exit, Return, Print, True, False, None,
+  Exit, Return, Print, True, False, None, Include, Swap,
 
   Plus, Minus, Star, Slash, Assign, Equal, NotEqual,
-  Less, LessEqual, Greater, GreaterEqual,
+  Less, LessEqual, Greater, GreaterEqual, ShiftLeft, ShiftRight,
   And, Or, Xor, Not,
+  AndThen, OrElse,  // value-returning `and`/`or`
 
   LParen, RParen, LBrace, RBrace, LBracket, RBracket,
-  Comma, Semicolon, Dot, In, Range, Arrow, Newline,
+  Comma, Semicolon, Colon, Dot, In, Range, Spread, Arrow, Newline,
 
   // keywords of types for operator is
   TypeInt,     
@@ -29,3 +30,81 @@ pub enum Token{
 
   EOF,
 }
+
+// Reserved words, sorted by spelling. `div` is not one: it is only an
+// operator between two operands, so it stays usable as a name.
+static KEYWORDS: &[(&str, Token)] = &[
+  ("and", Token::And), ("andthen", Token::AndThen), ("bool", Token::TypeBool), ("else", Token::Else),
+  ("end", Token::End), ("exit", Token::Exit), ("false", Token::False), ("for", Token::For),
+  ("func", Token::Func), ("if", Token::If), ("in", Token::In), ("include", Token::Include),
+  ("int", Token::TypeInt), ("is", Token::Is), ("loop", Token::Loop), ("none", Token::None),
+  ("not", Token::Not), ("or", Token::Or), ("orelse", Token::OrElse), ("print", Token::Print),
+  ("real", Token::TypeReal), ("return", Token::Return), ("string", Token::TypeString), ("swap", Token::Swap),
+  ("then", Token::Then), ("true", Token::True), ("var", Token::Var), ("while", Token::While), ("xor", Token::Xor),
+];
+
+// Operators and punctuation, sorted by spelling
+static OPERATORS: &[(&str, Token)] = &[
+  ("(", Token::LParen), (")", Token::RParen), ("*", Token::Star), ("+", Token::Plus),
+  (",", Token::Comma), ("-", Token::Minus), (".", Token::Dot), ("..", Token::Range),
+  ("...", Token::Spread), ("/", Token::Slash), ("/=", Token::NotEqual), (":", Token::Colon),
+  (":=", Token::Assign), (";", Token::Semicolon), ("<", Token::Less), ("<<", Token::ShiftLeft),
+  ("<=", Token::LessEqual), ("=", Token::Equal), ("=>", Token::Arrow), (">", Token::Greater),
+  (">=", Token::GreaterEqual), (">>", Token::ShiftRight), ("[", Token::LBracket), ("]", Token::RBracket),
+  ("{", Token::LBrace), ("}", Token::RBrace),
+];
+
+impl Token {
+  pub fn keywords() -> &'static [(&'static str, Token)] {
+    KEYWORDS
+  }
+
+  pub fn operators() -> &'static [(&'static str, Token)] {
+    OPERATORS
+  }
+
+  // The reserved word spelled `word`, if it is one
+  pub fn from_keyword(word: &str) -> Option<Token> {
+    KEYWORDS.binary_search_by(|(spelling, _)| spelling.cmp(&word)).ok().map(|i| KEYWORDS[i].1.clone())
+  }
+
+  // Spelling of a reserved word, which can never be a name
+  pub fn keyword(&self) -> Option<&'static str> {
+    KEYWORDS.iter().find(|(_, token)| token == self).map(|(spelling, _)| *spelling)
+  }
+
+  // What the token is, for messages: "':=' assignment operator"
+  pub fn describe(&self) -> &'static str {
+    match self {
+      Token::Var => "'var' keyword", Token::If => "'if' keyword", Token::Then => "'then' keyword",
+      Token::Else => "'else' keyword", Token::End => "'end' keyword", Token::While => "'while' keyword",
+      Token::For => "'for' keyword", Token::Loop => "'loop' keyword", Token::Func => "'func' keyword",
+      Token::Is => "'is' keyword", Token::Exit => "'exit' keyword", Token::Return => "'return' keyword",
+      Token::Print => "'print' keyword", Token::True => "'true' literal", Token::False => "'false' literal",
+      Token::None => "'none' literal", Token::Include => "'include' keyword", Token::In => "'in' keyword",
+      Token::Swap => "'swap' keyword",
+      Token::TypeInt => "'int' type name", Token::TypeReal => "'real' type name",
+      Token::TypeBool => "'bool' type name", Token::TypeString => "'string' type name",
+
+      Token::Plus => "'+' operator", Token::Minus => "'-' operator", Token::Star => "'*' operator",
+      Token::Slash => "'/' operator", Token::Assign => "':=' assignment operator",
+      Token::Equal => "'=' comparison operator", Token::NotEqual => "'/=' comparison operator",
+      Token::Less => "'<' comparison operator", Token::LessEqual => "'<=' comparison operator",
+      Token::Greater => "'>' comparison operator", Token::GreaterEqual => "'>=' comparison operator",
+      Token::ShiftLeft => "'<<' shift operator", Token::ShiftRight => "'>>' shift operator",
+      Token::And => "'and' operator", Token::Or => "'or' operator", Token::Xor => "'xor' operator",
+      Token::Not => "'not' operator", Token::AndThen => "'andthen' operator", Token::OrElse => "'orelse' operator",
+
+      Token::LParen => "'(' opening parenthesis", Token::RParen => "')' closing parenthesis",
+      Token::LBrace => "'{' opening brace", Token::RBrace => "'}' closing brace",
+      Token::LBracket => "'[' opening bracket", Token::RBracket => "']' closing bracket",
+      Token::Comma => "',' comma", Token::Semicolon => "';' semicolon", Token::Colon => "':' colon",
+      Token::Dot => "'.' member access", Token::Range => "'..' range", Token::Spread => "'...' spread",
+      Token::Arrow => "'=>' arrow", Token::Newline => "line break",
+
+      Token::Identifier(_) => "identifier", Token::Integer(_) => "integer literal",
+      Token::Real(_) => "real literal", Token::String(_) => "string literal",
+      Token::Comment(_) => "comment", Token::Error { .. } => "invalid token", Token::EOF => "end of input",
+    }
+  }
+}
@@ -0,0 +1,96 @@
+use crate::interpreter::{Interpreter, Value};
+use crate::parser::Parser;
+use crate::pipeline::{run_program, PipelineError, RunOptions, RunOutput};
+
+// ====
+// Differential check of the optimizer: the same parsed program is run as
+// written and optimized, and anything observable that differs is a bug in
+// an optimization. Both runs use the same RNG seed.
+// ====
+
+const SEED: u64 = 0x5EED;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    // 1-based line; None when that side printed fewer lines
+    Output { line: usize, original: Option<String>, optimized: Option<String> },
+    // None when that side finished without an error
+    Error { original: Option<String>, optimized: Option<String> },
+    Global { name: String, original: String, optimized: String },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let or_none = |side: &Option<String>, none: &str| side.clone().unwrap_or_else(|| none.to_string());
+        match self {
+            Divergence::Output { line, original, optimized } => write!(
+                f,
+                "output line {} differs: {} (original) vs {} (optimized)",
+                line,
+                or_none(original, "<no line>"),
+                or_none(optimized, "<no line>")
+            ),
+            Divergence::Error { original, optimized } => write!(
+                f,
+                "runs end differently: {} (original) vs {} (optimized)",
+                or_none(original, "<success>"),
+                or_none(optimized, "<success>")
+            ),
+            Divergence::Global { name, original, optimized } => {
+                write!(f, "global '{}' differs: {} (original) vs {} (optimized)", name, original, optimized)
+            }
+        }
+    }
+}
+
+// Programs that do not parse have nothing to compare, and ones the checker
+// rejects fail the same way on both sides
+pub fn check_optimizer(source: &str) -> Result<(), Divergence> {
+    let Ok(program) = Parser::new(source).parse_program() else {
+        return Ok(());
+    };
+    let run = |optimize: bool| {
+        let mut interpreter = Interpreter::new();
+        interpreter.seed_rng(SEED);
+        run_program(program.clone(), RunOptions { optimize, ..RunOptions::default() }, interpreter)
+    };
+    compare(run(false), run(true))
+}
+
+fn compare(original: Result<RunOutput, PipelineError>, optimized: Result<RunOutput, PipelineError>) -> Result<(), Divergence> {
+    let (original_output, original_error, original_globals) = split(original);
+    let (optimized_output, optimized_error, optimized_globals) = split(optimized);
+
+    let lines = original_output.len().max(optimized_output.len());
+    for line in 0..lines {
+        let (a, b) = (original_output.get(line), optimized_output.get(line));
+        if a != b {
+            return Err(Divergence::Output { line: line + 1, original: a.cloned(), optimized: b.cloned() });
+        }
+    }
+    if original_error != optimized_error {
+        return Err(Divergence::Error { original: original_error, optimized: optimized_error });
+    }
+
+    // unused variables the optimizer removed are missing on one side; function
+    // values compare by identity, which depends on how many were created
+    for (name, value) in &original_globals {
+        if let Some(other) = optimized_globals.get(name)
+            && !matches!(value, Value::Function { .. })
+            && value != other
+        {
+            return Err(Divergence::Global { name: name.clone(), original: format!("{:?}", value), optimized: format!("{:?}", other) });
+        }
+    }
+    Ok(())
+}
+
+type Outcome = (Vec<String>, Option<String>, std::collections::BTreeMap<String, Value>);
+
+fn split(result: Result<RunOutput, PipelineError>) -> Outcome {
+    match result {
+        Ok(run) => (run.output, None, run.globals),
+        Err(PipelineError::Runtime { error, output }) => (output, Some(format!("Runtime error: {}", error)), Default::default()),
+        Err(e) => (Vec::new(), Some(e.to_string()), Default::default()),
+    }
+}
@@ -1,9 +1,12 @@
-use dlang::{Parser, SemanticChecker, Optimizer};
+use dlang::{ChangeSet, Parser, SemanticChecker, Optimizer, diff_programs};
 use std::fs;
 
 
 // helper funcs with debug output
 
+// Statement levels shown when printing trees
+const AST_SUMMARY_DEPTH: usize = 4;
+
 fn get_program(source: &str) -> dlang::ast::Program {
     let mut parser = Parser::new(source);
     parser.parse_program().expect("Failed to parse program")
@@ -18,7 +21,7 @@ fn check_semantics_verbose(source: &str, test_name: &str) -> Result<Vec<String>,
     
     let ast = get_program(source);
     println!("ORIGINAL AST:");
-    println!("{:#?}", ast);
+    print!("{}", ast.summary(AST_SUMMARY_DEPTH));
     println!("====");
     println!("SEMANTIC ANALYSIS:");
     
@@ -49,7 +52,7 @@ fn optimize_program_verbose(source: &str, test_name: &str) -> Result<dlang::ast:
     
     let mut ast = get_program(source);
     println!("ORIGINAL AST:");
-    println!("{:#?}", ast);
+    print!("{}", ast.summary(AST_SUMMARY_DEPTH));
     println!("====");
     
     let mut checker = SemanticChecker::new();
@@ -77,7 +80,7 @@ fn optimize_program_verbose(source: &str, test_name: &str) -> Result<dlang::ast:
         println!();
         println!("=======");
         println!("OPTIMIZED AST:");
-        println!("{:#?}", ast);
+        print!("{}", ast.summary(AST_SUMMARY_DEPTH));
     } else {
         println!("+ No optimizations applied");
     }    
@@ -95,7 +98,7 @@ fn optimize_program_verbose_unchecked(source: &str, test_name: &str) -> Result<d
     
     let mut ast = get_program(source);
     println!("ORIGINAL AST:");
-    println!("{:#?}", ast);
+    print!("{}", ast.summary(AST_SUMMARY_DEPTH));
     println!("====");
     
     println!("RUNNING OPTIMIZATIONS (skipping semantic checks):");
@@ -107,7 +110,7 @@ fn optimize_program_verbose_unchecked(source: &str, test_name: &str) -> Result<d
         println!();
         println!("=======");
         println!("OPTIMIZED AST:");
-        println!("{:#?}", ast);
+        print!("{}", ast.summary(AST_SUMMARY_DEPTH));
     } else {
         println!("+ No optimizations applied");
     }
@@ -132,6 +135,31 @@ fn test_semantic_declarations_before_usage() {
     assert!(errors[0].contains("used before declaration"));
 }
 
+#[test]
+fn test_semantic_undefined_name_suggestions() {
+    let errors = check_semantics_verbose("var length := 3\nprint lenght", "Typo Suggestion").expect("Semantic check failed");
+    assert!(errors[0].contains("'lenght' used before declaration; did you mean 'length'?"), "{:?}", errors);
+
+    // builtins are suggested too
+    let errors = check_semantics_verbose("print summ([1, 2])", "Builtin Suggestion").expect("Semantic check failed");
+    assert!(errors[0].contains("did you mean 'sum'?"), "{:?}", errors);
+
+    let errors = check_semantics_verbose("var length := 3\nprint velocity", "Unrelated Name").expect("Semantic check failed");
+    assert!(errors[0].contains("used before declaration") && !errors[0].contains("did you mean"), "{:?}", errors);
+
+    // `counter` only exists inside the function body
+    let source = "var f := func() is\n  var counter := 1\n  return counter\nend\nprint countr";
+    let errors = check_semantics_verbose(source, "Out of Scope").expect("Semantic check failed");
+    assert!(errors[0].contains("'countr' used before declaration") && !errors[0].contains("did you mean"), "{:?}", errors);
+}
+
+#[test]
+fn test_semantic_long_names_shortened() {
+    let name = "n".repeat(1000);
+    let errors = check_semantics_verbose(&format!("print {}", name), "Long Name").expect("Semantic check failed");
+    assert_eq!(errors, [format!("Variable or function '{}...' used before declaration", "n".repeat(80))]);
+}
+
 #[test]
 fn test_semantic_valid_declaration() {
     let source = "var x := 10\nprint x";
@@ -185,6 +213,252 @@ fn test_semantic_division_valid() {
     assert!(errors.is_empty(), "Should have no errors for valid division");
 }
 
+#[test]
+fn test_semantic_division_by_zero_in_dead_code() {
+    let sources = [
+        "if false then print 1/0 end",
+        "if 1 > 2 then\n    print 1 / 0\nelse\n    print 1\nend",
+        "while false loop\n    print 1 / 0\nend",
+        "var f := func() is\n    return 1\n    print 1 / 0\nend",
+    ];
+    for source in sources {
+        let errors = check_semantics_verbose(source, "Division by Zero (Dead Code)").expect("Semantic check failed");
+        assert!(errors.is_empty(), "Dead division should not be flagged: {}", source);
+    }
+}
+
+#[test]
+fn test_semantic_division_by_zero_in_live_branch() {
+    let source = "if true then print 1 else print 2 end\nif 1 < 2 then print 1/0 end";
+    let errors = check_semantics_verbose(source, "Division by Zero (Live Branch)").expect("Semantic check failed");
+
+    assert_eq!(errors.len(), 1, "Reachable division should still be flagged");
+    assert!(errors[0].contains("Division by zero"));
+}
+
+#[test]
+fn test_semantic_int_div_by_zero() {
+    let source = "var x := 10 div 0";
+    let errors = check_semantics_verbose(source, "Division by Zero (div)").expect("Semantic check failed");
+
+    assert!(!errors.is_empty(), "Should detect division by zero for div");
+    assert!(errors[0].contains("Division by zero"));
+}
+
+#[test]
+fn test_semantic_discarded_comparison_warns() {
+    let message = "Comparison result is discarded; did you mean ':='?";
+    for source in ["var x := 1\nx = 5", "var a := [1]\na[1] = 2", "var t := {f := 1}\nt.f = 2"] {
+        let mut checker = SemanticChecker::new();
+        checker.check(&get_program(source)).expect("Should pass");
+        assert_eq!(checker.warnings(), &[message.to_string()], "{}", source);
+    }
+
+    // a comparison that is used, or one that is not about a variable, is fine
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var x := 1\nprint x = 5\nif x = 1 then print x end")).expect("Should pass");
+    assert!(checker.warnings().is_empty(), "{:?}", checker.warnings());
+    checker.check(&get_program("1 = 1")).expect("Should pass");
+    assert!(checker.warnings().iter().all(|w| !w.contains(":=")), "{:?}", checker.warnings());
+}
+
+#[test]
+fn test_semantic_comparison_of_mismatched_types() {
+    let warnings = |source: &str| {
+        let mut checker = SemanticChecker::new();
+        checker.check(&get_program(source)).expect("Should pass");
+        checker.warnings().to_vec()
+    };
+
+    assert_eq!(
+        warnings("var x := 5\nif x = \"5\" then print 1 end\nprint x /= \"5\""),
+        vec!["Comparison between int and string is always false", "Comparison between int and string is always true"]
+    );
+    assert_eq!(warnings("var s := \"a\"\nprint s < 3"), vec!["Comparison between string and int will fail at runtime"]);
+    assert_eq!(warnings("var n := 1 + 2\nprint n = true, [1] >= {a := 1}").len(), 2);
+
+    // numbers compare with each other, arrays order among themselves
+    for source in [
+        "var x := 5\nprint x = 5.0, x < 2.5, [1] < [2], none = none",
+        // unknown types are not reported
+        "var f := func(a) => a = \"5\"\nvar x := f(1)\nprint x = \"5\"",
+        // nor are variables that are assigned another type anywhere
+        "var x := 5\nwhile x = \"5\" loop\n    exit\nend\nx := \"5\"",
+        "var x := 0\nvar set := func() is\n    x := \"s\"\nend\nset()\nprint x = \"s\"",
+        // or whose name is reused by a variable that is not known
+        "var x := 5\nfor x in [\"5\"] loop\n    print x = \"5\"\nend",
+    ] {
+        let found = warnings(source);
+        assert!(found.iter().all(|w| !w.starts_with("Comparison between")), "{}: {:?}", source, found);
+    }
+    // assignments that keep the type keep it known
+    assert_eq!(warnings("var count := 0\ncount := count + 1\nprint count = \"1\"").len(), 1);
+}
+
+#[test]
+fn test_semantic_tuple_position_out_of_bounds() {
+    let errors = check_semantics_verbose("var t := {a := 1, 2}\nprint t.2, t[1], t.a\nt.2 := 5", "Tuple Positions").expect("Semantic check failed");
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    let errors = check_semantics_verbose("var t := {a := 1, 2}\nprint t.3\nt[0] := 5\nprint {1}.2", "Tuple Positions Out Of Bounds")
+        .expect("Semantic check failed");
+    assert_eq!(errors.concat().lines().collect::<Vec<_>>(), vec![
+        "Tuple position 3 out of bounds (the tuple has 2 elements)",
+        "Tuple position 0 out of bounds (the tuple has 2 elements)",
+        "Tuple position 2 out of bounds (the tuple has 1 element)",
+    ]);
+
+    // after reassignment the size is no longer known
+    let errors = check_semantics_verbose("var t := {1}\nt := t + {2}\nprint t.2", "Tuple Reassigned").expect("Semantic check failed");
+    assert!(errors.is_empty(), "{:?}", errors);
+}
+
+#[test]
+fn test_semantic_duplicate_tuple_field() {
+    let errors = check_semantics_verbose("var t := {end := 1, x := 2, end := 3}", "Duplicate Field").expect("Semantic check failed");
+    assert_eq!(errors, vec!["Tuple field 'end' is defined more than once".to_string()]);
+
+    let errors = check_semantics_verbose("var t := {end := 1, 2, x := 3}", "Distinct Fields").expect("Semantic check failed");
+    assert!(errors.is_empty(), "{:?}", errors);
+}
+
+#[test]
+fn test_semantic_truncating_division_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var x := 7 / 2\nvar y := 8 / 2\nvar z := 7 div 2")).expect("Should pass");
+
+    // only the division that silently drops a remainder is reported
+    assert_eq!(checker.warnings().len(), 1);
+    assert!(checker.warnings()[0].contains("7 / 2 truncates to 3"));
+}
+
+#[test]
+fn test_semantic_negative_index_out_of_bounds() {
+    let source = "var arr := [1, 2, 3]\nprint arr[-1]";
+    let errors = check_semantics_verbose(source, "Negative Index").expect("Semantic check failed");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("Index -1 out of bounds"));
+}
+
+#[test]
+fn test_semantic_folded_index_out_of_bounds() {
+    let cases = [
+        ("var arr := [1, 2, 3]\nprint arr[2 + 2]", Some("Index 4 out of bounds")),
+        ("var arr := [1, 2, 3]\nprint arr[1 - 3]", Some("Index -2 out of bounds")),
+        ("var arr := [1, 2, 3]\nprint arr[2 * 2 - 1]", None),
+        ("var arr := [1, 2, 3]\nvar last := 3\nprint arr[last + 1]", Some("Index 4 out of bounds")),
+        ("var arr := [1, 2, 3]\nvar i := 2 + 2\narr[i] := 0", Some("Index 4 out of bounds")),
+        ("var arr := [1, 2, 3]\nvar last := 3\nprint arr[last]", None),
+        // a variable assigned anywhere is no constant
+        ("var arr := [1, 2, 3]\nvar i := 4\ni := 1\nprint arr[i]", None),
+        ("var t := {a := 1, b := 2}\nvar i := 1\nprint t[i + 2]", Some("Tuple position 3 out of bounds")),
+    ];
+    for (source, expected) in cases {
+        let mut checker = SemanticChecker::new();
+        let errors = match checker.check(&get_program(source)) {
+            Ok(_) => Vec::new(),
+            Err(e) => e.to_string().lines().map(str::to_string).collect(),
+        };
+        match expected {
+            Some(message) => assert!(errors.len() == 1 && errors[0].contains(message), "{}: {:?}", source, errors),
+            None => assert!(errors.is_empty(), "{}: {:?}", source, errors),
+        }
+    }
+}
+
+#[test]
+fn test_semantic_spread_operand_types() {
+    let errors = check_semantics_verbose("var t := {a := 1}\nprint [1, ...t]", "Tuple Spread Into Array").expect("Semantic check failed");
+    assert_eq!(errors, ["Spread in an array literal needs an array, got tuple"]);
+    let errors = check_semantics_verbose("print {...[1, 2], b := 3}", "Array Spread Into Tuple").expect("Semantic check failed");
+    assert_eq!(errors, ["Spread in a tuple literal needs a tuple, got array"]);
+
+    // unknown operands are left to the interpreter, and spread literals have no known size
+    let errors = check_semantics_verbose("var f := func(v) => [...v]\nvar xs := [...f([1])]\nprint xs[5]", "Unknown Spread").expect("Semantic check failed");
+    assert!(errors.is_empty(), "{:?}", errors);
+}
+
+#[test]
+fn test_semantic_loop_labels() {
+    let errors = check_semantics_verbose("outer: for i in 1..3 loop\n    while true loop\n        exit outer\n    end\nend", "Known Label").expect("Semantic check failed");
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    // a label is only seen inside its own loop, and not from a function body in it
+    let errors = check_semantics_verbose("outer: for loop\n    exit\nend\nfor loop\n    exit outer\nend", "Label Out Of Scope").expect("Semantic check failed");
+    assert_eq!(errors, ["Exit label 'outer' does not name an enclosing loop"]);
+    let errors = check_semantics_verbose("outer: for loop\n    var f := func() is\n        exit outer\n    end\n    f()\nend", "Label In Function").expect("Semantic check failed");
+    assert_eq!(errors, ["Exit label 'outer' does not name an enclosing loop"]);
+}
+
+#[test]
+fn test_semantic_loop_label_shadowing_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("l: for loop\n    l: while true loop\n        exit l\n    end\n    exit l\nend")).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Loop label 'l' shadows the label of an enclosing loop; `exit l` leaves the inner one"]);
+}
+
+#[test]
+fn test_semantic_closure_capturing_loop_variable_noted() {
+    let mut checker = SemanticChecker::new();
+    let source = "var fs := []\nfor i in 1..3 loop\n    fs := [...fs, func() => i]\nend\nprint fs";
+    checker.check(&get_program(source)).expect("Should pass");
+    assert_eq!(checker.notes(), ["Function captures the loop variable 'i'; it keeps the value of the iteration that created it"]);
+    assert_eq!(checker.note_sites()[0].span.line, 3);
+    assert!(checker.warnings().is_empty(), "{:?}", checker.warnings());
+
+    // a parameter of the same name, or a variable shared by every iteration, is no loop variable
+    for source in [
+        "var fs := []\nfor i in 1..3 loop\n    fs := [...fs, func(i) => i]\nend\nprint fs",
+        "var fs := []\nvar i := 1\nwhile i <= 3 loop\n    fs := [...fs, func() => i]\n    i := i + 1\nend\nprint fs",
+    ] {
+        let mut checker = SemanticChecker::new();
+        checker.check(&get_program(source)).expect("Should pass");
+        assert!(checker.notes().is_empty(), "{}: {:?}", source, checker.notes());
+    }
+}
+
+#[test]
+fn test_semantic_unset_variable_read_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var y\nprint y\nprint y")).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Variable 'y' is read before it is assigned a value"]);
+    assert_eq!(checker.warning_sites()[0].span.line, 2);
+    assert_eq!(checker.warning_sites()[0].name.as_deref(), Some("y"));
+
+    // a deliberate none, a value assigned first, or a read inside a function
+    // that may run later is fine
+    for source in [
+        "var y := none\nprint y",
+        "var y\ny := 2\nprint y",
+        "var y\nvar f := func() => y\ny := 1\nprint f()",
+    ] {
+        let mut checker = SemanticChecker::new();
+        checker.check(&get_program(source)).expect("Should pass");
+        assert!(checker.warnings().is_empty(), "{}: {:?}", source, checker.warnings());
+    }
+}
+
+#[test]
+fn test_semantic_unary_plus_needs_a_number() {
+    for (source, type_name) in [("print +\"abc\"", "string"), ("print +true", "bool"), ("var s := \"x\"\nprint +s", "string")] {
+        let errors = check_semantics_verbose(source, "Unary Plus").expect("Semantic check failed");
+        assert_eq!(errors, [format!("Unary '+' expects a number, got {}", type_name)], "{}", source);
+    }
+    let errors = check_semantics_verbose("var x := 2\nprint +x, +-1.5", "Unary Plus on Numbers").expect("Semantic check failed");
+    assert!(errors.is_empty(), "{:?}", errors);
+}
+
+#[test]
+fn test_semantic_range_literal_bounds() {
+    let errors = check_semantics_verbose("for i in -3..3 loop\n    print i\nend", "Negative Range").expect("Semantic check failed");
+    assert!(errors.is_empty());
+
+    let errors = check_semantics_verbose("for i in 1..-2.5 loop\n    print i\nend", "Real Range").expect("Semantic check failed");
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("Range end must be an integer"));
+}
+
 #[test]
 fn test_semantic_variable_redeclaration() {
     let source = "var x := 10\nvar x := 20";
@@ -194,6 +468,179 @@ fn test_semantic_variable_redeclaration() {
     assert!(errors[0].contains("already declared"));
 }
 
+#[test]
+fn test_semantic_redeclaration_allowed_for_sessions() {
+    let source = "var f := func(a) => a\nprint f(1)\nvar f := func(a, b) => a + b\nprint f(1, 2)";
+    let mut checker = SemanticChecker::new();
+    assert!(checker.check(&get_program(source)).is_err(), "An error by default");
+
+    let mut checker = SemanticChecker::new();
+    checker.set_allow_redeclaration(true);
+    checker.check(&get_program(source)).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Function 'f' is redeclared; the new declaration replaces the old one"]);
+    assert_eq!(checker.warning_sites()[0].previous.map(|span| span.line), Some(1));
+
+    // the newest definition's arity is the one enforced
+    let mut checker = SemanticChecker::new();
+    checker.set_allow_redeclaration(true);
+    let err = checker.check(&get_program(&format!("{}\nprint f(1)", source))).unwrap_err().to_string();
+    assert!(err.contains("expects 2 arguments (a, b), got 1"), "{}", err);
+}
+
+
+#[test]
+fn test_semantic_self_reference_in_initializer() {
+    let source = "var n := n + 1";
+    let errors = check_semantics_verbose(source, "Self-Reference in Initializer").expect("Semantic check failed");
+
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("Variable 'n' used in its own initializer"));
+
+    // also through a closure created by the initializer
+    let errors = check_semantics_verbose("var a := [func() => a]", "Self-Reference via Closure").expect("Semantic check failed");
+    assert!(errors[0].contains("used in its own initializer"));
+}
+
+#[test]
+fn test_semantic_self_reference_resolves_to_outer_variable() {
+    let source = "var n := 1\nif true then\n    var n := n + 1\n    print n\nend";
+    let errors = check_semantics_verbose(source, "Self-Reference Shadowing Outer").expect("Semantic check failed");
+
+    assert!(errors.is_empty(), "Inner initializer reads the outer n");
+}
+
+#[test]
+fn test_semantic_recursive_function_references_allowed() {
+    let sources = [
+        "var f := func(k) is\n    if k = 0 then\n        return 0\n    end\n    return f(k - 1)\nend",
+        "var f := func() is\n    var g := f\n    return g\nend",
+        "var f := func() => func() => f",
+    ];
+    for source in sources {
+        let errors = check_semantics_verbose(source, "Recursive Function Reference").expect("Semantic check failed");
+        assert!(errors.is_empty(), "Function may refer to itself: {}", source);
+    }
+}
+
+#[test]
+fn test_semantic_loop_variable_shadowing_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var i := 100\nfor i in 1..3 loop\n    print i\nend\nprint i")).expect("Should pass");
+    assert_eq!(checker.warnings().len(), 1);
+    assert!(checker.warnings()[0].contains("Loop variable 'i' shadows an outer variable"));
+
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("for i in 1..3 loop\n    print i\nend")).expect("Should pass");
+    assert!(checker.warnings().is_empty());
+}
+
+#[test]
+fn test_semantic_unused_parameter_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var f := func(x, y) => x + 1\nprint f(1, 2)")).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Parameter 'y' is never used; rename it to '_y' if that is intended"]);
+    assert_eq!(checker.warning_sites()[0].name.as_deref(), Some("y"));
+
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var f := func(x, _ignored) => x + 1\nprint f(1, 2)")).expect("Should pass");
+    assert!(checker.warnings().is_empty(), "{:?}", checker.warnings());
+
+    // used only by the inner function, which is only returned
+    let source = "var adder := func(n) is\n    return func(x) => x + n\nend\nprint adder(1)(2)";
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program(source)).expect("Should pass");
+    assert!(checker.warnings().is_empty(), "{:?}", checker.warnings());
+}
+
+#[test]
+fn test_semantic_expression_statement_without_effect_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var x := 1\nx + 1")).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Expression statement has no effect"]);
+
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var f := func(a) => a\nf(1)")).expect("Should pass");
+    assert!(checker.warnings().is_empty(), "A call may have an effect");
+}
+
+#[test]
+fn test_semantic_printing_function_value_warns() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var f := func(a) => a\nprint f\nprint f(1)")).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Printing a function value 'f'; did you mean to call it?"]);
+}
+
+#[test]
+fn test_semantic_style_warnings_can_be_disabled() {
+    let mut checker = SemanticChecker::new();
+    checker.set_warn_no_effect(false);
+    checker.set_warn_function_print(false);
+    checker.check(&get_program("var f := func(a) => a\nprint f\nf")).expect("Should pass");
+    assert!(checker.warnings().is_empty());
+}
+
+#[test]
+fn test_semantic_strict_bool_literals() {
+    let source = "if 3 then\n    print 1\nend\nwhile \"x\" loop\n    exit\nend\nprint not 0, true or [1], true and false";
+
+    let mut checker = SemanticChecker::new();
+    assert!(checker.check(&get_program(source)).is_ok(), "Truthiness is allowed by default");
+
+    let mut checker = SemanticChecker::new();
+    checker.set_strict_bool(true);
+    let err = checker.check(&get_program(source)).expect_err("Strict mode should reject non-bool literals").to_string();
+    assert!(err.contains("'if' condition must be a bool, got int"));
+    assert!(err.contains("'while' condition must be a bool, got string"));
+    assert!(err.contains("Operand of 'not' must be a bool, got int"));
+    assert!(err.contains("Operand of 'or' must be a bool, got array"));
+    assert_eq!(err.lines().count(), 4);
+
+    // int literals only combine bitwise with other ints
+    let mut checker = SemanticChecker::new();
+    checker.set_strict_bool(true);
+    assert!(checker.check(&get_program("var n := 1\nprint 6 and 3, n or 8, 1 and true")).is_err());
+    let mut checker = SemanticChecker::new();
+    checker.set_strict_bool(true);
+    assert!(checker.check(&get_program("var n := 1\nprint 6 and 3, n or 8")).is_ok());
+}
+
+#[test]
+fn test_semantic_builtins_are_declared() {
+    let source = "var r := random()\nvar n := randomInt(1, 6)\nvar t := now()\nprint r, n, t";
+    let errors = check_semantics_verbose(source, "Builtins Are Declared").expect("Semantic check failed");
+
+    assert!(errors.is_empty(), "Builtin calls should not be reported as undeclared");
+}
+
+#[test]
+fn test_semantic_builtin_arity() {
+    let source = "var n := randomInt(1)";
+    let errors = check_semantics_verbose(source, "Builtin Arity").expect("Semantic check failed");
+
+    assert!(!errors.is_empty(), "Should detect wrong builtin argument count");
+    assert!(errors[0].contains("expects 2 arguments, got 1"));
+}
+
+#[test]
+fn test_semantic_aggregate_builtin_arity() {
+    let source = "var s := sum([1, 2], [3])";
+    let errors = check_semantics_verbose(source, "Aggregate Builtin Arity").expect("Semantic check failed");
+
+    assert!(!errors.is_empty(), "Should detect wrong builtin argument count");
+    assert!(errors[0].contains("Builtin 'sum' expects 1 arguments, got 2"));
+}
+
+
+#[test]
+fn test_semantic_range_enumerate_arity() {
+    let source = "var a := range()\nvar b := enumerate([1], [2])\nvar c := range(1, 3)";
+    let errors = check_semantics_verbose(source, "Range and Enumerate Arity").expect("Semantic check failed");
+
+    assert!(errors[0].contains("Builtin 'range' expects 1 to 2 arguments, got 0"));
+    assert!(errors[0].contains("Builtin 'enumerate' expects 1 arguments, got 2"));
+    assert_eq!(errors[0].lines().count(), 2, "range(1, 3) is fine");
+}
+
 
 // OPTIMIZATION TESTS: CONSTANT FOLDING
 
@@ -208,7 +655,7 @@ fn test_opt_constant_folding_addition() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Integer(val) = init {
+        if let Some(dlang::ast::Expr::Integer(val)) = init {
             assert_eq!(*val, 8, "Should fold 5 + 3 to 8");
         }
     }
@@ -224,7 +671,7 @@ fn test_opt_constant_folding_subtraction() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Integer(val) = init {
+        if let Some(dlang::ast::Expr::Integer(val)) = init {
             assert_eq!(*val, 7, "Should fold 10 - 3 to 7");
         }
     }
@@ -240,7 +687,7 @@ fn test_opt_constant_folding_multiplication() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Integer(val) = init {
+        if let Some(dlang::ast::Expr::Integer(val)) = init {
             assert_eq!(*val, 20, "Should fold 4 * 5 to 20");
         }
     }
@@ -256,12 +703,43 @@ fn test_opt_constant_folding_division() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Integer(val) = init {
+        if let Some(dlang::ast::Expr::Integer(val)) = init {
             assert_eq!(*val, 5, "Should fold 20 / 4 to 5");
         }
     }
 }
 
+#[test]
+fn test_opt_constant_folding_int_div() {
+    let source = "print 7 div 2";
+    let optimized = optimize_program_verbose(source, "Constant Folding: div").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    assert!(
+        matches!(&stmts[0], dlang::ast::Stmt::Print { args, .. } if args == &vec![dlang::ast::Expr::Integer(3)]),
+        "Should fold 7 div 2 to 3"
+    );
+}
+
+#[test]
+fn test_opt_constant_folding_negative_range() {
+    let source = "for i in -3..3 loop\n    print i\nend";
+    let optimized = optimize_program_verbose(source, "Constant Folding: Negative Range").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    match &stmts[0] {
+        dlang::ast::Stmt::For { iterable: dlang::ast::Expr::Range(low, high), .. } => {
+            assert_eq!(low.as_ref(), &dlang::ast::Expr::Integer(-3));
+            assert_eq!(high.as_ref(), &dlang::ast::Expr::Integer(3));
+        }
+        other => panic!("Expected For over a range, got {:?}", other),
+    }
+}
+
 #[test]
 fn test_opt_constant_folding_comparison_true() {
     let source = "var x := 5 < 10\nprint x";
@@ -272,7 +750,7 @@ fn test_opt_constant_folding_comparison_true() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Bool(val) = init {
+        if let Some(dlang::ast::Expr::Bool(val)) = init {
             assert!(*val, "Should fold 5 < 10 to true");
         }
     }
@@ -288,12 +766,144 @@ fn test_opt_constant_folding_comparison_false() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Bool(val) = init {
+        if let Some(dlang::ast::Expr::Bool(val)) = init {
             assert!(!*val, "Should fold 10 < 5 to false");
         }
     }
 }
 
+#[test]
+fn test_opt_constant_folding_equality_of_other_literals() {
+    let cases = [
+        ("true = false", false),
+        ("true /= false", true),
+        ("\"a\" = \"a\"", true),
+        ("\"a\" /= \"a\"", false),
+        ("none = none", true),
+        ("none /= none", false),
+        ("1 = \"1\"", false),
+        ("none /= 0", true),
+    ];
+    for (expr, expected) in cases {
+        let source = format!("print {}", expr);
+        let optimized = optimize_program_verbose(&source, "Constant Folding: Equality").expect("Optimization failed");
+        let dlang::ast::Program::Stmts(stmts) = optimized;
+        match &stmts[0] {
+            dlang::ast::Stmt::Print { args, .. } if matches!(args[..], [dlang::ast::Expr::Bool(_)]) => {
+                assert_eq!(args[0], dlang::ast::Expr::Bool(expected), "{}", expr)
+            }
+            other => panic!("{} was not folded: {:?}", expr, other),
+        }
+    }
+}
+
+#[test]
+fn test_opt_constant_folding_bitwise() {
+    let cases = [
+        ("12 and 10", dlang::ast::Expr::Integer(8)),
+        ("12 or 3", dlang::ast::Expr::Integer(15)),
+        ("12 xor 10", dlang::ast::Expr::Integer(6)),
+        ("3 << 4", dlang::ast::Expr::Integer(48)),
+        ("-64 >> 3", dlang::ast::Expr::Integer(-8)),
+        ("true xor false", dlang::ast::Expr::Bool(true)),
+    ];
+    for (expr, expected) in cases {
+        let source = format!("print {}", expr);
+        let optimized = optimize_program_verbose(&source, "Constant Folding: Bitwise").expect("Optimization failed");
+        let dlang::ast::Program::Stmts(stmts) = optimized;
+        match &stmts[0] {
+            dlang::ast::Stmt::Print { args, .. } => assert_eq!(args[0], expected, "{}", expr),
+            other => panic!("Unexpected statement: {:?}", other),
+        }
+    }
+
+    // an out-of-range shift is left for the interpreter to report
+    let optimized = optimize_program_verbose("print 1 << 64", "Constant Folding: Bad shift").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    assert!(matches!(&stmts[0], dlang::ast::Stmt::Print { args, .. } if matches!(args[0], dlang::ast::Expr::Binary { .. })));
+}
+
+#[test]
+fn test_opt_real_folding_uses_runtime_semantics() {
+    let optimized = optimize_program_verbose("print 0.1 + 0.2 = 0.3", "Constant Folding: Real equality").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    assert!(matches!(&stmts[0], dlang::ast::Stmt::Print { args, .. } if args[..] == [dlang::ast::Expr::Bool(true)]));
+
+    // an overflowing product is left for the interpreter to report
+    let huge = format!("1{}.0", "0".repeat(299));
+    let source = format!("print {} * {}", huge, huge);
+    let optimized = optimize_program_verbose(&source, "Constant Folding: Real overflow").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    match &stmts[0] {
+        dlang::ast::Stmt::Print { args, .. } => assert!(matches!(args[0], dlang::ast::Expr::Binary { .. })),
+        other => panic!("Unexpected statement: {:?}", other),
+    }
+}
+
+#[test]
+fn test_opt_string_comparison_removes_branch() {
+    let source = "var mode := \"debug\"\nif mode = \"debug\" then print 1 else print 2 end";
+    let optimized = optimize_program_verbose(source, "Constant Folding: String Condition").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    assert!(stmts.iter().all(|s| !matches!(s, dlang::ast::Stmt::If { .. })), "{:?}", stmts);
+    assert!(stmts.iter().any(|s| matches!(s, dlang::ast::Stmt::Print { args, .. } if args == &vec![dlang::ast::Expr::Integer(1)])), "{:?}", stmts);
+}
+
+#[test]
+fn test_opt_keeps_unused_declarations_that_may_fail() {
+    let cases = [
+        ("var x := [1, 2, 3]\nvar i := 0; i := 5\nvar y := x[i]\nprint \"done\"", "out of bounds"),
+        ("var z := 0\nvar y := 10 / z\nprint \"done\"", "Division by zero"),
+        ("var t := {a := 1}\nvar y := t.b\nprint \"done\"", "b"),
+        ("var x := [1]\nvar i := 0; i := 3\nvar y := false and x[i] > 0\nprint \"done\"", "out of bounds"),
+    ];
+    for (source, expected) in cases {
+        let optimized = optimize_program_verbose(source, "Unused Declaration That Fails").expect("Optimization failed");
+        let dlang::ast::Program::Stmts(stmts) = optimized;
+        assert!(stmts.iter().any(|s| matches!(s, dlang::ast::Stmt::VarDecl { name, .. } if name == "y")), "{}", source);
+
+        let options = dlang::RunOptions { capture_output: true, ..dlang::RunOptions::default() };
+        match dlang::run(source, options) {
+            Err(dlang::PipelineError::Runtime { error, output }) => {
+                assert!(error.to_string().contains(expected), "{}: {}", source, error);
+                assert!(output.is_empty(), "{}", source);
+            }
+            other => panic!("{} should still fail at run time: {:?}", source, other.map(|run| run.output)),
+        }
+    }
+}
+
+#[test]
+fn test_opt_keeps_unused_operators_on_wrong_types() {
+    let cases = [
+        ("var x := [1] - 1\nprint \"done\"", "Invalid operands for subtraction"),
+        ("var x := -true\nprint \"done\"", "Cannot negate non-numeric value"),
+        ("var x := none < 1\nprint \"done\"", "Cannot order none and int"),
+        // a loop variable over an array has no known type
+        ("var xs := [[1]]\nvar total := 0\nfor x in xs loop\n    total := total + x\nend\nprint \"done\"", "Invalid operands for addition"),
+    ];
+    for (source, expected) in cases {
+        for optimize in [true, false] {
+            let options = dlang::RunOptions { capture_output: true, optimize, ..dlang::RunOptions::default() };
+            match dlang::run(source, options) {
+                Err(dlang::PipelineError::Runtime { error, output }) => {
+                    assert!(error.to_string().contains(expected), "{} (optimize: {}): {}", source, optimize, error);
+                    assert!(output.is_empty(), "{}", source);
+                }
+                other => panic!("{} (optimize: {}) should fail at run time: {:?}", source, optimize, other.map(|run| run.output)),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_opt_still_removes_unused_declarations_that_cannot_fail() {
+    let source = "var a := 2\nvar y := [a, a * 3, 10 / 2]\nprint \"done\"";
+    let optimized = optimize_program_verbose(source, "Unused Safe Declaration").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    assert!(stmts.iter().all(|s| !matches!(s, dlang::ast::Stmt::VarDecl { name, .. } if name == "y")), "{:?}", stmts);
+}
+
 #[test]
 fn test_opt_constant_folding_unary_negation() {
     let source = "var x := -5\nprint x";
@@ -304,13 +914,111 @@ fn test_opt_constant_folding_unary_negation() {
     };
     
     if let dlang::ast::Stmt::VarDecl { init, .. } = &stmts[0] {
-        if let dlang::ast::Expr::Integer(val) = init {
+        if let Some(dlang::ast::Expr::Integer(val)) = init {
             assert_eq!(*val, -5, "Should fold -5 to -5");
         }
     }
 }
 
 
+#[test]
+fn test_opt_constant_folding_orelse_andthen() {
+    use dlang::ast::Expr;
+    let cases = [
+        ("none orelse \"default\"", Expr::String("default".to_string())),
+        ("\"\" orelse \"default\"", Expr::String("default".to_string())),
+        ("\"set\" orelse 1 / 0", Expr::String("set".to_string())),
+        ("none orelse 0 orelse \"c\"", Expr::String("c".to_string())),
+        ("0 andthen 1 / 0", Expr::Integer(0)),
+        ("[] andthen 2", Expr::Array(vec![])),
+        ("1 andthen 2.5", Expr::Real(2.5)),
+    ];
+    for (expr, expected) in cases {
+        let source = format!("print {}", expr);
+        let optimized = optimize_program_verbose_unchecked(&source, "Constant Folding: orelse/andthen").expect("Optimization failed");
+        let dlang::ast::Program::Stmts(stmts) = optimized;
+        match &stmts[0] {
+            dlang::ast::Stmt::Print { args, .. } => assert_eq!(args[0], expected, "{}", expr),
+            other => panic!("Unexpected statement: {:?}", other),
+        }
+    }
+
+    // an operand that is not a constant decides at run time
+    let optimized = optimize_program_verbose("var x := 1\nx := x + 1\nprint x orelse 2", "orelse kept").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    assert!(matches!(&stmts[2], dlang::ast::Stmt::Print { args, .. } if args[0].to_string() == "x orelse 2"), "{:?}", stmts[2]);
+}
+
+#[test]
+fn test_opt_unset_declaration_is_not_a_constant() {
+    let optimized = optimize_program_verbose_unchecked("var y\nprint y\nvar z := none\nprint z", "Unset declaration").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    assert!(matches!(&stmts[0], dlang::ast::Stmt::VarDecl { init: None, .. }), "{:?}", stmts[0]);
+    assert!(matches!(&stmts[1], dlang::ast::Stmt::Print { args, .. } if args[0] == dlang::ast::Expr::Ident("y".to_string())), "{:?}", stmts[1]);
+    assert!(stmts.iter().any(|s| matches!(s, dlang::ast::Stmt::Print { args, .. } if args[0] == dlang::ast::Expr::None)), "{:?}", stmts);
+}
+
+#[test]
+fn test_opt_constant_folding_unary_chains() {
+    use dlang::ast::Expr;
+    let cases = [
+        ("- - 5", Expr::Integer(5)),
+        ("- - - 2.5", Expr::Real(-2.5)),
+        ("-(3 - 10)", Expr::Integer(7)),
+        ("not not not true", Expr::Bool(false)),
+        ("+5", Expr::Integer(5)),
+        ("- +4", Expr::Integer(-4)),
+        ("not not (1 < 2)", Expr::Bool(true)),
+    ];
+    for (expr, expected) in cases {
+        let source = format!("print {}", expr);
+        let optimized = optimize_program_verbose(&source, "Constant Folding: Unary Chains").expect("Optimization failed");
+        let dlang::ast::Program::Stmts(stmts) = optimized;
+        match &stmts[0] {
+            dlang::ast::Stmt::Print { args, .. } => assert_eq!(args[0], expected, "{}", expr),
+            other => panic!("Unexpected statement: {:?}", other),
+        }
+    }
+
+    // `not not x` is only x when x is a bool
+    let optimized = optimize_program_verbose("var b := 1 < 2\nb := not b\nvar n := 3\nn := n + 1\nprint not not not b, not not n", "Unary Chains on Variables").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = optimized;
+    let dlang::ast::Stmt::Print { args, .. } = &stmts[4] else { panic!("Expected print, got {:?}", stmts[4]) };
+    assert_eq!(args[0].to_string(), "not b");
+    assert_eq!(args[1].to_string(), "not (not n)");
+}
+
+#[test]
+fn test_opt_fold_string_repetition() {
+    let source = "print \"-\" * 3";
+    let optimized = optimize_program_verbose(source, "Constant Folding: String Repetition").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+
+    match &stmts[0] {
+        dlang::ast::Stmt::Print { args, .. } => assert_eq!(args[0], dlang::ast::Expr::String("---".to_string())),
+        other => panic!("Expected print, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_opt_string_repetition_over_cap_not_folded() {
+    let source = "print \"ab\" * 10";
+    let mut ast = get_program(source);
+    let mut optimizer = Optimizer::new();
+    optimizer.set_max_string_len(5);
+    optimizer.optimize(&mut ast);
+
+    let dlang::ast::Program::Stmts(stmts) = ast;
+    match &stmts[0] {
+        dlang::ast::Stmt::Print { args, .. } => assert!(matches!(args[0], dlang::ast::Expr::Binary { .. }), "Should leave the error for runtime"),
+        other => panic!("Expected print, got {:?}", other),
+    }
+}
+
+
 // OPTIMIZATION TESTS: UNUSED VARIABLE REMOVAL
 
 
@@ -339,6 +1047,124 @@ fn test_opt_keep_used_variables() {
 }
 
 
+#[test]
+fn test_opt_remove_dead_store_chain() {
+    let source = "var n := 3\nvar total := 0\nfor x in 1..n loop\n    total := total + x\nend\nprint \"done\"";
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program(source)).expect("Should pass");
+    assert_eq!(checker.warnings(), ["Variable 'total' is assigned but its value is never used"]);
+    assert_eq!(checker.warning_sites()[0].span.line, 2);
+
+    let optimized = optimize_program_verbose(source, "Remove Dead Stores").expect("Optimization failed");
+    let printed = dlang::pretty_print(&optimized);
+    assert!(!printed.contains("total"), "{}", printed);
+
+    // reading it afterwards keeps the whole chain
+    let source = format!("{}\nprint total", source);
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program(&source)).expect("Should pass");
+    assert!(checker.warnings().is_empty(), "{:?}", checker.warnings());
+
+    let optimized = optimize_program_verbose(&source, "Keep Read Stores").expect("Optimization failed");
+    let printed = dlang::pretty_print(&optimized);
+    assert!(printed.contains("var total := 0") && printed.contains("total := total + x"), "{}", printed);
+}
+
+#[test]
+fn test_opt_flatten_literal_spreads() {
+    let source = "var xs := [1, ...[2, 3], 4]\nvar t := {...{a := 1}, b := 2}\nprint xs, t.b";
+    let optimized = optimize_program_verbose(source, "Flatten Literal Spreads").expect("Optimization failed");
+    let printed = dlang::pretty_print(&optimized);
+    assert!(printed.contains("[1, 2, 3, 4]") && printed.contains("{a := 1, b := 2}"), "{}", printed);
+
+    // a repeated name stays spread: a literal may not give a field twice
+    let source = "var t := {x := 1, ...{x := 2}}\nprint t.x";
+    let optimized = optimize_program_verbose(source, "Keep Overriding Spread").expect("Optimization failed");
+    assert!(dlang::pretty_print(&optimized).contains("...{x := 2}"));
+}
+
+#[test]
+fn test_opt_repeat_until_body() {
+    let source = "var n := 0\nrepeat\n    var unused := 1\n    n := n + 1\n    exit\n    print n\nuntil n > 3\nprint n";
+    let optimized = optimize_program_verbose(source, "Repeat Until Body").expect("Optimization failed");
+    let printed = dlang::pretty_print(&optimized);
+    assert!(!printed.contains("unused") && !printed.contains("    print n"), "{}", printed);
+    assert!(printed.contains("until n > 3"), "the loop itself is kept: {}", printed);
+}
+
+#[test]
+fn test_opt_known_constants() {
+    let source = "var x := 2 + 3\nvar y := 1\ny := 2\nvar z := 4\nif y > 1 then\n    var z := 5\n    print z\nend\nvar f := func(a) => a\nprint x, y, z, f(1)";
+    let mut ast = get_program(source);
+    let mut optimizer = Optimizer::new();
+    optimizer.optimize(&mut ast);
+
+    // `x` is folded before it is recorded; `y` is reassigned, `z` shadowed
+    // and `f` a function
+    let known = optimizer.known_constants();
+    assert_eq!(known.get("x"), Some(&dlang::ast::Expr::Integer(5)));
+    assert_eq!(known.len(), 1, "{:?}", known);
+}
+
+#[test]
+fn test_opt_remove_unused_local_in_if_branch() {
+    let source = "var n := 0\nfor i in 1..3 loop\n    if i > 1 then\n        var tmp := 1\n        n := n + i\n    end\nend\nprint n";
+    let optimized = optimize_program_verbose(source, "Remove Unused Local in Branch").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    let Some(dlang::ast::Stmt::For { body, .. }) = stmts.get(1) else {
+        panic!("Loop should remain");
+    };
+    let Some(dlang::ast::Stmt::If { then_branch, .. }) = body.first() else {
+        panic!("Branch should remain");
+    };
+    assert_eq!(then_branch.len(), 1, "Unused local should be removed");
+}
+
+#[test]
+fn test_opt_shadowed_outer_variable_kept() {
+    let source = "var x := 1\nx := x + 1\nfor i in 1..3 loop\n    var x := i\nend\nprint x";
+    let optimized = optimize_program_verbose(source, "Keep Shadowed Outer Variable").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    assert!(matches!(&stmts[0], dlang::ast::Stmt::VarDecl { name, .. } if name == "x"), "Outer x is used by print");
+    let Some(dlang::ast::Stmt::For { body, .. }) = stmts.get(2) else {
+        panic!("Loop should remain");
+    };
+    assert!(body.is_empty(), "Inner x is never read");
+}
+
+#[test]
+fn test_opt_unused_function_local_removed_param_kept() {
+    let source = "var f := func(n) is\n    if n > 0 then\n        var n := 5\n    end\n    var unused := 2\n    return n\nend\nprint f(3)";
+    let optimized = optimize_program_verbose(source, "Remove Unused Function Local").expect("Optimization failed");
+
+    let body = function_body(&optimized, "f");
+    assert_eq!(body.len(), 2, "Only the if and the return should remain");
+    let dlang::ast::Stmt::If { then_branch, .. } = &body[0] else {
+        panic!("Branch should remain");
+    };
+    assert!(then_branch.is_empty(), "Local shadowing the parameter is unused");
+    assert_eq!(body[1].without_spans(), dlang::ast::Stmt::Return(Some(dlang::ast::Expr::Ident("n".to_string())), Default::default()));
+    assert_eq!((body[1].span().line, body[1].span().col), (6, 5), "The return keeps its position");
+}
+
+#[test]
+fn test_opt_unused_variable_with_call_kept() {
+    let source = "var log := func(v) is\n    print v\n    return v\nend\nvar ignored := log(1)";
+    let optimized = optimize_program_verbose(source, "Keep Unused Variable with Call").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    assert_eq!(stmts.len(), 2, "The call still has to run");
+}
+
+
 // OPTIMIZATION TESTS: CONDITIONAL SIMPLIFICATION
 
 
@@ -354,6 +1180,48 @@ fn test_opt_simplify_if_true() {
     assert_eq!(stmts.len(), 1, "Should have 1 statement (if removed)");
 }
 
+#[test]
+fn test_opt_spliced_statements_keep_their_ids() {
+    let source = "var x := 1\nif true then\n    print x\n    x := 2\nend\nprint x";
+    let original = get_program(source);
+    let mut optimized = original.clone();
+    Optimizer::new().optimize(&mut optimized);
+
+    let dlang::ast::Program::Stmts(before) = &original;
+    let dlang::ast::Stmt::If { then_branch, .. } = &before[1] else {
+        panic!("Expected an if, got {:?}", before[1]);
+    };
+    let dlang::ast::Program::Stmts(after) = &optimized;
+    assert!(!after.iter().any(|s| matches!(s, dlang::ast::Stmt::If { .. })), "{:?}", after);
+    // the branch's statements now sit at the top level under their old ids
+    for stmt in then_branch.iter().chain(&before[2..]) {
+        let moved = optimized.find_by_id(stmt.span().id).expect("The statement should survive");
+        assert_eq!(moved.span().line, stmt.span().line);
+        assert!(after.iter().any(|s| std::ptr::eq(s, moved)), "{:?}", moved);
+    }
+    assert!(optimized.find_by_id(before[1].span().id).is_none());
+}
+
+#[test]
+fn test_opt_made_statement_gets_a_fresh_id() {
+    let source = "var f := func(n) => n\nif f(1) > 0 then\n    print 2\nelse\n    print 2\nend";
+    let original = get_program(source);
+    let mut optimized = original.clone();
+    Optimizer::new().optimize(&mut optimized);
+
+    // the condition is kept as a statement of its own, which the `if` was not
+    let dlang::ast::Program::Stmts(after) = &optimized;
+    let dlang::ast::Stmt::Expr(_, span) = &after[1] else {
+        panic!("Expected the condition, got {:?}", after[1]);
+    };
+    assert!(original.find_by_id(span.id).is_none(), "{:?}", span);
+    assert_eq!(span.id, original.max_id() + 1);
+    let mut ids: Vec<_> = after.iter().map(|s| s.span().id).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), after.len(), "{:?}", ids);
+}
+
 #[test]
 fn test_opt_simplify_if_false_with_else() {
     let source = "if false then print \"hello\" else print \"goodbye\" end";
@@ -378,10 +1246,70 @@ fn test_opt_simplify_if_false_without_else() {
     assert_eq!(stmts.len(), 2, "Should remove if false statement");
 }
 
+#[test]
+fn test_identical_branches_warn_and_collapse() {
+    let source = "var x := 1\nx := x * 3\nif x > 2 then\n    print x\nelse\n    print x\nend";
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program(source)).expect("Should pass");
+    assert!(checker.warnings().iter().any(|w| w.contains("branches of this 'if' are identical")), "{:?}", checker.warnings());
+
+    let optimized = optimize_program_verbose(source, "Simplify: Identical Branches").expect("Optimization failed");
+    assert_eq!(dlang::pretty_print(&optimized), "var x := 1\nx := x * 3\nprint x\n");
+}
+
+#[test]
+fn test_branches_differing_in_a_literal_are_kept() {
+    let source = "var x := 1\nx := x * 3\nif x > 2 then\n    print 1\nelse\n    print 2\nend";
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program(source)).expect("Should pass");
+    assert!(!checker.warnings().iter().any(|w| w.contains("identical")), "{:?}", checker.warnings());
+
+    let optimized = optimize_program_verbose(source, "Simplify: Different Branches").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = &optimized;
+    assert!(matches!(stmts.last(), Some(dlang::ast::Stmt::If { .. })), "{:?}", stmts);
+}
+
+#[test]
+fn test_identical_branches_keep_an_impure_condition() {
+    let source = "var f := func(n) is\n    print n\n    return n\nend\nif f(1) > 0 then\n    print 2\nelse\n    print 2\nend";
+    let optimized = optimize_program_verbose(source, "Simplify: Impure Condition").expect("Optimization failed");
+    let dlang::ast::Program::Stmts(stmts) = &optimized;
+    assert_eq!(stmts.len(), 3, "{:?}", stmts);
+    assert!(matches!(&stmts[1], dlang::ast::Stmt::Expr(dlang::ast::Expr::Binary { .. }, _)), "{:?}", stmts[1]);
+    assert!(matches!(&stmts[2], dlang::ast::Stmt::Print { .. }), "{:?}", stmts[2]);
+}
+
 // OPTIMIZATION TESTS: UNREACHABLE CODE REMOVAL
 
 
 
+#[test]
+fn test_opt_no_division_warning_in_dead_branch() {
+    let mut ast = get_program("if false then\n    var a := 1 / 0\n    print a\nend\nprint 2");
+    let mut optimizer = Optimizer::new();
+    optimizer.optimize(&mut ast);
+    assert!(optimizer.warnings().is_empty(), "Unexpected warnings: {:?}", optimizer.warnings());
+
+    let mut ast = get_program("var z := 0\nprint 1 / z");
+    optimizer.optimize(&mut ast);
+    assert_eq!(optimizer.warnings().len(), 1, "Reachable division should still be reported");
+}
+
+#[test]
+fn test_opt_remove_unreachable_in_loop_body() {
+    let source = "var i := 0\nwhile i < 3 loop\n    i := i + 1\n    exit\n    print i\nend\nprint i";
+    let optimized = optimize_program_verbose(source, "Unreachable Code in Loop").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    let body = stmts.iter().find_map(|s| match s {
+        dlang::ast::Stmt::While { body, .. } => Some(body),
+        _ => None,
+    }).expect("Loop should remain");
+    assert_eq!(body.len(), 2, "Statement after exit should be removed");
+}
+
 #[test]
 fn test_opt_remove_unreachable_after_exit() {
     let source = "print \"before\"\nexit\nprint \"after\"";
@@ -410,6 +1338,349 @@ fn test_opt_multiple_optimizations_applied() {
     assert!(stmts.len() >= 2, "Should have at least 2 statements after optimizations");
 }
 
+// body of the function declared as `name`
+fn function_body(program: &dlang::ast::Program, name: &str) -> Vec<dlang::ast::Stmt> {
+    let dlang::ast::Program::Stmts(stmts) = program;
+    stmts.iter()
+        .find_map(|s| match s {
+            dlang::ast::Stmt::VarDecl { name: n, init: Some(dlang::ast::Expr::Func { body: dlang::ast::FuncBody::Block(body), .. }), .. }
+                if n == name => Some(body.clone()),
+            _ => None,
+        })
+        .expect("Function declaration not found")
+}
+
+#[test]
+fn test_opt_dead_branch_removed_inside_function() {
+    let source = "var DEBUG := false\nvar f := func(x) is\n    if DEBUG then\n        print \"debug\"\n    end\n    return x\nend\nprint f(1)";
+    let optimized = optimize_program_verbose(source, "Dead Branch In Function").expect("Optimization failed");
+
+    let body = function_body(&optimized, "f");
+    assert_eq!(body.len(), 1, "The `if DEBUG` should be removed");
+    assert!(matches!(body[0], dlang::ast::Stmt::Return(..)));
+}
+
+#[test]
+fn test_opt_outer_constant_not_propagated_into_shadowing_loop() {
+    let source = "var i := 100\nfor i in 1..3 loop\n    print i\nend\nprint i";
+    let optimized = optimize_program_verbose(source, "Loop Variable Shadows Constant").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    let body = stmts.iter().find_map(|s| match s {
+        dlang::ast::Stmt::For { body, .. } => Some(body),
+        _ => None,
+    }).expect("Loop should remain");
+    assert!(
+        matches!(&body[0], dlang::ast::Stmt::Print { args, .. } if args == &vec![dlang::ast::Expr::Ident("i".to_string())]),
+        "Loop body must print the loop variable"
+    );
+}
+
+#[test]
+fn test_opt_reassigned_flag_not_propagated_into_function() {
+    let source = "var DEBUG := false\nDEBUG := true\nvar f := func(x) is\n    if DEBUG then\n        print \"debug\"\n    end\n    return x\nend\nprint f(1)";
+    let optimized = optimize_program_verbose(source, "Reassigned Flag").expect("Optimization failed");
+
+    let body = function_body(&optimized, "f");
+    assert_eq!(body.len(), 2, "The `if DEBUG` must stay");
+}
+
+#[test]
+fn test_opt_flag_shadowed_in_function_not_propagated() {
+    // by a parameter, and by a global that another function assigns
+    let source = "var DEBUG := false\nvar f := func(DEBUG) is\n    if DEBUG then\n        print 1\n    end\nend\nvar g := func() is\n    if DEBUG then\n        print 2\n    end\nend\nf(true)\ng()";
+    let optimized = optimize_program_verbose(source, "Shadowed Flag").expect("Optimization failed");
+    assert_eq!(function_body(&optimized, "f").len(), 1);
+    assert_eq!(function_body(&optimized, "g").len(), 0);
+
+    let source = "var DEBUG := false\nvar on := func() is\n    DEBUG := true\nend\nvar g := func() is\n    if DEBUG then\n        print 2\n    end\nend\non()\ng()";
+    let optimized = optimize_program_verbose(source, "Flag Assigned In Function").expect("Optimization failed");
+    assert_eq!(function_body(&optimized, "g").len(), 1);
+}
+
+#[test]
+fn test_opt_variable_assigned_in_closure_not_propagated() {
+    let source = "var n := 1\nvar bump := func() is\n    n := 2\nend\nbump()\nprint n";
+    let optimized = optimize_program_verbose(source, "Assigned in Closure").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    assert!(
+        matches!(stmts.last(), Some(dlang::ast::Stmt::Print { args, .. }) if args == &vec![dlang::ast::Expr::Ident("n".to_string())]),
+        "n changes when bump runs"
+    );
+
+    // closures in a loop condition count too
+    let source = "var n := 1\nwhile [func() is\n    n := 2\nend][1] = none loop\n    exit\nend\nprint n";
+    let optimized = optimize_program_verbose_unchecked(source, "Assigned in Condition Closure").expect("Optimization failed");
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    assert!(
+        matches!(stmts.last(), Some(dlang::ast::Stmt::Print { args, .. }) if args == &vec![dlang::ast::Expr::Ident("n".to_string())]),
+        "n changes when the condition runs"
+    );
+}
+
+#[test]
+fn test_opt_variable_read_in_closure_propagated() {
+    let source = "var n := 1\nvar get := func() => n\nprint get()\nprint n";
+    let optimized = optimize_program_verbose(source, "Read in Closure").expect("Optimization failed");
+
+    let stmts = match optimized {
+        dlang::ast::Program::Stmts(s) => s,
+    };
+    assert!(
+        matches!(stmts.last(), Some(dlang::ast::Stmt::Print { args, .. }) if args == &vec![dlang::ast::Expr::Integer(1)]),
+        "A closure that only reads n leaves it constant"
+    );
+}
+
+
+// 625 blocks of 16 statements. Each `a_k` only becomes a constant once the
+// branch assigning it is known dead, which takes the previous link, so a
+// chain of length L needs about 2L iterations; most blocks finish early
+fn constant_heavy_source() -> String {
+    let mut source = String::new();
+    for b in 0..625 {
+        let chain = 1 + b % 7;
+        source.push_str(&format!("var a{b}_0 := {b}\n"));
+        for k in 1..=chain {
+            source.push_str(&format!("var a{b}_{k} := {k}\nif a{b}_{} > 5000 then a{b}_{k} := 0 end\n", k - 1));
+        }
+        source.push_str(&format!("print a{b}_{chain} * 2\n"));
+        for _ in 2 * chain + 2..16 {
+            source.push_str(&format!("print {b}\n"));
+        }
+    }
+    source
+}
+
+#[test]
+fn test_opt_dirty_tracking_matches_naive_fixpoint() {
+    let source = constant_heavy_source();
+    let mut naive_program = get_program(&source);
+    let dlang::ast::Program::Stmts(stmts) = &naive_program;
+    assert_eq!(stmts.len(), 10_000);
+    let mut tracked_program = naive_program.clone();
+
+    let mut naive = Optimizer::new().with_dirty_tracking(false);
+    let mut tracked = Optimizer::new();
+    assert!(naive.optimize(&mut naive_program));
+    assert!(tracked.optimize(&mut tracked_program));
+    assert_eq!(tracked_program, naive_program);
+    assert_eq!(tracked.warnings(), naive.warnings());
+
+    let (naive, tracked) = (naive.report(), tracked.report());
+    assert_eq!(tracked.iterations, naive.iterations);
+    assert!(naive.iterations > 5, "{:?}", naive);
+    assert_eq!(tracked.visits[0], 10_000);
+    let total = |report: &dlang::OptReport| report.visits.iter().sum::<usize>();
+    assert!(total(tracked) * 3 < total(naive), "tracked {:?}, naive {:?}", tracked.visits, naive.visits);
+    assert!(tracked.visits.last() < Some(&1000), "{:?}", tracked.visits);
+}
+
+
+#[test]
+fn test_opt_long_string_constant_not_copied() {
+    let text = "x".repeat(10_000);
+    let mut source = format!("var big := \"{}\"\nvar n := 7\n", text);
+    for _ in 0..100 {
+        source.push_str("print big, n\n");
+    }
+    let mut program = get_program(&source);
+    let before = program.size();
+    Optimizer::new().optimize(&mut program);
+    let dlang::ast::Program::Stmts(stmts) = &program;
+
+    // one copy of the text stays in the declaration, the small constant still propagates
+    assert!(program.size() < before, "{} -> {}", before, program.size());
+    assert!(program.size() < 2 * text.len());
+    assert!(matches!(&stmts[0], dlang::ast::Stmt::VarDecl { name, .. } if name == "big"));
+    assert!(matches!(&stmts[1], dlang::ast::Stmt::Print { args, .. }
+        if args == &vec![dlang::ast::Expr::Ident("big".into()), dlang::ast::Expr::Integer(7)]));
+
+    // with a higher limit every use gets its own copy
+    let mut program = get_program(&source);
+    let mut optimizer = Optimizer::new();
+    optimizer.set_max_inline_len(20_000);
+    optimizer.optimize(&mut program);
+    assert!(program.size() > 100 * text.len());
+}
+
+
+
+// RECHECK TESTS
+
+
+// Everything a check reports, to compare a recheck with a check from scratch
+fn check_results(checker: &mut SemanticChecker, program: &dlang::ast::Program, changes: Option<&ChangeSet>) -> Vec<String> {
+    let result = match changes {
+        Some(changes) => checker.recheck(program, changes),
+        None => checker.check(program),
+    };
+    let mut results = vec![format!("{:?}", result)];
+    results.extend(checker.warnings().iter().cloned());
+    results.extend(checker.error_sites().iter().chain(checker.warning_sites()).map(|site| format!("{:?}", site)));
+    results
+}
+
+#[test]
+fn test_recheck_only_changed_statements_and_dependents() {
+    let mut checker = SemanticChecker::new();
+    let original = get_program("var a := 1\nvar b := 2\nprint a\nprint b\nprint a + b");
+    checker.check(&original).expect("Should pass");
+    assert_eq!(checker.rechecked(), [0, 1, 2, 3, 4]);
+
+    // `b` changes, so the statements that mention it are checked again
+    let edited = get_program("var a := 1\nvar b := 3\nprint a\nprint b\nprint a + b");
+    let changes = ChangeSet::new(vec![Some(0), None, Some(2), Some(3), Some(4)]);
+    let rechecked = check_results(&mut checker, &edited, Some(&changes));
+    assert_eq!(checker.rechecked(), [1, 3, 4]);
+    assert_eq!(rechecked, check_results(&mut SemanticChecker::new(), &edited, None));
+
+    // nothing changed, nothing checked
+    let again = check_results(&mut checker, &edited, Some(&ChangeSet::unchanged(5)));
+    assert!(checker.rechecked().is_empty());
+    assert_eq!(again, rechecked);
+}
+
+#[test]
+fn test_recheck_after_removing_a_declaration() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var a := 1\nvar c := 2\nprint a\nprint c")).expect("Should pass");
+
+    let edited = get_program("var c := 2\nprint a\nprint c");
+    let result = checker.recheck(&edited, &ChangeSet::new(vec![Some(1), Some(2), Some(3)]));
+    assert_eq!(result, Err(dlang::AnalysisError::Message("Variable or function 'a' used before declaration".to_string())));
+    assert_eq!(checker.rechecked(), [1]);
+}
+
+#[test]
+fn test_recheck_after_reordering_statements() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var x := 1\nprint x")).expect("Should pass");
+
+    let edited = get_program("print x\nvar x := 1");
+    let changes = ChangeSet::new(vec![Some(1), Some(0)]);
+    assert!(changes.is_changed(1));
+    let result = checker.recheck(&edited, &changes);
+    assert_eq!(result, SemanticChecker::new().check(&edited));
+    assert_eq!(result, Err(dlang::AnalysisError::Message("Variable or function 'x' used before declaration".to_string())));
+}
+
+#[test]
+fn test_recheck_after_optimizing_matches_check() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_programs");
+    let mut paths: Vec<_> = fs::read_dir(dir).expect("Failed to read test_programs")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dl" || ext == "txt"))
+        .collect();
+    paths.sort();
+    let mut programs: Vec<String> = paths.iter().map(|path| fs::read_to_string(path).expect("Failed to read program")).collect();
+    programs.push(constant_heavy_source());
+
+    for source in &programs {
+        let Ok(original) = Parser::new(source).parse_program() else { continue };
+        let mut checker = SemanticChecker::new();
+        if checker.check(&original).is_err() {
+            continue;
+        }
+        let mut optimized = original.clone();
+        let mut optimizer = Optimizer::new();
+        optimizer.optimize(&mut optimized);
+
+        let rechecked = check_results(&mut checker, &optimized, Some(optimizer.changes()));
+        assert_eq!(rechecked, check_results(&mut SemanticChecker::new(), &optimized, None), "{}", source);
+    }
+}
+
+
+// CHECK WITH CONTEXT TESTS
+
+
+// The first statement of `source`
+fn first_stmt(source: &str) -> dlang::Stmt {
+    let dlang::Program::Stmts(mut stmts) = get_program(source);
+    stmts.remove(0)
+}
+
+#[test]
+fn test_check_stmt_with_context_sees_session_names() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var total := 10\nprint total")).expect("Should pass");
+    let session = checker.symbol_table();
+    assert!(session.get("total").is_some());
+
+    let diagnostics = checker.check_stmt_with_context(&first_stmt("print total + 1"), &session);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+
+    let diagnostics = checker.check_stmt_with_context(&first_stmt("\nprint missing"), &session);
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0].message.contains("missing"), "{}", diagnostics[0]);
+    assert_eq!((diagnostics[0].line, diagnostics[0].col), (2, 1));
+
+    let expr = dlang::Parser::new("total * missing").parse_standalone_expression().expect("Should parse");
+    assert_eq!(checker.check_expr_with_context(&expr, &session).len(), 1);
+}
+
+#[test]
+fn test_check_stmt_with_context_commits_declarations() {
+    let mut checker = SemanticChecker::new();
+    checker.check(&get_program("var total := 10\nprint total")).expect("Should pass");
+    let session = checker.symbol_table();
+
+    // without commit the declaration is forgotten
+    let declare = first_stmt("var extra := total * 2");
+    assert!(checker.check_stmt_with_context(&declare, &session).is_empty());
+    assert!(checker.symbol_table().get("extra").is_none());
+    assert!(!checker.check_stmt_with_context(&first_stmt("print extra"), &checker.symbol_table()).is_empty());
+
+    checker.set_commit_declarations(true);
+    assert!(checker.check_stmt_with_context(&declare, &session).is_empty());
+    let session = checker.symbol_table();
+    assert_eq!(session.names(), ["extra", "total"]);
+    assert!(checker.check_stmt_with_context(&first_stmt("print extra"), &session).is_empty());
+}
+
+
+// AST DIFF TESTS
+
+
+#[test]
+fn test_diff_reports_optimizer_changes() {
+    let source = "var keep := 2\nkeep := keep + 1\nprint 5 + 3\nif false then\n    print 1\nend\nvar unused := 7\nif true then\n    print 2 * 3\nend\nprint keep";
+    let original = get_program(source);
+    let mut optimized = original.clone();
+    Optimizer::new().optimize(&mut optimized);
+
+    let changes: Vec<String> = diff_programs(&original, &optimized).iter().map(|c| c.to_string()).collect();
+    println!("{:#?}", changes);
+    assert_eq!(changes, vec![
+        "statement 3: `5 + 3` folded to `8`",
+        "statement 4 removed: dead branch `if false`",
+        "statement 5 removed: unused variable 'unused'",
+        "statement 6: `if true` replaced by its then branch",
+        "statement 6.1: `2 * 3` folded to `6`",
+    ]);
+}
+
+#[test]
+fn test_diff_nested_blocks_and_unchanged_program() {
+    let source = "var f := func(n) is\n    return n * (2 + 2)\nend\nprint f(1)";
+    let original = get_program(source);
+    let mut optimized = original.clone();
+    Optimizer::new().optimize(&mut optimized);
+    let changes = diff_programs(&original, &optimized);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].to_string(), "statement 1.1: `n * (2 + 2)` rewritten to `n * 4`");
+
+    assert!(diff_programs(&original, &original).is_empty());
+}
+
 
 // INTEGRATION TESTS
 
@@ -0,0 +1,43 @@
+use dlang::Parser;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts heap allocations, so a test can compare what the two parser modes
+// allocate. This file holds a single test, so nothing else allocates while
+// it measures.
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    f();
+    ALLOCATIONS.load(Ordering::SeqCst) - before
+}
+
+#[test]
+fn test_arena_parse_allocates_less_than_boxed_parse() {
+    // few names, since every name is a string of its own in both modes
+    let source = "print (1 + 2) * -3 - [4, 5][1] / 6, 7 < 8\nif 1 = 2 then print 3 else print {4, 5}.1 end\n".repeat(5_000);
+    let lexing = allocations(|| drop(Parser::new(&source)));
+    let boxed = allocations(|| drop(Parser::new(&source).parse_program().expect("should parse"))) - lexing;
+    let arena = allocations(|| drop(Parser::new(&source).parse_arena().expect("should parse"))) - lexing;
+    // every boxed node and list is an allocation of its own; the arena only
+    // grows its vectors, plus one for the fields of each tuple literal
+    assert!(arena * 10 < boxed, "arena mode made {} allocations, boxed mode {}", arena, boxed);
+}
@@ -0,0 +1,201 @@
+use dlang::diagnostics::{collect_all, from_runtime, Severity};
+use dlang::{run, PipelineError, RunOptions};
+
+// The semantic checker and the interpreter must agree: a program the
+// checker rejects fails the same way when run unchecked, with the same code
+// and message, and a program it accepts runs. Each case records the
+// checker's verdict, the unchecked run's verdict and what the run printed.
+// A verdict is "ok" or the first error's code and message.
+struct Case {
+    source: &'static str,
+    checker: &'static str,
+    runtime: &'static str,
+    output: &'static [&'static str],
+}
+
+const CASES: &[Case] = &[
+    Case {
+        source: "var x := 10\nprint x * 2, x / 4",
+        checker: "ok",
+        runtime: "ok",
+        output: &["20 2"],
+    },
+    Case {
+        source: "print 1 / 0",
+        checker: "E0006 Division by zero",
+        runtime: "E0006 Division by zero",
+        output: &[],
+    },
+    Case {
+        source: "var z := 0\nz := z\nprint 1 / z",
+        checker: "ok",
+        runtime: "E0006 Division by zero",
+        output: &[],
+    },
+    Case {
+        source: "var a := [1, 2, 3]\nprint a[20]",
+        checker: "E0007 Index 20 out of bounds (array size: 3, valid range: 1..3)",
+        runtime: "E0007 Index 20 out of bounds (array size: 3, valid range: 1..3)",
+        output: &[],
+    },
+    Case {
+        source: "var a := [1, 2, 3]\nvar i := 20\ni := i\nprint a[1], a[i]",
+        checker: "ok",
+        runtime: "E0007 Index 20 out of bounds (array size: 3, valid range: 1..3)",
+        output: &[],
+    },
+    Case {
+        source: "print y",
+        checker: "E0001 Variable or function 'y' used before declaration",
+        runtime: "E0001 Variable or function 'y' used before declaration",
+        output: &[],
+    },
+    Case {
+        source: "var x := x + 1",
+        checker: "E0003 Variable 'x' used in its own initializer",
+        runtime: "E0003 Variable 'x' used in its own initializer",
+        output: &[],
+    },
+    Case {
+        source: "var f := func(x) is return x end\nprint f(1, 2)",
+        checker: "E0004 Function 'f' expects 1 arguments (x), got 2",
+        runtime: "E0004 Function 'f' expects 1 arguments (x), got 2",
+        output: &[],
+    },
+    Case {
+        source: "var g := func(x) is return x end\nvar f := g\nf := f\nprint f(1, 2)",
+        checker: "ok",
+        runtime: "E0004 Function 'g' expects 1 arguments (x), got 2",
+        output: &[],
+    },
+    Case {
+        source: "return 1",
+        checker: "E0005 Return statement outside of function",
+        runtime: "E0005 Return statement outside of function",
+        output: &[],
+    },
+    Case {
+        source: "exit",
+        checker: "ok",
+        runtime: "E0200 Exit statement outside of loop",
+        output: &[],
+    },
+    Case {
+        source: "for i in 1..3 loop\n    print i\n    exit nope\nend",
+        checker: "E0013 Exit label 'nope' does not name an enclosing loop",
+        runtime: "E0013 Exit label 'nope' does not name an enclosing loop",
+        output: &["1"],
+    },
+    Case {
+        source: "var t := {a := 1}\nprint t.2",
+        checker: "E0007 Tuple position 2 out of bounds (the tuple has 1 element)",
+        runtime: "E0007 Tuple position 2 out of bounds (the tuple has 1 element)",
+        output: &[],
+    },
+    Case {
+        source: "var t := {a := 1}\nvar i := 2\ni := i\nprint t[i]",
+        checker: "ok",
+        runtime: "E0007 Tuple position 2 out of bounds (the tuple has 1 element)",
+        output: &[],
+    },
+    Case {
+        source: "for i in 1..true loop print i end",
+        checker: "E0009 Range end must be an integer",
+        runtime: "E0009 Range end must be an integer",
+        output: &[],
+    },
+    Case {
+        source: "var b := true\nb := b\nfor i in 1..b loop print i end",
+        checker: "ok",
+        runtime: "E0009 Range end must be an integer",
+        output: &[],
+    },
+    Case {
+        source: "print [...5]",
+        checker: "E0012 Spread in an array literal needs an array, got int",
+        runtime: "E0012 Spread in an array literal needs an array, got int",
+        output: &[],
+    },
+    Case {
+        source: "var n := 5\nn := n\nprint [...n]",
+        checker: "E0012 Spread in an array literal needs an array, got int",
+        runtime: "E0012 Spread in an array literal needs an array, got int",
+        output: &[],
+    },
+    Case {
+        source: "print +\"a\"",
+        checker: "E0014 Unary '+' expects a number, got string",
+        runtime: "E0014 Unary '+' expects a number, got string",
+        output: &[],
+    },
+    Case {
+        source: "var s := \"a\"\ns := s\nprint +s",
+        checker: "E0014 Unary '+' expects a number, got string",
+        runtime: "E0014 Unary '+' expects a number, got string",
+        output: &[],
+    },
+    Case {
+        source: "var x\nprint x",
+        checker: "ok",
+        runtime: "ok",
+        output: &["none"],
+    },
+    Case {
+        source: "print \"before\"\nvar n := 70\nn := n\nprint 1 << n",
+        checker: "ok",
+        runtime: "E0200 Shift amount must be between 0 and 63, got 70",
+        output: &["before"],
+    },
+    Case {
+        source: "var t := {a := 1}\nprint t.b",
+        checker: "ok",
+        runtime: "E0200 Tuple field 'b' not found",
+        output: &[],
+    },
+    Case {
+        source: "var a := 5\na := a\nprint a(1)",
+        checker: "ok",
+        runtime: "E0202 Cannot call non-function value of type int",
+        output: &[],
+    },
+    Case {
+        source: "var x := 3\nswap 1, x",
+        checker: "E0015 'swap' needs a variable, array element or tuple field, got 1",
+        runtime: "E0015 'swap' needs a variable, array element or tuple field, got 1",
+        output: &[],
+    },
+];
+
+fn checker_verdict(source: &str) -> String {
+    collect_all(source).into_iter()
+        .find(|d| d.severity == Severity::Error)
+        .map(|d| format!("{} {}", d.code, d.message))
+        .unwrap_or_else(|| "ok".to_string())
+}
+
+// Runs without the checker or the optimizer, so every error is found at run time
+fn runtime_verdict(source: &str) -> (String, Vec<String>) {
+    let options = RunOptions { strict: false, optimize: false, ..RunOptions::default() };
+    match run(source, options) {
+        Ok(run) => ("ok".to_string(), run.output),
+        Err(PipelineError::Runtime { error, output }) => {
+            let d = from_runtime(source, &error);
+            (format!("{} {}", d.code, d.message), output)
+        }
+        Err(e) => panic!("{:?} did not parse: {}", source, e),
+    }
+}
+
+#[test]
+fn test_checker_and_interpreter_agree() {
+    for case in CASES {
+        let checker = checker_verdict(case.source);
+        let (runtime, output) = runtime_verdict(case.source);
+        assert_eq!((checker.as_str(), runtime.as_str()), (case.checker, case.runtime), "{:?}", case.source);
+        assert_eq!(output, case.output, "{:?}", case.source);
+        // what the checker rejects must fail the same way when it runs
+        if checker != "ok" {
+            assert_eq!(checker, runtime, "{:?}", case.source);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use dlang::diagnostics::messages;
+use dlang::{run, PipelineError, RunOptions};
+
+// Every error class the checker reports statically is also reported at run
+// time when the checker cannot see it. Both sides build their messages from
+// diagnostics::messages, so each test asserts the same text on both.
+
+// The checker's errors for a program it rejects
+fn static_errors(source: &str, options: RunOptions) -> Vec<String> {
+    match run(source, options) {
+        Err(PipelineError::Semantic(diagnostics)) => diagnostics.into_iter().map(|d| d.message).collect(),
+        other => panic!("Expected a semantic error for {:?}, got {:?}", source, other),
+    }
+}
+
+// The interpreter's error for a program the checker lets through
+fn runtime_error(source: &str, options: RunOptions) -> String {
+    match run(source, options) {
+        Err(PipelineError::Runtime { error, .. }) => error.to_string(),
+        other => panic!("Expected a runtime error for {:?}, got {:?}", source, other),
+    }
+}
+
+fn strict() -> RunOptions {
+    RunOptions::default()
+}
+
+// nothing removed or folded, so the interpreter sees the program as written
+fn dynamic() -> RunOptions {
+    RunOptions { optimize: false, ..RunOptions::default() }
+}
+
+// the checker's errors are ignored, for classes it always catches
+fn unchecked() -> RunOptions {
+    RunOptions { strict: false, optimize: false, ..RunOptions::default() }
+}
+
+fn assert_reported(errors: &[String], expected: &str) {
+    assert!(errors.iter().any(|e| e.contains(expected)), "{:?} does not report {:?}", errors, expected);
+}
+
+// ========
+// ARITY
+// ========
+
+#[test]
+fn test_contract_arity() {
+    let expected = messages::arity(Some("f"), &["x".to_string()], 2);
+    assert_reported(&static_errors("var f := func(x) => x\nprint f(1, 2)", strict()), &expected);
+    // the alias hides the function from the checker, but the value keeps its name
+    let err = runtime_error("var f := func(x) => x\nvar g := f\nprint g(1, 2)", dynamic());
+    assert!(err.contains(&expected), "{}", err);
+}
+
+// ========
+// DIVISION BY ZERO
+// ========
+
+#[test]
+fn test_contract_division_by_zero() {
+    assert_reported(&static_errors("print 1 / 0", strict()), messages::DIVISION_BY_ZERO);
+    let err = runtime_error("var half := func(d) => 10 / d\nprint half(0)", dynamic());
+    assert!(err.contains(messages::DIVISION_BY_ZERO), "{}", err);
+}
+
+// ========
+// ARRAY BOUNDS
+// ========
+
+#[test]
+fn test_contract_array_bounds() {
+    let expected = messages::index_out_of_bounds(4, 3, "1..3");
+    assert_reported(&static_errors("var a := [1, 2, 3]\nprint a[4]", strict()), &expected);
+    let err = runtime_error("var a := [1, 2, 3]\nvar at := func(i) => a[i]\nprint at(4)", dynamic());
+    assert!(err.contains(&expected), "{}", err);
+}
+
+// ========
+// TUPLE POSITIONS
+// ========
+
+#[test]
+fn test_contract_tuple_position() {
+    let expected = messages::tuple_position_out_of_bounds(3, 2);
+    assert_reported(&static_errors("var t := {x := 1, y := 2}\nprint t[3]", strict()), &expected);
+    let err = runtime_error("var t := {x := 1, y := 2}\nvar at := func(i) => t[i]\nprint at(3)", dynamic());
+    assert!(err.contains(&expected), "{}", err);
+}
+
+// ========
+// STRICT BOOLS
+// ========
+
+#[test]
+fn test_contract_strict_bool() {
+    let options = RunOptions { strict_bool: true, ..strict() };
+    let expected = messages::not_a_bool("'if' condition", "int");
+    assert_reported(&static_errors("if 1 then\n    print 1\nend", options.clone()), &expected);
+    let options = RunOptions { strict_bool: true, ..dynamic() };
+    let err = runtime_error("var test := func(c) is\n    if c then\n        print 1\n    end\nend\ntest(1)", options);
+    assert!(err.contains(&expected), "{}", err);
+}
+
+// ========
+// RANGE BOUNDS
+// ========
+
+#[test]
+fn test_contract_range_bounds() {
+    for (bound, source) in [("start", "1.5..3"), ("end", "1..2.5")] {
+        let expected = messages::range_bound_not_integer(bound);
+        let program = format!("for i in {} loop\n    print i\nend", source);
+        assert_reported(&static_errors(&program, strict()), &expected);
+    }
+    let err = runtime_error("var go := func(n) is\n    for i in 1..n loop\n        print i\n    end\nend\ngo(2.5)", dynamic());
+    assert!(err.contains(&messages::range_bound_not_integer("end")), "{}", err);
+}
+
+// ========
+// STATIC-ONLY CLASSES
+// ========
+// The checker always catches these, so the runtime wording is checked with
+// its errors ignored.
+
+#[test]
+fn test_contract_undeclared() {
+    let expected = messages::undeclared("missing");
+    assert_reported(&static_errors("print missing", strict()), &expected);
+    let err = runtime_error("print missing", unchecked());
+    assert!(err.contains(&expected), "{}", err);
+}
+
+#[test]
+fn test_contract_return_outside_function() {
+    assert_reported(&static_errors("return 1", strict()), messages::RETURN_OUTSIDE_FUNCTION);
+    let err = runtime_error("return 1", unchecked());
+    assert!(err.contains(messages::RETURN_OUTSIDE_FUNCTION), "{}", err);
+}
+
+#[test]
+fn test_contract_unknown_loop_label() {
+    let source = "for i in 1..2 loop\n    exit outer\nend";
+    let expected = messages::unknown_loop_label("outer");
+    assert_reported(&static_errors(source, strict()), &expected);
+    let err = runtime_error(source, unchecked());
+    assert!(err.contains(&expected), "{}", err);
+}
+
+// ========
+// RUNTIME-ONLY CLASSES
+// ========
+
+#[test]
+fn test_contract_exit_outside_loop() {
+    // the checker accepts `exit` anywhere; whether a loop encloses it is only known when it runs
+    let err = runtime_error("var stop := func() is\n    exit\nend\nstop()", dynamic());
+    assert!(err.contains(messages::EXIT_OUTSIDE_LOOP), "{}", err);
+}
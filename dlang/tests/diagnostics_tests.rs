@@ -0,0 +1,102 @@
+use dlang::ast::Span;
+use dlang::diagnostics::{collect_all, from_runtime, render_snippet, Position, Range, Severity};
+use dlang::{run, PipelineError, RunOptions};
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+    Range {
+        start: Position { line: start.0, character: start.1 },
+        end: Position { line: end.0, character: end.1 },
+    }
+}
+
+#[test]
+fn test_undeclared_variable_range() {
+    let diagnostics = collect_all("var a := 1\nif a > 0 then\n  print a + missing\nend\n");
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    let d = &diagnostics[0];
+    assert_eq!(d.code, "E0001");
+    assert_eq!(d.severity, Severity::Error);
+    assert_eq!(d.range, range((2, 12), (2, 19)));
+    assert!(d.message.contains("'missing'"));
+    assert!(d.related.is_empty());
+}
+
+#[test]
+fn test_redeclaration_points_at_first_declaration() {
+    let diagnostics = collect_all("var total := 0\nprint total\n  var total := 5\n");
+    let d = diagnostics.iter().find(|d| d.code == "E0002").expect("redeclaration error");
+    assert_eq!(d.range, range((2, 6), (2, 11)));
+    assert_eq!(d.related.len(), 1);
+    assert_eq!(d.related[0].range, range((0, 4), (0, 9)));
+    assert!(d.related[0].message.contains("first declared here"));
+}
+
+#[test]
+fn test_ranges_count_utf16_units() {
+    // 'é' is one UTF-16 unit, '😀' two
+    let diagnostics = collect_all("print \"é😀\", nope\n");
+    assert_eq!(diagnostics[0].range, range((0, 13), (0, 17)));
+}
+
+#[test]
+fn test_warnings_and_parse_errors() {
+    let diagnostics = collect_all("var x := 1\nx\n");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[0].code, "W0003");
+    assert_eq!(diagnostics[0].range, range((1, 0), (1, 1)));
+
+    let diagnostics = collect_all("print 1\nprint @\n");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, "E0100");
+}
+
+#[test]
+fn test_runtime_error_range() {
+    let source = "var a := [1, 2]\nvar i := 0; i := 5\n  print a[i]\n";
+    let Err(PipelineError::Runtime { error, .. }) = run(source, RunOptions::default()) else {
+        panic!("expected a runtime error");
+    };
+    let d = from_runtime(source, &error);
+    assert_eq!(d.code, "E0007");  // the checker's code for the same error
+    assert_eq!(d.range, range((2, 2), (2, 7)));
+    assert!(!d.message.contains(" at "));
+}
+
+#[test]
+fn test_parse_errors_after_recovery_are_reported() {
+    let diagnostics = collect_all("if x := 5 then print x end\nprint @\n");
+    assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+    assert!(diagnostics[0].message.contains("did you mean '='?"));
+    assert_eq!(diagnostics[0].range, range((0, 5), (0, 7)));
+    assert_eq!(diagnostics[1].code, "E0100");
+}
+
+#[test]
+fn test_snippet_points_at_a_mid_line_parse_error() {
+    let source = "var a := 1\nprint a + )\n";
+    let Err(PipelineError::Parse(error)) = run(source, RunOptions::default()) else {
+        panic!("expected a parse error");
+    };
+    assert_eq!((error.line, error.col), (2, 11));
+    assert_eq!(render_snippet(source, Span::new(error.line, error.col)), "2 | print a + )\n  |           ^");
+}
+
+#[test]
+fn test_snippet_underlines_a_first_column_runtime_error() {
+    let lines: Vec<String> = (1..=9).map(|i| format!("var v{} := {}", i, i)).collect();
+    let source = format!("{}\nvar a := [1]\nvar i := 0; i := 3\nprint a[i]\n", lines.join("\n"));
+    let Err(PipelineError::Runtime { error, .. }) = run(&source, RunOptions::default()) else {
+        panic!("expected a runtime error");
+    };
+    let (line, col) = error.position().expect("runtime errors carry the failing statement's position");
+    assert_eq!(render_snippet(&source, Span::new(line, col)), "12 | print a[i]\n   | ^^^^^");
+}
+
+#[test]
+fn test_snippet_of_a_multi_line_token_and_unknown_position() {
+    let source = "var s := \"one\ntwo\"\n";
+    assert_eq!(render_snippet(source, Span::new(1, 10)), "1 | var s := \"one\n  |          ^^^^ ...");
+    assert_eq!(render_snippet(source, Span::new(0, 0)), "");
+    assert_eq!(render_snippet(source, Span::new(7, 1)), "");
+}
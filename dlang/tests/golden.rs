@@ -0,0 +1,107 @@
+use dlang::{PipelineError, RunOptions};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Golden-output tests: every `test_programs/*.dl` with a sibling `*.expected`
+// file is run through the full pipeline and its captured output compared
+// line by line. Run with DLANG_BLESS=1 to (re)write the expected files.
+
+const BLESS_VAR: &str = "DLANG_BLESS";
+
+// Printed lines followed by the error (if any) that stopped the pipeline
+fn run_program(source: &str) -> String {
+    let lines = match dlang::run(source, RunOptions::default()) {
+        Ok(run) => run.output,
+        Err(PipelineError::Runtime { error, mut output }) => {
+            output.push(format!("Runtime error: {}", error));
+            output
+        }
+        Err(e) => vec![e.to_string()],
+    };
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+fn golden_programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_programs");
+    let mut programs: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("Failed to read test_programs")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dl"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+// First differing line with some context, easier to read than two full dumps
+fn describe_diff(expected: &str, actual: &str) -> String {
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let line = expected_lines
+        .iter()
+        .zip(actual_lines.iter())
+        .position(|(e, a)| e != a)
+        .unwrap_or(expected_lines.len().min(actual_lines.len()));
+
+    format!(
+        "first difference at line {}\n  expected: {}\n  actual:   {}\n({} expected lines, {} actual lines)",
+        line + 1,
+        expected_lines.get(line).unwrap_or(&"<end of output>"),
+        actual_lines.get(line).unwrap_or(&"<end of output>"),
+        expected_lines.len(),
+        actual_lines.len(),
+    )
+}
+
+#[test]
+fn test_golden_programs() {
+    let bless = env::var_os(BLESS_VAR).is_some();
+    let mut checked = 0;
+    let mut failures = Vec::new();
+
+    for program in golden_programs() {
+        let expected_path = program.with_extension("expected");
+        let source = fs::read_to_string(&program).expect("Failed to read program");
+        let actual = run_program(&source);
+
+        if bless {
+            fs::write(&expected_path, &actual).expect("Failed to write expected output");
+            println!("blessed {}", expected_path.display());
+            continue;
+        }
+
+        // Programs without an expected file are not part of the golden set yet
+        let Ok(expected) = fs::read_to_string(&expected_path) else {
+            continue;
+        };
+        checked += 1;
+        if expected != actual {
+            failures.push(format!("{}: {}", program.display(), describe_diff(&expected, &actual)));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} golden program(s) differ (rerun with {}=1 to update):\n\n{}",
+        failures.len(),
+        BLESS_VAR,
+        failures.join("\n\n")
+    );
+    if !bless {
+        assert!(checked > 0, "No golden programs found in test_programs/");
+    }
+}
+
+// Parsing into the arena and converting back must not change what a program does
+#[test]
+fn test_arena_path_matches_boxed_path() {
+    for program in golden_programs() {
+        let source = fs::read_to_string(&program).expect("Failed to read program");
+        let boxed = dlang::Parser::new(&source).parse_program().map(|p| dlang::ProgramRunner::from_program(p, RunOptions::default()).run());
+        let arena = dlang::Parser::new(&source).parse_arena().map(|a| dlang::ProgramRunner::from_program(a.to_program(), RunOptions::default()).run());
+        assert_eq!(arena.ok(), boxed.ok(), "{}", program.display());
+    }
+}
@@ -0,0 +1,143 @@
+use dlang::incremental::{Document, Reparse};
+use dlang::{Parser, Program, Stmt};
+use std::rc::Rc;
+
+// The document must always agree with parsing its source from scratch, spans
+// included; only the ids differ, as the document numbers the statements it
+// reparses past those it keeps
+fn assert_matches_full_parse(doc: &Document) {
+    let full = Parser::new(doc.source()).parse_program().expect("source should parse");
+    let positions = |stmts: &[Stmt]| -> Vec<Stmt> {
+        stmts.iter().map(|s| {
+            let mut s = s.clone();
+            s.for_each_span_mut(&mut |span| span.id = 0);
+            s
+        }).collect()
+    };
+    let Program::Stmts(stmts) = full;
+    let kept: Vec<Stmt> = doc.statements().iter().map(|s| (**s).clone()).collect();
+    assert_eq!(positions(&kept), positions(&stmts));
+    let Some(Program::Stmts(program)) = doc.program() else {
+        panic!("document should have a program");
+    };
+    assert_eq!(positions(program), positions(&stmts));
+}
+
+fn edit(doc: &mut Document, old: &str, new: &str) {
+    let start = doc.source().find(old).expect("text to edit");
+    doc.apply_edit(start..start + old.len(), new);
+}
+
+#[test]
+fn test_edit_inside_statement_keeps_other_statements() {
+    let mut doc = Document::new("var a := 1\nvar b := 2\nprint a + b\n");
+    let before: Vec<Rc<Stmt>> = doc.statements().to_vec();
+
+    edit(&mut doc, "2", "20");
+    assert_eq!(doc.last_reparse(), &Reparse::Statements(1..2));
+    assert!(Rc::ptr_eq(&before[0], &doc.statements()[0]));
+    assert!(Rc::ptr_eq(&before[2], &doc.statements()[2]));
+    assert!(!Rc::ptr_eq(&before[1], &doc.statements()[1]));
+    assert_matches_full_parse(&doc);
+    assert!(doc.diagnostics().is_empty());
+}
+
+#[test]
+fn test_edit_adding_lines_moves_later_spans() {
+    let mut doc = Document::new("var a := 1\nvar b := func(x) is\n  return x\nend\nprint b(a)\n");
+    edit(&mut doc, "1\n", "1 // one\n\n\n");
+    assert_eq!(doc.last_reparse(), &Reparse::Statements(0..1));
+    assert_matches_full_parse(&doc);
+    assert_eq!(doc.statements()[2].span().line, 7);
+
+    // a string running over several lines is re-lexed from its start
+    edit(&mut doc, "var a := 1", "var a := \"x\ny\"");
+    assert_matches_full_parse(&doc);
+    edit(&mut doc, "y\"", "z\"");
+    assert_matches_full_parse(&doc);
+    edit(&mut doc, "x\nz", "xz");
+    assert_matches_full_parse(&doc);
+}
+
+#[test]
+fn test_edit_merging_statements_falls_back_to_full_parse() {
+    let mut doc = Document::new("var a := 1\n- 2\nprint a\n");
+    assert_eq!(doc.statements().len(), 3);
+
+    edit(&mut doc, "1\n", "1 ");
+    assert_eq!(doc.last_reparse(), &Reparse::Full);
+    assert_eq!(doc.statements().len(), 2);
+    assert_matches_full_parse(&doc);
+}
+
+#[test]
+fn test_diagnostics_follow_edits() {
+    let mut doc = Document::new("var a := 1\nprint a\n");
+    edit(&mut doc, "print a", "print (a");
+    assert!(doc.program().is_none());
+    assert_eq!(doc.diagnostics().len(), 1);
+
+    edit(&mut doc, "(a", "b");
+    assert_eq!(doc.last_reparse(), &Reparse::Full);
+    let messages: Vec<String> = doc.diagnostics().iter().map(|d| d.message.clone()).collect();
+    assert!(messages.iter().any(|m| m.contains("'b'")), "{:?}", messages);
+
+    edit(&mut doc, "print b", "print a");
+    assert!(doc.diagnostics().is_empty());
+    assert_matches_full_parse(&doc);
+}
+
+#[test]
+fn test_edit_splitting_a_line_moves_the_rest() {
+    let mut doc = Document::new("print b(a); print 5\nprint 1\n");
+    doc.apply_edit(7..10, "\n");
+    assert_matches_full_parse(&doc);
+
+    let mut doc = Document::new("print b(a); print 5\nprint 1\n");
+    edit(&mut doc, "a", "\n");
+    assert_matches_full_parse(&doc);
+}
+
+#[test]
+fn test_edit_after_string_left_open_at_end() {
+    // the string runs to the end of the source, ending where the edit's line starts
+    let mut doc = Document::new("print \"e\";\nprint \"abc\n");
+    let end = doc.source().len();
+    doc.apply_edit(end..end, "\n");
+    assert_matches_full_parse(&doc);
+
+    // an escaped quote leaves it open too
+    let mut doc = Document::new("print \"e\\\";\n");
+    edit(&mut doc, "e\\\";\n", "e\\\";\n\n");
+    assert_matches_full_parse(&doc);
+    assert_eq!(doc.statements().len(), 1);
+}
+
+// Small xorshift generator, so the edits are the same on every run
+fn next(state: &mut u64) -> usize {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state as usize
+}
+
+#[test]
+fn test_random_edits_match_full_parse() {
+    const SOURCE: &str = "var a := 1; var b := 2\nprint a, b; print a + b\nif a < b then print a; print b end\nprint 3; print 4; print 5\n";
+    const INSERTS: &[&str] = &["", "\n", " ", "; print 7", "\nprint 8\n", "9", "a", "; ", "\n\n", "// note\n"];
+    for seed in 1..=40u64 {
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let mut doc = Document::new(SOURCE);
+        for _ in 0..25 {
+            let len = doc.source().len();
+            let start = next(&mut state) % (len + 1);
+            let end = (start + next(&mut state) % 4).min(len);
+            let text = INSERTS[next(&mut state) % INSERTS.len()];
+            doc.apply_edit(start..end, text);
+            match Parser::new(doc.source()).parse_program() {
+                Ok(_) => assert_matches_full_parse(&doc),
+                Err(_) => assert!(doc.program().is_none(), "{:?}", doc.source()),
+            }
+        }
+    }
+}
@@ -1,9 +1,17 @@
 use dlang::parser::Parser;
-use dlang::analyzer::{SemanticChecker, Optimizer};
-use dlang::interpreter::Interpreter;
+use dlang::interpreter::{Environment, Interpreter, Value};
+use dlang::{IndexBase, PipelineError, RunOptions};
+use dlang::resolver::Resolver;
+use dlang::ast::{Expr, Program, Span, Stmt, UnOp};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Options for the formatted helpers: prints go straight to stdout
+fn live_output() -> RunOptions {
+    RunOptions { capture_output: false, ..RunOptions::default() }
+}
 
-/// Helper function to run interpreter tests with formatted output
-fn run_test_formatted(test_name: &str, source: &str) -> Result<(), String> {
+fn print_test_header(test_name: &str, source: &str) {
     println!("\n----------------------------");
     println!("TEST: {}", test_name);
     println!("----------------------------");
@@ -11,42 +19,20 @@ fn run_test_formatted(test_name: &str, source: &str) -> Result<(), String> {
     for line in source.trim().lines() {
         println!("  {}", line);
     }
+}
+
+/// Helper function to run interpreter tests with formatted output
+fn run_test_formatted(test_name: &str, source: &str) -> Result<(), String> {
+    print_test_header(test_name, source);
     println!("\nOUTPUT:");
-    
-    // Parse
-    let mut parser = Parser::new(source);
-    let mut ast = parser.parse_program()
-        .map_err(|e| {
-            let err = format!("Parse error: {}", e);
-            println!("\n  {}", err);
-            println!("----------------------------\n");
-            err
-        })?;
-
-    // Semantic check
-    let mut checker = SemanticChecker::new();
-    checker.check(&ast)
-        .map_err(|e| {
-            let err = format!("Semantic error: {}", e);
-            println!("\n  {}", err);
-            println!("----------------------------\n");
-            err
-        })?;
-
-    // Optimize
-    let mut optimizer = Optimizer::new();
-    optimizer.optimize(&mut ast);
-
-    // Interpret
-    let mut interpreter = Interpreter::new();
-    interpreter.interpret(&ast)
-        .map_err(|e| {
-            let err = format!("Runtime error: {}", e);
-            println!("\n  {}", err);
-            println!("----------------------------\n");
-            err
-        })?;
-    
+
+    if let Err(e) = dlang::run(source, live_output()) {
+        let err = e.to_string();
+        println!("\n  {}", err);
+        println!("----------------------------\n");
+        return Err(err);
+    }
+
     println!("\n  PASSED");
     println!("----------------------------\n");
 
@@ -56,54 +42,40 @@ fn run_test_formatted(test_name: &str, source: &str) -> Result<(), String> {
 
 /// Helper for tests that should fail
 fn run_test_formatted_error(test_name: &str, source: &str) -> bool {
-    println!("\n----------------------------");
-    println!("TEST: {}", test_name);
-    println!("----------------------------");
-    println!("INPUT:");
-    for line in source.trim().lines() {
-        println!("  {}", line);
-    }
+    print_test_header(test_name, source);
     println!("\nEXPECTED: ERROR");
-    
-    // Parse
-    let mut parser = Parser::new(source);
-    let mut ast = match parser.parse_program() {
-        Ok(ast) => ast,
-        Err(e) => {
-            println!("\nERROR: {}", e);
-            println!("\n PASSED (Error detected as expected)");
-            println!("----------------------------\n");
-            return true;
-        }
-    };
 
-    // Semantic check
-    let mut checker = SemanticChecker::new();
-    if let Err(e) = checker.check(&ast) {
+    if let Err(e) = dlang::run(source, live_output()) {
         println!("\nERROR: {}", e);
         println!("\n  PASSED (Error detected as expected)");
         println!("----------------------------\n");
         return true;
     }
 
-    // Optimize
-    let mut optimizer = Optimizer::new();
-    optimizer.optimize(&mut ast);
-
-    // Interpret
-    let mut interpreter = Interpreter::new();
-    if let Err(e) = interpreter.interpret(&ast) {
-        println!("\nERROR: {}", e);
-        println!("\n  PASSED (Error detected as expected)");
-        println!("----------------------------\n");
-        return true;
-    }
-    
     println!("\n  FAILED (Expected error, but succeeded)");
     println!("----------------------------\n");
     false
 }
 
+/// Helper that runs the full pipeline and returns the printed lines
+fn run_captured(source: &str) -> Result<Vec<String>, String> {
+    run_captured_with(source, Interpreter::new())
+}
+
+/// Same as `run_captured`, but with a preconfigured interpreter
+fn run_captured_with(source: &str, interpreter: Interpreter) -> Result<Vec<String>, String> {
+    let result = dlang::run_with(source, RunOptions::default(), interpreter);
+    let output = match &result {
+        Ok(run) => &run.output,
+        Err(PipelineError::Runtime { output, .. }) => output,
+        Err(_) => return Err(result.unwrap_err().to_string()),
+    };
+    for line in output {
+        println!("  {}", line);
+    }
+    result.map(|run| run.output).map_err(|e| e.to_string())
+}
+
 // ========
 // BASIC TESTS
 // ========
@@ -147,6 +119,59 @@ print greeting + " " + name
     assert!(run_test_formatted("String Concatenation", source).is_ok());
 }
 
+#[test]
+fn test_print_without_args_prints_a_blank_line() {
+    assert_eq!(run_captured("print \"a\"\nprint\nprint \"b\""), Ok(vec!["a".to_string(), String::new(), "b".to_string()]));
+    let source = "var n := 2\nwhile n > 0 loop\n    print n\n    n := n - 1\n    print\nend";
+    assert_eq!(run_captured(source), Ok(vec!["2".to_string(), String::new(), "1".to_string(), String::new()]));
+}
+
+#[test]
+fn test_swap_variables() {
+    let source = "var a := 1\nvar b := \"two\"\nswap a, b\nprint a, b";
+    assert_eq!(run_captured(source), Ok(vec!["two 1".to_string()]));
+}
+
+#[test]
+fn test_swap_array_elements_while_sorting() {
+    let source = "\
+var arr := [5, 3, 9, 1, 7]
+for i in 1..4 loop
+    for j in 1..5 - i loop
+        if arr[j] > arr[j + 1] then
+            swap arr[j], arr[j + 1]
+        end
+    end
+end
+print arr";
+    assert_eq!(run_captured(source), Ok(vec!["[1, 3, 5, 7, 9]".to_string()]));
+}
+
+#[test]
+fn test_swap_tuple_fields() {
+    let source = "var t := {x := 1, y := \"b\"}\nswap t.x, t.y\nprint t.x, t.y\nswap t.y, t.x\nprint t.x, t.y";
+    assert_eq!(run_captured(source), Ok(vec!["b 1".to_string(), "1 b".to_string()]));
+
+    // like assignment, a swap stores under the key it names, so the
+    // positions of named fields keep their old values
+    let source = "var u := {x := 1, y := 2}\nswap u.x, u.y\nprint u.x, u.y, u[1], u[2]";
+    assert_eq!(run_captured(source), Ok(vec!["2 1 1 2".to_string()]));
+}
+
+#[test]
+fn test_swap_needs_assignable_operands() {
+    let source = "var x := 3\nswap 1, x";
+    let err = run_captured(source).unwrap_err();
+    assert!(err.contains("'swap' needs a variable, array element or tuple field, got 1"), "{}", err);
+
+    // unchecked, the interpreter refuses it before changing anything
+    let options = RunOptions { strict: false, ..RunOptions::default() };
+    let Err(PipelineError::Runtime { error, .. }) = dlang::run(&format!("{}\nprint x", source), options) else {
+        panic!("Expected a runtime error");
+    };
+    assert!(error.to_string().contains("'swap' needs a variable"), "{}", error);
+}
+
 // ========
 // CONDITIONALS
 // ========
@@ -218,6 +243,69 @@ end
     assert!(run_test_formatted("For Loop (Range)", source).is_ok());
 }
 
+#[test]
+fn test_for_loop_tuple_pattern() {
+    let points = "var points := [{x := 1, y := 2}, {x := 3, y := 4}]\n";
+    let named = format!("{}for {{x, y}} in points loop\n    print x + y\nend", points);
+    assert_eq!(run_captured(&named).unwrap(), vec!["3", "7"]);
+    let positional = format!("{}for {{b := 2, a := 1}} in points loop\n    print a, b\nend", points);
+    assert_eq!(run_captured(&positional).unwrap(), vec!["1 2", "3 4"]);
+
+    let err = run_captured(&format!("{}for {{x, z}} in points loop\n    print z\nend", points)).unwrap_err();
+    assert!(err.contains("Tuple field 'z' not found"), "{}", err);
+    let err = run_captured(&format!("{}for {{x, c := 3}} in points loop\n    print c\nend", points)).unwrap_err();
+    assert!(err.contains("Tuple position 3 out of bounds (the tuple has 2 elements)"), "{}", err);
+    let err = run_captured("for {x} in [1, 2] loop\n    print x\nend").unwrap_err();
+    assert!(err.contains("Cannot unpack int with the loop pattern {x}"), "{}", err);
+}
+
+#[test]
+fn test_repeat_until() {
+    // the body runs once even though the condition already holds
+    let source = "var n := 10\nrepeat\n    print n\n    n := n + 1\nuntil n > 5";
+    assert_eq!(run_captured(source).unwrap(), vec!["10"]);
+
+    let source = "var n := 0\nrepeat\n    n := n + 1\n    if n = 3 then\n        exit\n    end\nuntil false\nprint n";
+    assert_eq!(run_captured(source).unwrap(), vec!["3"]);
+
+    // `repeat` is still the builtin when called
+    assert_eq!(run_captured("print repeat(\"ab\", 2)").unwrap(), vec!["abab"]);
+}
+
+#[test]
+fn test_repeat_until_condition_scope() {
+    // the condition sees the enclosing scope only, as a `while` condition does
+    let source = "repeat\n    var done := true\nuntil done";
+    let err = run_captured(source).unwrap_err();
+    assert!(err.contains("Variable or function 'done' used before declaration"), "{}", err);
+    let options = RunOptions { strict: false, optimize: false, ..RunOptions::default() };
+    let err = dlang::run(source, options).unwrap_err();
+    assert!(err.to_string().contains("Variable or function 'done' used before declaration"), "{}", err);
+}
+
+#[test]
+fn test_labelled_exit() {
+    // `exit outer` leaves both loops; the plain exit only the inner one
+    let source = "outer: for i in 1..3 loop\n    for j in 1..3 loop\n        if j = 2 then\n            exit\n        end\n        if i = 2 then\n            exit outer\n        end\n        print i, j\n    end\nend\nprint \"done\"";
+    assert_eq!(run_captured(source).unwrap(), vec!["1 1", "done"]);
+
+    // intermediate loops of every kind pass it on
+    let source = "var n := 0\nouter: while true loop\n    repeat\n        for loop\n            n := n + 1\n            exit outer\n        end\n    until false\nend\nprint n";
+    assert_eq!(run_captured(source).unwrap(), vec!["1"]);
+}
+
+#[test]
+fn test_labelled_exit_shadowing() {
+    // the innermost loop with the label is the one left
+    let source = "var n := 0\nl: for i in 1..3 loop\n    l: for j in 1..3 loop\n        n := n + 1\n        exit l\n    end\nend\nprint n";
+    assert_eq!(run_captured(source).unwrap(), vec!["3"]);
+
+    // unchecked, a label no loop has is reported once it leaves them all
+    let options = RunOptions { strict: false, optimize: false, ..RunOptions::default() };
+    let err = dlang::run("for i in 1..2 loop\n    exit nowhere\nend", options).unwrap_err();
+    assert!(err.to_string().contains("Exit label 'nowhere' does not name an enclosing loop"), "{}", err);
+}
+
 #[test]
 fn test_exit_loop() {
     let source = r#"
@@ -259,6 +347,32 @@ print outer(5)
     assert!(run_test_formatted("Nested Function", source).is_ok());
 }
 
+#[test]
+fn test_closures_capture_each_for_iteration() {
+    let source = r#"
+var fs := []
+for i in 1..3 loop
+    fs := [...fs, func() => i]
+end
+print fs[1](), fs[2](), fs[3]()
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["1 2 3"]);
+}
+
+#[test]
+fn test_closures_share_a_variable_declared_outside_a_while_loop() {
+    let source = r#"
+var fs := []
+var i := 1
+while i <= 3 loop
+    fs := [...fs, func() => i]
+    i := i + 1
+end
+print fs[1](), fs[2](), fs[3]()
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["4 4 4"]);
+}
+
 // ========
 // ARRAYS
 // ========
@@ -330,6 +444,25 @@ print t3.c
     assert!(run_test_formatted("Tuple Concatenation", source).is_ok());
 }
 
+#[test]
+fn test_spread_in_literals() {
+    let source = "var rest := [2, 3]\nvar xs := [1, ...rest, 9]\nprint xs, sum([...xs, ...[]])";
+    assert_eq!(run_captured(source).unwrap(), vec!["[1, 2, 3, 9] 15"]);
+
+    // later entries override earlier ones; positions continue as with `+`,
+    // and a name overridden after a spread keeps its position
+    let source = "var base := {x := 1, y := 2}\nvar moved := {...base, x := 5}\nvar kept := {x := 5, ...base}\nprint moved.x, moved.y, moved.1, kept.x, kept.3";
+    assert_eq!(run_captured(source).unwrap(), vec!["5 2 5 1 2"]);
+    let source = "var t := {...{a := 1, b := 2}, b := 5}\nprint t\nprint t[2], t.b";
+    assert_eq!(run_captured(source).unwrap(), vec!["{1: 1, 2: 5, a: 1, b: 5}", "5 5"]);
+
+    // the checker cannot tell what a parameter holds
+    let err = run_captured("var wrap := func(v) => [0, ...v]\nprint wrap(7)").unwrap_err();
+    assert!(err.contains("Spread in an array literal needs an array, got int"), "{}", err);
+    let err = run_captured("var wrap := func(v) => {...v}\nprint wrap([1])").unwrap_err();
+    assert!(err.contains("Spread in a tuple literal needs a tuple, got array"), "{}", err);
+}
+
 #[test]
 fn test_tuple_assignment() {
     let source = r#"
@@ -361,6 +494,36 @@ print t
     assert!(run_test_formatted("Empty Tuple", source).is_ok());
 }
 
+#[test]
+fn test_keyword_field_names() {
+    let source = r#"
+var record := {end := 10, in := "x"}
+record.end := record.end + 1
+record := record + {loop := true}
+print record.end, record.in, record.loop, record.1, record.3
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["11 x true 10 true"]);
+}
+
+#[test]
+fn test_tuple_assignment_keeps_fields() {
+    let source = r#"
+var t := {a := 1, 2, c := 3}
+t.2 := 20
+t[3] := 30
+t["a"] := 10
+print t.a, t.1, t.2, t.3, t.c
+var joined := {5, 6} + {first := 7, 8}
+print joined.1, joined.2, joined.3, joined.first, joined.4
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["10 1 20 30 3", "5 6 7 7 8"]);
+
+    for source in ["var t := {a := 1, 2}\nt.b := 3", "var t := {1, 2}\nvar i := 0; i := 3\nt[i] := 3", "var t := {x := 1}\nvar k := \"y\"\nt[k] := 2"] {
+        let err = run_captured(source).expect_err("Tuples do not grow");
+        assert!(err.contains("Tuple has no field") && err.contains("build a new tuple"), "{}: {}", source, err);
+    }
+}
+
 // ========
 // TYPE CHECKING
 // ========
@@ -443,6 +606,203 @@ print div(x, y)
     assert!(run_test_formatted("Calculator", source).is_ok());
 }
 
+#[test]
+fn test_integer_division() {
+    let source = r#"
+print 7 / 2
+print 7 div 2
+print -7 div 2
+print 7.0 / 2
+var div := 4
+var n := 9
+print n div div
+"#;
+    let output = run_captured(source).expect("Should run");
+    assert_eq!(output, vec!["3", "3", "-3", "3.5", "2"]);
+}
+
+#[test]
+fn test_negative_range_endpoints() {
+    let source = r#"
+var total := 0
+for i in -3..3 loop
+    total := total + i
+end
+print total
+for i in -3..-1 loop
+    print i
+end
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["0", "-3", "-2", "-1"]);
+}
+
+#[test]
+fn test_negative_index_reaches_interpreter() {
+    // the checker rejects it statically, so run without it
+    let unchecked = RunOptions { strict: false, ..RunOptions::default() };
+    let err = dlang::run("var arr := [1, 2, 3]\nprint arr[-1]", unchecked).expect_err("Negative index should fail");
+    assert!(err.to_string().contains("Index -1 out of bounds"), "Unexpected error: {}", err);
+}
+
+#[test]
+fn test_shadowing_initializer_reads_outer_variable() {
+    let source = r#"
+var n := 1
+if true then
+    var n := n + 1
+    print n
+end
+print n
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["2", "1"]);
+}
+
+#[test]
+fn test_loop_variable_leaves_outer_variable_unchanged() {
+    let source = r#"
+var i := 100
+for i in 1..3 loop
+    print i
+end
+print i
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["1", "2", "3", "100"]);
+}
+
+#[test]
+fn test_strict_bool_conditions() {
+    let programs = [
+        ("var n := 3\nn := n - 1\nif n then\n    print \"yes\"\nend", "'if' condition must be a bool, got int"),
+        ("var s := \"x\"\nwhile s loop\n    s := \"\"\nend\nprint \"done\"", "'while' condition must be a bool, got string"),
+        ("var arr := [1]\narr := []\nprint not arr", "Operand of 'not' must be a bool, got array"),
+        ("var flag := true\nvar n := 0\nn := n + 1\nprint flag and n", "Operand of 'and' must be a bool, got int"),
+    ];
+    for (source, message) in programs {
+        // permissive by default: truthiness decides
+        assert!(run_captured(source).is_ok(), "Permissive run failed: {}", source);
+
+        let err = run_captured_with(source, Interpreter::new().with_strict_bool(true)).expect_err("Strict run should fail");
+        assert!(err.contains(message), "Expected '{}', got '{}'", message, err);
+    }
+    let strict = Interpreter::new().with_strict_bool(true);
+    assert_eq!(run_captured_with("var b := 1 < 2\nif b and not false then\n    print 1\nend", strict), Ok(vec!["1".to_string()]));
+}
+
+#[test]
+fn test_orelse_andthen_return_an_operand() {
+    let source = r#"
+var input := func(s) => s
+var name := input(none) orelse "default"
+var title := input("") orelse "untitled"
+var given := input("dlang") orelse "default"
+print name, title, given
+print input(none) orelse input("") orelse "last", input(0) orelse input(none) orelse 3
+var n := 4
+print n andthen n * 2, input(0) andthen n, input([]) andthen 1
+print true or false, input(none) or false
+"#;
+    assert_eq!(
+        run_captured(source).expect("Should run"),
+        vec!["default untitled dlang", "last 3", "8 0 []", "true false"]
+    );
+
+    // the right operand only runs when the left one does not decide, and
+    // strict mode does not apply: any value has a truthiness here
+    let source = r#"
+var noisy := func() is
+    print "ran"
+    return 1
+end
+var a := 5
+print a orelse noisy()
+a := 0
+print a andthen noisy()
+print a orelse noisy()
+"#;
+    let expected = vec!["5", "0", "ran", "1"];
+    assert_eq!(run_captured(source).expect("Should run"), expected);
+    assert_eq!(run_captured_with(source, Interpreter::new().with_strict_bool(true)).expect("Should run"), expected);
+}
+
+#[test]
+fn test_unset_declaration_under_strict_unset() {
+    let strict = || RunOptions { strict_unset: true, ..RunOptions::default() };
+    let run = |source: &str, options: RunOptions| {
+        dlang::run_with(source, options, Interpreter::new()).map(|run| run.output).map_err(|e| e.to_string())
+    };
+
+    // identical by default: both hold none
+    for source in ["var y\nprint y", "var y := none\nprint y"] {
+        assert_eq!(run(source, RunOptions::default()), Ok(vec!["none".to_string()]), "{}", source);
+    }
+
+    // strict: only the declaration without a value is refused until assigned
+    let err = run("var y\nprint y", strict()).expect_err("Reading an unset variable should fail");
+    assert!(err.contains("Variable 'y' is read before it is assigned a value"), "got: {}", err);
+    assert_eq!(run("var y := none\nprint y", strict()), Ok(vec!["none".to_string()]));
+    let source = r#"
+var total
+total := 0
+for i in 1..3 loop
+    var last
+    last := i
+    total := total + last
+end
+print total
+"#;
+    assert_eq!(run(source, strict()), Ok(vec!["6".to_string()]));
+    let err = run("if true then\n    var x\n    print x + 1\nend", strict()).expect_err("Locals are tracked too");
+    assert!(err.contains("Variable 'x' is read before it is assigned a value"), "got: {}", err);
+}
+
+#[test]
+fn test_unary_chains_and_plus() {
+    let source = "var x := true\nvar five := 5\nprint - - five, -(3 - 10), not not not x, +five, +-2.5";
+    assert_eq!(run_captured(source), Ok(vec!["5 7 false 5 -2.5".to_string()]));
+
+    // optimized or not, the chains give the same values
+    let options = RunOptions { optimize: false, ..RunOptions::default() };
+    let unoptimized = dlang::run_with(source, options, Interpreter::new()).map(|run| run.output).map_err(|e| e.to_string());
+    assert_eq!(unoptimized, run_captured(source));
+
+    // the checker rejects a literal; a value only known at run time is a type error then
+    let err = run_captured("print +\"abc\"").expect_err("Unary plus on a string");
+    assert!(err.contains("Unary '+' expects a number, got string"), "got: {}", err);
+    let err = run_captured("var s := [\"abc\"]\nprint +s[1]").expect_err("Unary plus on a string at run time");
+    assert!(err.contains("Unary '+' expects a number, got string"), "got: {}", err);
+}
+
+#[test]
+fn test_bitwise_and_logical_operators() {
+    let source = r#"
+var five := 5
+var three := 3
+print five and three, five or three, five xor three, -1 and 255
+print true and false, true or false, true xor true
+print five and true, 0 or false, five xor false
+print 1 << 10, five << 1 + 1, -16 >> 2, 1 << 63
+"#;
+    assert_eq!(
+        run_captured(source).expect("Should run"),
+        vec!["1 7 6 255", "false true false", "true false true", "1024 20 -4 -9223372036854775808"]
+    );
+
+    // two ints are bitwise even in strict mode; an int next to a bool is not
+    let strict = || Interpreter::new().with_strict_bool(true);
+    assert_eq!(run_captured_with("var n := 6\nprint n and 3", strict()), Ok(vec!["2".to_string()]));
+    let err = run_captured_with("var n := 6\nprint n and true", strict()).expect_err("Mixed operands are logical");
+    assert!(err.contains("Operand of 'and' must be a bool, got int"), "got: {}", err);
+
+    for (source, message) in [
+        ("var n := 64\nprint 1 << n", "Shift amount must be between 0 and 63, got 64"),
+        ("var n := -1\nprint 1 >> n", "Shift amount must be between 0 and 63, got -1"),
+        ("print 1.5 << 1", "Operator '<<' expects integer operands"),
+    ] {
+        let err = run_captured(source).expect_err("Shift should fail");
+        assert!(err.contains(message), "Expected '{}', got '{}'", message, err);
+    }
+}
+
 #[test]
 fn test_shadowing() {
     let source = r#"
@@ -470,6 +830,15 @@ var x := 10 / 0
     assert!(run_test_formatted_error("Division by Zero", source));
 }
 
+#[test]
+fn test_int_div_requires_integers() {
+    let source = r#"
+var x := 7.5
+print x div 2
+"#;
+    assert!(run_test_formatted_error("div on Real", source));
+}
+
 #[test]
 fn test_undefined_variable() {
     let source = r#"
@@ -487,6 +856,87 @@ print f(5)
     assert!(run_test_formatted_error("Wrong Argument Count", source));
 }
 
+#[test]
+fn test_function_values_display_signature() {
+    let source = r#"
+var add := func(x, y) => x + y
+var alias := add
+print add
+print alias
+print [func(a) => a, func() => 1]
+"#;
+    let output = run_captured(source).expect("Should run");
+    assert_eq!(output, vec!["<func add(x, y) at line 2>", "<func add(x, y) at line 2>", "[<func(a)>, <func()>]"]);
+}
+
+#[test]
+fn test_function_identity() {
+    let source = r#"
+var f := func(x) => x
+var g := f
+var h := func(x) => x
+print f = g, f = h, f /= h
+var callbacks := [h, f]
+var found := 0
+for i in 1..2 loop
+    if callbacks[i] = f then
+        found := i
+    end
+end
+print found
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["true false true", "2"]);
+}
+
+#[test]
+fn test_function_debug_shows_id() {
+    let options = RunOptions { optimize: false, ..RunOptions::default() };
+    let run = dlang::run("var f := func(x) => x\nvar g := f", options).expect("Should run");
+    let debug = format!("{:?}", run.globals["g"]);
+    assert!(debug.starts_with("Function { id: 1, name: Some(\"f\"), params: [\"x\"]"), "{}", debug);
+}
+
+#[test]
+fn test_arity_error_names_function() {
+    // called through an alias, so only the interpreter can catch it
+    let err = run_captured("var add := func(x, y) => x + y\nvar alias := add\nprint alias(1)").expect_err("Arity error expected");
+    assert!(err.contains("Function 'add' expects 2 arguments (x, y), got 1"), "{}", err);
+
+    let err = run_captured("var fs := [func(a) => a]\nprint fs[1](1, 2)").expect_err("Arity error expected");
+    assert!(err.contains("Function expects 1 arguments (a), got 2"), "{}", err);
+
+    let err = run_captured("var now2 := func() => now()\nvar alias := now2\nprint alias(1)").expect_err("Arity error expected");
+    assert!(err.contains("Function 'now2' expects 0 arguments, got 1"), "{}", err);
+}
+
+#[test]
+fn test_calling_or_indexing_the_wrong_kind_of_value() {
+    let err = run_captured("var arr := [1, 2, 3]\nprint arr(1)").expect_err("Call error expected");
+    assert!(err.contains("Cannot call non-function value of type array; did you mean 'arr[...]' for indexing?"), "{}", err);
+
+    let err = run_captured("var point := {x := 1, y := 2}\nprint point(1)").expect_err("Call error expected");
+    assert!(err.contains("of type tuple; did you mean 'point[...]' for indexing?"), "{}", err);
+
+    let err = run_captured("var f := func(x) => x\nprint f[1]").expect_err("Index error expected");
+    assert!(err.contains("Cannot index a function; did you mean 'f(...)' to call it?"), "{}", err);
+
+    // nothing to suggest for a plain value
+    let err = run_captured("var n := 5\nprint n(1)").expect_err("Call error expected");
+    assert!(err.contains("Cannot call non-function value of type int at 2:1"), "{}", err);
+    assert!(!err.contains("did you mean"), "{}", err);
+}
+
+#[test]
+fn test_undefined_variable_suggestion() {
+    // unchecked, so the interpreter is the one to notice; unoptimized, so `total` is still declared
+    let options = RunOptions { strict: false, optimize: false, ..RunOptions::default() };
+    let err = dlang::run("var total := 1\nprint totl + 1", options.clone()).unwrap_err();
+    assert!(err.to_string().contains("Variable or function 'totl' used before declaration; did you mean 'total'?"), "{}", err);
+
+    let err = dlang::run("var total := 1\nprint x", options).unwrap_err();
+    assert!(err.to_string().ends_with("Variable or function 'x' used before declaration at 2:1"), "{}", err);
+}
+
 // ========
 // OPTIMIZATION TESTS
 // ========
@@ -595,3 +1045,1465 @@ print factorial(5)
 "#;
     assert!(run_test_formatted("Recursive Factorial", source).is_ok());
 }
+
+// ========
+// MAPS
+// ========
+
+#[test]
+fn test_map_word_frequency() {
+    let source = r#"
+var text := "the cat and the dog and the bird"
+var counts := dict()
+for word in split(text) loop
+    if has(counts, word) then
+        counts[word] := counts[word] + 1
+    else
+        counts[word] := 1
+    end
+end
+for word in counts loop
+    print word, counts[word]
+end
+"#;
+    let output = run_captured(source).expect("Word frequency should run");
+    assert_eq!(output, vec!["and 2", "bird 1", "cat 1", "dog 1", "the 3"]);
+}
+
+#[test]
+fn test_map_builtins() {
+    let source = r#"
+var m := dict()
+m := put(m, "b", 2)
+m := put(m, "a", 1)
+print getKey(m, "a"), has(m, "c")
+print keys(m)
+m := delete(m, "a")
+print m
+"#;
+    let output = run_captured(source).expect("Map builtins should run");
+    assert_eq!(output, vec!["1 false", "[a, b]", "map{b: 2}"]);
+}
+
+#[test]
+fn test_map_split_with_separator() {
+    let source = r#"
+var parts := split("a,b,,c", ",")
+print parts
+"#;
+    let output = run_captured(source).expect("split should run");
+    assert_eq!(output, vec!["[a, b, , c]"]);
+}
+
+#[test]
+fn test_map_missing_key() {
+    let source = r#"
+var m := dict()
+print m["nope"]
+"#;
+    assert!(run_test_formatted_error("Map Missing Key", source));
+}
+
+#[test]
+fn test_map_non_string_key() {
+    let source = r#"
+var m := dict()
+m[1] := 2
+"#;
+    assert!(run_test_formatted_error("Map Non-String Key", source));
+}
+
+#[test]
+fn test_builtin_wrong_argument_count() {
+    let source = r#"
+var m := dict()
+print has(m)
+"#;
+    let err = run_captured(source).expect_err("Arity error expected");
+    assert!(err.contains("Builtin 'has' expects 2 arguments, got 1"), "{}", err);
+}
+
+// ========
+// RANDOM AND TIME
+// ========
+
+fn seeded(seed: u64) -> Interpreter {
+    let mut interpreter = Interpreter::with_output_capture();
+    interpreter.seed_rng(seed);
+    interpreter
+}
+
+#[test]
+fn test_random_seeded_sequence_is_reproducible() {
+    let source = r#"
+for i in 1..5 loop
+    print random(), randomInt(1, 100)
+end
+"#;
+    let first = run_captured_with(source, seeded(42)).expect("random should run");
+    let second = run_captured_with(source, seeded(42)).expect("random should run");
+    let other = run_captured_with(source, seeded(7)).expect("random should run");
+    assert_eq!(first.len(), 5);
+    assert_eq!(first, second, "Same seed must give the same sequence");
+    assert_ne!(first, other, "Different seeds should give different sequences");
+}
+
+#[test]
+fn test_random_real_in_unit_interval() {
+    let source = r#"
+var bad := 0
+for i in 1..1000 loop
+    var r := random()
+    if r < 0.0 or r >= 1.0 then
+        bad := bad + 1
+    end
+end
+print bad
+"#;
+    let output = run_captured_with(source, seeded(1)).expect("random should run");
+    assert_eq!(output, vec!["0"]);
+}
+
+#[test]
+fn test_random_int_respects_bounds() {
+    let source = r#"
+var lo := 100
+var hi := 0
+for i in 1..1000 loop
+    var r := randomInt(3, 7)
+    if r < lo then
+        lo := r
+    end
+    if r > hi then
+        hi := r
+    end
+end
+print lo, hi
+"#;
+    let output = run_captured_with(source, seeded(2024)).expect("randomInt should run");
+    assert_eq!(output, vec!["3 7"]);
+}
+
+#[test]
+fn test_random_int_inverted_bounds() {
+    let source = r#"
+print randomInt(5, 1)
+"#;
+    let err = run_captured(source).expect_err("lo > hi should fail");
+    assert!(err.contains("greater than upper bound"), "{}", err);
+}
+
+#[test]
+fn test_now_is_non_negative_integer() {
+    let source = r#"
+var t := now()
+print t is int, t >= 0
+"#;
+    let output = run_captured(source).expect("now should run");
+    assert_eq!(output, vec!["true true"]);
+}
+
+// ========
+// COPY SEMANTICS
+// ========
+
+#[test]
+fn test_copy_on_var_decl() {
+    let source = r#"
+var a := [1, 2, 3]
+var b := a
+b[1] := 99
+print a, b
+"#;
+    let output = run_captured(source).expect("Copy on declaration should run");
+    assert_eq!(output, vec!["[1, 2, 3] [99, 2, 3]"]);
+}
+
+#[test]
+fn test_copy_on_assign() {
+    let source = r#"
+var a := [1, 2, 3]
+var b := []
+b := a
+b[2] := 99
+print a, b
+"#;
+    let output = run_captured(source).expect("Copy on assignment should run");
+    assert_eq!(output, vec!["[1, 2, 3] [1, 99, 3]"]);
+}
+
+#[test]
+fn test_copy_on_function_argument() {
+    let source = r#"
+var mutate := func(arr) is
+    arr[1] := 99
+    return arr
+end
+var a := [1, 2, 3]
+var b := mutate(a)
+print a, b
+"#;
+    let output = run_captured(source).expect("Copy on argument passing should run");
+    assert_eq!(output, vec!["[1, 2, 3] [99, 2, 3]"]);
+}
+
+#[test]
+fn test_copy_on_element_extraction() {
+    let source = r#"
+var grid := [[1, 2], [3, 4]]
+var row := grid[1]
+row[1] := 99
+print grid, row
+"#;
+    let output = run_captured(source).expect("Copy on element extraction should run");
+    assert_eq!(output, vec!["[[1, 2], [3, 4]] [99, 2]"]);
+}
+
+#[test]
+fn test_copy_of_tuple_and_map() {
+    let source = r#"
+var p := {x := 1}
+var q := p
+q.x := 2
+var m := dict()
+m["k"] := 1
+var n := m
+n["k"] := 2
+print p.x, q.x, m["k"], n["k"]
+"#;
+    let output = run_captured(source).expect("Tuple and map copies should run");
+    assert_eq!(output, vec!["1 2 1 2"]);
+}
+
+#[test]
+fn test_clone_builtin() {
+    let source = r#"
+var a := [[1, 2], {x := 3}]
+var b := clone(a)
+print a = b
+b[1] := 0
+print a, b[1]
+"#;
+    let output = run_captured(source).expect("clone should run");
+    assert_eq!(output, vec!["true", "[[1, 2], {1: 3, x: 3}] 0"]);
+}
+
+// ========
+// STRING REPETITION
+// ========
+
+#[test]
+fn test_string_repetition() {
+    let source = r#"
+var n := 3
+print "-" * 5
+print n * "ab"
+print repeat("xy", n)
+"#;
+    let output = run_captured(source).expect("String repetition should run");
+    assert_eq!(output, vec!["-----", "ababab", "xyxyxy"]);
+}
+
+#[test]
+fn test_string_repetition_zero_count() {
+    let source = r#"
+var s := "abc"
+print "[" + s * 0 + "]"
+print "[" + repeat(s, 0) + "]"
+"#;
+    let output = run_captured(source).expect("Zero repetition should run");
+    assert_eq!(output, vec!["[]", "[]"]);
+}
+
+#[test]
+fn test_string_repetition_negative_count() {
+    let source = r#"
+var n := -1
+print "ab" * n
+"#;
+    let err = run_captured(source).expect_err("Negative count should fail");
+    assert!(err.contains("must be non-negative"), "{}", err);
+}
+
+#[test]
+fn test_string_repetition_size_cap() {
+    let source = r#"
+var n := 100
+print repeat("abc", n)
+"#;
+    let mut interpreter = Interpreter::with_output_capture();
+    interpreter.set_max_string_len(50);
+    let err = run_captured_with(source, interpreter).expect_err("Size cap should fail");
+    assert!(err.contains("exceeds the limit of 50"), "{}", err);
+}
+
+// ========
+// AGGREGATION
+// ========
+
+#[test]
+fn test_aggregate_int_array() {
+    let source = r#"
+var data := [4, 8, 1, 7]
+print sum(data), minOf(data), maxOf(data), avg(data)
+"#;
+    let output = run_captured(source).expect("Aggregation should run");
+    assert_eq!(output, vec!["20 1 8 5"]);
+}
+
+#[test]
+fn test_aggregate_mixed_array_promotes_to_real() {
+    let source = r#"
+var data := [1, 2.5, 3]
+print sum(data), maxOf(data), avg(data) is real
+"#;
+    let output = run_captured(source).expect("Mixed aggregation should run");
+    assert_eq!(output, vec!["6.5 3 true"]);
+}
+
+#[test]
+fn test_aggregate_empty_array() {
+    let output = run_captured("var data := []\nprint sum(data)").expect("Empty sum should run");
+    assert_eq!(output, vec!["0"]);
+
+    for builtin in ["minOf", "maxOf", "avg"] {
+        let source = format!("var data := []\nprint {}(data)", builtin);
+        let err = run_captured(&source).expect_err("Empty aggregate should fail");
+        assert!(err.contains("of an empty array"), "{}", err);
+    }
+}
+
+#[test]
+fn test_aggregate_large_range_is_fast() {
+    let source = r#"
+var n := 100000000
+print sum(1..n), minOf(n..1), maxOf(1..n), avg(1..4)
+"#;
+    let started = std::time::Instant::now();
+    let output = run_captured(source).expect("Range aggregation should run");
+    assert_eq!(output, vec!["5000000050000000 1 100000000 2.5"]);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1), "Range should not be materialized");
+}
+
+#[test]
+fn test_aggregate_range_needs_integer_bounds() {
+    for (bounds, expected) in [("1..x", "Range end must be an integer"), ("x..1", "Range start must be an integer")] {
+        for source in [format!("var x := 2.5\nprint sum({})", bounds), format!("var x := 2.5\nprint {}", bounds)] {
+            let err = run_captured(&source).expect_err("Range error expected");
+            assert!(err.contains(expected), "{}: {}", source, err);
+        }
+    }
+}
+
+// ========
+// RANGE AND ENUMERATE
+// ========
+
+#[test]
+fn test_range_builtin() {
+    let source = r#"
+print range(3)
+print range(2, 4)
+print range(0), range(3, 2)
+var total := 0
+for i in range(4) loop
+    total := total + i
+end
+print total
+"#;
+    let output = run_captured(source).expect("range should run");
+    assert_eq!(output, vec!["[1, 2, 3]", "[2, 3, 4]", "[] []", "10"]);
+}
+
+#[test]
+fn test_enumerate_builtin() {
+    let source = r#"
+for e in enumerate(["a", "b"]) loop
+    print e.index, e.value
+end
+print enumerate([])
+for row in enumerate([[10], [20, 30]]) loop
+    for cell in enumerate(row.value) loop
+        print row.index, cell.index, cell.value
+    end
+end
+"#;
+    let output = run_captured(source).expect("enumerate should run");
+    assert_eq!(output, vec!["1 a", "2 b", "[]", "1 1 10", "2 1 20", "2 2 30"]);
+}
+
+#[test]
+fn test_range_and_enumerate_argument_errors() {
+    let err = run_captured("print range(1.5)").expect_err("Type error expected");
+    assert!(err.contains("range bounds must be integers"), "{}", err);
+    let err = run_captured("print enumerate(\"abc\")").expect_err("Type error expected");
+    assert!(err.contains("enumerate expects an array"), "{}", err);
+    let err = run_captured("print range(1, 2, 3)").expect_err("Arity error expected");
+    assert!(err.contains("Builtin 'range' expects 1 to 2 arguments, got 3"), "{}", err);
+}
+
+#[test]
+fn test_arity_and_params_builtins() {
+    let source = r#"
+var add := func(x, y) => x + y
+print params(add), arity(add)
+print params(func() => 1), arity(func(a) => a)
+print arity(sum), arity(randomInt), arity(range)
+"#;
+    let output = run_captured(source).expect("arity and params should run");
+    assert_eq!(output, vec!["[x, y] 2", "[] 1", "1 2 1"]);
+
+    let err = run_captured("print params(sum)").expect_err("Runtime error expected");
+    assert!(err.contains("Builtin 'sum' has no parameter names"), "{}", err);
+    let err = run_captured("print arity(3)").expect_err("Type error expected");
+    assert!(err.contains("arity expects a function"), "{}", err);
+}
+
+// ========
+// INDEX BASE
+// ========
+
+fn run_with_base(source: &str, index_base: IndexBase) -> Result<Vec<String>, String> {
+    let options = RunOptions { index_base, ..RunOptions::default() };
+    dlang::run(source, options).map(|run| run.output).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_index_base_valid_ranges() {
+    let source = r#"
+var a := [10, 20, 30]
+var i := 1
+a[i] := 99
+print a[i], a
+for e in enumerate(a) loop
+    print e.index
+end
+"#;
+    let one = run_with_base(source, IndexBase::One).expect("1-based run failed");
+    assert_eq!(one, vec!["99 [99, 20, 30]", "1", "2", "3"]);
+    let zero = run_with_base(source, IndexBase::Zero).expect("0-based run failed");
+    assert_eq!(zero, vec!["99 [10, 99, 30]", "0", "1", "2"]);
+}
+
+#[test]
+fn test_index_base_out_of_bounds_messages() {
+    let read_last = "var a := [10, 20, 30]\nvar i := 3\nprint a[i]";
+    assert_eq!(run_with_base(read_last, IndexBase::One).unwrap(), vec!["30"]);
+    let err = run_with_base(read_last, IndexBase::Zero).expect_err("a[3] is past the end when 0-based");
+    assert!(err.contains("Index 3 out of bounds (array size: 3, valid range: 0..2)"), "{}", err);
+
+    let write_first = "var a := [10, 20, 30]\nvar i := 0\na[i] := 1";
+    assert!(run_with_base(write_first, IndexBase::Zero).is_ok());
+    let err = run_with_base(write_first, IndexBase::One).expect_err("a[0] does not exist when 1-based");
+    assert!(err.contains("Index 0 out of bounds (array size: 3, valid range: 1..3)"), "{}", err);
+}
+
+#[test]
+fn test_index_base_constant_bounds_check() {
+    // the checker rejects constant indices with the interpreter's base
+    let err = run_with_base("var a := [1, 2]\nprint a[2]", IndexBase::Zero).expect_err("Semantic error expected");
+    assert!(err.contains("Index 2 out of bounds (array size: 2, valid range: 0..1)"), "{}", err);
+    assert_eq!(run_with_base("var a := [1, 2]\nprint a[0]", IndexBase::Zero).unwrap(), vec!["1"]);
+    let err = run_with_base("var a := [1, 2]\nprint a[0]", IndexBase::One).expect_err("Semantic error expected");
+    assert!(err.contains("Index 0 out of bounds (array size: 2, valid range: 1..2)"), "{}", err);
+}
+
+// ========
+// IMPLICIT NONE RETURNS
+// ========
+
+fn run_implicit_none_checked(source: &str) -> Result<Vec<String>, String> {
+    run_captured_with(source, Interpreter::new().with_error_on_implicit_none_return(true))
+}
+
+#[test]
+fn test_implicit_none_result_used() {
+    let source = r#"
+var f := func(x) is
+    print x
+end
+var y := f(1)
+"#;
+    let err = run_implicit_none_checked(source).expect_err("Using the fall-through result should fail");
+    assert!(err.contains("function 'f' ended without returning a value but its result is used"), "{}", err);
+    assert!(run_captured(source).is_ok(), "The check is off by default");
+
+    let in_expression = "var f := func() is\n    var t := 1\nend\nprint f()";
+    let err = run_implicit_none_checked(in_expression).expect_err("Printing the result should fail");
+    assert!(err.contains("function 'f' ended"), "{}", err);
+}
+
+#[test]
+fn test_implicit_none_discarded_call() {
+    let source = r#"
+var log := func(x) is
+    print x
+end
+var g := func() is
+    log(1)
+    return 2
+end
+log(0)
+print g()
+"#;
+    let output = run_implicit_none_checked(source).expect("Discarded calls may fall through");
+    assert_eq!(output, vec!["0", "1", "2"]);
+}
+
+#[test]
+fn test_explicit_return_none_is_not_implicit() {
+    let source = r#"
+var f := func() is
+    return none
+end
+var g := func() is
+    return
+end
+print f(), g()
+"#;
+    let output = run_implicit_none_checked(source).expect("Explicit returns are fine");
+    assert_eq!(output, vec!["none none"]);
+}
+
+// ========
+// RESOLVER AND SLOT FRAMES
+// ========
+
+#[test]
+fn test_resolver_binds_locals_to_slots() {
+    let source = r#"
+var g := 1
+var f := func(a, b) is
+    var c := a + b
+    return c + g
+end
+"#;
+    let mut ast = Parser::new(source).parse_program().expect("Parse should succeed");
+    Resolver::new().resolve(&mut ast);
+
+    let Program::Stmts(stmts) = &ast;
+    let Stmt::VarDecl { init: Some(Expr::Func { body: dlang::ast::FuncBody::Block(body), .. }), .. } = &stmts[1] else {
+        panic!("Expected function declaration");
+    };
+    let Stmt::VarDecl { init: Some(Expr::Binary { left, right, .. }), .. } = &body[0] else {
+        panic!("Expected local declaration");
+    };
+    assert_eq!(**left, Expr::Local { name: "a".into(), depth: 0, slot: 0 });
+    assert_eq!(**right, Expr::Local { name: "b".into(), depth: 0, slot: 1 });
+
+    let Stmt::Return(Some(Expr::Binary { left, right, .. }), _) = &body[1] else {
+        panic!("Expected return");
+    };
+    assert_eq!(**left, Expr::Local { name: "c".into(), depth: 0, slot: 2 });
+    assert_eq!(**right, Expr::Ident("g".into()), "Globals stay name-based");
+}
+
+#[test]
+fn test_slots_closures_share_frames() {
+    let source = r#"
+var makeCounter := func() is
+    var count := 0
+    return func() is
+        count := count + 1
+        return count
+    end
+end
+var a := makeCounter()
+var b := makeCounter()
+print a(), a(), b(), a()
+"#;
+    let output = run_captured(source).expect("Closures should run");
+    assert_eq!(output, vec!["1 2 1 3"]);
+}
+
+#[test]
+fn test_slots_nested_scopes_and_shadowing() {
+    let source = r#"
+var x := 1
+var f := func(n) is
+    var x := n * 10
+    for i in 1..2 loop
+        var x := i
+        if true then
+            x := x + 100
+            print x
+        end
+    end
+    return x
+end
+print f(5), x
+"#;
+    let output = run_captured(source).expect("Nested scopes should run");
+    assert_eq!(output, vec!["101", "102", "50 1"]);
+}
+
+#[test]
+fn test_slots_recursion_and_element_assignment() {
+    let source = r#"
+var build := func(n) is
+    var arr := [0, 0, 0]
+    var fill := func(i) is
+        if i > n then
+            return arr
+        end
+        arr[i] := i * i
+        return fill(i + 1)
+    end
+    return fill(1)
+end
+print build(3)
+"#;
+    let output = run_captured(source).expect("Recursion with captured array should run");
+    assert_eq!(output, vec!["[1, 4, 9]"]);
+}
+
+fn time_program(source: &str) -> std::time::Duration {
+    let ast = Parser::new(source).parse_program().expect("Parse should succeed");
+    let mut interpreter = Interpreter::with_output_capture();
+    let started = std::time::Instant::now();
+    interpreter.interpret(&ast).expect("Benchmark should run");
+    started.elapsed()
+}
+
+const LOCAL_LOOP: &str = r#"
+var run := func(n) is
+    var i := 0
+    var total := 0
+    while i < n loop
+        total := total + i
+        i := i + 1
+    end
+    return total
+end
+print run(1000000)
+"#;
+
+const GLOBAL_LOOP: &str = r#"
+var n := 1000000
+var i := 0
+var total := 0
+while i < n loop
+    total := total + i
+    i := i + 1
+end
+print total
+"#;
+
+#[test]
+fn test_slots_local_loop_reads_slots() {
+    let mut ast = Parser::new(LOCAL_LOOP).parse_program().expect("Parse should succeed");
+    Resolver::new().resolve(&mut ast);
+
+    let Program::Stmts(stmts) = &ast;
+    let Stmt::VarDecl { init: Some(Expr::Func { body: dlang::ast::FuncBody::Block(body), .. }), .. } = &stmts[0] else {
+        panic!("Expected function declaration");
+    };
+    let Stmt::While { cond: Expr::Binary { left, right, .. }, .. } = &body[2] else {
+        panic!("Expected while loop");
+    };
+    assert_eq!(**left, Expr::Local { name: "i".into(), depth: 0, slot: 1 });
+    assert_eq!(**right, Expr::Local { name: "n".into(), depth: 0, slot: 0 });
+
+    assert_eq!(run_captured(LOCAL_LOOP).unwrap(), run_captured(GLOBAL_LOOP).unwrap());
+}
+
+#[test]
+#[ignore = "timing benchmark; run with --ignored in a release build"]
+fn test_slots_benchmark_local_loop() {
+    // the same 1e6-iteration loop, once over slot-resolved locals and
+    // once over name-based globals
+    let local_time = time_program(LOCAL_LOOP);
+    let global_time = time_program(GLOBAL_LOOP);
+    println!("locals: {:?}, globals: {:?}", local_time, global_time);
+    assert!(local_time * 3 / 2 < global_time, "Slot lookups should be significantly faster");
+}
+
+#[test]
+fn test_blocks_without_declarations_add_no_scopes() {
+    let mut source = String::from("var f := func(n) is\n    var outer := n\n    var total := 0\n");
+    for _ in 0..200 {
+        source.push_str("if outer > 0 then\n");
+    }
+    source.push_str("for i in 1..20000 loop\n    total := total + outer\nend\n");
+    for _ in 0..200 {
+        source.push_str("end\n");
+    }
+    source.push_str("    return total\nend\nprint f(2)\n");
+
+    // 200 nested statements take more native stack than a test thread has in debug builds
+    let run = move || {
+        let mut interpreter = Interpreter::with_output_capture();
+        let started = std::time::Instant::now();
+        assert_eq!(run_cell(&mut interpreter, &source), vec!["40000"]);
+        assert!(started.elapsed().as_secs() < 10, "took {:?}", started.elapsed());
+
+        // the call's frame and one per loop iteration, none for the 200 branches
+        let stats = interpreter.stats();
+        assert_eq!(stats.max_env_depth, 2, "{:?}", stats);
+        assert_eq!(stats.scopes_created, 1 + 20000);
+        assert_eq!(stats.slots_defined, 3 + 20000);
+
+        // a branch that declares something still gets its own scope
+        let output = run_cell(&mut interpreter, "var g := func() is\n    var x := 1\n    if true then\n        var x := 2\n        print x\n    end\n    return x\nend\nprint g()");
+        assert_eq!(output, vec!["2", "1"]);
+    };
+    std::thread::Builder::new().stack_size(64 << 20).spawn(run).unwrap().join().unwrap();
+}
+
+// ========
+// ERROR POSITIONS
+// ========
+
+#[test]
+fn test_error_position_index_out_of_bounds() {
+    let source = "var arr := [1, 2, 3]\nvar i := 0; i := 10\nprint arr[i]";
+    let err = run_captured(source).expect_err("Index error expected");
+    assert!(err.contains("Index 10 out of bounds (array size: 3, valid range: 1..3) at 3:1"), "{}", err);
+}
+
+#[test]
+fn test_error_position_type_error_in_function() {
+    let source = r#"var f := func(x) is
+    var y := 1
+    return x - "a"
+end
+print f(2)"#;
+    let err = run_captured(source).expect_err("Type error expected");
+    assert!(err.contains("Invalid operands for subtraction at 3:5"), "{}", err);
+}
+
+#[test]
+fn test_error_position_accessors() {
+    let ast = Parser::new("var x := 1\n  x := x / 0").parse_program().expect("Parse should succeed");
+    let err = Interpreter::new().interpret(&ast).expect_err("Division error expected");
+    assert_eq!(err.position(), Some((2, 3)));
+    assert!(matches!(err.kind(), dlang::interpreter::InterpreterError::DivisionByZero));
+}
+
+// ========
+// DEEP AND LARGE VALUES
+// ========
+
+#[test]
+fn test_deeply_nested_array_prints_and_compares() {
+    let source = r#"
+var a := []
+for i in 1..10000 loop
+    a := [a]
+end
+print a
+var b := a
+print a = b
+b := [b]
+print a = b, a = b[1]
+"#;
+    let output = run_captured(source).expect("Deep array should not overflow");
+    let expected = format!("{}...{}", "[".repeat(100), "]".repeat(100));
+    assert_eq!(output, vec![expected, "true".to_string(), "false true".to_string()]);
+}
+
+// Runs the interpreter alone, without the checker and optimizer
+fn interpret_captured(program: &Program, mut interpreter: Interpreter) -> Result<Vec<String>, String> {
+    interpreter.set_output_capture(true);
+    interpreter.interpret(program).map_err(|e| e.to_string())?;
+    Ok(interpreter.take_output())
+}
+
+#[test]
+fn test_long_binary_chains_do_not_overflow() {
+    // checked, optimized and run
+    let sum = format!("print {}", vec!["1"; 100_000].join(" + "));
+    assert!(dlang::check(&sum).is_ok());
+    assert_eq!(run_captured(&sum), Ok(vec!["100000".to_string()]));
+    let unoptimized = RunOptions { optimize: false, ..RunOptions::default() };
+    assert_eq!(dlang::run(&sum, unoptimized).map(|run| run.output), Ok(vec!["100000".to_string()]));
+
+    let mixed = format!("print 0{}", " - 1 * 2 + \"\" + 1".repeat(2)) + &" - 1 + 2".repeat(50_000);
+    // the running value turns into a string halfway, and a string minus a number fails
+    let err = run_captured(&mixed).expect_err("Type error expected");
+    assert!(err.contains("Invalid operands for subtraction"), "{}", err);
+
+    let countdown = format!("var n := 100000\nprint n{}", " - 1".repeat(100_000));
+    assert_eq!(run_captured(&countdown), Ok(vec!["0".to_string()]));
+}
+
+#[test]
+fn test_deeply_nested_expression_hits_depth_guard() {
+    let mut expr = Expr::Integer(1);
+    for _ in 0..100_000 {
+        expr = Expr::Unary { op: UnOp::Neg, expr: Box::new(expr) };
+    }
+    let program = Program::Stmts(vec![Stmt::Print { args: vec![expr], span: Span::default() }]);
+    let err = interpret_captured(&program, Interpreter::new()).expect_err("Depth error expected");
+    assert_eq!(err, "Runtime error: Expressions nest more than 256 levels deep");
+    // the pipeline refuses it before checking and optimizing
+    let err = dlang::ProgramRunner::from_program(program, RunOptions::default()).run().expect_err("Depth error expected");
+    assert!(err.message.ends_with("Expressions nest more than 256 levels deep"), "{}", err);
+
+    // one level per nested literal, index or call argument
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_expr_depth(8);
+    let err = run_captured_with("print [[[[[f([[1]])]]]]]", interpreter).expect_err("Depth error expected");
+    assert!(err.ends_with("Expressions nest more than 8 levels deep"), "{}", err);
+    let output = run_captured("var f := func(a) => a[1][1]\nprint [[[[[f([[1]])]]]]]").expect("Should run");
+    assert_eq!(output, vec!["[[[[[1]]]]]"]);
+}
+
+#[test]
+fn test_deeply_nested_source_is_a_parse_error() {
+    let deep = [
+        format!("print {}1{}", "(".repeat(10_000), ")".repeat(10_000)),
+        format!("print {}1{}", "[".repeat(10_000), "]".repeat(10_000)),
+        format!("print {}1", "-".repeat(10_000)),
+        format!("print {}1", "func() => ".repeat(10_000)),
+        format!("{}print 1\n{}", "if true then\n".repeat(10_000), "end\n".repeat(10_000)),
+        format!("{}print 1\n{}", "var f := func() is\n".repeat(10_000), "end\n".repeat(10_000)),
+    ];
+    // the parser stops at the limit, but 256 levels still take more native stack than a test thread has in debug builds
+    let run = move || {
+        for source in &deep {
+            let err = Parser::new(source).parse_program().expect_err("Parse error expected");
+            assert_eq!(err.message, "Statements and expressions nest more than 256 levels deep");
+            let err = run_captured(source).expect_err("Parse error expected");
+            assert!(err.contains("Statements and expressions nest more than 256 levels deep"), "{}", err);
+        }
+        let shallow = format!("print {}1{}", "(".repeat(200), ")".repeat(200));
+        assert_eq!(run_captured(&shallow).expect("Should run"), vec!["1"]);
+    };
+    std::thread::Builder::new().stack_size(64 << 20).spawn(run).unwrap().join().unwrap();
+}
+
+#[test]
+fn test_configurable_print_depth() {
+    let mut interpreter = Interpreter::with_output_capture();
+    interpreter.set_max_print_depth(2);
+    let output = run_captured_with("print [1, [2, [3, [4]]]], [[]]", interpreter).expect("Should run");
+    assert_eq!(output, vec!["[1, [2, ...]] [[]]"]);
+}
+
+#[test]
+fn test_large_array_print_is_truncated() {
+    let source = r#"
+var s := ""
+for i in 1..1500 loop
+    s := s + i + " "
+end
+print split(s)
+"#;
+    let output = run_captured(source).expect("Should run");
+    assert!(output[0].starts_with("[1, 2, 3, "));
+    assert!(output[0].ends_with(", 999, 1000, ... (+500 more)]"));
+}
+
+#[test]
+fn test_configurable_print_elements() {
+    let mut interpreter = Interpreter::with_output_capture();
+    interpreter.set_max_print_elements(2);
+    let source = r#"
+var m := dict()
+m := put(m, "a", 1)
+m := put(m, "b", 2)
+m := put(m, "c", 3)
+print [1, 2, 3, 4], m, [1, 2]
+"#;
+    let output = run_captured_with(source, interpreter).expect("Should run");
+    assert_eq!(output, vec!["[1, 2, ... (+2 more)] map{a: 1, b: 2, ... (+1 more)} [1, 2]"]);
+}
+
+// ========
+// CONCATENATION CHAINS
+// ========
+
+#[test]
+fn test_concat_chain_matches_pairwise_addition() {
+    let chain = r#"
+var t := {a := 1}
+print 1 + 2 + "x" + 3 + 4.5 + true + none + [1, 2] + t + "end"
+print 1 + 2 + 3.5
+print t + {b := 2} + "!"
+"#;
+    let pairwise = r#"
+var t := {a := 1}
+var s := 1 + 2
+s := s + "x"
+s := s + 3
+s := s + 4.5
+s := s + true
+s := s + none
+s := s + [1, 2]
+s := s + t
+s := s + "end"
+print s
+var n := 1 + 2
+print n + 3.5
+var u := t + {b := 2}
+print u + "!"
+"#;
+    let output = run_captured(chain).expect("Chain should run");
+    assert_eq!(output, run_captured(pairwise).expect("Pairwise should run"));
+    assert_eq!(output[1], "6.5");
+}
+
+#[test]
+fn test_concat_chain_fails_where_pairwise_addition_does() {
+    // `[1] + [2]` fails before the call to the right of it is evaluated
+    let source = r#"
+var side := func() is
+    print "called"
+    return "s"
+end
+print "before"
+print [1] + [2] + side() + "tail"
+"#;
+    let options = RunOptions { capture_output: true, ..RunOptions::default() };
+    let Err(PipelineError::Runtime { error, output }) = dlang::run(source, options) else {
+        panic!("expected a runtime error");
+    };
+    assert!(error.to_string().contains("Invalid operands for addition"), "{}", error);
+    assert_eq!(output, vec!["before"]);
+}
+
+#[test]
+fn test_concat_chain_in_loop() {
+    let source = r#"
+var s := ""
+for i in 1..10000 loop
+    s := s + "<" + i + ">"
+end
+print s
+"#;
+    let started = std::time::Instant::now();
+    let output = run_captured(source).expect("Should run");
+    assert!(started.elapsed().as_secs() < 10, "took {:?}", started.elapsed());
+    assert!(output[0].starts_with("<1><2><3>"));
+    assert!(output[0].ends_with("<9999><10000>"));
+    assert_eq!(output[0].len(), (1..=10000).map(|i: i32| i.to_string().len() + 2).sum::<usize>());
+}
+
+// ========
+// SHARED STRINGS
+// ========
+
+#[test]
+fn test_large_string_moves_through_loop() {
+    let source = r#"
+var big := repeat("x", 1000000)
+var same := 0
+for i in 1..10000 loop
+    var items := [big, {s := big}]
+    var copy := items[1]
+    if copy = items[2].s then
+        same := same + 1
+    end
+end
+print same
+"#;
+    let started = std::time::Instant::now();
+    assert_eq!(run_captured(source).expect("Should run"), vec!["10000"]);
+    assert!(started.elapsed().as_secs() < 10, "took {:?}", started.elapsed());
+}
+
+#[test]
+fn test_concatenation_leaves_copies_independent() {
+    let source = r#"
+var a := "abc"
+var b := a
+b := b + "d"
+var arr := [a, a]
+arr[1] := arr[1] + "!"
+var t := {s := a}
+t.s := t.s + "?"
+print a, b, arr, t.s
+print "ab" = "a" + "b", a = "abc", a = b
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["abc abcd [abc!, abc] abc?", "true true false"]);
+}
+
+#[test]
+fn test_filling_large_array_by_element() {
+    let source = r#"
+var a := range(50000)
+for i in 1..50000 loop
+    a[i] := i * 2
+end
+print a[1], a[50000], sum(a)
+"#;
+    let started = std::time::Instant::now();
+    assert_eq!(run_captured(source).expect("Should run"), vec!["2 100000 2500050000"]);
+    assert!(started.elapsed().as_secs() < 10, "took {:?}", started.elapsed());
+}
+
+#[test]
+fn test_array_assignment_leaves_copies_independent() {
+    let source = r#"
+var a := [1, 2, 3]
+var b := a
+b[1] := 99
+var nested := [a, a]
+nested[2] := [0]
+var f := func(x) is
+    x[3] := 0
+    return x
+end
+var c := [1, 2]
+c[1] := c
+print a, b, nested, f(a), a, c
+"#;
+    assert_eq!(
+        run_captured(source).expect("Should run"),
+        vec!["[1, 2, 3] [99, 2, 3] [[1, 2, 3], [0]] [1, 2, 0] [1, 2, 3] [[1, 2], 2]"]
+    );
+}
+
+// ========
+// REAL NUMBERS
+// ========
+
+#[test]
+fn test_real_overflow_is_an_error() {
+    let source = r#"
+var x := 10.0
+for i in 1..400 loop
+    x := x * 10.0
+end
+print x
+"#;
+    let error = run_captured(source).expect_err("Overflow should fail");
+    assert!(error.contains("Real overflow"), "got: {}", error);
+
+    let divided = run_captured("var tiny := 0.5\nfor i in 1..1070 loop tiny := tiny / 2 end\nprint 1.0 / tiny");
+    assert!(divided.expect_err("Overflow should fail").contains("Real overflow"));
+}
+
+#[test]
+fn test_real_equality_and_comparisons_agree() {
+    let source = r#"
+var a := 0.1 + 0.2
+print a = 0.3, a <= 0.3, a >= 0.3, a < 0.3, a > 0.3
+print 1.0 < 1.5, 2.5 >= 3, 100000000000000000000.0 = 100000000000000000000.0 + 100000.0
+print 9007199254740993 > 9007199254740992, 3 <= 3.0
+"#;
+    assert_eq!(
+        run_captured(source).expect("Should run"),
+        vec!["true true true false false", "true false false", "true true"]
+    );
+}
+
+// ========
+// ARRAY ORDERING
+// ========
+
+#[test]
+fn test_array_ordering() {
+    let source = r#"
+print [1, 2] < [1, 3], [1, 3] < [1, 2], [1, 2] <= [1, 2], [1, 2] >= [1, 2], [1, 2] > [1, 2]
+print [1] < [1, 0], [] < [0], [1, 2] > [1], [2] > [1, 99], [] <= []
+print [[1, 2], 3] < [[1, 2.5]], [0.1 + 0.2] = [0.3], [0.1 + 0.2] <= [0.3]
+print minOf([[2, 1], [1, 5], [1, 4, 0]]), maxOf([[2, 1], [1, 5], [2]])
+"#;
+    assert_eq!(
+        run_captured(source).expect("Should run"),
+        vec![
+            "true false true true false",
+            "true true true true true",
+            "true true true",
+            "[1, 4, 0] [2, 1]",
+        ]
+    );
+}
+
+#[test]
+fn test_array_ordering_errors() {
+    for (source, message) in [
+        ("print [1, \"a\"] < [1, 2]", "Cannot order string and int"),
+        ("print [none] < [none]", "Cannot order none and none"),
+        ("print [1, 2] < 3", "Cannot order array and int"),
+        ("print {a := 1} < {a := 2}", "Tuples are unordered"),
+        ("print [{a := 1}] < [{a := 2}]", "Tuples are unordered"),
+    ] {
+        let err = run_captured(source).expect_err("Comparison should fail");
+        assert!(err.contains(message), "Expected '{}', got '{}'", message, err);
+    }
+    // elements past the first difference are never compared
+    assert_eq!(run_captured("print [1, \"a\"] < [2, 2]"), Ok(vec!["true".to_string()]));
+}
+
+#[test]
+fn test_sorting_arrays_of_arrays() {
+    let source = r#"
+var sort := func(items) is
+    var count := 0
+    for item in items loop
+        count := count + 1
+    end
+    for i in 2..count loop
+        var j := i
+        while j > 1 loop
+            if items[j] >= items[j - 1] then
+                exit
+            end
+            var held := items[j]
+            items[j] := items[j - 1]
+            items[j - 1] := held
+            j := j - 1
+        end
+    end
+    return items
+end
+print sort([[3, 1], [1, 2, 3], [1, 2], [], [2], [1, 10]])
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["[[], [1, 2], [1, 2, 3], [1, 10], [2], [3, 1]]"]);
+}
+
+#[test]
+fn test_ordering_deeply_nested_arrays() {
+    let source = r#"
+var a := [1]
+var b := [2]
+for i in 1..100000 loop
+    a := [a]
+    b := [b]
+end
+print a < b, a = a
+"#;
+    assert_eq!(run_captured(source).expect("Should run"), vec!["true true"]);
+}
+
+// ========
+// SNAPSHOTS
+// ========
+
+fn run_cell(interpreter: &mut Interpreter, source: &str) -> Vec<String> {
+    let program = Parser::new(source).parse_program().expect("Should parse");
+    interpreter.interpret(&program).expect("Should run");
+    interpreter.take_output()
+}
+
+#[test]
+fn test_restore_undoes_later_changes() {
+    let mut interpreter = Interpreter::with_output_capture();
+    run_cell(&mut interpreter, "var a := [1, 2]\nvar t := {x := 1}\nvar n := 5");
+    let state = interpreter.snapshot();
+
+    run_cell(&mut interpreter, "a[1] := 100\nt.x := 2\nn := n + 1\nvar extra := 1");
+    interpreter.restore(state);
+
+    assert_eq!(run_cell(&mut interpreter, "print a, t.x, n"), vec!["[1, 2] 1 5"]);
+    assert!(!interpreter.globals().contains_key("extra"));
+}
+
+#[test]
+fn test_restore_copies_closures_and_keeps_them_shared() {
+    let mut interpreter = Interpreter::with_output_capture();
+    let source = r#"
+var make := func() is
+    var count := 0
+    return {inc := func() is count := count + 1; return count end, get := func() => count}
+end
+var c := make()
+c.inc()
+"#;
+    run_cell(&mut interpreter, source);
+    let state = interpreter.snapshot();
+
+    run_cell(&mut interpreter, "c.inc()\nc.inc()");
+    assert_eq!(run_cell(&mut interpreter, "print c.get()"), vec!["3"]);
+
+    interpreter.restore(state);
+    assert_eq!(run_cell(&mut interpreter, "print c.get()"), vec!["1"]);
+    // both closures still see the same (copied) count
+    assert_eq!(run_cell(&mut interpreter, "c.inc()\nprint c.get()"), vec!["2"]);
+}
+
+#[test]
+fn test_state_json_round_trip() {
+    let mut interpreter = Interpreter::with_output_capture();
+    run_cell(&mut interpreter, r#"
+var a := [1, 2.5, 'q"uote', none, [true]]
+var t := {name := "x", 7}
+var m := put(dict(), "k", [1])
+var f := func(x) => x
+"#);
+    let json = interpreter.snapshot().to_json().expect("Plain values have JSON");
+    assert!(!json.contains("\"f\""), "functions are left out: {}", json);
+
+    let state = dlang::InterpreterState::from_json(&json).expect("Should read back");
+    let mut restored = Interpreter::with_output_capture();
+    restored.restore(state);
+    let mut expected = interpreter.globals();
+    expected.remove("f");
+    assert_eq!(restored.globals(), expected);
+    assert_eq!(run_cell(&mut restored, "print a[3], t.name, t.2, m"), vec![r#"q"uote x 7 map{k: [1]}"#]);
+}
+
+#[test]
+fn test_state_json_rejects_nested_functions() {
+    let mut interpreter = Interpreter::with_output_capture();
+    run_cell(&mut interpreter, "var fs := [func() => 1]");
+    assert!(interpreter.snapshot().to_json().is_err());
+    assert!(dlang::InterpreterState::from_json("[1]").is_err());
+    assert!(dlang::InterpreterState::from_json("{\"globals\": {\"a\": [1,}}").is_err());
+}
+
+// ========
+// HOOKS AND COVERAGE
+// ========
+
+#[test]
+fn test_hook_events_follow_execution_order() {
+    use dlang::HookEvent;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let source = "var f := func(x) => x\nfor i in 1..2 loop\n    print f(i)\nend";
+    let program = Parser::new(source).parse_program().expect("Parse should succeed");
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut interpreter = Interpreter::with_output_capture();
+    let recorded = Rc::clone(&events);
+    interpreter.set_hook(Some(Box::new(move |event| recorded.borrow_mut().push(event))));
+    interpreter.interpret(&program).expect("Should run");
+
+    let shown: Vec<String> = events.borrow().iter().map(|event| match event {
+        HookEvent::Statement(span) => format!("stmt {}:{}", span.line, span.col),
+        HookEvent::Call { name, line } => format!("call {} from line {}", name.as_deref().unwrap_or("?"), line),
+        HookEvent::LoopIteration { span, iteration } => format!("iteration {} of line {}", iteration, span.line),
+    }).collect();
+    assert_eq!(shown, [
+        "stmt 1:1", "stmt 2:1",
+        "iteration 1 of line 2", "stmt 3:5", "call f from line 1",
+        "iteration 2 of line 2", "stmt 3:5", "call f from line 1",
+    ]);
+
+    // without a hook nothing is recorded
+    interpreter.set_hook(None);
+    interpreter.interpret(&program).expect("Should run");
+    assert_eq!(events.borrow().len(), shown.len());
+}
+
+#[test]
+fn test_coverage_reports_untaken_branch() {
+    let source = r#"var x := 1
+var describe := func(n) is
+    if n > 5 then
+        print "big"
+        return "big"
+    end
+    return "small"
+end
+var unused := func() is
+    print "never"
+end
+while x < 3 loop
+    x := x + 1
+end
+print describe(x)"#;
+    let program = Parser::new(source).parse_program().expect("Parse should succeed");
+    let mut interpreter = Interpreter::with_output_capture();
+    let coverage = dlang::Coverage::attach(&mut interpreter);
+    interpreter.interpret(&program).expect("Should run");
+    assert_eq!(interpreter.take_output(), vec!["small"]);
+
+    let unexecuted: Vec<usize> = coverage.unexecuted(&program).iter().map(|stmt| stmt.span().line).collect();
+    assert_eq!(unexecuted, [4, 5, 10]);
+    assert!(coverage.is_executed(Span::new(13, 5)));
+    let executed: Vec<usize> = coverage.executed().iter().map(|span| span.line).collect();
+    assert_eq!(executed, [1, 2, 3, 7, 9, 12, 13, 15]);
+}
+
+// ============================================================================
+// ENVIRONMENT DUMP TESTS
+// ============================================================================
+
+fn final_environment(source: &str) -> String {
+    let program = Parser::new(source).parse_program().expect("Parse should succeed");
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output_capture(true);
+    interpreter.interpret(&program).expect("Program should run");
+    interpreter.dump_environment()
+}
+
+#[test]
+fn test_environment_dump_is_sorted_and_stable() {
+    let source = r#"
+        var zeta := 3
+        var alpha := "a"
+        var point := {y := 2, x := 1, label := "p"}
+        var mid := [1, 2]
+    "#;
+    let dump = final_environment(source);
+    assert_eq!(
+        dump,
+        "alpha = String(\"a\")\n\
+         mid = Array([Integer(1), Integer(2)])\n\
+         point = Tuple({\"1\": Integer(2), \"2\": Integer(1), \"3\": String(\"p\"), \"label\": String(\"p\"), \"x\": Integer(1), \"y\": Integer(2)})\n\
+         zeta = Integer(3)\n"
+    );
+    assert_eq!(final_environment(source), dump);
+
+    // printing the same tuple twice gives the same text
+    let printed = run_captured("var t := {b := 2, c := 3, a := 1}\nprint t\nprint t").unwrap();
+    assert_eq!(printed[0], printed[1]);
+}
+
+#[test]
+fn test_environment_dump_indents_parent_scopes() {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    globals.borrow_mut().define("b".to_string(), Value::Integer(2));
+    globals.borrow_mut().define("a".to_string(), Value::Bool(true));
+    let mut local = Environment::new_with_parent(globals);
+    local.define("c".to_string(), Value::None);
+    assert_eq!(local.dump(), "c = None\nparent:\n  a = Bool(true)\n  b = Integer(2)\n");
+    assert_eq!(format!("{:?}", local), local.dump());
+}
+
+// ============================================================================
+// HOISTED FUNCTION TESTS
+// ============================================================================
+
+fn interpret_hoisted(source: &str) -> Result<Vec<String>, String> {
+    let program = Parser::new(source).parse_program().expect("Parse should succeed");
+    let mut interpreter = Interpreter::with_output_capture();
+    interpreter.interpret_hoisted(&program).map_err(|e| e.to_string())?;
+    Ok(interpreter.take_output())
+}
+
+#[test]
+fn test_hoisted_function_called_above_its_declaration() {
+    let source = "print helper(1)\nvar helper := func(x) => x + 1\nprint helper(2)";
+    assert_eq!(interpret_hoisted(source), Ok(vec!["2".to_string(), "3".to_string()]));
+    let err = interpret_captured(&Parser::new(source).parse_program().unwrap(), Interpreter::new()).expect_err("Not hoisted");
+    assert!(err.contains("helper"), "{}", err);
+
+    // a block body sees the later globals as well
+    let source = "print twice(5)\nvar twice := func(x) is\n    return add(x, x)\nend\nvar add := func(a, b) => a + b";
+    assert_eq!(interpret_hoisted(source), Ok(vec!["10".to_string()]));
+}
+
+#[test]
+fn test_hoisting_leaves_other_declarations_in_order() {
+    let err = interpret_hoisted("print limit\nvar limit := 10").expect_err("Only functions are hoisted");
+    assert!(err.contains("limit"), "{}", err);
+
+    // a hoisted function that reads a later variable works once it is declared
+    let source = "var show := func() => limit\nvar limit := 10\nprint show()";
+    assert_eq!(interpret_hoisted(source), Ok(vec!["10".to_string()]));
+    let err = interpret_hoisted("print show()\nvar show := func() => limit\nvar limit := 10").expect_err("limit is not declared yet");
+    assert!(err.contains("limit"), "{}", err);
+}
+
+#[test]
+fn test_hoisted_mutual_recursion() {
+    let source = "\
+print is_even(10)
+print is_odd(7)
+var is_even := func(n) is
+    if n = 0 then return true end
+    return is_odd(n - 1)
+end
+var is_odd := func(n) is
+    if n = 0 then return false end
+    return is_even(n - 1)
+end";
+    assert_eq!(interpret_hoisted(source), Ok(vec!["true".to_string(), "true".to_string()]));
+}
+
+// ============================================================================
+// MEMORY LIMIT TESTS
+// ============================================================================
+
+fn run_with_memory_limit(source: &str, limit: usize) -> Result<Vec<String>, String> {
+    let options = RunOptions { memory_limit: Some(limit), ..RunOptions::default() };
+    dlang::run(source, options).map(|run| run.output).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_doubling_array_hits_memory_limit() {
+    let source = "var arr := [1, 2, 3]\nvar n := 0\nwhile n < 100 loop\n    arr := [...arr, ...arr]\n    n := n + 1\nend\nprint n, arr[1]";
+    let err = run_with_memory_limit(source, 1 << 20).expect_err("Should stop at the limit");
+    assert!(err.contains("Memory limit of 1048576 bytes exceeded"), "{}", err);
+    assert!(err.ends_with("at 4:5"), "{}", err);
+
+    // two arrays each under the limit, caught together by the periodic sweep
+    let unit = dlang::interpreter::approx_size(&Value::Integer(0));
+    let source = "\
+var a := []
+var b := []
+for i in 1..1000 loop
+    a := [...a, i]
+    b := [...b, i]
+end
+var n := 0
+for i in 1..2000 loop
+    n := n + 1
+end
+print n, a[1000], b[1000]";
+    let err = run_with_memory_limit(source, 1500 * unit).expect_err("Should stop at the limit");
+    assert!(err.contains("Memory limit"), "{}", err);
+    assert_eq!(run_with_memory_limit(source, 2500 * unit), Ok(vec!["2000 1000 1000".to_string()]));
+}
+
+#[test]
+fn test_memory_limit_leaves_normal_programs_alone() {
+    let source = "\
+var squares := []
+for i in 1..200 loop
+    squares := [...squares, i * i]
+end
+var t := {total := 0, label := \"sum\"}
+for s in squares loop
+    t.total := t.total + s
+end
+print t.label, t.total
+print squares[200]";
+    let expected = run_captured(source).expect("Should run");
+    assert_eq!(expected, vec!["sum 2686700", "40000"]);
+    assert_eq!(run_with_memory_limit(source, 1 << 20), Ok(expected));
+
+    // arrays shared by several values are counted once
+    let source = "var a := [1]\nfor i in 1..60 loop\n    a := [a, a]\nend\nprint a is []";
+    assert_eq!(run_with_memory_limit(source, 1 << 16), Ok(vec!["true".to_string()]));
+}
+
+#[test]
+fn test_approx_size_grows_with_contents() {
+    use dlang::interpreter::approx_size;
+    let small = approx_size(&Value::String("ab".into()));
+    let large = approx_size(&Value::String("ab".repeat(100).into()));
+    assert_eq!(large - small, 198);
+    let array = Value::Array(Rc::new(vec![Value::Integer(1); 10]));
+    assert_eq!(approx_size(&array), 11 * approx_size(&Value::Integer(1)));
+}
+
+// ========
+// REAL FORMATTING TESTS
+// ========
+
+#[test]
+fn test_reals_print_the_same_folded_and_at_runtime() {
+    let cases = [
+        ("0.0", "0"),
+        ("-0.0", "0"),
+        ("0.0 * -1.0", "0"),
+        ("1.5", "1.5"),
+        ("-2.25", "-2.25"),
+        ("3.0", "3"),
+        ("0.1", "0.1"),
+        ("0.1 * 3.0", "0.30000000000000004"),
+        ("0.0001", "0.0001"),
+        ("0.00001", "1e-5"),
+        ("0.0000001", "1e-7"),
+        ("-0.000025", "-2.5e-5"),
+        ("123456.789", "123456.789"),
+        ("999999999999999.0", "999999999999999"),
+        ("1000000000000000.0", "1e15"),
+        ("-15000000000000000000.0", "-1.5e19"),
+    ];
+    for (literal, expected) in cases {
+        let folded = dlang::fold_expr_str(&format!("\"\" + ({})", literal)).expect("Should parse");
+        assert_eq!(folded, Expr::String(expected.to_string()), "folding {}", literal);
+
+        // `s` is assigned, so the concatenation happens at runtime
+        let source = format!("var s := \"\"\ns := s + \"\"\nprint s + ({})\nprint {}", literal, literal);
+        assert_eq!(run_captured(&source), Ok(vec![expected.to_string(), expected.to_string()]), "running {}", literal);
+    }
+}
@@ -0,0 +1,48 @@
+use dlang::lexer::Lexer;
+use dlang::token::Token;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts live heap bytes and their peak, so a test can check what lexing
+// costs beyond the source itself. This file holds a single test, so nothing
+// else allocates while it measures.
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let live = LIVE.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(live, Ordering::SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[test]
+fn test_lexing_large_input_stays_within_budget() {
+    let source = "var total := total + 12345 // running sum\n".repeat(100_000);
+    assert!(source.len() > 4_000_000);
+
+    let before = LIVE.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let mut lexer = Lexer::new(&source);
+    let mut tokens = 0;
+    while lexer.next_token() != Token::EOF {
+        tokens += 1;
+    }
+    let extra = PEAK.load(Ordering::SeqCst) - before;
+
+    assert_eq!(tokens, 800_000);
+    // a copy of the input as chars would be four times its size
+    assert!(extra < 64 * 1024, "lexing allocated up to {} bytes at once", extra);
+}
@@ -0,0 +1,514 @@
+use dlang::interpreter::Value;
+use dlang::{check, check_with, PipelineError, RunOptions};
+
+// ========
+// CHECK-ONLY PIPELINE
+// ========
+
+#[test]
+fn test_check_good_program() {
+    let report = check("var x := 5\nprint x + 1");
+    assert!(report.is_ok());
+    assert!(report.program.is_some());
+    assert!(report.parse_errors.is_empty());
+    assert!(report.semantic_errors.is_empty());
+    assert!(report.optimizer_warnings.is_empty());
+}
+
+#[test]
+fn test_check_lexer_error() {
+    let report = check("var x := 1 @ 2");
+    assert!(!report.is_ok());
+    assert!(report.program.is_none());
+    assert_eq!(report.parse_errors.len(), 1);
+    assert!(report.parse_errors[0].message.contains("Unexpected character"));
+}
+
+#[test]
+fn test_check_parse_error() {
+    let report = check("if x then print 1");
+    assert!(report.program.is_none());
+    assert_eq!(report.parse_errors.len(), 1);
+    assert!(report.semantic_errors.is_empty());
+}
+
+#[test]
+fn test_check_semantic_errors() {
+    let report = check("print a\nreturn 1");
+    assert!(!report.is_ok());
+    assert!(report.program.is_some(), "Program still parses");
+    assert!(report.parse_errors.is_empty());
+    assert_eq!(report.semantic_errors.len(), 2);
+    assert!(!report.optimizable());
+}
+
+#[test]
+fn test_check_reports_optimizations_and_warnings() {
+    assert!(check("var x := 2 + 3\nprint x").optimizable());
+    assert!(!check("var i := 0\nwhile i < 3 loop\n    i := i + 1\nend\nprint i").optimizable());
+
+    let report = check("var z := 0\nprint 4 / z");
+    assert!(report.is_ok());
+    assert_eq!(report.optimizer_warnings, vec!["Division by zero detected during optimization"]);
+}
+
+#[test]
+fn test_check_reports_truncating_division() {
+    let report = check("print 7 / 2");
+    assert!(report.is_ok());
+    assert_eq!(report.semantic_warnings.len(), 1);
+    assert!(report.semantic_warnings[0].contains("7 div 2"));
+    assert!(check("print 7 div 2\nprint 7.0 / 2").semantic_warnings.is_empty());
+}
+
+#[test]
+fn test_dead_division_passes_check_and_runs() {
+    let source = "if false then print 1/0 end\nprint 2";
+    let report = check(source);
+    assert!(report.is_ok());
+    assert!(report.optimizer_warnings.is_empty());
+    assert_eq!(dlang::run(source, RunOptions::default()).expect("Should run").output, vec!["2"]);
+
+    assert!(!check("if true then print 1/0 end").is_ok());
+}
+
+// ========
+// EXPRESSION FOLDING
+// ========
+
+#[test]
+fn test_fold_expr_str() {
+    use dlang::{fold_expr_str, Expr};
+
+    assert_eq!(fold_expr_str("2*(3+4)"), Ok(Expr::Integer(14)));
+    assert_eq!(fold_expr_str("((1 + 2) * (10 - (2 + 3))) div 2"), Ok(Expr::Integer(7)));
+    assert_eq!(fold_expr_str("\"ab\" * 2 = \"abab\""), Ok(Expr::Bool(true)));
+    // names are unknown, so only the constant parts fold
+    assert_eq!(fold_expr_str("x * (2 + 3)").map(|e| e.to_string()), Ok("x * 5".to_string()));
+    assert_eq!(fold_expr_str("f(1 + 1, y)").map(|e| e.to_string()), Ok("f(2, y)".to_string()));
+
+    let err = fold_expr_str("1 + 2 print").expect_err("Trailing tokens are an error");
+    assert!(err.message.contains("after the expression"), "{}", err.message);
+    assert!(fold_expr_str("1 +").is_err());
+}
+
+#[test]
+fn test_fold_expression_reports_division_by_zero() {
+    let mut expr = dlang::Parser::new("(4 + 6) / (3 - 3)").parse_standalone_expression().expect("Should parse");
+    let mut optimizer = dlang::Optimizer::new();
+    assert!(optimizer.fold_expression(&mut expr), "the operands still fold");
+    assert_eq!(expr.to_string(), "10 / 0");
+    assert_eq!(optimizer.warnings(), &["Division by zero detected during optimization".to_string()]);
+
+    let mut expr = dlang::Expr::Ident("x".to_string());
+    assert!(!optimizer.fold_expression(&mut expr));
+    assert!(optimizer.warnings().is_empty());
+}
+
+// ========
+// RUN PIPELINE
+// ========
+
+fn unoptimized() -> RunOptions {
+    RunOptions { optimize: false, ..RunOptions::default() }
+}
+
+#[test]
+fn test_run_captures_output_and_globals() {
+    let run = dlang::run("var x := 2\nvar y := x * 3\nprint y", unoptimized()).expect("Should run");
+    assert_eq!(run.output, vec!["6"]);
+    assert_eq!(run.globals.get("x"), Some(&Value::Integer(2)));
+    assert_eq!(run.globals.get("y"), Some(&Value::Integer(6)));
+}
+
+#[test]
+fn test_run_optimize_flag() {
+    let source = "var unused := 5\nprint 1";
+    assert!(dlang::run(source, unoptimized()).expect("Should run").globals.contains_key("unused"));
+    assert!(!dlang::run(source, RunOptions::default()).expect("Should run").globals.contains_key("unused"));
+}
+
+#[test]
+fn test_run_strict_flag() {
+    // `y` is undeclared, but the function is never called
+    let source = "var f := func() => y\nprint 1";
+    assert!(matches!(dlang::run(source, RunOptions::default()), Err(PipelineError::Semantic(_))));
+
+    let lenient = RunOptions { strict: false, ..RunOptions::default() };
+    assert_eq!(dlang::run(source, lenient).expect("Should run").output, vec!["1"]);
+}
+
+#[test]
+fn test_run_step_limit() {
+    let options = RunOptions { max_steps: Some(100), ..RunOptions::default() };
+    let err = dlang::run("print 1\nwhile true loop\n    print 2\nend", options).expect_err("Limit expected");
+    match err {
+        PipelineError::Runtime { error, output } => {
+            assert!(error.to_string().contains("Step limit of 100 exceeded"));
+            assert_eq!(output.len(), 50);  // print, while, then a step per iteration and one per print in the body
+        }
+        other => panic!("Expected runtime error, got {}", other),
+    }
+}
+
+#[test]
+fn test_run_step_limit_counts_loop_iterations() {
+    // none of these bodies has a statement to count
+    for source in ["while true loop end", "for loop end", "for i in 1..1000000 loop end", "repeat until false"] {
+        let options = RunOptions { max_steps: Some(100), ..RunOptions::default() };
+        match dlang::run(source, options) {
+            Err(PipelineError::Runtime { error, .. }) => assert!(error.to_string().contains("Step limit of 100 exceeded"), "{}: {}", source, error),
+            other => panic!("{}: expected the step limit, got {:?}", source, other.map(|run| run.output)),
+        }
+    }
+}
+
+#[test]
+fn test_run_call_depth_limit() {
+    let source = r#"
+var down := func(n) is
+    if n = 0 then
+        return 0
+    end
+    return down(n - 1)
+end
+print down(50)
+"#;
+    let shallow = RunOptions { max_call_depth: Some(10), ..RunOptions::default() };
+    let err = dlang::run(source, shallow).expect_err("Limit expected");
+    assert!(err.to_string().contains("Maximum call depth of 10 exceeded"));
+
+    let deep = RunOptions { max_call_depth: Some(100), ..RunOptions::default() };
+    assert_eq!(dlang::run(source, deep).expect("Should run").output, vec!["0"]);
+}
+
+#[test]
+fn test_run_strict_bool_flag() {
+    let source = "if 1 then\n    print 1\nend";
+    assert_eq!(dlang::run(source, RunOptions::default()).expect("Should run").output, vec!["1"]);
+
+    let strict_bool = RunOptions { strict_bool: true, ..RunOptions::default() };
+    assert!(matches!(dlang::run(source, strict_bool.clone()), Err(PipelineError::Semantic(_))));
+
+    // without the checker the interpreter enforces it
+    let unchecked = RunOptions { strict: false, ..strict_bool };
+    let err = dlang::run(source, unchecked).expect_err("Runtime type error expected");
+    assert!(err.to_string().contains("'if' condition must be a bool, got int"));
+}
+
+#[test]
+fn test_check_after_optimize() {
+    let source = "var debug := false\nif debug then\n    print 1 / 0\nend\nprint \"ok\"";
+    let err = dlang::run(source, RunOptions::default()).expect_err("Checked before optimizing");
+    assert!(err.to_string().contains("Division by zero"), "{}", err);
+
+    let after = RunOptions { check_after_optimize: true, ..RunOptions::default() };
+    assert_eq!(dlang::run(source, after.clone()).expect("The branch is optimized away").output, vec!["ok"]);
+
+    // errors that survive optimization are still reported, and nothing runs
+    let err = dlang::run("print \"start\"\nprint undefined", after.clone()).expect_err("Still checked");
+    assert!(matches!(&err, PipelineError::Semantic(_)), "{}", err);
+    // without optimizing there is nothing to check after
+    let unoptimized = RunOptions { optimize: false, ..after };
+    assert!(dlang::run(source, unoptimized).is_err());
+}
+
+#[test]
+fn test_redeclaration_rebinds_globals() {
+    // a function reads globals by name, so it sees the newest declaration
+    let source = "var x := 1\nprint x\nvar x := 2\nvar g := func() => x\nvar x := 5\nprint g()";
+    assert!(matches!(dlang::run(source, RunOptions::default()), Err(PipelineError::Semantic(_))));
+
+    let options = RunOptions { allow_redeclaration: true, ..RunOptions::default() };
+    let optimized = dlang::run(source, options.clone()).expect("Should run").output;
+    let plain = dlang::run(source, RunOptions { optimize: false, ..options }).expect("Should run").output;
+    assert_eq!(optimized, vec!["1", "5"]);
+    assert_eq!(optimized, plain);
+}
+
+#[test]
+fn test_run_capture_flag() {
+    let live = RunOptions { capture_output: false, ..RunOptions::default() };
+    assert!(dlang::run("print 1", live).expect("Should run").output.is_empty());
+}
+
+#[test]
+fn test_run_error_kinds() {
+    assert!(matches!(dlang::run("print (", RunOptions::default()), Err(PipelineError::Parse(_))));
+    let err = dlang::run("print 1\nvar z := 0\nprint 1 / z", unoptimized()).expect_err("Division error expected");
+    assert!(err.to_string().starts_with("Runtime error: Division by zero"));
+    assert!(matches!(err, PipelineError::Runtime { output, .. } if output == vec!["1"]));
+}
+
+// ========
+// INCLUDES
+// ========
+
+// Fresh directory under the system temp dir holding the given files
+fn temp_project(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("dlang_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (file, source) in files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_include_helper_file() {
+    let dir = temp_project("include", &[
+        ("main.dl", "include \"lib/math.dl\"\nprint square(3), cube(2)"),
+        ("lib/math.dl", "include \"base.dl\"\nvar cube := func(x) => x * square(x)"),
+        ("lib/base.dl", "var square := func(x) => x * x"),
+    ]);
+    let main = dir.join("main.dl");
+    assert!(dlang::check_file(&main).is_ok());
+    let run = dlang::run_file(&main, RunOptions::default()).expect("Included helpers should run");
+    assert_eq!(run.output, vec!["9 8"]);
+}
+
+#[test]
+fn test_include_missing_file() {
+    let dir = temp_project("include_missing", &[("main.dl", "include \"nope.dl\"\nprint 1")]);
+    let err = dlang::run_file(&dir.join("main.dl"), RunOptions::default()).expect_err("Missing include should fail");
+    assert!(matches!(err, PipelineError::Load(_)));
+    let message = err.to_string();
+    assert!(message.contains(&format!("'{}'", dir.join("nope.dl").display())), "{}", message);
+    assert!(message.contains(&format!("included from '{}'", dir.join("main.dl").display())), "{}", message);
+}
+
+#[test]
+fn test_include_cycle() {
+    let dir = temp_project("include_cycle", &[
+        ("a.dl", "include \"b.dl\"\nprint 1"),
+        ("b.dl", "include \"a.dl\""),
+    ]);
+    let err = dlang::run_file(&dir.join("a.dl"), RunOptions::default()).expect_err("Cycle should fail");
+    let a = dir.join("a.dl").display().to_string();
+    let b = dir.join("b.dl").display().to_string();
+    assert_eq!(err.to_string(), format!("Load error: Include cycle: {} -> {} -> {}", a, b, a));
+}
+
+#[test]
+fn test_include_parse_error_names_file() {
+    let dir = temp_project("include_parse", &[("main.dl", "include \"bad.dl\""), ("bad.dl", "\nprint (")]);
+    let report = dlang::check_file(&dir.join("main.dl"));
+    assert_eq!(report.parse_errors.len(), 1);
+    assert!(report.parse_errors[0].message.starts_with(&format!("In '{}'", dir.join("bad.dl").display())));
+
+    // a nested include is not spliced and the checker says so
+    let report = check("if true then\n    include \"x.dl\"\nend");
+    assert!(report.semantic_errors[0].message.contains("Unresolved include of 'x.dl'"));
+}
+
+// ========
+// PRELUDE
+// ========
+
+#[test]
+fn test_prelude_functions_available() {
+    let source = "print max3(3, 9, 4), clamp(15, 0, 10), clamp(-2, 0, 10)\nprint join([\"a\", \"b\", \"c\"], \"-\")";
+    assert!(check(source).is_ok());
+    let run = dlang::run(source, RunOptions::default()).expect("Prelude functions should be declared");
+    assert_eq!(run.output, vec!["9 10 0", "a-b-c"]);
+}
+
+#[test]
+fn test_no_prelude() {
+    let options = RunOptions { no_prelude: true, ..RunOptions::default() };
+    match dlang::run("print clamp(5, 0, 3)", options) {
+        Err(PipelineError::Semantic(errors)) => {
+            assert!(errors[0].message.contains("'clamp' used before declaration"), "{:?}", errors);
+        }
+        other => panic!("Expected a declaration error, got {:?}", other.map(|run| run.output)),
+    }
+}
+
+#[test]
+fn test_user_code_shadows_prelude() {
+    let source = "var clamp := func(x) => x * 2\nprint clamp(5)\nvar join := 1\nprint join";
+    assert!(check(source).is_ok());
+    let run = dlang::run(source, RunOptions::default()).expect("Shadowing a prelude name is allowed");
+    assert_eq!(run.output, vec!["10", "1"]);
+
+    let run = dlang::run(source, RunOptions { optimize: false, ..RunOptions::default() }).unwrap();
+    let names: Vec<&str> = run.globals.keys().map(String::as_str).collect();
+    assert_eq!(names, vec!["clamp", "join"], "Replaced prelude names are the program's own");
+}
+
+#[test]
+fn test_prelude_left_out_of_globals() {
+    let run = dlang::run("var x := max3(1, 2, 3)", RunOptions { optimize: false, ..RunOptions::default() }).unwrap();
+    let names: Vec<&str> = run.globals.keys().map(String::as_str).collect();
+    assert_eq!(names, vec!["x"]);
+}
+
+#[test]
+fn test_check_honours_no_prelude() {
+    let source = "print clamp(5, 0, 3)";
+    assert!(check(source).is_ok());
+    let report = check_with(source, &RunOptions { no_prelude: true, ..RunOptions::default() });
+    assert!(report.semantic_errors[0].message.contains("'clamp' used before declaration"), "{:?}", report.semantic_errors);
+}
+
+// ========
+// THREADS
+// ========
+
+fn assert_send<T: Send + 'static>() {}
+
+#[test]
+fn test_program_runner_on_eight_threads() {
+    assert_send::<dlang::ProgramRunner>();
+    assert_send::<Result<Vec<String>, dlang::RunnerError>>();
+
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_programs");
+    let mut sources: Vec<String> = ["closures", "collections", "fibonacci", "strings"]
+        .iter()
+        .map(|name| std::fs::read_to_string(dir.join(format!("{}.dl", name))).unwrap())
+        .collect();
+    sources.extend([
+        "var total := 0\nfor i in 1..100 loop\n    total := total + i\nend\nprint total".to_string(),
+        "var fact := func(n) is\n    if n <= 1 then return 1 end\n    return n * fact(n - 1)\nend\nprint fact(10)".to_string(),
+        "print join([\"x\", \"y\", \"z\"], \", \")".to_string(),
+        "var t := {a := 1, b := 2.5}\nprint t.a + t.b, t is {}".to_string(),
+    ]);
+
+    let handles: Vec<_> = sources
+        .iter()
+        .map(|source| {
+            let runner = dlang::ProgramRunner::new(source, RunOptions::default()).expect("sample should parse");
+            std::thread::spawn(move || runner.run())
+        })
+        .collect();
+    for (source, handle) in sources.iter().zip(handles) {
+        let output = handle.join().expect("thread should not panic").expect("sample should run");
+        let expected = dlang::run(source, RunOptions::default()).expect("sample should run").output;
+        assert!(!output.is_empty());
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn test_run_parallel_keeps_order_and_errors() {
+    let runners = ["print 1", "var a := [1]\nvar i := 0; i := 5\nprint 2\nprint a[i]", "print 3"]
+        .iter()
+        .map(|source| dlang::ProgramRunner::new(source, RunOptions::default()).unwrap())
+        .collect();
+    let results = dlang::run_parallel(runners);
+    assert_eq!(results[0], Ok(vec!["1".to_string()]));
+    let error = results[1].clone().expect_err("index out of bounds");
+    assert!(error.message.starts_with("Runtime error:"), "{}", error);
+    assert_eq!(error.output, vec!["2"]);
+    assert_eq!(results[2], Ok(vec!["3".to_string()]));
+
+    // output capture is forced on, so nothing is written to stdout
+    let options = RunOptions { capture_output: false, ..RunOptions::default() };
+    assert_eq!(dlang::ProgramRunner::new("print 4", options).unwrap().run(), Ok(vec!["4".to_string()]));
+}
+
+// ========
+// PARSE CACHE
+// ========
+
+#[test]
+fn test_parse_cache_hits_identical_source() {
+    use dlang::{ParseCache, Parser};
+
+    let cache = ParseCache::new(8);
+    let source = "var x := 2 * 3\nprint x";
+    let first = Parser::parse_cached(source, &cache).expect("Should parse");
+    let second = Parser::parse_cached(source, &cache).expect("Should parse");
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    assert!(std::sync::Arc::ptr_eq(&first, &second));
+    assert_eq!(*second, Parser::new(source).parse_program().unwrap());
+
+    // failed parses are not cached
+    assert!(Parser::parse_cached("print (", &cache).is_err());
+    assert!(Parser::parse_cached("print (", &cache).is_err());
+    assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 3, 1));
+}
+
+#[test]
+fn test_parse_cache_is_not_changed_by_optimizing() {
+    let cache = dlang::ParseCache::default();
+    let source = "var x := 2 * 3\nprint x";
+    let cached = dlang::Parser::parse_cached(source, &cache).unwrap();
+
+    let mut optimized = cached.clone_for_mutation();
+    assert!(dlang::Optimizer::new().optimize(&mut optimized));
+    assert_ne!(optimized, *cached);
+
+    let again = dlang::Parser::parse_cached(source, &cache).unwrap();
+    assert_eq!(*again, dlang::Parser::new(source).parse_program().unwrap());
+    assert_eq!(cache.hits(), 1);
+}
+
+#[test]
+fn test_parse_cache_evicts_least_recently_used() {
+    let cache = dlang::ParseCache::new(2);
+    for source in ["print 1", "print 2", "print 1", "print 3"] {
+        dlang::Parser::parse_cached(source, &cache).unwrap();
+    }
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.hits(), 1);
+    // "print 2" was the least recently used
+    dlang::Parser::parse_cached("print 1", &cache).unwrap();
+    dlang::Parser::parse_cached("print 2", &cache).unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (2, 4));
+}
+
+// ========
+// PIPELINE ERRORS
+// ========
+
+#[test]
+fn test_pipeline_error_from_each_stage() {
+    use dlang::{Interpreter, Parser, SemanticChecker, Stage};
+
+    let parse_error = Parser::new("var x := 1\nprint @").parse_program().unwrap_err();
+    let error = PipelineError::from(parse_error.clone());
+    assert_eq!(error.stage(), Stage::Parse);
+    assert_eq!(error.span(), Some((2, parse_error.col)));
+    assert_eq!(PipelineError::from(dlang::parser::ParseError { line: 0, ..parse_error }).span(), None);
+
+    let program = Parser::new("print missing").parse_program().unwrap();
+    let analysis_error = SemanticChecker::new().check(&program).unwrap_err();
+    let error = PipelineError::from(analysis_error);
+    assert_eq!(error.stage(), Stage::Semantic);
+    assert_eq!(error.span(), None);  // the message alone has no positions
+    assert!(error.to_string().contains("missing"), "{}", error);
+    let error = dlang::run("var x := 1\nprint missing", RunOptions::default()).unwrap_err();
+    assert_eq!(error.span(), Some((2, 1)));  // the run takes them from the checker
+
+    let program = Parser::new("var x := 1\nvar y := x / 0").parse_program().unwrap();
+    let runtime_error = Interpreter::new().interpret(&program).unwrap_err();
+    let error = PipelineError::from(runtime_error);
+    assert_eq!(error.stage(), Stage::Runtime);
+    assert_eq!(error.span().map(|(line, _)| line), Some(2));
+
+    // every stage's error works as a std::error::Error
+    let boxed: Box<dyn std::error::Error> = Box::new(error.clone());
+    assert_eq!(boxed.to_string(), error.to_string());
+}
+
+#[test]
+fn test_run_and_check_report_pipeline_errors() {
+    use dlang::Stage;
+
+    let err = dlang::run("print (", RunOptions::default()).unwrap_err();
+    assert_eq!(err.stage(), Stage::Parse);
+    assert_eq!(check("print (").into_result().unwrap_err(), err);
+
+    let strict = RunOptions { strict: true, ..RunOptions::default() };
+    let err = dlang::run("print missing", strict).unwrap_err();
+    assert_eq!(err.stage(), Stage::Semantic);
+    assert_eq!(check("print missing").into_result().unwrap_err(), err);
+
+    let err = dlang::run("var f := func(d) => 1 / d\nprint f(0)", RunOptions::default()).unwrap_err();
+    assert_eq!(err.stage(), Stage::Runtime);
+    assert_eq!(err.span().map(|(line, _)| line), Some(2));
+    assert!(check("print 1").into_result().is_ok());
+}
@@ -0,0 +1,169 @@
+use dlang::lexer::{reconstruct, Lexer, TriviaOrToken};
+use dlang::robust::parse_and_check;
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+// Deterministic pseudo-fuzzing: mutate the sample programs and make sure the
+// front end only ever answers with a program or diagnostics, never a panic.
+
+const MUTANTS_PER_PROGRAM: usize = 400;
+
+// Fragments spliced into programs; chosen to hit number parsing, brackets,
+// tuple literals and unterminated tokens
+const FRAGMENTS: &[&str] = &[
+    "99999999999999999999999", "1.", "..", ".", ":=", ":", "{", "}", "(", ")", "[", "]",
+    ",", "\n", "\"", "'", "/*", "//", "=>", "func", "is", "end", "var", "x", "{x, y := 2}",
+    "loop", "if", "then", "else", "return", "exit", "-", "not", "and", "é", "\0", "🙂",
+];
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 { 0 } else { (self.next() % n as u64) as usize }
+    }
+}
+
+fn sample_programs() -> Vec<String> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_programs");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .expect("Failed to read test_programs")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dl" || ext == "txt"))
+        .collect();
+    paths.sort();
+    paths.into_iter().map(|p| fs::read_to_string(p).expect("Failed to read program")).collect()
+}
+
+// Applies a few random edits on char boundaries
+fn mutate(source: &str, rng: &mut Rng) -> String {
+    let mut chars: Vec<char> = source.chars().collect();
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(chars.len() + 1);
+        match rng.below(4) {
+            0 => {
+                let end = (at + 1 + rng.below(8)).min(chars.len());
+                chars.drain(at.min(end)..end);
+            }
+            1 => {
+                let fragment = FRAGMENTS[rng.below(FRAGMENTS.len())];
+                chars.splice(at..at, fragment.chars());
+            }
+            2 => {
+                let end = (at + 1 + rng.below(16)).min(chars.len());
+                let copy: Vec<char> = chars[at.min(end)..end].to_vec();
+                chars.splice(at..at, copy);
+            }
+            _ => {
+                if !chars.is_empty() {
+                    let last = chars.len() - 1;
+                    let other = rng.below(chars.len());
+                    chars.swap(at.min(last), other);
+                }
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+#[test]
+fn test_parse_and_check_never_panics_on_mutated_programs() {
+    let programs = sample_programs();
+    assert!(!programs.is_empty(), "No sample programs found");
+
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for program in &programs {
+        for _ in 0..MUTANTS_PER_PROGRAM {
+            let mutant = mutate(program, &mut rng);
+            let result = panic::catch_unwind(|| {
+                let _ = parse_and_check(&mutant);
+            });
+            assert!(result.is_ok(), "parse_and_check panicked on input:\n{:?}", mutant);
+        }
+    }
+}
+
+#[test]
+fn test_parse_and_check_reports_overflowing_literal() {
+    let diagnostics = parse_and_check("var x := 99999999999999999999").expect_err("Overflow should be diagnosed");
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0].message.contains("out of range"));
+    assert_eq!((diagnostics[0].line, diagnostics[0].col), (1, 10));
+}
+
+#[test]
+fn test_parse_and_check_reports_each_semantic_error() {
+    let diagnostics = parse_and_check("print a\nprint b").expect_err("Undeclared variables expected");
+    assert_eq!(diagnostics.len(), 2);
+    assert!(parse_and_check("var a := 1\nprint a").is_ok());
+}
+
+#[test]
+fn test_parse_and_check_knows_the_prelude() {
+    assert!(parse_and_check("print max3(1, 5, 3)").is_ok());
+    let diagnostics = parse_and_check("print max3(1, 5)").expect_err("Wrong argument count expected");
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+}
+
+#[test]
+fn test_parse_and_check_reports_deep_nesting() {
+    let sources = [
+        format!("print {}1{}", "(".repeat(10_000), ")".repeat(10_000)),
+        format!("print {}", "[{(".repeat(10_000)),
+        format!("print {}1", "not -".repeat(10_000)),
+        format!("{}print 1", "while true loop\nif true then\n".repeat(10_000)),
+        format!("var f := {}1", "func(x) => func() is\nreturn ".repeat(10_000)),
+    ];
+    // 256 levels take more native stack than a test thread has in debug builds
+    let check = move || {
+        for source in &sources {
+            let result = panic::catch_unwind(|| parse_and_check(source));
+            let diagnostics = result.expect("parse_and_check panicked").expect_err("Nesting should be diagnosed");
+            assert!(diagnostics.iter().any(|d| d.message == "Statements and expressions nest more than 256 levels deep" && d.line > 0), "{:?}", diagnostics);
+        }
+    };
+    std::thread::Builder::new().stack_size(64 << 20).spawn(check).unwrap().join().unwrap();
+}
+
+#[test]
+fn test_trivia_stream_reconstructs_sources() {
+    let programs = sample_programs();
+    assert!(!programs.is_empty(), "No sample programs found");
+
+    for program in &programs {
+        let crlf = program.replace("\r\n", "\n").replace('\n', "\r\n");
+        let tabs = program.replace("    ", "\t");
+        for source in [program, &crlf, &tabs] {
+            let items = Lexer::tokenize_with_trivia(source);
+            assert_eq!(reconstruct(&items), *source);
+            // the items tile the source in order, each with its own text
+            let mut next = 0;
+            for item in &items {
+                assert_eq!(item.range().start, next, "{:?}", item);
+                assert_eq!(&source[item.range()], item.text());
+                next = item.range().end;
+            }
+            assert_eq!(next, source.len());
+        }
+    }
+
+    // the blanks are kept apart from the tokens the parser sees
+    let items = Lexer::tokenize_with_trivia("print\t1 \r\n");
+    let kinds: Vec<bool> = items.iter().map(|item| matches!(item, TriviaOrToken::Whitespace { .. })).collect();
+    assert_eq!(kinds, [false, true, false, true, false]);
+    assert_eq!(items[3].text(), " \r");
+}
+
+#[test]
+fn test_trivia_stream_of_empty_source() {
+    assert!(Lexer::tokenize_with_trivia("").is_empty());
+    assert_eq!(reconstruct(&Lexer::tokenize_with_trivia("  \t")), "  \t");
+}
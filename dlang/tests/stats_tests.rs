@@ -0,0 +1,40 @@
+use dlang::stats::source_stats;
+use std::collections::BTreeMap;
+
+#[test]
+fn test_stats_counts_small_program() {
+    let source = "var f := func(x) is\n    if x > 0 then\n        return x * 2\n    end\n    return 0\nend\nprint f(3)";
+    let stats = source_stats(source);
+
+    let tokens: BTreeMap<String, usize> = [
+        ("Assign", 1), ("End", 2), ("Func", 1), ("Greater", 1), ("Identifier", 5), ("If", 1), ("Integer", 4),
+        ("Is", 1), ("LParen", 2), ("Newline", 6), ("Print", 1), ("RParen", 2), ("Return", 2), ("Star", 1),
+        ("Then", 1), ("Var", 1),
+    ].into_iter().map(|(kind, count)| (kind.to_string(), count)).collect();
+    assert_eq!(stats.tokens, tokens);
+    assert_eq!(stats.token_count(), 32);
+
+    assert_eq!(stats.statements, BTreeMap::from([("If", 1), ("Print", 1), ("Return", 2), ("VarDecl", 1)]));
+    assert_eq!(stats.expressions, BTreeMap::from([("Binary", 2), ("Call", 1), ("Func", 1), ("Ident", 3), ("Integer", 4)]));
+    assert_eq!(stats.node_count(), 16);
+    assert_eq!(stats.function_literals, 1);
+    // VarDecl > Func > If > Return > Binary > Ident
+    assert_eq!(stats.max_depth, 6);
+    assert_eq!(stats.parse_error, None);
+}
+
+#[test]
+fn test_stats_depth() {
+    assert_eq!(source_stats("print 1").max_depth, 2);
+    assert_eq!(source_stats("print [[[1]]]").max_depth, 5);
+    assert_eq!(source_stats("while true loop\n    for i in 1..2 loop\n        exit\n    end\nend").max_depth, 4);  // While > For > Range > Integer
+    assert_eq!(source_stats("").max_depth, 0);
+}
+
+#[test]
+fn test_stats_parse_error_keeps_token_counts() {
+    let stats = source_stats("print (1 +");
+    assert!(stats.parse_error.is_some());
+    assert_eq!(stats.token_count(), 4);
+    assert_eq!(stats.node_count(), 0);
+}
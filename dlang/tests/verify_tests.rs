@@ -0,0 +1,46 @@
+use dlang::verify::{check_optimizer, Divergence};
+use std::fs;
+use std::path::Path;
+
+// the demo snippets of main.rs
+#[path = "../src/samples.rs"]
+mod samples;
+
+#[test]
+fn test_optimizer_preserves_demo_samples() {
+    for (i, source) in samples::SAMPLES.iter().enumerate() {
+        if let Err(divergence) = check_optimizer(source) {
+            panic!("sample {}: {}\n{}", i + 1, divergence, source);
+        }
+    }
+}
+
+#[test]
+fn test_optimizer_preserves_test_programs() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_programs");
+    for entry in fs::read_dir(dir).expect("Failed to read test_programs") {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "dl") {
+            let source = fs::read_to_string(&path).unwrap();
+            assert_eq!(check_optimizer(&source), Ok(()), "{}", path.display());
+        }
+    }
+}
+
+#[test]
+fn test_programs_that_do_not_run_are_not_divergences() {
+    assert_eq!(check_optimizer("print ("), Ok(()));
+    assert_eq!(check_optimizer("print missing"), Ok(()));
+    // the same runtime error after the same output
+    assert_eq!(check_optimizer("var a := [1]\nvar i := 3\nprint 1\nprint a[i]"), Ok(()));
+    // folding and dead code removal do not change what is printed
+    assert_eq!(check_optimizer("var x := 2 * 3\nif false then print 0 end\nprint x, random() < 2"), Ok(()));
+}
+
+#[test]
+fn test_divergence_messages() {
+    let output = Divergence::Output { line: 2, original: Some("1".into()), optimized: None };
+    assert_eq!(output.to_string(), "output line 2 differs: 1 (original) vs <no line> (optimized)");
+    let error = Divergence::Error { original: None, optimized: Some("Runtime error: boom".into()) };
+    assert_eq!(error.to_string(), "runs end differently: <success> (original) vs Runtime error: boom (optimized)");
+}